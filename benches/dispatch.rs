@@ -0,0 +1,55 @@
+//! Measures instruction dispatch overhead in the virtual machine.
+//!
+//! Each benchmark runs a small script whose cost is dominated by the
+//! interpretation of simple instructions rather than by system functions.
+//!
+//! Run with `cargo bench --bench dispatch`.
+
+extern crate ketos;
+
+use std::time::{Duration, Instant};
+
+use ketos::Interpreter;
+
+/// Number of times each benchmark is run; the fastest run is reported.
+const RUNS: u32 = 5;
+
+const BENCHES: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("count", "
+        (define (count n)
+          (if (= n 0) () (count (- n 1))))",
+        "(count 1000000)"),
+    ("fib", "
+        (define (fib n)
+          (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))",
+        "(fib 25)"),
+    ("let-loop", "
+        (define (loop n acc)
+          (let ((a (+ acc 1))
+                (b (* 2 n)))
+            (if (= n 0) acc (loop (- n 1) (- b a)))))",
+        "(loop 500000 0)"),
+];
+
+fn main() {
+    for &(name, setup, expr) in BENCHES {
+        let interp = Interpreter::new();
+
+        interp.run_code(setup, None).unwrap();
+        let code = interp.compile_exprs(expr).unwrap();
+
+        let mut best = None::<Duration>;
+
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            interp.execute_program(code.clone()).unwrap();
+            let time = start.elapsed();
+
+            best = Some(best.map_or(time, |b| b.min(time)));
+        }
+
+        let best = best.unwrap();
+        println!("{:<10} {:>8.2} ms", name,
+            best.as_secs() as f64 * 1e3 + best.subsec_nanos() as f64 / 1e6);
+    }
+}
@@ -0,0 +1,76 @@
+//! Measures lookup time in `NameMap` against binary search of a sorted `Vec`,
+//! for maps of increasing size.
+//!
+//! Run with `cargo bench --bench names`.
+
+extern crate ketos;
+
+use std::time::{Duration, Instant};
+
+use ketos::name::{Name, NameMap, NameStore};
+
+/// Number of times each benchmark is run; the fastest run is reported.
+const RUNS: u32 = 5;
+
+/// Number of lookups performed in each run
+const LOOKUPS: usize = 1_000_000;
+
+const SIZES: &'static [usize] = &[4, 8, 16, 24, 32, 64, 256, 1024, 4096];
+
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let mut best = None::<Duration>;
+    let mut sum = 0;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        sum += f();
+        let time = start.elapsed();
+
+        best = Some(best.map_or(time, |b| b.min(time)));
+    }
+
+    (best.unwrap(), sum)
+}
+
+fn nanos_per_lookup(d: Duration) -> f64 {
+    (d.as_secs() as f64 * 1e9 + d.subsec_nanos() as f64) / LOOKUPS as f64
+}
+
+fn main() {
+    let mut store = NameStore::new();
+
+    println!("{:>6} {:>12} {:>12}", "size", "NameMap", "binary");
+
+    for &size in SIZES {
+        let names = (0..size)
+            .map(|i| store.add(&format!("name{}", i)))
+            .collect::<Vec<Name>>();
+
+        let map = names.iter().map(|&n| (n, n.get() as usize))
+            .collect::<NameMap<usize>>();
+        let mut sorted = names.iter().map(|&n| (n, n.get() as usize))
+            .collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Visit names in a scattered order
+        let keys = (0..LOOKUPS)
+            .map(|i| names[i.wrapping_mul(7919) % size])
+            .collect::<Vec<_>>();
+
+        let (map_time, a) = time(|| {
+            keys.iter().map(|&k| *map.get(k).unwrap()).fold(0, usize::wrapping_add)
+        });
+
+        let (vec_time, b) = time(|| {
+            keys.iter().map(|&k| {
+                let pos = sorted.binary_search_by(|&(n, _)| n.cmp(&k)).unwrap();
+                sorted[pos].1
+            }).fold(0, usize::wrapping_add)
+        });
+
+        assert_eq!(a, b);
+
+        println!("{:>6} {:>9.2} ns {:>9.2} ns", size,
+            nanos_per_lookup(map_time), nanos_per_lookup(vec_time));
+    }
+}
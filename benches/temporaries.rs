@@ -0,0 +1,75 @@
+//! Measures evaluation of scripts which create short-lived buffers.
+//!
+//! Each benchmark calls, in a loop, a function which builds a temporary
+//! that is discarded once the call returns.
+//!
+//! Run with `cargo bench --bench temporaries`.
+
+extern crate ketos;
+
+use std::cell::RefCell;
+use std::fmt::Arguments;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use ketos::{BuiltinModuleLoader, GlobalScope, Interpreter, NameStore};
+use ketos::io::{IoError, SharedWrite};
+use ketos::lexer::CodeMap;
+use ketos::module::ModuleRegistry;
+use ketos::scope::GlobalIo;
+
+/// Number of times each benchmark is run; the fastest run is reported.
+const RUNS: u32 = 5;
+
+const BENCHES: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("keywords", "
+        (define (f :key a b c) a)
+        (define (loop n)
+          (if (= n 0) () (do (f :c 3 :a 1) (loop (- n 1)))))",
+        "(loop 200000)"),
+    ("apply", "
+        (define args '(1 2 3 4 5 6 7 8))
+        (define (loop n)
+          (if (= n 0) () (do (apply + args) (loop (- n 1)))))",
+        "(loop 200000)"),
+    ("print", "
+        (define (loop n)
+          (if (= n 0) () (do (print \"~a ~a\" n \"abcdefghijklmnop\") (loop (- n 1)))))",
+        "(loop 200000)"),
+];
+
+/// Discards all output
+struct Sink;
+
+impl SharedWrite for Sink {
+    fn write_all(&self, _buf: &[u8]) -> Result<(), IoError> { Ok(()) }
+    fn write_fmt(&self, _fmt: Arguments) -> Result<(), IoError> { Ok(()) }
+    fn flush(&self) -> Result<(), IoError> { Ok(()) }
+}
+
+fn main() {
+    for &(name, setup, expr) in BENCHES {
+        let interp = Interpreter::with_scope(Rc::new(GlobalScope::new(
+            Rc::new(RefCell::new(NameStore::new())),
+            Rc::new(RefCell::new(CodeMap::new())),
+            Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))),
+            Rc::new(GlobalIo::new(Rc::new(Sink))))));
+
+        interp.run_code(setup, None).unwrap();
+        let code = interp.compile_exprs(expr).unwrap();
+
+        let mut best = None::<Duration>;
+
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            interp.execute_program(code.clone()).unwrap();
+            let time = start.elapsed();
+
+            best = Some(best.map_or(time, |b| b.min(time)));
+        }
+
+        let best = best.unwrap();
+        println!("{:<10} {:>8.2} ms", name,
+            best.as_secs() as f64 * 1e3 + best.subsec_nanos() as f64 / 1e6);
+    }
+}
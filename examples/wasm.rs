@@ -0,0 +1,158 @@
+//! Demonstrates driving the `ketos` interpreter from JavaScript
+//! when compiled for the `wasm32-unknown-unknown` target.
+//!
+//! Build using:
+//!
+//! ```text
+//! cargo build --release --target wasm32-unknown-unknown --example wasm
+//! ```
+//!
+//! Then, from JavaScript:
+//!
+//! ```js
+//! const { instance } = await WebAssembly.instantiateStreaming(fetch("wasm.wasm"));
+//! const k = instance.exports;
+//!
+//! function run(src) {
+//!     const bytes = new TextEncoder().encode(src);
+//!     const ptr = k.ketos_alloc(bytes.length);
+//!     new Uint8Array(k.memory.buffer, ptr, bytes.length).set(bytes);
+//!     const ok = k.ketos_eval(ptr, bytes.length) === 0;
+//!     k.ketos_free(ptr, bytes.length);
+//!
+//!     const out = new Uint8Array(k.memory.buffer,
+//!         k.ketos_output_ptr(), k.ketos_output_len());
+//!     return { ok, output: new TextDecoder().decode(out) };
+//! }
+//!
+//! k.ketos_seed(Date.now() >>> 0);
+//! console.log(run('(println "hello from ketos") (+ 1 2)'));
+//! ```
+//!
+//! Scripts are sandboxed: only builtin modules may be loaded, no input
+//! is available, and output is captured and returned to the caller.
+
+extern crate ketos;
+extern crate rand;
+
+use std::cell::RefCell;
+use std::fmt::{Arguments, Write};
+use std::mem;
+use std::rc::Rc;
+use std::str;
+
+use rand::{SeedableRng, XorShiftRng};
+
+use ketos::{BuiltinModuleLoader, GlobalScope, Interpreter, NameStore};
+use ketos::io::{IoError, SharedWrite};
+use ketos::lexer::CodeMap;
+use ketos::module::ModuleRegistry;
+use ketos::scope::GlobalIo;
+
+/// Collects output written by scripts
+struct Output(RefCell<String>);
+
+impl SharedWrite for Output {
+    fn write_all(&self, buf: &[u8]) -> Result<(), IoError> {
+        self.0.borrow_mut().push_str(&String::from_utf8_lossy(buf));
+        Ok(())
+    }
+
+    fn write_fmt(&self, fmt: Arguments) -> Result<(), IoError> {
+        let _ = self.0.borrow_mut().write_fmt(fmt);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), IoError> { Ok(()) }
+}
+
+struct State {
+    interp: Interpreter,
+    output: Rc<Output>,
+    result: RefCell<String>,
+}
+
+thread_local!(static STATE: State = new_state());
+
+fn new_state() -> State {
+    let output = Rc::new(Output(RefCell::new(String::new())));
+
+    let interp = Interpreter::with_scope(Rc::new(GlobalScope::new(
+        Rc::new(RefCell::new(NameStore::new())),
+        Rc::new(RefCell::new(CodeMap::new())),
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))),
+        Rc::new(GlobalIo::with_stderr(output.clone(), output.clone())))));
+
+    State{
+        interp: interp,
+        output: output,
+        result: RefCell::new(String::new()),
+    }
+}
+
+/// Allocates a buffer of `len` bytes into which the caller may write input.
+#[no_mangle]
+pub extern "C" fn ketos_alloc(len: usize) -> *mut u8 {
+    let mut buf = Vec::<u8>::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    mem::forget(buf);
+    ptr
+}
+
+/// Releases a buffer allocated by `ketos_alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn ketos_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Seeds the random number generator used by the `random` module.
+///
+/// `wasm32-unknown-unknown` provides no source of entropy,
+/// so the host must supply one.
+#[no_mangle]
+pub extern "C" fn ketos_seed(seed: u32) {
+    STATE.with(|st| {
+        let rng = XorShiftRng::from_seed([seed, !seed, 0x9e37_79b9, 0x7f4a_7c15]);
+        st.interp.get_scope().set_rng(Box::new(rng));
+    });
+}
+
+/// Runs the UTF-8 encoded source code in the given buffer.
+///
+/// Returns `0` on success and `1` on error. Output written by the script,
+/// followed by the result value or error message, may be retrieved using
+/// `ketos_output_ptr` and `ketos_output_len`.
+#[no_mangle]
+pub unsafe extern "C" fn ketos_eval(ptr: *const u8, len: usize) -> u32 {
+    let input = std::slice::from_raw_parts(ptr, len);
+
+    STATE.with(|st| {
+        let (status, text) = match str::from_utf8(input) {
+            Ok(src) => match st.interp.run_code(src, None) {
+                Ok(v) => (0, st.interp.format_value(&v)),
+                Err(e) => (1, st.interp.display(&e)),
+            },
+            Err(e) => (1, e.to_string()),
+        };
+
+        // Move captured output, followed by the result, into the buffer
+        // read by the caller.
+        let mut result = mem::replace(&mut *st.output.0.borrow_mut(), String::new());
+        result.push_str(&text);
+        *st.result.borrow_mut() = result;
+
+        status
+    })
+}
+
+/// Returns a pointer to the output of the last call to `ketos_eval`.
+#[no_mangle]
+pub extern "C" fn ketos_output_ptr() -> *const u8 {
+    STATE.with(|st| st.result.borrow().as_ptr())
+}
+
+/// Returns the length, in bytes, of the output of the last call to `ketos_eval`.
+#[no_mangle]
+pub extern "C" fn ketos_output_len() -> usize {
+    STATE.with(|st| st.result.borrow().len())
+}
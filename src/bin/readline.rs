@@ -11,7 +11,7 @@ use std::sync::{Once, ONCE_INIT};
 use libc::{self, c_char, c_int, size_t};
 use ketos::scope::GlobalScope;
 
-use completion::complete;
+use ketos::repl::complete;
 
 static INIT_READLINE: Once = ONCE_INIT;
 
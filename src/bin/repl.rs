@@ -7,9 +7,9 @@ use std::io::{stderr, Write};
 use std::path::{Path, PathBuf};
 
 use getopts::{Options, ParsingStyle};
-use ketos::{Interpreter, Error, ParseErrorKind};
+use ketos::Interpreter;
+use ketos::repl::{Prompt, Repl, ReplOutput};
 
-mod completion;
 mod readline;
 
 fn main() {
@@ -117,65 +117,37 @@ fn run_file(interp: &Interpreter, file: &Path) -> bool {
     }
 }
 
-#[derive(Copy, Clone)]
-enum Prompt {
-    Normal,
-    OpenComment,
-    OpenParen,
-    OpenString,
-}
-
 fn read_line(interp: &Interpreter, prompt: Prompt) -> Option<String> {
     let prompt = match prompt {
         Prompt::Normal => "ketos=> ",
         Prompt::OpenComment => "ketos#> ",
         Prompt::OpenParen => "ketos(> ",
         Prompt::OpenString => "ketos\"> ",
+        Prompt::Continue => "ketos-> ",
     };
 
     readline::read_line(prompt, interp.get_scope())
 }
 
 fn run_repl(interp: &Interpreter) {
-    let mut buf = String::new();
-    let mut prompt = Prompt::Normal;
+    let mut repl = Repl::new(interp.clone());
 
-    while let Some(line) = read_line(interp, prompt) {
+    while let Some(line) = read_line(interp, repl.prompt()) {
         if line.chars().all(|c| c.is_whitespace()) {
             continue;
         }
 
         readline::push_history(&line);
-        buf.push_str(&line);
-        buf.push('\n');
-
-        match interp.compile_exprs(&buf) {
-            Ok(code) => {
-                prompt = Prompt::Normal;
-                if !code.is_empty() {
-                    match interp.execute_program(code) {
-                        Ok(v) => interp.display_value(&v),
-                        Err(e) => interp.display_error(&e)
-                    }
-                }
-            }
-            Err(Error::ParseError(ref e)) if e.kind == ParseErrorKind::MissingCloseParen => {
-                prompt = Prompt::OpenParen;
-                continue;
-            }
-            Err(Error::ParseError(ref e)) if e.kind == ParseErrorKind::UnterminatedComment => {
-                prompt = Prompt::OpenComment;
-                continue;
-            }
-            Err(Error::ParseError(ref e)) if e.kind == ParseErrorKind::UnterminatedString => {
-                prompt = Prompt::OpenString;
-                continue;
+
+        match repl.feed(&line) {
+            ReplOutput::Value{number: Some(n), ref text, ..} =>
+                println!("${} = {}", n, text),
+            ReplOutput::Value{ref text, ..} => println!("{}", text),
+            ReplOutput::Error{ref message, ..} => {
+                let _ = stderr().write_all(message.as_bytes());
             }
-            Err(ref e) => interp.display_error(e)
+            ReplOutput::Empty | ReplOutput::Incomplete(_) => ()
         }
-
-        buf.clear();
-        interp.clear_codemap();
     }
 
     println!("");
@@ -0,0 +1,196 @@
+//! Provides reusable buffers for short-lived values created during evaluation.
+//!
+//! System functions frequently build a `String` which is discarded as soon
+//! as the function returns; for example, the text written by `print` or
+//! `println`. Likewise, the virtual machine collects keyword arguments into
+//! a `Vec<Value>` which is emptied onto the stack before a call begins.
+//! Rather than allocating a fresh buffer for each such temporary,
+//! a buffer may be borrowed from an `Arena` and is returned to it, with its
+//! capacity intact, when the borrow ends.
+//!
+//! Text or lists which become part of a returned value, such as the result
+//! of `format` or `concat`, are built in a buffer of their own and moved
+//! into the value, rather than borrowing from an `Arena`.
+//!
+//! An `Arena` is shared by all scopes created from the same `Interpreter`
+//! and is reset at the start of each top-level evaluation, releasing any
+//! buffers retained during previous evaluations.
+
+use std::cell::RefCell;
+use std::mem::replace;
+use std::ops;
+
+use value::Value;
+
+/// Maximum number of buffers of each kind retained by an `Arena`
+const MAX_RETAINED: usize = 16;
+
+/// Maximum capacity, in elements, of a buffer retained by an `Arena`.
+/// Larger buffers are dropped rather than returned, so that a single large
+/// temporary does not hold memory for the life of an evaluation.
+const MAX_RETAINED_CAPACITY: usize = 64 * 1024;
+
+/// Holds reusable buffers for temporary strings and lists
+#[derive(Default)]
+pub struct Arena {
+    strings: RefCell<Vec<String>>,
+    lists: RefCell<Vec<Vec<Value>>>,
+}
+
+/// Buffer which can be reused once its contents are cleared
+pub trait Reuse: Default {
+    /// Removes all contents, retaining allocated capacity.
+    fn clear(&mut self);
+
+    /// Returns the allocated capacity, in elements.
+    fn capacity(&self) -> usize;
+
+    /// Returns the pool of buffers of this type within an `Arena`.
+    fn pool(arena: &Arena) -> &RefCell<Vec<Self>>;
+}
+
+impl Reuse for String {
+    fn clear(&mut self) { String::clear(self) }
+    fn capacity(&self) -> usize { String::capacity(self) }
+    fn pool(arena: &Arena) -> &RefCell<Vec<String>> { &arena.strings }
+}
+
+impl Reuse for Vec<Value> {
+    fn clear(&mut self) { Vec::clear(self) }
+    fn capacity(&self) -> usize { Vec::capacity(self) }
+    fn pool(arena: &Arena) -> &RefCell<Vec<Vec<Value>>> { &arena.lists }
+}
+
+impl Arena {
+    /// Creates a new `Arena` containing no buffers.
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    /// Borrows an empty `String` buffer from the arena.
+    pub fn string(&self) -> Temp<String> {
+        self.take()
+    }
+
+    /// Borrows an empty `Vec<Value>` buffer from the arena.
+    pub fn list(&self) -> Temp<Vec<Value>> {
+        self.take()
+    }
+
+    /// Borrows an empty buffer of any reusable type from the arena.
+    pub fn take<T: Reuse>(&self) -> Temp<T> {
+        let buf = T::pool(self).borrow_mut().pop().unwrap_or_default();
+
+        Temp{
+            arena: self,
+            buf: buf,
+        }
+    }
+
+    /// Releases all buffers retained by the arena.
+    pub fn reset(&self) {
+        self.strings.borrow_mut().clear();
+        self.lists.borrow_mut().clear();
+    }
+
+    fn give<T: Reuse>(&self, mut buf: T) {
+        if buf.capacity() == 0 || buf.capacity() > MAX_RETAINED_CAPACITY {
+            return;
+        }
+
+        buf.clear();
+
+        let mut pool = T::pool(self).borrow_mut();
+
+        if pool.len() < MAX_RETAINED {
+            pool.push(buf);
+        }
+    }
+}
+
+/// Buffer borrowed from an `Arena`; the buffer is returned when dropped.
+pub struct Temp<'a, T: Reuse + 'a> {
+    arena: &'a Arena,
+    buf: T,
+}
+
+impl<'a, T: Reuse> Temp<'a, T> {
+    /// Takes the contained buffer, leaving an empty buffer in its place.
+    /// The taken buffer will not be returned to the arena.
+    pub fn take(&mut self) -> T {
+        replace(&mut self.buf, T::default())
+    }
+}
+
+impl<'a, T: Reuse> ops::Deref for Temp<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.buf
+    }
+}
+
+impl<'a, T: Reuse> ops::DerefMut for Temp<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.buf
+    }
+}
+
+impl<'a, T: Reuse> Drop for Temp<'a, T> {
+    fn drop(&mut self) {
+        let buf = self.take();
+        self.arena.give(buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Arena;
+    use value::Value;
+
+    #[test]
+    fn test_reuse() {
+        let arena = Arena::new();
+
+        let cap = {
+            let mut s = arena.string();
+            s.push_str("hello");
+            s.capacity()
+        };
+
+        let s = arena.string();
+        assert!(s.is_empty());
+        assert_eq!(s.capacity(), cap);
+
+        {
+            let mut t = arena.string();
+            t.push_str("taken");
+            let t = t.take();
+            assert_eq!(t, "taken");
+        }
+
+        assert_eq!(arena.string().capacity(), 0);
+
+        let cap = {
+            let mut li = arena.list();
+            li.push(Value::Unit);
+            li.capacity()
+        };
+
+        let li = arena.list();
+        assert!(li.is_empty());
+        assert_eq!(li.capacity(), cap);
+    }
+
+    #[test]
+    fn test_reset() {
+        let arena = Arena::new();
+
+        arena.string().push('a');
+        arena.list().push(Value::Unit);
+        arena.reset();
+
+        assert_eq!(arena.string().capacity(), 0);
+        assert_eq!(arena.list().capacity(), 0);
+    }
+}
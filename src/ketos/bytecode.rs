@@ -17,17 +17,23 @@
 //! with a single opcode, e.g. the `CONST_0` opcode replaces the two-byte
 //! sequence `CONST`, followed by operand `0`.
 
+use std::cell::RefCell;
+use std::fmt::{self, Write as FmtWrite};
+use std::rc::{Rc, Weak};
+
 use compile::CompileError;
 use exec::ExecError;
-use function::Arity;
-use name::Name;
+use function::{plural, Arity, Function, Lambda};
+use lexer::Span;
+use name::{debug_names, get_standard_name, Name, NameStore};
+use scope::GlobalScope;
 use value::Value;
 
 /// Bytecode version number, indicating the version of the most recent breaking
 /// change to the bytecode format. The version represents a `ketos` version
 /// number, e.g. `0x01_02_03_00` corresponds to version `1.2.3`.
 /// (The least significant 8 bits don't mean anything yet.)
-pub const BYTECODE_VERSION: u32 = 0x00_00_03_00;
+pub const BYTECODE_VERSION: u32 = 0x00_00_03_12;
 
 /// Maximum value of a short-encoded operand.
 pub const MAX_SHORT_OPERAND: u32 = 0x7f;
@@ -128,6 +134,10 @@ pub enum Instruction {
     Inc,
     /// Decrement integer value
     Dec,
+    /// Increment, then push
+    IncPush,
+    /// Decrement, then push
+    DecPush,
     /// Append value to list on top of stack; result will be in value
     Append,
     /// Take first element of list and assign to value
@@ -152,6 +162,11 @@ pub enum Instruction {
     /// Call system function with *n* arguments on the stack;
     /// parameters are `(sys_fn, n_args)`.
     CallSysArgs(u32, u32),
+    /// Call system function *n*, then push
+    CallSysPush(u32),
+    /// Call system function with arguments, then push;
+    /// parameters are `(sys_fn, n_args)`.
+    CallSysArgsPush(u32, u32),
     /// Call const function with arguments on the stack;
     /// parameters are `(const, n_args)`.
     CallConst(u32, u32),
@@ -165,6 +180,25 @@ pub enum Instruction {
     CallSelf(u32),
     /// Perform tail-recursive call with *n* arguments from the top of the stack
     TailCall(u32),
+    /// Call const function in tail position, replacing the current
+    /// call frame; parameters are `(const, n_args)`.
+    TailCallConst(u32, u32),
+    /// Call function on the stack in tail position with *n* arguments
+    /// from the top of the stack, replacing the current call frame
+    TailCallFunction(u32),
+    /// Call function on the stack in tail position with *n* stack arguments
+    /// and additional arguments from list value, replacing the current call frame
+    TailApply(u32),
+    /// Push each of exactly *n* elements of list value onto the stack
+    Unpack(u32),
+    /// Push the first *n* elements of list value onto the stack,
+    /// followed by a list of the remaining elements
+    UnpackRest(u32),
+    /// Install an error handler at label; execution continues with the
+    /// following instruction
+    Try(u32),
+    /// Remove the most recently installed error handler
+    EndTry,
     /// Remove *n* values from the top of the stack
     Skip(u32),
     /// Return value from function
@@ -307,6 +341,17 @@ opcodes!{
     SKIP_3 = 119,
     SKIP_4 = 120,
     RETURN = 121,
+    INC_PUSH = 122,
+    DEC_PUSH = 123,
+    CALL_SYS_PUSH = 124,
+    CALL_SYS_ARGS_PUSH = 125,
+    TAIL_CALL_CONST = 126,
+    TAIL_CALL_FUNCTION = 127,
+    TAIL_APPLY = 128,
+    UNPACK = 129,
+    UNPACK_REST = 130,
+    TRY = 131,
+    END_TRY = 132,
 }
 
 impl Instruction {
@@ -409,6 +454,8 @@ impl Instruction {
             NOT => Not,
             INC => Inc,
             DEC => Dec,
+            INC_PUSH => IncPush,
+            DEC_PUSH => DecPush,
             APPEND => Append,
             FIRST => First,
             TAIL => Tail,
@@ -420,6 +467,8 @@ impl Instruction {
             LAST_PUSH => LastPush,
             CALL_SYS => CallSys(operand!()),
             CALL_SYS_ARGS => CallSysArgs(operand!(), operand!()),
+            CALL_SYS_PUSH => CallSysPush(operand!()),
+            CALL_SYS_ARGS_PUSH => CallSysArgsPush(operand!(), operand!()),
             CALL_CONST => CallConst(operand!(), operand!()),
             CALL_CONST_0 => CallConst(0, operand!()),
             CALL_CONST_1 => CallConst(1, operand!()),
@@ -433,6 +482,13 @@ impl Instruction {
             APPLY => Apply(operand!()),
             CALL_SELF => CallSelf(operand!()),
             TAIL_CALL => TailCall(operand!()),
+            TAIL_CALL_CONST => TailCallConst(operand!(), operand!()),
+            TAIL_CALL_FUNCTION => TailCallFunction(operand!()),
+            TAIL_APPLY => TailApply(operand!()),
+            UNPACK => Unpack(operand!()),
+            UNPACK_REST => UnpackRest(operand!()),
+            TRY => Try(operand!()),
+            END_TRY => EndTry,
             SKIP => Skip(operand!()),
             SKIP_1 => Skip(1),
             SKIP_2 => Skip(2),
@@ -562,6 +618,8 @@ impl Instruction {
             Not => op!(NOT),
             Inc => op!(INC),
             Dec => op!(DEC),
+            IncPush => op!(INC_PUSH),
+            DecPush => op!(DEC_PUSH),
             Append => op!(APPEND),
             First => op!(FIRST),
             Tail => op!(TAIL),
@@ -573,6 +631,8 @@ impl Instruction {
             LastPush => op!(LAST_PUSH),
             CallSys(n) => op!(CALL_SYS, n),
             CallSysArgs(n_args, n_rest) => op!(CALL_SYS_ARGS, n_args, n_rest),
+            CallSysPush(n) => op!(CALL_SYS_PUSH, n),
+            CallSysArgsPush(n, n_args) => op!(CALL_SYS_ARGS_PUSH, n, n_args),
             CallConst(0, n_args) => op!(CALL_CONST_0, n_args),
             CallConst(1, n_args) => op!(CALL_CONST_1, n_args),
             CallConst(2, n_args) => op!(CALL_CONST_2, n_args),
@@ -586,6 +646,13 @@ impl Instruction {
             Apply(n) => op!(APPLY, n),
             CallSelf(n) => op!(CALL_SELF, n),
             TailCall(n) => op!(TAIL_CALL, n),
+            TailCallConst(n, n_args) => op!(TAIL_CALL_CONST, n, n_args),
+            TailCallFunction(n) => op!(TAIL_CALL_FUNCTION, n),
+            TailApply(n) => op!(TAIL_APPLY, n),
+            Unpack(n) => op!(UNPACK, n),
+            UnpackRest(n) => op!(UNPACK_REST, n),
+            Try(label) => op!(TRY, label),
+            EndTry => op!(END_TRY),
             Skip(1) => op!(SKIP, 1),
             Skip(2) => op!(SKIP, 2),
             Skip(3) => op!(SKIP, 3),
@@ -610,7 +677,8 @@ impl Instruction {
             JumpIfEq(label) |
             JumpIfNotEq(label) |
             JumpIfEqConst(label, _) |
-            JumpIfNotEqConst(label, _) => Some(label),
+            JumpIfNotEqConst(label, _) |
+            Try(label) => Some(label),
             _ => None
         }
     }
@@ -634,6 +702,7 @@ pub enum JumpInstruction {
     JumpIfNotEq,
     JumpIfEqConst(u32),
     JumpIfNotEqConst(u32),
+    Try,
 }
 
 impl JumpInstruction {
@@ -652,6 +721,7 @@ impl JumpInstruction {
             JumpIfNotEq => Instruction::JumpIfNotEq(label),
             JumpIfEqConst(n) => Instruction::JumpIfEqConst(label, n),
             JumpIfNotEqConst(n) => Instruction::JumpIfNotEqConst(label, n),
+            Try => Instruction::Try(label),
         }
     }
 
@@ -667,7 +737,8 @@ impl JumpInstruction {
             JumpIfNull |
             JumpIfNotNull |
             JumpIfEq |
-            JumpIfNotEq => 1 + len,
+            JumpIfNotEq |
+            Try => 1 + len,
             JumpIfBound(n) |
             JumpIfEqConst(n) |
             JumpIfNotEqConst(n) => {
@@ -699,16 +770,54 @@ pub struct Code {
     pub req_params: u32,
     /// Miscellaneous flags; see `code_flags` for bit flag values.
     pub flags: u32,
+    /// Maximum number of values held on the stack during execution,
+    /// including parameters and local bindings. Used only to size the
+    /// stack in advance; execution remains correct if it is inaccurate.
+    pub max_stack: u32,
+    /// Spans of source expressions, each paired with the offset of the first
+    /// instruction compiled from it, in ascending order of offset.
+    /// Empty if source locations are not known; these are not encoded
+    /// into compiled bytecode files.
+    pub spans: Box<[(u32, Span)]>,
+    /// Instructions decoded from `code`, populated on first execution
+    decoded: DecodeCache,
+    /// Functions resolved by `CallConst` instructions, populated on execution
+    pub calls: CallCache,
 }
 
 impl Code {
+    /// Returns a new `Code` object containing the given bytecode
+    /// and const values.
+    ///
+    /// The returned code has no name and accepts no parameters;
+    /// other fields may be assigned after construction.
+    pub fn new(code: Box<[u8]>, consts: Box<[Value]>) -> Code {
+        Code{
+            name: None,
+            consts: consts,
+            code: code,
+            kw_params: Vec::new().into_boxed_slice(),
+            n_params: 0,
+            req_params: 0,
+            flags: 0,
+            max_stack: 0,
+            spans: Vec::new().into_boxed_slice(),
+            decoded: DecodeCache::new(),
+            calls: CallCache::new(),
+        }
+    }
+
     /// Returns the computed arity of the compiled function.
+    ///
+    /// Each keyword parameter accounts for two arguments:
+    /// the keyword and its value. A function collecting rest keyword
+    /// arguments accepts any number of trailing keyword/value pairs.
     pub fn arity(&self) -> Arity {
-        if self.has_rest_params() {
+        if self.has_rest_params() || self.has_rest_keys() {
             Arity::Min(self.req_params)
         } else {
             let kw = self.kw_params.len() as u32;
-            let max = self.n_params + kw;
+            let max = self.n_params.saturating_add(kw.saturating_mul(2));
             if self.req_params == max {
                 Arity::Exact(max)
             } else {
@@ -726,6 +835,318 @@ impl Code {
     pub fn has_kw_params(&self) -> bool {
         self.flags & code_flags::PARAM_FLAGS_MASK == code_flags::HAS_KW_PARAMS
     }
+
+    /// Returns whether the function collects unrecognized keyword arguments
+    /// into a rest keyword parameter.
+    pub fn has_rest_keys(&self) -> bool {
+        self.flags & code_flags::HAS_REST_KEYS != 0
+    }
+
+    /// Returns the span of the source expression from which the instruction
+    /// at the given offset was compiled, if it is known.
+    pub fn span_at(&self, offset: u32) -> Option<Span> {
+        match self.spans.binary_search_by_key(&offset, |&(off, _)| off) {
+            Ok(i) => Some(self.spans[i].1),
+            Err(0) => None,
+            Err(i) => Some(self.spans[i - 1].1)
+        }
+    }
+
+    /// Returns an iterator over the instructions of the code object's bytecode.
+    pub fn instructions(&self) -> Instructions {
+        Instructions::new(&self.code)
+    }
+
+    /// Returns the decoded instructions of the code object's bytecode.
+    ///
+    /// Bytecode is decoded upon the first call; subsequent calls return
+    /// the cached result.
+    pub fn decoded(&self) -> DecodedCode {
+        let mut cache = self.decoded.0.borrow_mut();
+
+        if let Some(ref d) = *cache {
+            return d.clone();
+        }
+
+        let d = decode_code(&self.code);
+        *cache = Some(d.clone());
+        d
+    }
+
+    /// Discards instructions decoded by a previous call to `decoded`.
+    ///
+    /// This method must be called after modifying `code`.
+    pub fn clear_decoded(&mut self) {
+        self.decoded = DecodeCache::new();
+    }
+}
+
+/// Instructions decoded from the bytecode of a `Code` object.
+///
+/// Each element corresponds to a byte offset in the bytecode. The element
+/// at the start of an instruction contains the instruction and the offset
+/// of the instruction following it; all other elements are `None`.
+pub type DecodedCode = Rc<[Option<(Instruction, u32)>]>;
+
+/// Holds the decoded instructions of a `Code` object, once decoded.
+///
+/// Cloning a `DecodeCache` produces an empty cache.
+#[derive(Default)]
+struct DecodeCache(RefCell<Option<DecodedCode>>);
+
+impl DecodeCache {
+    /// Returns a new, empty `DecodeCache`.
+    fn new() -> DecodeCache {
+        DecodeCache::default()
+    }
+}
+
+impl Clone for DecodeCache {
+    fn clone(&self) -> DecodeCache {
+        DecodeCache::new()
+    }
+}
+
+impl fmt::Debug for DecodeCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DecodeCache")
+    }
+}
+
+/// Holds the functions resolved by each `CallConst` instruction of a `Code`
+/// object, indexed by the const value holding the called name.
+///
+/// An entry is valid only while the scope from which it was resolved
+/// remains unchanged; redefining any value in the scope invalidates it.
+///
+/// Each entry also records the number of arguments with which a call
+/// to the cached function was last accepted, so that subsequent calls
+/// with the same number of arguments may skip arity validation.
+///
+/// Cloning a `CallCache` produces an empty cache.
+#[derive(Default)]
+pub struct CallCache(RefCell<Vec<Option<CallSite>>>);
+
+struct CallSite {
+    scope: *const GlobalScope,
+    generation: usize,
+    callee: Callee,
+    checked_args: Option<u32>,
+}
+
+enum Callee {
+    Function(Rc<Function>),
+    // A strong reference would form a cycle when a function calls itself.
+    Lambda(Weak<Lambda>),
+}
+
+impl CallCache {
+    /// Returns a new, empty `CallCache`.
+    pub fn new() -> CallCache {
+        CallCache::default()
+    }
+
+    /// Returns the function cached for const `n`, if it was resolved
+    /// in the given scope and the scope has not since been modified.
+    ///
+    /// The returned `bool` indicates whether a call with `n_args` arguments
+    /// has already been accepted by the function.
+    pub fn get(&self, n: u32, scope: &GlobalScope, n_args: u32) -> Option<(Value, bool)> {
+        let sites = self.0.borrow();
+
+        match sites.get(n as usize) {
+            Some(&Some(ref site)) if site.scope == scope as *const _ &&
+                    site.generation == scope.generation() => {
+                let v = match site.callee {
+                    Callee::Function(ref f) => Value::Function(f.clone()),
+                    Callee::Lambda(ref l) => match l.upgrade() {
+                        Some(l) => Value::Lambda(l),
+                        None => return None
+                    }
+                };
+
+                Some((v, site.checked_args == Some(n_args)))
+            }
+            _ => None
+        }
+    }
+
+    /// Records that the function cached for const `n` accepted a call
+    /// with `n_args` arguments.
+    pub fn set_checked(&self, n: u32, n_args: u32) {
+        if let Some(&mut Some(ref mut site)) = self.0.borrow_mut().get_mut(n as usize) {
+            site.checked_args = Some(n_args);
+        }
+    }
+
+    /// Caches the function resolved for const `n` in the given scope.
+    /// Values other than functions and lambdas are not cached.
+    pub fn insert(&self, n: u32, scope: &GlobalScope, value: &Value) {
+        let callee = match *value {
+            Value::Function(ref f) => Callee::Function(f.clone()),
+            Value::Lambda(ref l) => Callee::Lambda(Rc::downgrade(l)),
+            _ => return
+        };
+
+        let mut sites = self.0.borrow_mut();
+        let n = n as usize;
+
+        if sites.len() <= n {
+            sites.resize_with(n + 1, || None);
+        }
+
+        sites[n] = Some(CallSite{
+            scope: scope,
+            generation: scope.generation(),
+            callee: callee,
+            checked_args: None,
+        });
+    }
+}
+
+impl Clone for CallCache {
+    fn clone(&self) -> CallCache {
+        CallCache::new()
+    }
+}
+
+impl fmt::Debug for CallCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CallCache")
+    }
+}
+
+/// Decodes instructions from the start of `code`.
+///
+/// Decoding stops at the first invalid instruction, if any, leaving its
+/// element and all following elements `None`. Execution then decodes
+/// from the bytecode directly, reporting the error when it is reached.
+fn decode_code(code: &[u8]) -> DecodedCode {
+    let mut res = vec![None; code.len()];
+    let mut r = CodeReader::new(code, 0);
+
+    while r.get_offset() < code.len() {
+        let off = r.get_offset();
+
+        match r.read_instruction() {
+            Ok(instr) => res[off] = Some((instr, r.get_offset() as u32)),
+            Err(_) => break
+        }
+    }
+
+    res.into()
+}
+
+/// Renders the bytecode of a code object in human-readable form.
+///
+/// Each instruction is listed on its own line, along with its offset.
+/// Jump targets are marked with `>>`. Instructions referring to const values,
+/// global names, or system functions are annotated with the value or name.
+pub fn disassemble(code: &Code, names: &NameStore) -> String {
+    disassemble_code(code, None, names)
+}
+
+/// Renders the bytecode of a lambda in human-readable form.
+///
+/// In addition to the annotations of `disassemble`, instructions which load
+/// an enclosed value are annotated with the value.
+pub fn disassemble_lambda(lambda: &Lambda, names: &NameStore) -> String {
+    disassemble_code(&lambda.code, lambda.values.as_ref().map(|v| &v[..]), names)
+}
+
+fn disassemble_code(code: &Code, values: Option<&[Value]>, names: &NameStore) -> String {
+    let mut instrs = Vec::new();
+    let mut error = None;
+
+    for r in code.instructions() {
+        match r {
+            Ok(instr) => instrs.push(instr),
+            Err(e) => error = Some(e)
+        }
+    }
+
+    let mut labels = instrs.iter()
+        .filter_map(|&(_, ref instr)| instr.get_jump_label())
+        .collect::<Vec<_>>();
+
+    labels.sort();
+    labels.dedup();
+
+    let mut buf = String::new();
+
+    let _ = writeln!(buf, "{} bytecode instruction{}:",
+        instrs.len(), plural(instrs.len() as u32));
+
+    for &(off, instr) in &instrs {
+        let label_str = if labels.binary_search(&off).is_ok() { ">>" } else { "  " };
+
+        match annotate_instruction(code, values, names, instr) {
+            Some(s) => {
+                // fmt::Debug does not honor "<n" formatting,
+                // so we make this string first and format again.
+                let instr = format!("{:?}", instr);
+                let _ = writeln!(buf, "  {} {:>4}  {:<30} ; {}",
+                    label_str, off, instr, s);
+            }
+            None => {
+                let _ = writeln!(buf, "  {} {:>4}  {:?}", label_str, off, instr);
+            }
+        }
+    }
+
+    if let Some(e) = error {
+        let _ = writeln!(buf, "  invalid bytecode: {}", e);
+    }
+
+    buf
+}
+
+fn annotate_instruction(code: &Code, values: Option<&[Value]>,
+        names: &NameStore, instr: Instruction) -> Option<String> {
+    use self::Instruction::*;
+
+    match instr {
+        LoadC(n) |
+        LoadCPush(n) =>
+            values.and_then(|v| v.get(n as usize))
+                .map(|v| debug_names(names, v).to_string()),
+        GetDef(n) |
+        Const(n) |
+        GetDefPush(n) |
+        ConstPush(n) |
+        SetDef(n) |
+        BuildClosure(n, _) |
+        CallConst(n, _) |
+        TailCallConst(n, _)
+            => code.consts.get(n as usize).map(
+                |c| debug_names(names, c).to_string()),
+        Jump(l) |
+        JumpIf(l) |
+        JumpIfNull(l) |
+        JumpIfNotNull(l) |
+        JumpIfNot(l) |
+        JumpIfEq(l) |
+        JumpIfNotEq(l) |
+        JumpIfBound(l, _) |
+        Try(l)
+            => Some(format!("L{}", l)),
+        JumpIfEqConst(l, n) |
+        JumpIfNotEqConst(l, n)
+            => Some(match code.consts.get(n as usize) {
+                None => format!("L{}", l),
+                Some(c) => format!("L{} {}", l, debug_names(names, c))
+            }),
+        EqConst(n) |
+        NotEqConst(n)
+            => code.consts.get(n as usize).map(
+                |c| debug_names(names, c).to_string()),
+        CallSys(n) |
+        CallSysArgs(n, _) |
+        CallSysPush(n) |
+        CallSysArgsPush(n, _) =>
+            get_standard_name(n).map(|n| names.get(n).to_owned()),
+        _ => None
+    }
 }
 
 /// Bit flag values for `Code::flags`
@@ -738,9 +1159,12 @@ pub mod code_flags {
     pub const HAS_REST_PARAMS: u32  = 0x4;
     /// Mask of mutually exclusive parameter flags
     pub const PARAM_FLAGS_MASK: u32 = 0x6;
+    /// Whether the code collects unrecognized keyword arguments;
+    /// may not be combined with `HAS_REST_PARAMS`
+    pub const HAS_REST_KEYS: u32    = 0x8;
 
     /// Mask of all valid flags
-    pub const ALL_FLAGS: u32        = 0x7;
+    pub const ALL_FLAGS: u32        = 0xf;
 }
 
 /// Reads `Instruction` values from a stream of bytes.
@@ -788,6 +1212,45 @@ impl<'a> CodeReader<'a> {
     }
 }
 
+/// Iterates over the instructions of encoded bytecode.
+///
+/// Each item is an instruction, along with its offset in bytes.
+/// Iteration ends after the first invalid instruction, if any.
+pub struct Instructions<'a> {
+    reader: CodeReader<'a>,
+    done: bool,
+}
+
+impl<'a> Instructions<'a> {
+    /// Creates an iterator over instructions encoded in a series of bytes.
+    pub fn new(bytes: &[u8]) -> Instructions {
+        Instructions{
+            reader: CodeReader::new(bytes, 0),
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<(u32, Instruction), ExecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.reader.offset >= self.reader.bytes.len() {
+            return None;
+        }
+
+        let off = self.reader.get_offset() as u32;
+
+        match self.reader.read_instruction() {
+            Ok(instr) => Some(Ok((off, instr))),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 /// Contains a series of bytecode instructions
 #[derive(Debug)]
 pub struct CodeBlock {
@@ -799,6 +1262,9 @@ pub struct CodeBlock {
     pub jump: Option<(JumpInstruction, u32)>,
     /// Refers to the block that immediately follows this block
     pub next: Option<u32>,
+    /// Spans of source expressions, paired with the offset within the block
+    /// at which code compiled from each begins
+    spans: Vec<(usize, Span)>,
 }
 
 impl CodeBlock {
@@ -809,6 +1275,7 @@ impl CodeBlock {
             instr_part: None,
             jump: None,
             next: None,
+            spans: Vec::new(),
         }
     }
 
@@ -819,6 +1286,7 @@ impl CodeBlock {
             instr_part: None,
             jump: None,
             next: None,
+            spans: Vec::new(),
         }
     }
 
@@ -858,6 +1326,34 @@ impl CodeBlock {
         }
     }
 
+    /// Returns spans of source expressions marked within the block,
+    /// paired with the offset at which code compiled from each begins.
+    pub fn spans(&self) -> &[(usize, Span)] {
+        &self.spans
+    }
+
+    /// Marks the beginning of code compiled from the source expression
+    /// with the given span.
+    ///
+    /// Code belongs to the most recently marked span, until another is marked.
+    pub fn mark_span(&mut self, span: Span) {
+        let part_len = self.instr_part.map_or(0, |instr| {
+            let mut b = CodeBlock::empty();
+            let _ = instr.encode(&mut b, false);
+            b.len()
+        });
+
+        let pos = self.bytes.len() + part_len;
+
+        if let Some(&(last, _)) = self.spans.last() {
+            if last == pos {
+                self.spans.pop();
+            }
+        }
+
+        self.spans.push((pos, span));
+    }
+
     /// Sets the block which will immediately follow this block.
     ///
     /// May only be called once.
@@ -999,6 +1495,10 @@ fn merge_instructions(a: Instruction, b: Instruction) -> Option<Instruction> {
         (Tail, Push) => TailPush,
         (Init, Push) => InitPush,
         (Last, Push) => LastPush,
+        (Inc, Push) => IncPush,
+        (Dec, Push) => DecPush,
+        (CallSys(n), Push) => CallSysPush(n),
+        (CallSysArgs(n, n_args), Push) => CallSysArgsPush(n, n_args),
         (Const(n), Push) => ConstPush(n),
         (Null, Not) => NotNull,
         (NotNull, Not) => Null,
@@ -1007,6 +1507,9 @@ fn merge_instructions(a: Instruction, b: Instruction) -> Option<Instruction> {
         (EqConst(n), Not) => NotEqConst(n),
         (NotEqConst(n), Not) => EqConst(n),
         (CallSelf(n), Return) => TailCall(n),
+        (CallConst(n, n_args), Return) => TailCallConst(n, n_args),
+        (Call(n), Return) => TailCallFunction(n),
+        (Apply(n), Return) => TailApply(n),
         (Skip(_), Return) => Return,
         _ => return None
     };
@@ -1017,3 +1520,33 @@ fn merge_instructions(a: Instruction, b: Instruction) -> Option<Instruction> {
 fn is_short_operand(op: u32) -> bool {
     op <= MAX_SHORT_OPERAND
 }
+
+#[cfg(test)]
+mod test {
+    use super::BYTECODE_VERSION;
+    use name::{get_standard_name, standard_name, NUM_STANDARD_NAMES};
+
+    /// Returns an FNV-1a hash of all standard names, in order.
+    fn standard_names_hash() -> u64 {
+        let mut h = 0xcbf2_9ce4_8422_2325_u64;
+
+        for i in 0..NUM_STANDARD_NAMES {
+            let name = get_standard_name(i).unwrap();
+
+            for &b in standard_name(name).unwrap().as_bytes().iter().chain(b"\0") {
+                h = (h ^ u64::from(b)).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+
+        h
+    }
+
+    #[test]
+    fn test_standard_names_version() {
+        // Standard names are encoded in bytecode by number. If this fails,
+        // the table of standard names has changed: increment
+        // `BYTECODE_VERSION` and update the expected hash.
+        assert_eq!((BYTECODE_VERSION, standard_names_hash()),
+            (0x00_00_03_12, 0xe8a9_28ca_0aac_02cc));
+    }
+}
@@ -0,0 +1,170 @@
+//! Provides typed handles for calling Ketos functions from Rust.
+//!
+//! ```
+//! use ketos::Interpreter;
+//! use ketos::callable::Callable;
+//!
+//! let interp = Interpreter::new();
+//!
+//! let v = interp.run_code("(lambda (a b) (+ a b))", None).unwrap();
+//! let add: Callable<(i32, i32), i32> = Callable::new(interp.get_scope(), v).unwrap();
+//!
+//! assert_eq!(add.call((1, 2)).unwrap(), 3);
+//!
+//! let f = add.into_fn();
+//! assert_eq!(f(3, 4).unwrap(), 7);
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use error::Error;
+use exec::{call_function, ExecError};
+use scope::Scope;
+use value::{FromValue, Value};
+
+/// Converts a series of Rust values into function arguments.
+///
+/// This trait is implemented for `Vec<Value>` and for tuples of up to
+/// eight elements, each of which implements `Into<Value>`.
+pub trait IntoArgs {
+    /// Returns the series of argument values.
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+impl IntoArgs for () {
+    fn into_args(self) -> Vec<Value> {
+        Vec::new()
+    }
+}
+
+/// Describes the closure produced by `Callable::into_fn` for arguments
+/// of type `Self` and a result of type `R`.
+///
+/// For a tuple of arguments, the closure accepts one parameter for each
+/// element of the tuple. For `Vec<Value>`, the closure accepts the vector.
+pub trait IntoFn<R>: IntoArgs + Sized {
+    /// Type of the closure
+    type Closure: ?Sized;
+
+    /// Returns a closure which calls the given `Callable`.
+    fn into_fn(callable: Callable<Self, R>) -> Box<Self::Closure>;
+}
+
+impl<R: FromValue + 'static> IntoFn<R> for Vec<Value> {
+    type Closure = Fn(Vec<Value>) -> Result<R, Error>;
+
+    fn into_fn(callable: Callable<Vec<Value>, R>) -> Box<Self::Closure> {
+        Box::new(move |args| callable.call(args))
+    }
+}
+
+impl<R: FromValue + 'static> IntoFn<R> for () {
+    type Closure = Fn() -> Result<R, Error>;
+
+    fn into_fn(callable: Callable<(), R>) -> Box<Self::Closure> {
+        Box::new(move || callable.call(()))
+    }
+}
+
+macro_rules! impl_into_args {
+    ( $( $name:ident )+ ) => {
+        impl<$( $name: Into<Value> ),+> IntoArgs for ( $( $name , )+ ) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ( $( $name , )+ ) = self;
+                vec![ $( $name.into() ),+ ]
+            }
+        }
+
+        impl<R: FromValue + 'static, $( $name: Into<Value> + 'static ),+>
+                IntoFn<R> for ( $( $name , )+ ) {
+            type Closure = Fn( $( $name ),+ ) -> Result<R, Error>;
+
+            #[allow(non_snake_case)]
+            fn into_fn(callable: Callable<Self, R>) -> Box<Self::Closure> {
+                Box::new(move |$( $name : $name ),+| callable.call(( $( $name , )+ )))
+            }
+        }
+    }
+}
+
+impl_into_args!{ A }
+impl_into_args!{ A B }
+impl_into_args!{ A B C }
+impl_into_args!{ A B C D }
+impl_into_args!{ A B C D E }
+impl_into_args!{ A B C D E F }
+impl_into_args!{ A B C D E F G }
+impl_into_args!{ A B C D E F G H }
+
+/// A handle to a function value which accepts arguments of type `A`
+/// and returns a result of type `R`.
+///
+/// A `Callable` keeps its scope alive and may be stored in ordinary
+/// Rust data structures to be called later, e.g. as a script callback.
+pub struct Callable<A, R> {
+    scope: Scope,
+    value: Value,
+    _marker: PhantomData<fn(A) -> R>,
+}
+
+impl<A: IntoArgs, R: FromValue> Callable<A, R> {
+    /// Creates a new `Callable` from a function, lambda,
+    /// or foreign function value.
+    ///
+    /// Returns an error if `value` is not a function.
+    pub fn new(scope: &Scope, value: Value) -> Result<Callable<A, R>, ExecError> {
+        match value {
+            Value::Function(_) | Value::Lambda(_) => (),
+            Value::Foreign(ref fv) if fv.is_callable() => (),
+            ref v => return Err(ExecError::expected("function", v))
+        }
+
+        Ok(Callable{
+            scope: scope.clone(),
+            value: value,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Calls the function with the given arguments and converts
+    /// the result into a Rust value.
+    pub fn call(&self, args: A) -> Result<R, Error> {
+        let v = try!(call_function(&self.scope, self.value.clone(), args.into_args()));
+        R::from_value(v).map_err(From::from)
+    }
+
+    /// Returns the wrapped function value.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Converts the `Callable` into a boxed closure which accepts
+    /// one parameter for each element of the argument tuple.
+    pub fn into_fn(self) -> Box<A::Closure> where A: IntoFn<R> {
+        A::into_fn(self)
+    }
+}
+
+impl<A, R> Clone for Callable<A, R> {
+    fn clone(&self) -> Callable<A, R> {
+        Callable{
+            scope: self.scope.clone(),
+            value: self.value.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<A, R> fmt::Debug for Callable<A, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Callable { ... }")
+    }
+}
@@ -1,6 +1,8 @@
 //! Compiles expressions into bytecode objects.
 
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::Cell;
+use std::cmp::{max, min};
 use std::fmt;
 use std::mem::replace;
 use std::rc::Rc;
@@ -13,9 +15,15 @@ use error::Error;
 use exec::{ExecError, execute_lambda};
 use function::{Arity, Lambda, neg_number};
 use function::Arity::*;
+use lexer::Span;
+use map::Map;
+use metrics::Metrics;
+use module::ModuleVersion;
 use name::{get_system_fn, is_system_operator, standard_names,
     Name, NameDisplay, NameMap, NameSet, NameStore,
     NUM_SYSTEM_OPERATORS, SYSTEM_OPERATORS_BEGIN};
+use optimize::optimize;
+use parser::SpanMap;
 use scope::{GlobalScope, ImportSet, MasterScope, Scope};
 use value::{StructDef, Value};
 
@@ -76,6 +84,15 @@ pub enum CompileError {
     MissingExport,
     /// Failed to load a module
     ModuleError(Name),
+    /// Loaded module does not meet the version required by `use`
+    ModuleVersionError{
+        /// Module name
+        module: Name,
+        /// Minimum required version
+        required: ModuleVersion,
+        /// Version declared by the module
+        found: Option<ModuleVersion>,
+    },
     /// `const` operator value is not constant
     NotConstant(Name),
     /// Operand value overflow
@@ -118,6 +135,7 @@ impl fmt::Display for CompileError {
             MacroRecursionExceeded => f.write_str("macro recursion exceeded"),
             MissingExport => f.write_str("missing `export` declaration"),
             ModuleError(_) => f.write_str("module not found"),
+            ModuleVersionError{..} => f.write_str("module version requirement not met"),
             NotConstant(_) => f.write_str("value is not constant"),
             OperandOverflow(n) =>
                 write!(f, "operand overflow: {}", n),
@@ -151,6 +169,13 @@ impl NameDisplay for CompileError {
             ImportShadow{module, name} =>
                 write!(f, "importing `{}` from `{}` shadows an existing value",
                     names.get(name), names.get(module)),
+            ModuleVersionError{module, ref required, found: Some(ref found)} =>
+                write!(f, "module `{}` version {} is required; found version {}",
+                    names.get(module), required, found),
+            ModuleVersionError{module, ref required, found: None} =>
+                write!(f, "module `{}` version {} is required; \
+                    module does not declare a version",
+                    names.get(module), required),
             PrivacyError{module, name} =>
                 write!(f, "name `{}` in module `{}` is private",
                     names.get(name), names.get(module)),
@@ -164,18 +189,88 @@ pub fn compile(scope: &Scope, value: &Value) -> Result<Code, Error> {
     Compiler::new(scope).compile(value)
 }
 
+/// Compiles an expression into a code object, using the given `SpanMap`
+/// to locate errors.
+///
+/// If compilation fails, the error is returned along with the span of the
+/// innermost expression responsible for the error, if it is known.
+pub fn compile_with_spans(scope: &Scope, value: &Value, spans: &SpanMap)
+        -> Result<Code, (Error, Option<Span>)> {
+    let tracker = SpanTracker{
+        spans: spans,
+        error: Cell::new(None),
+    };
+
+    let mut compiler = Compiler::new(scope);
+    compiler.spans = Some(&tracker);
+
+    compiler.compile(value).map_err(|e| (e, tracker.error.get()))
+}
+
+/// Expands an expression once, if it is a call to a macro defined in the
+/// given scope. Otherwise, `None` is returned.
+pub fn macroexpand_1(scope: &Scope, value: &Value) -> Result<Option<Value>, Error> {
+    if let Value::List(ref li) = *value {
+        if let Value::Name(name) = li[0] {
+            if let Some(lambda) = scope.get_macro(name) {
+                return call_macro(scope, lambda, &li[1..]).map(Some);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Repeatedly expands an expression until it is not a call to a macro
+/// defined in the given scope.
+///
+/// Only the expression itself is expanded; macro calls within the result
+/// are left unexpanded.
+pub fn macroexpand(scope: &Scope, value: &Value) -> Result<Value, Error> {
+    let mut value = value.clone();
+
+    for _ in 0..MAX_MACRO_RECURSION {
+        match try!(macroexpand_1(scope, &value)) {
+            Some(v) => value = v,
+            None => return Ok(value)
+        }
+    }
+
+    Err(From::from(CompileError::MacroRecursionExceeded))
+}
+
+/// Calls a macro function with the given arguments.
+fn call_macro(scope: &Scope, lambda: Lambda, args: &[Value]) -> Result<Value, Error> {
+    scope.get_metrics().add(&Metrics{macro_expansions: 1, ..Metrics::new()});
+
+    execute_lambda(lambda, args.to_vec())
+}
+
 fn compile_lambda(compiler: &Compiler,
         name: Option<Name>,
         params: Vec<(Name, Option<Value>)>,
         req_params: u32,
         kw_params: Vec<(Name, Option<Value>)>,
-        rest: Option<Name>, value: &Value)
+        rest: Option<Name>,
+        rest_keys: Option<Name>,
+        patterns: Vec<(u32, Value)>, value: &Value)
         -> Result<(Code, Vec<Name>), Error> {
     let outer = compiler.outer.iter().cloned()
         .chain(Some(compiler)).collect::<Vec<_>>();
 
-    Compiler::with_outer(&compiler.scope, name, &outer)
-        .compile_lambda(name, params, req_params, kw_params, rest, value)
+    let mut lambda = Compiler::with_outer(&compiler.scope, name, &outer);
+    lambda.spans = compiler.spans;
+
+    lambda.compile_lambda(name, params, req_params, kw_params, rest, rest_keys,
+        patterns, value)
+}
+
+/// Tracks source locations of expressions during compilation
+struct SpanTracker<'a> {
+    /// Spans of parsed expressions
+    spans: &'a SpanMap,
+    /// Span of the innermost expression which produced an error
+    error: Cell<Option<Span>>,
 }
 
 /// Compiles a single expression or function body
@@ -193,6 +288,8 @@ struct Compiler<'a> {
     /// Current offset in stack; tracks addition and subtraction of named and
     /// unnamed stack values.
     stack_offset: u32,
+    /// Greatest value reached by `stack_offset`
+    max_stack: u32,
     /// Set of names from outer scope captured by lambda
     captures: Vec<Name>,
     /// Names in outer scopes available to lambda
@@ -201,6 +298,15 @@ struct Compiler<'a> {
     self_name: Option<Name>,
     /// Depth of macro expansion
     macro_recursion: u32,
+    /// Source locations of expressions, if available
+    spans: Option<&'a SpanTracker<'a>>,
+    /// Span of the innermost expression being compiled, if known
+    cur_span: Option<Span>,
+    /// Doc comment preceding the operator expression being compiled
+    form_doc: Option<&'a str>,
+    /// Number of enclosing `try` bodies; errors in constant evaluation
+    /// within a `try` body are deferred until runtime so they may be caught.
+    try_depth: u32,
 }
 
 impl<'a> Compiler<'a> {
@@ -217,23 +323,46 @@ impl<'a> Compiler<'a> {
             cur_block: 0,
             stack: Vec::new(),
             stack_offset: 0,
+            max_stack: 0,
             captures: Vec::new(),
             outer: outer,
             self_name: name,
             macro_recursion: 0,
+            spans: None,
+            cur_span: None,
+            form_doc: None,
+            try_depth: 0,
         }
     }
 
-    fn assemble_code(&mut self) -> Result<Box<[u8]>, CompileError> {
+    /// Assembles code blocks into bytecode.
+    /// Returns the bytecode and the table of source spans within it.
+    fn assemble_code(&mut self)
+            -> Result<(Box<[u8]>, Box<[(u32, Span)]>), CompileError> {
         let total = try!(self.write_jumps());
         let mut res = Vec::with_capacity(total);
+        let mut spans: Vec<(u32, Span)> = Vec::new();
 
         for block in &mut self.blocks {
+            let off = res.len();
             res.extend(block.get_bytes());
+
+            for &(pos, span) in block.spans() {
+                let pos = (off + min(pos, block.len())) as u32;
+
+                // A span marked at the same offset replaces the previous one
+                if spans.last().map(|&(last, _)| last) == Some(pos) {
+                    spans.pop();
+                }
+
+                if spans.last().map(|&(_, last)| last) != Some(span) {
+                    spans.push((pos, span));
+                }
+            }
         }
 
         assert_eq!(res.len(), total);
-        Ok(res.into_boxed_slice())
+        Ok((res.into_boxed_slice(), spans.into_boxed_slice()))
     }
 
     /// Writes jump instructions with real offsets to each code blocks.
@@ -309,25 +438,26 @@ impl<'a> Compiler<'a> {
     fn compile(mut self, value: &Value) -> Result<Code, Error> {
         try!(self.compile_value(value));
 
-        Ok(Code{
-            name: None,
-            code: try!(self.assemble_code()),
-            consts: self.consts.into_boxed_slice(),
-            kw_params: vec![].into_boxed_slice(),
-            n_params: 0,
-            req_params: 0,
-            flags: 0,
-        })
+        let (bytes, spans) = try!(self.assemble_code());
+
+        let mut code = Code::new(bytes, self.consts.into_boxed_slice());
+        code.max_stack = self.max_stack;
+        code.spans = spans;
+
+        optimize(&mut code);
+        Ok(code)
     }
 
     fn compile_lambda(mut self, name: Option<Name>,
             params: Vec<(Name, Option<Value>)>,
             req_params: u32,
             kw_params: Vec<(Name, Option<Value>)>,
-            rest: Option<Name>, value: &Value)
+            rest: Option<Name>,
+            rest_keys: Option<Name>,
+            patterns: Vec<(u32, Value)>, value: &Value)
             -> Result<(Code, Vec<Name>), Error> {
         let total_params = params.len() + kw_params.len() +
-            if rest.is_some() { 1 } else { 0 };
+            if rest.is_some() || rest_keys.is_some() { 1 } else { 0 };
 
         let n_params = params.len();
 
@@ -351,6 +481,7 @@ impl<'a> Compiler<'a> {
         assert!(self.stack.is_empty());
         self.stack.extend((0..total_params as u32).map(|n| (Name::dummy(), n)));
         self.stack_offset = total_params as u32;
+        self.max_stack = total_params as u32;
 
         let mut flags = 0;
 
@@ -360,6 +491,8 @@ impl<'a> Compiler<'a> {
 
         assert!(kw_params.is_empty() || rest.is_none(),
             "keyword parameters and rest parameters are mutually exclusive");
+        assert!(rest.is_none() || rest_keys.is_none(),
+            "rest parameters and rest keyword parameters are mutually exclusive");
 
         if !kw_params.is_empty() {
             flags |= code_flags::HAS_KW_PARAMS;
@@ -367,6 +500,10 @@ impl<'a> Compiler<'a> {
             flags |= code_flags::HAS_REST_PARAMS;
         }
 
+        if rest_keys.is_some() {
+            flags |= code_flags::HAS_REST_KEYS;
+        }
+
         let mut kw_names = Vec::with_capacity(kw_params.len());
 
         for (i, (name, default)) in params.into_iter().enumerate() {
@@ -394,30 +531,104 @@ impl<'a> Compiler<'a> {
             kw_names.push(name);
         }
 
-        if let Some(rest) = rest {
+        if let Some(rest) = rest.or(rest_keys) {
             let n = self.stack.len();
             self.stack[n - 1].0 = rest;
         }
 
+        // Destructure list parameters before the function body.
+        // Bound values remain on the stack until the function returns.
+        for (i, pat) in patterns {
+            try!(self.push_instruction(Instruction::Load(i)));
+            try!(self.bind_pattern(&pat));
+        }
+
         try!(self.compile_value(value));
 
-        let code = Code{
-            name: name,
-            code: try!(self.assemble_code()),
-            consts: self.consts.into_boxed_slice(),
-            kw_params: kw_names.into_boxed_slice(),
-            n_params: n_params as u32,
-            req_params: req_params,
-            flags: flags,
-        };
+        let (bytes, spans) = try!(self.assemble_code());
+
+        let mut code = Code::new(bytes, self.consts.into_boxed_slice());
+        code.name = name;
+        code.kw_params = kw_names.into_boxed_slice();
+        code.n_params = n_params as u32;
+        code.req_params = req_params;
+        code.flags = flags;
+        code.max_stack = self.max_stack;
+        code.spans = spans;
 
+        optimize(&mut code);
         Ok((code, self.captures))
     }
 
     fn compile_value(&mut self, value: &Value) -> Result<(), Error> {
+        let span = self.spans.and_then(|tracker| tracker.spans.get(value));
+        let outer = self.begin_span(span);
+
+        let r = self.compile_value_inner(value);
+
+        if r.is_err() {
+            self.record_error_span(value);
+        }
+
+        self.end_span(outer);
+        r
+    }
+
+    /// Marks the beginning of code compiled from an expression with the
+    /// given span. Expressions without a known span, such as the result of
+    /// macro expansion, belong to the span of the enclosing expression.
+    ///
+    /// Returns the enclosing span, which is passed to `end_span`.
+    fn begin_span(&mut self, span: Option<Span>) -> Option<Span> {
+        let outer = self.cur_span;
+
+        if let Some(sp) = span {
+            if outer != span {
+                self.current_block().mark_span(sp);
+                self.cur_span = span;
+            }
+        }
+
+        outer
+    }
+
+    /// Marks the end of code compiled from an expression,
+    /// restoring the span of the enclosing expression.
+    fn end_span(&mut self, outer: Option<Span>) {
+        if let Some(sp) = outer {
+            if self.cur_span != outer {
+                self.current_block().mark_span(sp);
+            }
+        }
+
+        self.cur_span = outer;
+    }
+
+    /// Records the span of an expression which produced an error,
+    /// unless a more specific span has already been recorded.
+    fn record_error_span(&self, value: &Value) {
+        if let Some(tracker) = self.spans {
+            if tracker.error.get().is_none() {
+                tracker.error.set(tracker.spans.get(value));
+            }
+        }
+    }
+
+    /// Returns the doc comment preceding an expression, if available.
+    fn get_doc(&self, value: &Value) -> Option<&'a str> {
+        self.spans.and_then(|tracker| tracker.spans.doc(value))
+    }
+
+    fn compile_value_inner(&mut self, value: &Value) -> Result<(), Error> {
         let mut value = Borrowed(value);
 
-        match try!(self.eval_constant(&value)) {
+        let res = match self.eval_constant(&value) {
+            Err(Error::ExecError(_)) if self.try_depth != 0 =>
+                ConstResult::IsRuntime,
+            r => try!(r)
+        };
+
+        match res {
             ConstResult::IsConstant |
             ConstResult::IsRuntime => (),
             ConstResult::Partial(v) => value = Owned(v),
@@ -456,6 +667,7 @@ impl<'a> Compiler<'a> {
 
                             return Ok(());
                         } else if is_system_operator(name) {
+                            self.form_doc = self.get_doc(&value);
                             return self.compile_operator(name, &li[1..]);
                         } else if try!(self.specialize_call(name, &li[1..])) {
                             return Ok(());
@@ -507,6 +719,18 @@ impl<'a> Compiler<'a> {
                     }
                 }
             }
+            Value::Map(ref m) => {
+                // Keys are read as data; only values are evaluated
+                for (k, v) in m.iter() {
+                    try!(self.load_quoted_value(Borrowed(k)));
+                    try!(self.push_instruction(Instruction::Push));
+                    try!(self.compile_value(v));
+                    try!(self.push_instruction(Instruction::Push));
+                }
+
+                try!(self.push_instruction(Instruction::CallSysArgs(
+                    standard_names::HASH_MAP.get(), (m.len() * 2) as u32)));
+            }
             Value::Comma(_, _) | Value::CommaAt(_, _) =>
                 return Err(From::from(CompileError::UnbalancedComma)),
             Value::Quasiquote(ref v, n) =>
@@ -529,7 +753,7 @@ impl<'a> Compiler<'a> {
         let lambda = self.scope.get_macro(name)
             .expect("macro not found in expand_macro");
 
-        execute_lambda(lambda, args.to_vec())
+        call_macro(&self.scope, lambda, args)
     }
 
     fn add_constant(&self, name: Name, value: Value) {
@@ -556,6 +780,7 @@ impl<'a> Compiler<'a> {
 
                 self.eval_constant_function(name, &li[1..])
             }
+            Value::Map(ref m) => self.eval_constant_map(m),
             Value::Quasiquote(ref v, n) => self.eval_constant_quasiquote(v, n),
             Value::Comma(_, _) |
             Value::CommaAt(_, _) => Err(From::from(CompileError::UnbalancedComma)),
@@ -566,6 +791,36 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    /// Evaluates the values of a map literal. The map is constant only if
+    /// all of its values are constant.
+    fn eval_constant_map(&mut self, m: &Map) -> Result<ConstResult, Error> {
+        let mut new_constant = false;
+        let mut values = Vec::with_capacity(m.len());
+
+        for v in m.values() {
+            match try!(self.eval_constant(v)) {
+                ConstResult::IsConstant => values.push(v.clone()),
+                ConstResult::Constant(v) => {
+                    new_constant = true;
+                    values.push(v);
+                }
+                _ => return Ok(ConstResult::IsRuntime)
+            }
+        }
+
+        if new_constant {
+            let mut new_map = Map::new();
+
+            for (k, v) in m.keys().iter().zip(values) {
+                try!(new_map.insert(k.clone(), v));
+            }
+
+            Ok(ConstResult::Constant(new_map.into()))
+        } else {
+            Ok(ConstResult::IsConstant)
+        }
+    }
+
     fn eval_constant_function(&mut self, name: Name, args: &[Value])
             -> Result<ConstResult, Error> {
         let n_args = args.len();
@@ -588,10 +843,17 @@ impl<'a> Compiler<'a> {
                     ref v => return Err(From::from(ExecError::expected("bool", v)))
                 };
 
-                if cond {
-                    self.eval_constant(&args[1])
+                let branch = if cond {
+                    &args[1]
+                } else if n_args == 3 {
+                    &args[2]
                 } else {
-                    self.eval_constant(&args[2])
+                    return Ok(ConstResult::Constant(Value::Unit));
+                };
+
+                match try!(self.eval_constant(branch)) {
+                    ConstResult::IsConstant => Ok(ConstResult::Constant(branch.clone())),
+                    r => Ok(r)
                 }
             }
             standard_names::ADD if args.is_empty() =>
@@ -646,9 +908,17 @@ impl<'a> Compiler<'a> {
         match *value {
             Value::List(ref li) =>
                 self.eval_constant_quasiquote_list(li, depth),
+            Value::Map(ref m) =>
+                self.eval_constant_quasiquote_map(m, depth),
             Value::Comma(_, n) if n > depth =>
                 Err(From::from(CompileError::UnbalancedComma)),
-            Value::Comma(ref v, n) if n == depth => self.eval_constant(v),
+            Value::Comma(ref v, n) if n == depth => {
+                match try!(self.eval_constant(v)) {
+                    ConstResult::IsConstant =>
+                        Ok(ConstResult::Constant((**v).clone())),
+                    res => Ok(res)
+                }
+            }
             Value::Comma(ref v, n) => {
                 match try!(self.eval_constant_quasi_value(v, depth - n)) {
                     ConstResult::Constant(v) =>
@@ -748,6 +1018,35 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    fn eval_constant_quasiquote_map(&mut self, m: &Map, depth: u32)
+            -> Result<ConstResult, Error> {
+        let mut new_constant = false;
+        let mut values = Vec::with_capacity(m.len());
+
+        for v in m.values() {
+            match try!(self.eval_constant_quasi_value(v, depth)) {
+                ConstResult::IsConstant => values.push(v.clone()),
+                ConstResult::Constant(v) => {
+                    new_constant = true;
+                    values.push(v);
+                }
+                res => return Ok(res)
+            }
+        }
+
+        if new_constant {
+            let mut new_map = Map::new();
+
+            for (k, v) in m.keys().iter().zip(values) {
+                try!(new_map.insert(k.clone(), v));
+            }
+
+            Ok(ConstResult::Constant(new_map.into()))
+        } else {
+            Ok(ConstResult::IsConstant)
+        }
+    }
+
     fn compile_operator(&mut self, name: Name, args: &[Value]) -> Result<(), Error> {
         let op = get_system_operator(name);
         let n_args = args.len() as u32;
@@ -804,6 +1103,18 @@ impl<'a> Compiler<'a> {
                 Err(From::from(CompileError::InvalidCommaAt)),
             Value::List(ref li) =>
                 self.compile_quasiquote_list(li, depth),
+            Value::Map(ref m) => {
+                for (k, v) in m.iter() {
+                    try!(self.load_quoted_value(Borrowed(k)));
+                    try!(self.push_instruction(Instruction::Push));
+                    try!(self.compile_quasi_value(v, depth));
+                    try!(self.push_instruction(Instruction::Push));
+                }
+
+                self.push_instruction(Instruction::CallSysArgs(
+                    standard_names::HASH_MAP.get(), (m.len() * 2) as u32))
+                    .map_err(From::from)
+            }
             Value::Quote(ref v, n) => {
                 try!(self.compile_quasi_value(v, depth));
                 try!(self.push_instruction(Instruction::Quote(n)));
@@ -908,6 +1219,15 @@ impl<'a> Compiler<'a> {
 
                 Ok(true)
             }
+            Value::Map(ref m) => {
+                for v in m.values() {
+                    if !try!(self.is_quasi_const(v, depth)) {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            }
             Value::Quasiquote(ref v, n) => self.is_quasi_const(v, depth + n),
             Value::Quote(ref v, _) => self.is_quasi_const(v, depth),
             Value::Comma(_, n) if n > depth =>
@@ -1199,6 +1519,56 @@ impl<'a> Compiler<'a> {
         self.stack.push((name, self.stack_offset));
     }
 
+    /// Emits code to bind names in a destructuring pattern to the elements
+    /// of the list in the value register.
+    /// Returns the number of values pushed onto the stack, which should be
+    /// removed with `Skip` and `pop_vars` when the names go out of scope.
+    fn bind_pattern(&mut self, pattern: &Value) -> Result<u32, Error> {
+        let (elems, rest) = match *pattern {
+            Value::Name(name) => {
+                self.push_var(name);
+                try!(self.push_instruction(Instruction::Push));
+                return Ok(1);
+            }
+            Value::Unit => (&[][..], None),
+            Value::List(ref li) => try!(split_pattern(li)),
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected name or list pattern")))
+        };
+
+        let base = self.stack_offset;
+        let mut n_vars = elems.len() as u32;
+
+        // Nested patterns are held on the stack under a dummy name
+        // until they are destructured.
+        for (i, pat) in elems.iter().enumerate() {
+            let name = match *pat {
+                Value::Name(name) => name,
+                _ => Name::dummy()
+            };
+            self.stack.push((name, base + i as u32));
+        }
+
+        if let Some(rest) = rest {
+            self.stack.push((rest, base + n_vars));
+            try!(self.push_instruction(Instruction::UnpackRest(n_vars)));
+            n_vars += 1;
+        } else {
+            try!(self.push_instruction(Instruction::Unpack(n_vars)));
+        }
+
+        for (i, pat) in elems.iter().enumerate() {
+            if let Value::Name(_) = *pat {
+                continue;
+            }
+
+            try!(self.push_instruction(Instruction::Load(base + i as u32)));
+            n_vars += try!(self.bind_pattern(pat));
+        }
+
+        Ok(n_vars)
+    }
+
     /// Remove `n` named values from the list of stack values.
     /// Should be followed by a `Skip` instruction to adjust `stack_offset`.
     fn pop_vars(&mut self, n: u32) {
@@ -1230,6 +1600,10 @@ impl<'a> Compiler<'a> {
 
     fn use_block(&mut self, block: u32) {
         self.cur_block = block as usize;
+
+        if let Some(span) = self.cur_span {
+            self.current_block().mark_span(span);
+        }
     }
 
     fn use_next(&mut self, block: u32) {
@@ -1245,6 +1619,15 @@ impl<'a> Compiler<'a> {
         match instr {
             Instruction::Push => {
                 self.stack_offset += 1;
+                self.max_stack = max(self.max_stack, self.stack_offset);
+            }
+            Instruction::Unpack(n) => {
+                self.stack_offset += n;
+                self.max_stack = max(self.max_stack, self.stack_offset);
+            }
+            Instruction::UnpackRest(n) => {
+                self.stack_offset += n + 1;
+                self.max_stack = max(self.max_stack, self.stack_offset);
             }
             Instruction::BuildClosure(_, n) |
             Instruction::List(n) |
@@ -1286,7 +1669,9 @@ fn is_const_system_fn(name: Name) -> bool {
         FLOAT | INF | NAN | DENOM | FRACT | NUMER | RAT | RECIP |
         CHARS | STRING |
         ID | IS | IS_INSTANCE | NULL | TYPE_OF |
-        XOR | NOT
+        XOR | NOT |
+        HASH_MAP | GET | INSERT | REMOVE | KEYS | VALUES | CONTAINS |
+        SET | UNION | INTERSECTION | DIFFERENCE | IS_SUBSET
             => true,
         _ => false
     }
@@ -1365,6 +1750,7 @@ fn fold_asymmetric<F: FoldOp>(compiler: &mut Compiler, name: Name, args: &[Value
         None => Ok(ConstResult::IsRuntime),
         Some(v) => {
             let v = try!(F::finish(v));
+            try!(compiler.scope.get_restrictions().check_value(&v));
 
             if new_args.is_empty() {
                 Ok(ConstResult::Constant(v))
@@ -1421,6 +1807,8 @@ fn fold_symmetric<F: FoldOp>(compiler: &mut Compiler, name: Name, args: &[Value]
     match value {
         None => Ok(ConstResult::IsRuntime),
         Some(v) => {
+            try!(compiler.scope.get_restrictions().check_value(&v));
+
             if new_args.is_empty() {
                 Ok(ConstResult::Constant(v))
             } else {
@@ -1467,6 +1855,7 @@ fn eval_system_fn(compiler: &mut Compiler, name: Name, args: &[Value])
     }
 
     let v = try!((sys_fn.callback)(compiler.scope, &mut values));
+    try!(compiler.scope.get_restrictions().check_value(&v));
 
     Ok(ConstResult::Constant(v))
 }
@@ -1541,9 +1930,10 @@ static SYSTEM_OPERATORS: [Operator; NUM_SYSTEM_OPERATORS] = [
     sys_op!(op_case, Min(2)),
     sys_op!(op_cond, Min(1)),
     sys_op!(op_lambda, Exact(2)),
-    sys_op!(op_export, Exact(1)),
+    sys_op!(op_export, Range(1, 3)),
     sys_op!(op_use, Min(2)),
     sys_op!(op_const, Exact(2)),
+    sys_op!(op_try, Exact(2)),
 ];
 
 /// `apply` calls a function or lambda with a series of arguments.
@@ -1591,15 +1981,17 @@ fn op_let(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     match args[0] {
         Value::Unit => (),
         Value::List(ref li) => {
-            n_vars = li.len() as u32;
             for v in li.iter() {
                 match *v {
                     Value::List(ref li) if li.len() == 2 => {
-                        let name = try!(get_name(&li[0]));
+                        match li[0] {
+                            Value::Name(_) | Value::List(_) => (),
+                            _ => return Err(From::from(CompileError::SyntaxError(
+                                "expected name or list pattern")))
+                        }
 
                         try!(compiler.compile_value(&li[1]));
-                        compiler.push_var(name);
-                        try!(compiler.push_instruction(Instruction::Push));
+                        n_vars += try!(compiler.bind_pattern(&li[0]));
                     }
                     _ => return Err(From::from(CompileError::SyntaxError(
                         "expected list of 2 elements")))
@@ -1646,7 +2038,7 @@ fn op_define(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
             let (lambda, captures) = try!(make_lambda(
                 &compiler, Some(name), &li[1..], &args[1]));
 
-            let code_c = compiler.add_const(Owned(Value::Lambda(lambda)));
+            let code_c = compiler.add_const(Owned(Value::Lambda(Rc::new(lambda))));
             try!(compiler.load_lambda(code_c, &captures));
             try!(compiler.push_instruction(Instruction::SetDef(c)));
             Ok(())
@@ -1684,14 +2076,22 @@ fn op_macro(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 
 /// `struct` creates a struct definition and binds to global scope.
 ///
+/// Doc comments preceding the definition and its fields are stored in the
+/// struct definition.
+///
 /// ```lisp
-/// (struct Foo ((name string)
-///              (num integer)))
+/// ;; A foo
+/// (struct Foo (
+///   ;; Name of the foo
+///   (name string)
+///   (num integer)))
 /// ```
 fn op_struct(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let doc = compiler.form_doc.take();
     let name = try!(get_name(&args[0]));
     try!(test_define_name(compiler.scope, name));
     let mut fields = NameMap::new();
+    let mut field_docs = NameMap::new();
 
     match args[1] {
         Value::Unit => (),
@@ -1703,6 +2103,10 @@ fn op_struct(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
                         let fty = try!(get_name(&li[1]));
 
                         fields.insert(fname, fty);
+
+                        if let Some(doc) = compiler.get_doc(v) {
+                            field_docs.insert(fname, doc.to_owned());
+                        }
                     }
                     _ => return Err(From::from(CompileError::SyntaxError(
                         "expected list of 2 elements")))
@@ -1712,7 +2116,12 @@ fn op_struct(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
         _ => return Err(From::from(CompileError::SyntaxError("expected list")))
     }
 
-    let def = Value::StructDef(Rc::new(StructDef::new(name, fields.into_slice())));
+    let mut def = StructDef::new(name, fields.into_slice());
+
+    def.doc = doc.map(|s| s.to_owned());
+    def.field_docs = field_docs.into_slice();
+
+    let def = Value::StructDef(Rc::new(def));
 
     let name_c = compiler.add_const(Owned(Value::Name(name)));
     let c = compiler.add_const(Owned(def));
@@ -1983,7 +2392,7 @@ fn op_lambda(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let (lambda, captures) = try!(make_lambda(
         &compiler, None, li, &args[1]));
 
-    let c = compiler.add_const(Owned(Value::Lambda(lambda)));
+    let c = compiler.add_const(Owned(Value::Lambda(Rc::new(lambda))));
     try!(compiler.load_lambda(c, &captures));
     Ok(())
 }
@@ -2011,6 +2420,15 @@ fn op_export(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
         names.insert(try!(get_name(v)));
     }
 
+    if args.len() > 1 {
+        match (&args[1], args.get(2)) {
+            (&Value::Keyword(standard_names::VERSION), Some(v)) =>
+                compiler.scope.set_version(try!(get_version(v))),
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected `:version` followed by version string")))
+        }
+    }
+
     compiler.scope.set_exports(names.into_slice());
 
     try!(compiler.push_instruction(Instruction::Unit));
@@ -2032,6 +2450,26 @@ fn op_use(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 
     let mut imp_set = ImportSet::new(mod_name);
 
+    // The version requirement is checked before any names are imported.
+    {
+        let mut iter = args[2..].iter();
+
+        while let Some(arg) = iter.next() {
+            if let Value::Keyword(standard_names::VERSION) = *arg {
+                let v = match iter.next() {
+                    Some(v) => try!(get_version(v)),
+                    None => return Err(From::from(CompileError::SyntaxError(
+                        "expected version string after keyword")))
+                };
+
+                try!(m.check_version(&v));
+                imp_set.min_version = Some(v);
+            } else {
+                iter.next();
+            }
+        }
+    }
+
     match args[1] {
         Value::Keyword(standard_names::ALL) => {
             let names = compiler.scope.import_all_values(&m.scope);
@@ -2078,8 +2516,12 @@ fn op_use(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
                         "expected `:all` or list of names after keyword")))
                 }
             }
+            Value::Keyword(standard_names::VERSION) => {
+                // Already handled above
+                iter.next();
+            }
             _ => return Err(From::from(CompileError::SyntaxError(
-                "expected keyword `:const` or `:macro`")))
+                "expected keyword `:const`, `:macro`, or `:version`")))
         }
     }
 
@@ -2091,13 +2533,16 @@ fn op_use(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 
 /// `const` declares a named constant value in global scope.
 /// The value must be a compile-time constant.
+/// A doc comment preceding the declaration is stored in global scope.
 ///
 /// ```lisp
 /// (const foo 123)
 ///
+/// ;; One more than foo
 /// (const bar (+ foo 1))
 /// ```
 fn op_const(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let doc = compiler.form_doc.take();
     let name = try!(get_name(&args[0]));
 
     try!(test_define_name(compiler.scope, name));
@@ -2118,10 +2563,63 @@ fn op_const(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 
     compiler.add_constant(name, value.into_owned());
 
+    if let Some(doc) = doc {
+        compiler.scope.add_doc(name, doc.to_owned());
+    }
+
     try!(compiler.load_quoted_value(Owned(Value::Name(name))));
     Ok(())
 }
 
+/// `try` evaluates an expression; if an error is produced, the name given
+/// in the `catch` clause is bound to a value describing the error and the
+/// handler expression is evaluated instead.
+///
+/// ```lisp
+/// (try (foo)
+///   (catch e (println "error: ~s" e)))
+/// ```
+fn op_try(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let (name, handler) = match args[1] {
+        Value::List(ref li) if li.len() == 3 => match li[0] {
+            Value::Name(standard_names::CATCH) => (try!(get_name(&li[1])), &li[2]),
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected `(catch name expr)`")))
+        },
+        _ => return Err(From::from(CompileError::SyntaxError(
+            "expected `(catch name expr)`")))
+    };
+
+    let body_block = compiler.new_block();
+    let handler_block = compiler.new_block();
+    let final_block = compiler.new_block();
+
+    compiler.current_block().jump_to(JumpInstruction::Try, handler_block);
+
+    compiler.use_next(body_block);
+    compiler.try_depth += 1;
+    let r = compiler.compile_value(&args[0]);
+    compiler.try_depth -= 1;
+    try!(r);
+    try!(compiler.push_instruction(Instruction::EndTry));
+    compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+
+    // The handler is entered with the error value in the value register
+    compiler.use_next(handler_block);
+    compiler.push_var(name);
+    try!(compiler.push_instruction(Instruction::Push));
+    try!(compiler.compile_value(handler));
+
+    let skip_block = compiler.new_block();
+    compiler.use_next(skip_block);
+
+    try!(compiler.push_instruction(Instruction::Skip(1)));
+    compiler.pop_vars(1);
+
+    compiler.use_next(final_block);
+    Ok(())
+}
+
 fn import_constants(mod_name: Name, imps: &mut ImportSet,
         a: &GlobalScope, b: &GlobalScope, names: &[Value]) -> Result<(), CompileError> {
     each_import(names, |src, dest| {
@@ -2136,6 +2634,10 @@ fn import_constants(mod_name: Name, imps: &mut ImportSet,
 
                 a.add_constant(dest, v);
                 imps.constants.push((src, dest));
+
+                if let Some(doc) = b.get_doc(src) {
+                    a.add_doc(dest, doc);
+                }
             }
             None => return Err(CompileError::ImportError{
                 module: mod_name,
@@ -2219,6 +2721,47 @@ fn each_import<F>(items: &[Value], mut f: F) -> Result<(), CompileError>
     Ok(())
 }
 
+fn get_version(v: &Value) -> Result<ModuleVersion, CompileError> {
+    match *v {
+        Value::String(ref s) => ModuleVersion::parse(s)
+            .ok_or(CompileError::SyntaxError("invalid version string")),
+        _ => Err(CompileError::SyntaxError("expected version string"))
+    }
+}
+
+/// Splits a destructuring pattern into element patterns and
+/// the name following `:rest`, if any.
+fn split_pattern(li: &[Value]) -> Result<(&[Value], Option<Name>), CompileError> {
+    let pos = li.iter().position(|v| match *v {
+        Value::Keyword(standard_names::REST) => true,
+        _ => false
+    });
+
+    match pos {
+        Some(pos) => {
+            if li.len() != pos + 2 {
+                return Err(CompileError::SyntaxError(
+                    "expected one name after `:rest` in pattern"));
+            }
+
+            let rest = try!(get_name(&li[pos + 1]));
+            Ok((&li[..pos], Some(rest)))
+        }
+        None => Ok((li, None))
+    }
+}
+
+/// Adds names bound by a destructuring pattern to `names`.
+fn pattern_names(pattern: &Value, names: &mut Vec<Name>) {
+    match *pattern {
+        Value::Name(name) => names.push(name),
+        Value::List(ref li) => for v in li.iter() {
+            pattern_names(v, names);
+        },
+        _ => ()
+    }
+}
+
 fn get_name(v: &Value) -> Result<Name, CompileError> {
     match *v {
         Value::Name(name) => Ok(name),
@@ -2249,6 +2792,12 @@ fn make_lambda(compiler: &Compiler, name: Option<Name>,
     let mut optional = false;
     // `:rest` argument, if encountered
     let mut rest = None;
+    // `:rest-keys` argument, if encountered
+    let mut rest_keys = None;
+    // Destructuring patterns of required parameters
+    let mut patterns = Vec::new();
+    // Names bound by destructuring patterns
+    let mut pattern_vars = Vec::new();
 
     let mut iter = args.iter();
 
@@ -2299,21 +2848,74 @@ fn make_lambda(compiler: &Compiler, name: Option<Name>,
                             None => break
                         }
                     }
+                    standard_names::REST_KEYS => {
+                        if optional {
+                            return Err(From::from(CompileError::SyntaxError(
+                                "`:optional` and `:rest-keys` are mutually exclusive")));
+                        }
+
+                        let arg = match iter.next() {
+                            Some(arg) => arg,
+                            None => return Err(From::from(CompileError::SyntaxError(
+                                "expected name after `:rest-keys`")))
+                        };
+
+                        let name = try!(get_name(arg));
+
+                        let exists = params.iter().any(|&(n, _)| n == name) ||
+                            kw_params.iter().any(|&(n, _)| n == name) ||
+                            pattern_vars.contains(&name);
+
+                        if exists {
+                            return Err(From::from(CompileError::DuplicateParameter(name)));
+                        }
+
+                        rest_keys = Some(name);
+
+                        match iter.next() {
+                            Some(&Value::Keyword(standard_names::REST)) =>
+                                return Err(From::from(CompileError::SyntaxError(
+                                    "`:rest` and `:rest-keys` are mutually exclusive"))),
+                            Some(_) => return Err(From::from(CompileError::SyntaxError(
+                                "extraneous token after `:rest-keys` argument"))),
+                            None => break
+                        }
+                    }
                     _ => return Err(From::from(CompileError::SyntaxError(
-                        "expected :key, :optional, or :rest")))
+                        "expected :key, :optional, :rest, or :rest-keys")))
                 }
                 continue;
             }
+            Value::List(_) if !key && !optional => {
+                let mut names = Vec::new();
+                pattern_names(v, &mut names);
+
+                for name in names {
+                    let exists = params.iter().any(|&(n, _)| n == name) ||
+                        pattern_vars.contains(&name);
+
+                    if exists {
+                        return Err(From::from(CompileError::DuplicateParameter(name)));
+                    }
+
+                    pattern_vars.push(name);
+                }
+
+                patterns.push((params.len() as u32, v.clone()));
+                (Name::dummy(), None)
+            }
             Value::List(ref li) if li.len() == 2 => {
                 let name = try!(get_name(&li[0]));
                 (name, Some(li[1].clone()))
             }
             _ => return Err(From::from(CompileError::SyntaxError(
-                "expected name, keyword, or list of 2 elements")))
+                "expected name, keyword, list pattern, or list of 2 elements")))
         };
 
-        let exists = params.iter().any(|&(n, _)| n == name) ||
-            kw_params.iter().any(|&(n, _)| n == name);
+        let exists = name != Name::dummy() && (
+            params.iter().any(|&(n, _)| n == name) ||
+            kw_params.iter().any(|&(n, _)| n == name) ||
+            pattern_vars.contains(&name));
 
         if exists {
             return Err(From::from(CompileError::DuplicateParameter(name)));
@@ -2330,7 +2932,7 @@ fn make_lambda(compiler: &Compiler, name: Option<Name>,
         req_params = params.len() as u32;
     }
 
-    if key && kw_params.is_empty() {
+    if key && kw_params.is_empty() && rest_keys.is_none() {
         return Err(From::from(CompileError::SyntaxError(
             "expected arguments after `:key`")));
     }
@@ -2341,7 +2943,7 @@ fn make_lambda(compiler: &Compiler, name: Option<Name>,
     }
 
     let (code, captures) = try!(compile_lambda(&compiler,
-        name, params, req_params, kw_params, rest, body));
+        name, params, req_params, kw_params, rest, rest_keys, patterns, body));
 
     Ok((Lambda::new(Rc::new(code), &compiler.scope), captures))
 }
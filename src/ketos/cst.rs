@@ -0,0 +1,369 @@
+//! Produces a lossless concrete syntax tree from Ketos source code.
+//!
+//! Unlike the `Value` produced by the `Parser`, a `SyntaxTree` preserves
+//! the exact text of every token, along with any whitespace and comments
+//! surrounding it. Writing a tree, using its `Display` implementation,
+//! reproduces the original source exactly. Tools may therefore modify
+//! some nodes of a tree without disturbing the formatting of the rest.
+
+use std::fmt;
+
+use lexer::{BytePos, Lexer, Span, Token};
+use parser::{ParseError, ParseErrorKind, Suggestion};
+
+/// Concrete syntax tree of a body of source code
+#[derive(Clone, Debug)]
+pub struct SyntaxTree<'a> {
+    /// Top-level expressions
+    pub nodes: Vec<SyntaxNode<'a>>,
+    /// Whitespace and comments following the last expression
+    pub trailing: Vec<Trivia<'a>>,
+}
+
+/// Expression within a `SyntaxTree`
+#[derive(Clone, Debug)]
+pub enum SyntaxNode<'a> {
+    /// Single token, such as a name or literal value
+    ///
+    /// A dispatch sequence, e.g. `#t`, is also represented as an atom.
+    /// Any tokens read by its reader macro appear as subsequent nodes.
+    Atom(SyntaxToken<'a>),
+    /// List of expressions enclosed in parentheses, brackets, or braces
+    List{
+        /// Opening delimiter
+        open: SyntaxToken<'a>,
+        /// Expressions within the list
+        items: Vec<SyntaxNode<'a>>,
+        /// Closing delimiter
+        close: SyntaxToken<'a>,
+    },
+    /// Expression preceded by a quote, comma, or datum label token
+    Prefix{
+        /// Prefix token
+        prefix: SyntaxToken<'a>,
+        /// Expression following the prefix
+        node: Box<SyntaxNode<'a>>,
+    },
+}
+
+/// Token within a `SyntaxTree`, along with any trivia preceding it
+#[derive(Clone, Debug)]
+pub struct SyntaxToken<'a> {
+    /// Whitespace and comments preceding the token
+    pub leading: Vec<Trivia<'a>>,
+    /// Token value
+    pub token: Token<'a>,
+    /// Span of source code containing the token
+    pub span: Span,
+    /// Source text of the token
+    pub text: &'a str,
+}
+
+/// Source text which does not affect the meaning of code
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Trivia<'a> {
+    /// Kind of trivia
+    pub kind: TriviaKind,
+    /// Span of source code containing the trivia
+    pub span: Span,
+    /// Source text of the trivia
+    pub text: &'a str,
+}
+
+/// Kind of source text represented by `Trivia`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TriviaKind {
+    /// Line or block comment
+    Comment,
+    /// Doc comment, beginning with `;;`
+    DocComment,
+    /// Shebang line, beginning with `#!`, at the start of input
+    Shebang,
+    /// Whitespace, including newlines
+    Whitespace,
+}
+
+/// Parses source code into a `SyntaxTree`.
+///
+/// Spans within the tree are relative to the start of `input`.
+pub fn parse(input: &str) -> Result<SyntaxTree, ParseError> {
+    let shebang = if input.starts_with("#!") {
+        input.find('\n').unwrap_or(input.len())
+    } else {
+        0
+    };
+
+    let mut reader = Reader{
+        input: input,
+        lexer: Lexer::new(&input[shebang..], shebang as BytePos),
+        last_end: shebang,
+    };
+
+    reader.lexer.set_comments(true);
+
+    let mut leading = Vec::new();
+
+    if shebang != 0 {
+        leading.push(Trivia{
+            kind: TriviaKind::Shebang,
+            span: Span{lo: 0, hi: shebang as BytePos},
+            text: &input[..shebang],
+        });
+    }
+
+    let mut nodes = Vec::new();
+
+    loop {
+        let tok = try!(reader.next(leading));
+
+        if let Token::End = tok.token {
+            return Ok(SyntaxTree{
+                nodes: nodes,
+                trailing: tok.leading,
+            });
+        }
+
+        nodes.push(try!(reader.read_node(tok)));
+        leading = Vec::new();
+    }
+}
+
+impl<'a> SyntaxNode<'a> {
+    /// Returns the first token of the node.
+    pub fn first_token(&self) -> &SyntaxToken<'a> {
+        match *self {
+            SyntaxNode::Atom(ref tok) => tok,
+            SyntaxNode::List{ref open, ..} => open,
+            SyntaxNode::Prefix{ref prefix, ..} => prefix,
+        }
+    }
+
+    /// Returns the whitespace and comments preceding the node.
+    pub fn leading(&self) -> &[Trivia<'a>] {
+        &self.first_token().leading
+    }
+
+    /// Returns the span of source code containing the node,
+    /// excluding any leading trivia.
+    pub fn span(&self) -> Span {
+        match *self {
+            SyntaxNode::Atom(ref tok) => tok.span,
+            SyntaxNode::List{ref open, ref close, ..} =>
+                Span{lo: open.span.lo, hi: close.span.hi},
+            SyntaxNode::Prefix{ref prefix, ref node} =>
+                Span{lo: prefix.span.lo, hi: node.span().hi},
+        }
+    }
+}
+
+impl<'a> fmt::Display for SyntaxTree<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in &self.nodes {
+            try!(fmt::Display::fmt(node, f));
+        }
+
+        write_trivia(&self.trailing, f)
+    }
+}
+
+impl<'a> fmt::Display for SyntaxNode<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyntaxNode::Atom(ref tok) => fmt::Display::fmt(tok, f),
+            SyntaxNode::List{ref open, ref items, ref close} => {
+                try!(fmt::Display::fmt(open, f));
+                for item in items {
+                    try!(fmt::Display::fmt(item, f));
+                }
+                fmt::Display::fmt(close, f)
+            }
+            SyntaxNode::Prefix{ref prefix, ref node} => {
+                try!(fmt::Display::fmt(prefix, f));
+                fmt::Display::fmt(node, f)
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Display for SyntaxToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write_trivia(&self.leading, f));
+        f.write_str(self.text)
+    }
+}
+
+fn write_trivia(trivia: &[Trivia], f: &mut fmt::Formatter) -> fmt::Result {
+    for t in trivia {
+        try!(f.write_str(t.text));
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    input: &'a str,
+    lexer: Lexer<'a>,
+    last_end: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Returns the next token, collecting any preceding trivia.
+    fn next(&mut self, mut leading: Vec<Trivia<'a>>) -> Result<SyntaxToken<'a>, ParseError> {
+        loop {
+            let (sp, tok) = try!(self.lexer.next_token());
+
+            if self.last_end < sp.lo as usize {
+                leading.push(Trivia{
+                    kind: TriviaKind::Whitespace,
+                    span: Span{lo: self.last_end as BytePos, hi: sp.lo},
+                    text: &self.input[self.last_end..sp.lo as usize],
+                });
+            }
+
+            self.last_end = sp.hi as usize;
+            let text = &self.input[sp.lo as usize..sp.hi as usize];
+
+            let kind = match tok {
+                Token::Comment(_) => TriviaKind::Comment,
+                Token::DocComment(_) => TriviaKind::DocComment,
+                _ => return Ok(SyntaxToken{
+                    leading: leading,
+                    token: tok,
+                    span: sp,
+                    text: text,
+                })
+            };
+
+            leading.push(Trivia{
+                kind: kind,
+                span: sp,
+                text: text,
+            });
+        }
+    }
+
+    fn read_node(&mut self, tok: SyntaxToken<'a>) -> Result<SyntaxNode<'a>, ParseError> {
+        let close = match tok.token {
+            Token::LeftParen => Token::RightParen,
+            Token::LeftBracket => Token::RightBracket,
+            Token::LeftBrace | Token::LeftSetBrace => Token::RightBrace,
+            _ => return self.read_atom(tok)
+        };
+
+        let mut items = Vec::new();
+
+        loop {
+            let next = try!(self.next(Vec::new()));
+
+            let kind = match next.token {
+                t if t == close => return Ok(SyntaxNode::List{
+                    open: tok,
+                    items: items,
+                    close: next,
+                }),
+                Token::RightParen | Token::RightBracket | Token::RightBrace =>
+                    ParseErrorKind::UnexpectedToken{
+                        expected: close.name(),
+                        found: next.token.name(),
+                    },
+                Token::End => ParseErrorKind::MissingCloseParen,
+                _ => {
+                    items.push(try!(self.read_node(next)));
+                    continue;
+                }
+            };
+
+            return Err(ParseError{
+                open_span: Some(tok.span),
+                suggestion: Some(Suggestion::Close(close.name())),
+                ..ParseError::new(next.span, kind)
+            });
+        }
+    }
+
+    /// Reads a node which does not begin with an opening delimiter.
+    fn read_atom(&mut self, tok: SyntaxToken<'a>) -> Result<SyntaxNode<'a>, ParseError> {
+        match tok.token {
+            Token::RightParen | Token::RightBracket | Token::RightBrace =>
+                Err(ParseError::new(tok.span, ParseErrorKind::UnmatchedParen)),
+            Token::BackQuote | Token::Comma | Token::CommaAt | Token::Quote |
+                    Token::Label(_) => {
+                let next = try!(self.next(Vec::new()));
+
+                match next.token {
+                    Token::End => Err(ParseError::new(next.span,
+                        ParseErrorKind::UnexpectedEof)),
+                    Token::RightParen | Token::RightBracket | Token::RightBrace =>
+                        Err(ParseError::new(next.span,
+                            ParseErrorKind::UnexpectedToken{
+                                expected: "expression",
+                                found: next.token.name(),
+                            })),
+                    _ => Ok(SyntaxNode::Prefix{
+                        prefix: tok,
+                        node: Box::new(try!(self.read_node(next))),
+                    })
+                }
+            }
+            _ => Ok(SyntaxNode::Atom(tok))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, SyntaxNode, TriviaKind};
+    use lexer::{Span, Token};
+    use parser::ParseErrorKind;
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = [
+            "",
+            "  \n",
+            "#!/usr/bin/env ketos\n(foo)\n",
+            "(define (foo a) ; comment\n  ;; doc\n  (+ a   1)) #| block |#\n",
+            "'(a `(b ,c ,@d)) #0=(e) #0#\r\n",
+            "(foo #<<END\ntext\nEND\n)",
+            "[1 [2]\n 3] { :a 1 }",
+        ];
+
+        for input in &inputs {
+            assert_eq!(parse(input).unwrap().to_string(), *input);
+        }
+    }
+
+    #[test]
+    fn test_structure() {
+        let tree = parse("; head\n(foo 'bar)  ").unwrap();
+
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.trailing.len(), 1);
+        assert_eq!(tree.trailing[0].kind, TriviaKind::Whitespace);
+
+        let node = &tree.nodes[0];
+
+        assert_eq!(node.span(), Span{lo: 7, hi: 17});
+        assert_eq!(node.leading().iter().map(|t| t.kind).collect::<Vec<_>>(),
+            [TriviaKind::Comment, TriviaKind::Whitespace]);
+
+        match *node {
+            SyntaxNode::List{ref items, ..} => {
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0].first_token().token, Token::Name("foo"));
+                assert_eq!(items[1].span(), Span{lo: 12, hi: 16});
+                assert_eq!(items[1].to_string(), " 'bar");
+            }
+            _ => panic!("expected list")
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse("(foo").unwrap_err().kind, ParseErrorKind::MissingCloseParen);
+        assert_eq!(parse("foo)").unwrap_err().kind, ParseErrorKind::UnmatchedParen);
+        assert_eq!(parse("'").unwrap_err().kind, ParseErrorKind::UnexpectedEof);
+        assert_eq!(parse("[foo)").unwrap_err().kind, ParseErrorKind::UnexpectedToken{
+            expected: "]", found: ")"});
+        assert_eq!(parse("(a {b").unwrap_err().open_span, Some(Span{lo: 3, hi: 4}));
+    }
+}
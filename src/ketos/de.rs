@@ -0,0 +1,449 @@
+//! Implements deserialization of Rust values from Ketos values using `serde`.
+//!
+//! Values are mapped to the `serde` data model as follows:
+//!
+//! * `()` is a unit value, a `None` option, or an empty sequence or map.
+//! * Integers, floats, and ratios are numbers; ratios are converted to `f64`.
+//! * Strings, names, and keywords are strings. Keywords are given without
+//!   the leading colon.
+//! * Lists and sets are sequences, tuples, or tuple structs.
+//! * Map values, struct values, and lists of alternating keywords and values,
+//!   such as `(:name "foo" :port 80)`, are maps or structs.
+//! * A name, keyword, or string is a unit enum variant; a list beginning with
+//!   a name or keyword is an enum variant whose contents are the remaining
+//!   elements of the list.
+//!
+//! Keyword and field names are matched against Rust field names exactly.
+//! Rust types may use `#[serde(rename_all = "kebab-case")]` to accept
+//! conventional Lisp names such as `:listen-port`.
+//!
+//! ```ignore
+//! let v = interp.run_single_expr("'(:name \"server\" :port 8080)", None)?;
+//! let cfg: Config = ketos::from_value(&interp.get_scope().borrow_names(), &v)?;
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::slice;
+
+use serde::de::{self, Deserialize, DeserializeSeed, EnumAccess, MapAccess,
+    SeqAccess, VariantAccess, Visitor};
+use serde::de::value::BorrowedStrDeserializer;
+
+use name::{Name, NameStore};
+use value::Value;
+
+/// Represents an error in deserializing a Rust value from a `Value`.
+#[derive(Debug)]
+pub enum DeError {
+    /// Error produced by the type being deserialized
+    Custom(String),
+    /// Odd number of elements in a keyword-value list
+    OddKeywordParams,
+    /// Number too large to be represented in the data model
+    Overflow,
+    /// Value of an unexpected type was found
+    TypeError{
+        /// Type expected
+        expected: &'static str,
+        /// Type found
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeError::Custom(ref s) => f.write_str(s),
+            DeError::OddKeywordParams => f.write_str("odd number of keyword params"),
+            DeError::Overflow => f.write_str("integer overflow"),
+            DeError::TypeError{expected, found} =>
+                write!(f, "type error: expected {}; found {}", expected, found),
+        }
+    }
+}
+
+impl StdError for DeError {
+    fn description(&self) -> &str {
+        match *self {
+            DeError::Custom(ref s) => s,
+            DeError::OddKeywordParams => "odd number of keyword params",
+            DeError::Overflow => "integer overflow",
+            DeError::TypeError{..} => "type error",
+        }
+    }
+}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> DeError {
+        DeError::Custom(msg.to_string())
+    }
+}
+
+/// Deserializes a Rust value from a `Value`.
+///
+/// The given `NameStore` is used to resolve names and keywords into strings.
+pub fn from_value<'de, T>(names: &'de NameStore, value: &'de Value) -> Result<T, DeError>
+        where T: Deserialize<'de> {
+    T::deserialize(ValueDeserializer::new(names, value))
+}
+
+/// Deserializes Rust values from a borrowed `Value`.
+#[derive(Copy, Clone)]
+pub struct ValueDeserializer<'de> {
+    names: &'de NameStore,
+    value: &'de Value,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    /// Creates a new `ValueDeserializer` for the given value.
+    pub fn new(names: &'de NameStore, value: &'de Value) -> ValueDeserializer<'de> {
+        ValueDeserializer{
+            names: names,
+            value: value,
+        }
+    }
+
+    fn type_error(&self, expected: &'static str) -> DeError {
+        DeError::TypeError{
+            expected: expected,
+            found: self.value.type_name(),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Value::Unit => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Integer(ref i) => {
+                if let Some(n) = i.to_i64() {
+                    visitor.visit_i64(n)
+                } else if let Some(n) = i.to_u64() {
+                    visitor.visit_u64(n)
+                } else {
+                    Err(DeError::Overflow)
+                }
+            }
+            Value::Ratio(ref r) => match r.to_f64() {
+                Some(f) => visitor.visit_f64(f),
+                None => Err(DeError::Overflow)
+            },
+            Value::Char(c) => visitor.visit_char(c),
+            Value::String(ref s) => visitor.visit_borrowed_str(s),
+            Value::Name(name) | Value::Keyword(name) =>
+                visitor.visit_borrowed_str(self.names.get(name)),
+            Value::List(ref li) => visitor.visit_seq(
+                SeqDeserializer::new(self.names, li)),
+            Value::Set(ref s) => visitor.visit_seq(
+                SeqDeserializer::new(self.names, s.values())),
+            Value::Map(ref m) => visitor.visit_map(
+                EntryDeserializer::new(self.names, m.keys(), m.values())),
+            Value::Struct(ref s) => visitor.visit_map(
+                FieldDeserializer::new(self.names, s.fields.get_values())),
+            _ => Err(self.type_error("data value"))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Value::Unit => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+            -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Value::Unit => visitor.visit_seq(SeqDeserializer::new(self.names, &[])),
+            Value::List(ref li) => visitor.visit_seq(SeqDeserializer::new(self.names, li)),
+            Value::Set(ref s) => visitor.visit_seq(SeqDeserializer::new(self.names, s.values())),
+            _ => Err(self.type_error("list or set"))
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V)
+            -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str,
+            _len: usize, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Value::Unit => visitor.visit_map(KeywordDeserializer::new(self.names, &[])),
+            Value::List(ref li) => visitor.visit_map(KeywordDeserializer::new(self.names, li)),
+            Value::Map(ref m) => visitor.visit_map(
+                EntryDeserializer::new(self.names, m.keys(), m.values())),
+            Value::Struct(ref s) => visitor.visit_map(
+                FieldDeserializer::new(self.names, s.fields.get_values())),
+            _ => Err(self.type_error("map, struct, or keyword list"))
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+            _fields: &'static [&'static str], visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+            _variants: &'static [&'static str], visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Value::Name(name) | Value::Keyword(name) => visitor.visit_enum(
+                EnumDeserializer::new(self.names, self.names.get(name), &[])),
+            Value::String(ref s) => visitor.visit_enum(
+                EnumDeserializer::new(self.names, s, &[])),
+            Value::List(ref li) => match li[0] {
+                Value::Name(name) | Value::Keyword(name) => visitor.visit_enum(
+                    EnumDeserializer::new(self.names, self.names.get(name), &li[1..])),
+                ref v => Err(DeError::TypeError{
+                    expected: "name",
+                    found: v.type_name(),
+                })
+            },
+            _ => Err(self.type_error("name or list"))
+        }
+    }
+
+    forward_to_deserialize_any!{
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string
+        bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
+
+/// Deserializes the elements of a list as a sequence.
+struct SeqDeserializer<'de> {
+    names: &'de NameStore,
+    iter: slice::Iter<'de, Value>,
+}
+
+impl<'de> SeqDeserializer<'de> {
+    fn new(names: &'de NameStore, values: &'de [Value]) -> SeqDeserializer<'de> {
+        SeqDeserializer{
+            names: names,
+            iter: values.iter(),
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer<'de> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+            -> Result<Option<T::Value>, DeError> {
+        match self.iter.next() {
+            Some(v) => seed.deserialize(ValueDeserializer::new(self.names, v)).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Deserializes a list of alternating keywords and values as a map.
+struct KeywordDeserializer<'de> {
+    names: &'de NameStore,
+    iter: slice::Iter<'de, Value>,
+    value: Option<&'de Value>,
+}
+
+impl<'de> KeywordDeserializer<'de> {
+    fn new(names: &'de NameStore, values: &'de [Value]) -> KeywordDeserializer<'de> {
+        KeywordDeserializer{
+            names: names,
+            iter: values.iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for KeywordDeserializer<'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+            -> Result<Option<K::Value>, DeError> {
+        let name = match self.iter.next() {
+            Some(&Value::Keyword(name)) => name,
+            Some(v) => return Err(DeError::TypeError{
+                expected: "keyword",
+                found: v.type_name(),
+            }),
+            None => return Ok(None)
+        };
+
+        match self.iter.next() {
+            Some(v) => self.value = Some(v),
+            None => return Err(DeError::OddKeywordParams)
+        }
+
+        seed.deserialize(BorrowedStrDeserializer::new(self.names.get(name))).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V)
+            -> Result<V::Value, DeError> {
+        let v = self.value.take().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(ValueDeserializer::new(self.names, v))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len() / 2)
+    }
+}
+
+/// Deserializes the fields of a struct value as a map.
+struct FieldDeserializer<'de> {
+    names: &'de NameStore,
+    iter: slice::Iter<'de, (Name, Value)>,
+    value: Option<&'de Value>,
+}
+
+impl<'de> FieldDeserializer<'de> {
+    fn new(names: &'de NameStore, fields: &'de [(Name, Value)])
+            -> FieldDeserializer<'de> {
+        FieldDeserializer{
+            names: names,
+            iter: fields.iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for FieldDeserializer<'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+            -> Result<Option<K::Value>, DeError> {
+        match self.iter.next() {
+            Some(&(name, ref v)) => {
+                self.value = Some(v);
+                seed.deserialize(BorrowedStrDeserializer::new(self.names.get(name)))
+                    .map(Some)
+            }
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V)
+            -> Result<V::Value, DeError> {
+        let v = self.value.take().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(ValueDeserializer::new(self.names, v))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Deserializes the entries of a map value as a map.
+struct EntryDeserializer<'de> {
+    names: &'de NameStore,
+    keys: slice::Iter<'de, Value>,
+    values: slice::Iter<'de, Value>,
+}
+
+impl<'de> EntryDeserializer<'de> {
+    fn new(names: &'de NameStore, keys: &'de [Value], values: &'de [Value])
+            -> EntryDeserializer<'de> {
+        EntryDeserializer{
+            names: names,
+            keys: keys.iter(),
+            values: values.iter(),
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for EntryDeserializer<'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+            -> Result<Option<K::Value>, DeError> {
+        match self.keys.next() {
+            Some(k) => seed.deserialize(ValueDeserializer::new(self.names, k)).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V)
+            -> Result<V::Value, DeError> {
+        let v = self.values.next().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(ValueDeserializer::new(self.names, v))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.keys.len())
+    }
+}
+
+/// Deserializes an enum variant from a name and the values following it.
+struct EnumDeserializer<'de> {
+    names: &'de NameStore,
+    variant: &'de str,
+    values: &'de [Value],
+}
+
+impl<'de> EnumDeserializer<'de> {
+    fn new(names: &'de NameStore, variant: &'de str, values: &'de [Value])
+            -> EnumDeserializer<'de> {
+        EnumDeserializer{
+            names: names,
+            variant: variant,
+            values: values,
+        }
+    }
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer<'de> {
+    type Error = DeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V)
+            -> Result<(V::Value, Self), DeError> {
+        let v = try!(seed.deserialize(
+            BorrowedStrDeserializer::<DeError>::new(self.variant)));
+        Ok((v, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for EnumDeserializer<'de> {
+    type Error = DeError;
+
+    fn unit_variant(self) -> Result<(), DeError> {
+        if self.values.is_empty() {
+            Ok(())
+        } else {
+            Err(de::Error::invalid_length(self.values.len(), &"no values"))
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T)
+            -> Result<T::Value, DeError> {
+        if self.values.len() == 1 {
+            seed.deserialize(ValueDeserializer::new(self.names, &self.values[0]))
+        } else {
+            Err(de::Error::invalid_length(self.values.len(), &"one value"))
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V)
+            -> Result<V::Value, DeError> {
+        visitor.visit_seq(SeqDeserializer::new(self.names, self.values))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V)
+            -> Result<V::Value, DeError> {
+        visitor.visit_map(KeywordDeserializer::new(self.names, self.values))
+    }
+}
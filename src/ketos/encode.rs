@@ -15,6 +15,8 @@ use error::Error;
 use function::Lambda;
 use integer::{Integer, Ratio, Sign};
 use io::{IoError, IoMode};
+use map::{Map, Set};
+use module::ModuleVersion;
 use name::{Name, NameMap, NameSet, NameSetSlice, NameStore,
     NameInputConversion, NameOutputConversion};
 use scope::{ImportSet, Scope};
@@ -38,6 +40,8 @@ pub enum DecodeError {
     InvalidChar(u32),
     /// Invalid flags in code object
     InvalidCodeFlags(u32),
+    /// Map key or set value of a type which may not be used as a key
+    InvalidMapKey,
     /// Invalid name value
     InvalidName(u32),
     /// Invalid parameter count in code object
@@ -68,6 +72,7 @@ impl fmt::Display for DecodeError {
             InvalidChar(n) => write!(f, "\\u{{{:x}}} is not a valid char", n),
             InvalidCodeFlags(flags) =>
                 write!(f, "invalid code object flags: {:#x}", flags),
+            InvalidMapKey => f.write_str("invalid map key"),
             InvalidName(n) => write!(f, "invalid name: {}", n),
             InvalidParamCount => f.write_str("invalid parameter count"),
             InvalidType(ty) => write!(f, "invalid type {:#x}", ty),
@@ -108,6 +113,8 @@ pub struct ModuleCode {
     pub imports: Vec<ImportSet>,
     /// Decoded macro objects
     pub macros: Vec<(Name, Rc<Code>)>,
+    /// Module version
+    pub version: Option<ModuleVersion>,
 }
 
 /// Read compiled bytecode from a file
@@ -156,6 +163,8 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
         exports.insert(name);
     }
 
+    let version = try!(dec.read_version());
+
     let n_imports = try!(dec.read_uint());
     let mut imports = Vec::new();
 
@@ -190,6 +199,8 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
             imp.values.push((src, dest));
         }
 
+        imp.min_version = try!(dec.read_version());
+
         imports.push(imp);
     }
 
@@ -213,6 +224,7 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
         macros: macros,
         exports: exports.into_slice(),
         imports: imports,
+        version: version,
     })
 }
 
@@ -255,6 +267,8 @@ pub fn write_bytecode<W: Write>(w: &mut W, path: &Path, module: &ModuleCode,
             try!(body_enc.write_name(src, &mut names));
             try!(body_enc.write_name(dest, &mut names));
         }
+
+        try!(body_enc.write_version(imp.min_version.as_ref()));
     }
 
     try!(body_enc.write_len(module.macros.len()));
@@ -282,6 +296,8 @@ pub fn write_bytecode<W: Write>(w: &mut W, path: &Path, module: &ModuleCode,
         try!(head_enc.write_name(name, &mut names));
     }
 
+    try!(head_enc.write_version(module.version.as_ref()));
+
     try!(w.write_all(MAGIC_NUMBER)
         .map_err(|e| IoError::new(IoMode::Write, path, e)));
 
@@ -402,9 +418,26 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
                     fields.insert(field, ty);
                 }
 
+                let doc = match try!(self.read_u8()) {
+                    0 => None,
+                    _ => Some(try!(self.read_string()).to_owned())
+                };
+
+                let n = try!(self.read_uint());
+                let mut field_docs = NameMap::new();
+
+                for _ in 0..n {
+                    let field = try!(self.read_name(names));
+                    let doc = try!(self.read_string());
+
+                    field_docs.insert(field, doc.to_owned());
+                }
+
                 Ok(Value::StructDef(Rc::new(StructDef{
                     name: name,
                     fields: fields.into_slice(),
+                    doc: doc,
+                    field_docs: field_docs.into_slice(),
                 })))
             }
             QUASI_QUOTE => {
@@ -442,9 +475,33 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
 
                 Ok(v.into())
             }
+            SET => {
+                let n = try!(self.read_len());
+                let mut s = Set::new();
+
+                for _ in 0..n {
+                    let v = try!(self.read_value(names));
+                    try!(s.insert(v).map_err(|_| DecodeError::InvalidMapKey));
+                }
+
+                Ok(s.into())
+            }
+            MAP => {
+                let n = try!(self.read_len());
+                let mut m = Map::new();
+
+                for _ in 0..n {
+                    let k = try!(self.read_value(names));
+                    let v = try!(self.read_value(names));
+
+                    try!(m.insert(k, v).map_err(|_| DecodeError::InvalidMapKey));
+                }
+
+                Ok(m.into())
+            }
             LAMBDA => {
                 let code = try!(self.read_code(names));
-                Ok(Value::Lambda(Lambda::new(Rc::new(code), &self.scope)))
+                Ok(Value::Lambda(Rc::new(Lambda::new(Rc::new(code), &self.scope))))
             }
             _ => Err(DecodeError::InvalidType(ty))
         }
@@ -459,7 +516,9 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
 
         let flags = try!(self.read_u8()) as u32;
 
-        if flags & ALL_FLAGS != flags {
+        if flags & ALL_FLAGS != flags ||
+                (flags & HAS_REST_KEYS != 0 &&
+                    flags & PARAM_FLAGS_MASK == HAS_REST_PARAMS) {
             return Err(DecodeError::InvalidCodeFlags(flags));
         }
 
@@ -483,6 +542,7 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
 
         let n_params = try!(self.read_uint());
         let req_params = try!(self.read_uint());
+        let max_stack = try!(self.read_uint());
 
         if n_params < req_params {
             return Err(DecodeError::InvalidParamCount);
@@ -508,15 +568,15 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
             _ => return Err(DecodeError::InvalidCodeFlags(flags))
         }
 
-        Ok(Code{
-            name: name,
-            consts: consts.into_boxed_slice(),
-            code: code.into_boxed_slice(),
-            kw_params: kw_params.into_boxed_slice(),
-            n_params: n_params,
-            req_params: req_params,
-            flags: flags
-        })
+        let mut code = Code::new(code.into_boxed_slice(), consts.into_boxed_slice());
+        code.name = name;
+        code.kw_params = kw_params.into_boxed_slice();
+        code.n_params = n_params;
+        code.req_params = req_params;
+        code.flags = flags;
+        code.max_stack = max_stack;
+
+        Ok(code)
     }
 
     fn read_name(&mut self, names: &NameInputConversion) -> Result<Name, DecodeError> {
@@ -531,6 +591,22 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
         from_utf8(b).map_err(|_| DecodeError::InvalidUtf8)
     }
 
+    fn read_version(&mut self) -> Result<Option<ModuleVersion>, DecodeError> {
+        let n = try!(self.read_len());
+
+        if n == 0 {
+            Ok(None)
+        } else {
+            let mut parts = Vec::with_capacity(n);
+
+            for _ in 0..n {
+                parts.push(try!(self.read_u32()));
+            }
+
+            Ok(Some(ModuleVersion::new(parts)))
+        }
+    }
+
     fn read_integer(&mut self, sign: Sign) -> Result<Integer, DecodeError> {
         let n = try!(self.read_uint());
         let b = try!(self.read_bytes(n as usize));
@@ -684,6 +760,21 @@ impl ValueEncoder {
                     try!(self.write_name(name, names));
                     try!(self.write_name(ty, names));
                 }
+
+                match def.doc {
+                    Some(ref doc) => {
+                        self.write_u8(1);
+                        try!(self.write_string(doc));
+                    }
+                    None => self.write_u8(0)
+                }
+
+                try!(self.write_len(def.field_docs.len()));
+
+                for &(name, ref doc) in &def.field_docs {
+                    try!(self.write_name(name, names));
+                    try!(self.write_string(doc));
+                }
             }
             Value::Quasiquote(ref v, 1) => {
                 self.write_u8(QUASI_QUOTE_ONE);
@@ -733,6 +824,23 @@ impl ValueEncoder {
                     try!(self.write_value(v, names));
                 }
             }
+            Value::Map(ref m) => {
+                self.write_u8(MAP);
+                try!(self.write_len(m.len()));
+
+                for (k, v) in m.iter() {
+                    try!(self.write_value(k, names));
+                    try!(self.write_value(v, names));
+                }
+            }
+            Value::Set(ref s) => {
+                self.write_u8(SET);
+                try!(self.write_len(s.len()));
+
+                for v in s.iter() {
+                    try!(self.write_value(v, names));
+                }
+            }
             Value::Lambda(ref l) => {
                 if l.values.is_some() {
                     panic!("cannot encode Lambda with enclosed values");
@@ -774,6 +882,7 @@ impl ValueEncoder {
 
         try!(self.write_uint(code.n_params));
         try!(self.write_uint(code.req_params));
+        try!(self.write_uint(code.max_stack));
 
         assert_eq!(code.flags & PARAM_FLAGS_MASK == HAS_KW_PARAMS,
             !code.kw_params.is_empty());
@@ -808,6 +917,21 @@ impl ValueEncoder {
         Ok(())
     }
 
+    fn write_version(&mut self, v: Option<&ModuleVersion>) -> Result<(), EncodeError> {
+        match v {
+            None => self.write_len(0),
+            Some(v) => {
+                try!(self.write_len(v.parts().len()));
+
+                for &n in v.parts() {
+                    self.write_u32(n);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
     fn write_u8(&mut self, b: u8) {
         self.data.push(b);
     }
@@ -896,4 +1020,6 @@ types!{
     QUOTE_ONE = 23,
     LIST = 24,
     LAMBDA = 25,
+    MAP = 26,
+    SET = 27,
 }
@@ -1,12 +1,14 @@
 //! Contains consolidated `Error` type.
 
-use std::fmt;
+use std::fmt::{self, Write};
 
 use compile::CompileError;
 use encode::{DecodeError, EncodeError};
 use exec::ExecError;
-use io::IoError;
-use parser::ParseError;
+use io::{IoError, IoMode};
+use lexer::{CodeMap, Span};
+use name::{display_names, NameDisplay, NameStore};
+use parser::{ParseError, ParseErrorKind};
 
 macro_rules! error_type {
     ( $( #[$meta:meta] )* pub enum $name:ident
@@ -50,5 +52,358 @@ error_type!{
         IoError(IoError),
         /// Error in scanning text or parsing syntax
         ParseError(ParseError),
+        /// Error with a description of the operation during which it occurred
+        Context(ErrorContext),
+    }
+}
+
+impl Error {
+    /// Wraps the error with a description of the operation
+    /// during which it occurred, e.g. `"while loading plugin foo"`.
+    pub fn context<S: Into<String>>(self, context: S) -> Error {
+        Error::Context(ErrorContext{
+            context: context.into(),
+            error: Box::new(self),
+        })
+    }
+
+    /// Returns a stable identifier for the kind of error, such as
+    /// `"exec/type-error"`, ignoring any context.
+    ///
+    /// Identifiers consist of the error category and the name of the
+    /// error variant, separated by a slash.
+    pub fn code(&self) -> &'static str {
+        match *self.root() {
+            Error::CompileError(ref e) => compile_code(e),
+            Error::DecodeError(ref e) => decode_code(e),
+            Error::EncodeError(ref e) => encode_code(e),
+            Error::ExecError(ref e) => exec_code(e),
+            Error::IoError(ref e) => match e.mode {
+                IoMode::Create => "io/create",
+                IoMode::Open => "io/open",
+                IoMode::Read => "io/read",
+                IoMode::Stat => "io/stat",
+                IoMode::Write => "io/write",
+            },
+            Error::ParseError(ref e) => parse_code(&e.kind),
+            Error::Context(_) => unreachable!(),
+        }
+    }
+
+    /// Returns whether the error was caused by input ending before
+    /// an expression was complete; see `ParseError::is_incomplete`.
+    pub fn is_incomplete(&self) -> bool {
+        match *self.root() {
+            Error::ParseError(ref e) => e.is_incomplete(),
+            _ => false
+        }
+    }
+
+    /// Returns context descriptions wrapping the error, outermost first.
+    pub fn contexts(&self) -> Vec<&str> {
+        let mut res = Vec::new();
+        let mut err = self;
+
+        while let Error::Context(ref ctx) = *err {
+            res.push(&ctx.context[..]);
+            err = &ctx.error;
+        }
+
+        res
+    }
+
+    /// Returns the innermost error, removing any context.
+    pub fn root(&self) -> &Error {
+        let mut err = self;
+
+        while let Error::Context(ref ctx) = *err {
+            err = &ctx.error;
+        }
+
+        err
+    }
+}
+
+impl NameDisplay for Error {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::CompileError(ref e) => NameDisplay::fmt(e, names, f),
+            Error::ExecError(ref e) => NameDisplay::fmt(e, names, f),
+            Error::ParseError(ref e) => NameDisplay::fmt(e, names, f),
+            Error::Context(ref e) => NameDisplay::fmt(e, names, f),
+            ref e => fmt::Display::fmt(e, f)
+        }
+    }
+}
+
+/// Error value with a description of the operation during which it occurred
+#[derive(Debug)]
+pub struct ErrorContext {
+    /// Description of the operation
+    pub context: String,
+    /// Error produced by the operation
+    pub error: Box<Error>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.error)
+    }
+}
+
+impl NameDisplay for ErrorContext {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.context, display_names(names, &*self.error))
+    }
+}
+
+/// Plain representation of an `Error`, suitable for serialization
+///
+/// An `ErrorReport` is produced by `Interpreter::error_report`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorReport {
+    /// Stable identifier of the kind of error; see `Error::code`
+    pub code: &'static str,
+    /// Error message, excluding context and source location
+    pub message: String,
+    /// Context descriptions, outermost first
+    pub context: Vec<String>,
+    /// Name of the source file containing the error, if known
+    pub file: Option<String>,
+    /// Span of the source code which caused the error, if known
+    pub span: Option<Span>,
+    /// Line number of the start of `span`, beginning at `1`
+    pub line: Option<usize>,
+    /// Column offset, in characters, of the start of `span`
+    pub column: Option<usize>,
+    /// Descriptions of the calls active when the error occurred,
+    /// innermost first; empty if not recorded
+    pub backtrace: Vec<String>,
+}
+
+impl ErrorReport {
+    /// Renders the report as a diagnostic message in the style of `rustc`.
+    ///
+    /// If `span` refers to source code held by `codemap`, the offending line
+    /// is included and the span is underlined. Context descriptions and
+    /// backtrace entries follow as notes. Each line of the result,
+    /// including the last, ends with a newline.
+    ///
+    /// ```
+    /// use ketos::Interpreter;
+    ///
+    /// let interp = Interpreter::new();
+    /// let err = interp.run_code("(define a 1)\n(let ((b)) a)",
+    ///     Some("foo.ket".to_owned())).unwrap_err();
+    ///
+    /// let report = interp.error_report(&err);
+    /// assert_eq!(report.render(&interp.get_scope().borrow_codemap()),
+    ///     "error[compile/syntax-error]: expected list of 2 elements\n\
+    ///     \x20--> foo.ket:2:0\n\
+    ///     \x20 |\n\
+    ///     2 | (let ((b)) a)\n\
+    ///     \x20 | ^~~~~~~~~~~~~\n");
+    /// ```
+    pub fn render(&self, codemap: &CodeMap) -> String {
+        let mut buf = String::new();
+        let mut pad = String::new();
+
+        let _ = writeln!(buf, "error[{}]: {}", self.code, self.message);
+
+        if let Some(hi) = self.span.and_then(|sp| codemap.try_highlight_span(sp)) {
+            let line = hi.line.to_string();
+
+            for _ in 0..line.len() {
+                pad.push(' ');
+            }
+
+            let _ = writeln!(buf, "{}--> {}:{}:{}", pad,
+                hi.filename.unwrap_or("<input>"), hi.line, hi.col);
+            let _ = writeln!(buf, "{} |", pad);
+            let _ = writeln!(buf, "{} | {}", line, hi.source);
+            let _ = writeln!(buf, "{} | {}", pad, hi.highlight);
+        }
+
+        for ctx in &self.context {
+            let _ = writeln!(buf, "{} = note: {}", pad, ctx);
+        }
+
+        for call in &self.backtrace {
+            let _ = writeln!(buf, "{} = note: in {}", pad, call);
+        }
+
+        buf
+    }
+}
+
+/// Adds context to the error value of a `Result`.
+///
+/// ```
+/// use ketos::{Error, Interpreter, ResultExt};
+///
+/// let interp = Interpreter::new();
+///
+/// let r = interp.run_code("(foo)", None)
+///     .with_context(|| format!("while evaluating hook `{}`", "on-save"));
+///
+/// match r {
+///     Err(Error::Context(ref ctx)) =>
+///         assert_eq!(ctx.context, "while evaluating hook `on-save`"),
+///     _ => panic!("expected error context")
+/// }
+/// ```
+pub trait ResultExt<T> {
+    /// Wraps an error value with the given context.
+    fn context<S: Into<String>>(self, context: S) -> Result<T, Error>;
+
+    /// Wraps an error value with context produced by the given function.
+    /// The function is called only if the `Result` contains an error.
+    fn with_context<S, F>(self, f: F) -> Result<T, Error>
+        where S: Into<String>, F: FnOnce() -> S;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn context<S: Into<String>>(self, context: S) -> Result<T, Error> {
+        self.map_err(|e| e.into().context(context))
+    }
+
+    fn with_context<S, F>(self, f: F) -> Result<T, Error>
+            where S: Into<String>, F: FnOnce() -> S {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+/// Returns a stable identifier for a `CompileError` value.
+fn compile_code(e: &CompileError) -> &'static str {
+    match *e {
+        CompileError::ArityError{..} => "compile/arity-error",
+        CompileError::CannotDefine{..} => "compile/cannot-define",
+        CompileError::ConstantExists{..} => "compile/constant-exists",
+        CompileError::DuplicateExports{..} => "compile/duplicate-exports",
+        CompileError::DuplicateParameter{..} => "compile/duplicate-parameter",
+        CompileError::ExportError{..} => "compile/export-error",
+        CompileError::ImportCycle{..} => "compile/import-cycle",
+        CompileError::ImportError{..} => "compile/import-error",
+        CompileError::ImportShadow{..} => "compile/import-shadow",
+        CompileError::InvalidCallExpression{..} => "compile/invalid-call-expression",
+        CompileError::InvalidCommaAt{..} => "compile/invalid-comma-at",
+        CompileError::InvalidModuleName{..} => "compile/invalid-module-name",
+        CompileError::MacroRecursionExceeded{..} => "compile/macro-recursion-exceeded",
+        CompileError::MissingExport{..} => "compile/missing-export",
+        CompileError::ModuleError{..} => "compile/module-error",
+        CompileError::ModuleVersionError{..} => "compile/module-version-error",
+        CompileError::NotConstant{..} => "compile/not-constant",
+        CompileError::OperandOverflow{..} => "compile/operand-overflow",
+        CompileError::PrivacyError{..} => "compile/privacy-error",
+        CompileError::SyntaxError{..} => "compile/syntax-error",
+        CompileError::UnbalancedComma{..} => "compile/unbalanced-comma",
+    }
+}
+
+/// Returns a stable identifier for a `DecodeError` value.
+fn decode_code(e: &DecodeError) -> &'static str {
+    match *e {
+        DecodeError::DivisionByZero{..} => "decode/division-by-zero",
+        DecodeError::EmptyList{..} => "decode/empty-list",
+        DecodeError::IncorrectMagicNumber{..} => "decode/incorrect-magic-number",
+        DecodeError::IncorrectVersion{..} => "decode/incorrect-version",
+        DecodeError::InvalidChar{..} => "decode/invalid-char",
+        DecodeError::InvalidCodeFlags{..} => "decode/invalid-code-flags",
+        DecodeError::InvalidMapKey{..} => "decode/invalid-map-key",
+        DecodeError::InvalidName{..} => "decode/invalid-name",
+        DecodeError::InvalidParamCount{..} => "decode/invalid-param-count",
+        DecodeError::InvalidType{..} => "decode/invalid-type",
+        DecodeError::InvalidUtf8{..} => "decode/invalid-utf8",
+        DecodeError::UnbalancedComma{..} => "decode/unbalanced-comma",
+        DecodeError::UnexpectedEof{..} => "decode/unexpected-eof",
+    }
+}
+
+/// Returns a stable identifier for a `EncodeError` value.
+fn encode_code(e: &EncodeError) -> &'static str {
+    match *e {
+        EncodeError::Overflow{..} => "encode/overflow",
+        EncodeError::UnencodableType{..} => "encode/unencodable-type",
+    }
+}
+
+/// Returns a stable identifier for a `ExecError` value.
+fn exec_code(e: &ExecError) -> &'static str {
+    match *e {
+        ExecError::ArityError{..} => "exec/arity-error",
+        ExecError::CompareNaN{..} => "exec/compare-nan",
+        ExecError::CallStackOverflow{..} => "exec/call-stack-overflow",
+        ExecError::CannotCompare{..} => "exec/cannot-compare",
+        ExecError::CannotDefine{..} => "exec/cannot-define",
+        ExecError::DivideByZero{..} => "exec/divide-by-zero",
+        ExecError::DuplicateField{..} => "exec/duplicate-field",
+        ExecError::DuplicateKeyword{..} => "exec/duplicate-keyword",
+        ExecError::DuplicateStructDef{..} => "exec/duplicate-struct-def",
+        ExecError::FieldError{..} => "exec/field-error",
+        ExecError::FieldTypeError{..} => "exec/field-type-error",
+        ExecError::FormatError{..} => "exec/format-error",
+        ExecError::InvalidClosureValue{..} => "exec/invalid-closure-value",
+        ExecError::InvalidConst{..} => "exec/invalid-const",
+        ExecError::Interrupted{..} => "exec/interrupted",
+        ExecError::InstructionLimit{..} => "exec/instruction-limit",
+        ExecError::IntegerLimit{..} => "exec/integer-limit",
+        ExecError::ListLimit{..} => "exec/list-limit",
+        ExecError::StringLimit{..} => "exec/string-limit",
+        ExecError::NamespaceLimit{..} => "exec/namespace-limit",
+        ExecError::InvalidDepth{..} => "exec/invalid-depth",
+        ExecError::InvalidJump{..} => "exec/invalid-jump",
+        ExecError::InvalidSlice{..} => "exec/invalid-slice",
+        ExecError::InvalidStack{..} => "exec/invalid-stack",
+        ExecError::InvalidSystemFn{..} => "exec/invalid-system-fn",
+        ExecError::MissingArgCount{..} => "exec/missing-arg-count",
+        ExecError::MissingField{..} => "exec/missing-field",
+        ExecError::NameError{..} => "exec/name-error",
+        ExecError::NotCharBoundary{..} => "exec/not-char-boundary",
+        ExecError::OddKeywordParams{..} => "exec/odd-keyword-params",
+        ExecError::OutOfBounds{..} => "exec/out-of-bounds",
+        ExecError::Overflow{..} => "exec/overflow",
+        ExecError::Panic{..} => "exec/panic",
+        ExecError::PatternMismatch{..} => "exec/pattern-mismatch",
+        ExecError::Raised{..} => "exec/raised",
+        ExecError::Restricted{..} => "exec/restricted",
+        ExecError::StackOverflow{..} => "exec/stack-overflow",
+        ExecError::StructDefError{..} => "exec/struct-def-error",
+        ExecError::TypeError{..} => "exec/type-error",
+        ExecError::StructMismatch{..} => "exec/struct-mismatch",
+        ExecError::TypeMismatch{..} => "exec/type-mismatch",
+        ExecError::UnexpectedEnd{..} => "exec/unexpected-end",
+        ExecError::UnrecognizedKeyword{..} => "exec/unrecognized-keyword",
+        ExecError::UnrecognizedOpCode{..} => "exec/unrecognized-op-code",
+    }
+}
+
+/// Returns a stable identifier for a `ParseErrorKind` value.
+fn parse_code(e: &ParseErrorKind) -> &'static str {
+    match *e {
+        ParseErrorKind::InvalidLiteral{..} => "parse/invalid-literal",
+        ParseErrorKind::InvalidToken{..} => "parse/invalid-token",
+        ParseErrorKind::InvalidChar{..} => "parse/invalid-char",
+        ParseErrorKind::InvalidDigit{..} => "parse/invalid-digit",
+        ParseErrorKind::InvalidExponent{..} => "parse/invalid-exponent",
+        ParseErrorKind::InvalidNumericEscape{..} => "parse/invalid-numeric-escape",
+        ParseErrorKind::LiteralParseError{..} => "parse/literal-parse-error",
+        ParseErrorKind::InvalidMapKey{..} => "parse/invalid-map-key",
+        ParseErrorKind::MissingMapValue{..} => "parse/missing-map-value",
+        ParseErrorKind::InvalidSetElement{..} => "parse/invalid-set-element",
+        ParseErrorKind::MissingCloseParen{..} => "parse/missing-close-paren",
+        ParseErrorKind::DepthExceeded{..} => "parse/depth-exceeded",
+        ParseErrorKind::DuplicateLabel{..} => "parse/duplicate-label",
+        ParseErrorKind::RecursiveLabel{..} => "parse/recursive-label",
+        ParseErrorKind::UndefinedLabel{..} => "parse/undefined-label",
+        ParseErrorKind::MissingDigits{..} => "parse/missing-digits",
+        ParseErrorKind::UnbalancedComma{..} => "parse/unbalanced-comma",
+        ParseErrorKind::UnexpectedEof{..} => "parse/unexpected-eof",
+        ParseErrorKind::UnexpectedToken{..} => "parse/unexpected-token",
+        ParseErrorKind::UnknownCharEscape{..} => "parse/unknown-char-escape",
+        ParseErrorKind::UnmatchedParen{..} => "parse/unmatched-paren",
+        ParseErrorKind::UnknownDispatch{..} => "parse/unknown-dispatch",
+        ParseErrorKind::UnterminatedChar{..} => "parse/unterminated-char",
+        ParseErrorKind::UnterminatedComment{..} => "parse/unterminated-comment",
+        ParseErrorKind::UnterminatedString{..} => "parse/unterminated-string",
     }
 }
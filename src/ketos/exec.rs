@@ -23,20 +23,25 @@
 //! returns a value, which is available to the calling function through the
 //! value register.
 
+use std::cell::Ref;
+use std::cmp::min;
 use std::fmt;
 use std::mem::replace;
 use std::rc::Rc;
 use std::vec::Drain;
 
-use bytecode::{Code, CodeReader};
+use bytecode::{Code, CodeReader, DecodedCode, Instruction};
 use error::Error;
-use function::{Arity, Function, Lambda, SystemFn};
+use function::{plural, Arity, Function, Lambda, SystemFn};
 use integer::{Integer, Ratio};
 use lexer::{highlight_span, Span};
+use metrics::Metrics;
+use restrict::output_cost;
 use scope::{MasterScope, Scope};
 use string_fmt::FormatError;
-use name::{display_names, get_standard_name, get_system_fn,
-    Name, NameDisplay, NameStore};
+use map::Map;
+use name::{debug_names, display_names, get_standard_name, get_system_fn,
+    standard_names, Name, NameDisplay, NameMap, NameStore};
 use value::{FromValueRef, Value};
 
 /// Represents an error generated while executing bytecode.
@@ -51,6 +56,8 @@ pub enum ExecError {
         /// Number of arguments present
         found: u32,
     },
+    /// Exceeded maximum depth of nested function calls
+    CallStackOverflow,
     /// Attempt to compare with a `NaN` `Float` value.
     CompareNaN,
     /// Type does not support ordered comparison
@@ -96,6 +103,18 @@ pub enum ExecError {
     InvalidClosureValue(u32),
     /// Invalid const index
     InvalidConst(u32),
+    /// Execution was interrupted by setting the interrupt flag
+    Interrupted,
+    /// Execution exceeded the maximum number of instructions
+    InstructionLimit,
+    /// An integer value exceeded the maximum size
+    IntegerLimit,
+    /// A list value exceeded the maximum length
+    ListLimit,
+    /// A string value exceeded the maximum length
+    StringLimit,
+    /// A global scope exceeded the maximum number of defined values
+    NamespaceLimit,
     /// Invalid (zero) depth value to `Quote`, `Quasiquote`, or `Comma` instruction
     InvalidDepth,
     /// Invalid jump label
@@ -127,6 +146,19 @@ pub enum ExecError {
     Overflow,
     /// Code called `panic`
     Panic(Option<Value>),
+    /// Value raised by `raise` which was not caught
+    Raised(Value),
+    /// Function is disabled by the restrictions of the executing scope
+    Restricted(Name),
+    /// List value does not match the shape of a destructuring pattern
+    PatternMismatch{
+        /// Number of elements expected
+        expected: u32,
+        /// Whether the pattern accepts additional elements
+        rest: bool,
+        /// Number of elements present
+        found: u32,
+    },
     /// Exceeded maximum stack size
     StackOverflow,
     /// Struct definition not found
@@ -178,6 +210,7 @@ impl fmt::Display for ExecError {
         match *self {
             ArityError{expected, found, ..} =>
                 write!(f, "expected {}; found {}", expected, found),
+            CallStackOverflow => f.write_str("call stack overflow"),
             CannotCompare(ty) => write!(f, "cannot compare values of type {}", ty),
             CannotDefine(_) =>
                 f.write_str("cannot define name of standard value or operator"),
@@ -192,6 +225,12 @@ impl fmt::Display for ExecError {
                 write!(f, "error in string formatting: {}", err),
             InvalidClosureValue(n) => write!(f, "invalid closure value: {}", n),
             InvalidConst(n) => write!(f, "invalid const: {}", n),
+            Interrupted => f.write_str("execution interrupted"),
+            InstructionLimit => f.write_str("instruction limit exceeded"),
+            IntegerLimit => f.write_str("integer size limit exceeded"),
+            ListLimit => f.write_str("list length limit exceeded"),
+            StringLimit => f.write_str("string length limit exceeded"),
+            NamespaceLimit => f.write_str("namespace size limit exceeded"),
             InvalidDepth => f.write_str("invalid depth operand"),
             InvalidJump(label) => write!(f, "invalid jump label: {}", label),
             InvalidSlice(begin, end) => write!(f, "invalid slice {}..{}", begin, end),
@@ -207,6 +246,12 @@ impl fmt::Display for ExecError {
             OutOfBounds(n) => write!(f, "index out of bounds: {}", n),
             Overflow => f.write_str("integer overflow"),
             Panic(_) => f.write_str("panic"),
+            Raised(_) => f.write_str("uncaught raised value"),
+            Restricted(_) => f.write_str("function disabled by restrictions"),
+            PatternMismatch{expected, rest, found} =>
+                write!(f, "pattern expected {}{} element{}; found {}",
+                    if rest { "at least " } else { "" },
+                    expected, plural(expected), found),
             StackOverflow => f.write_str("stack overflow"),
             TypeError{expected, found} =>
                 write!(f, "type error: expected {}; found {}", expected, found),
@@ -232,6 +277,7 @@ impl NameDisplay for ExecError {
             DuplicateKeyword(name) |
             DuplicateStructDef(name) |
             NameError(name) |
+            Restricted(name) |
             StructDefError(name) |
             UnrecognizedKeyword(name) =>
                 write!(f, "{}: {}", self, names.get(name)),
@@ -265,6 +311,8 @@ impl NameDisplay for ExecError {
                 Some(ref v) => write!(f, "panic: {}", display_names(names, v)),
                 None => f.write_str("explicit panic"),
             },
+            Raised(ref v) =>
+                write!(f, "uncaught raised value: {}", debug_names(names, v)),
             StructMismatch{lhs, rhs} =>
                 write!(f, "struct type mismatch: `{}` and `{}`",
                     names.get(lhs),
@@ -274,23 +322,43 @@ impl NameDisplay for ExecError {
     }
 }
 
+/// Number of instructions executed between checks of the interrupt flag
+const INTERRUPT_INTERVAL: u64 = 1024;
+
 /// Executes a code object and returns the value.
 pub fn execute(scope: &Scope, code: Rc<Code>) -> Result<Value, Error> {
     Machine::new().execute(scope, code)
 }
 
-/// Calls a function or lambda in the given scope with the given arguments.
-pub fn call_function(scope: &Scope, fun: Value, args: Vec<Value>)
+/// Calls a function, lambda, or foreign function value in the given scope
+/// with the given arguments.
+///
+/// This may be used by foreign functions to call a function value
+/// received as an argument.
+///
+/// The interrupt flag of the scope is checked before each call, so that
+/// a foreign function calling back many times, such as a sort with
+/// a comparison function, may be interrupted.
+pub fn call_function(scope: &Scope, fun: Value, mut args: Vec<Value>)
         -> Result<Value, Error> {
+    try!(scope.check_interrupt());
+
     match fun {
-        Value::Function(fun) => execute_function(scope, fun, args),
-        Value::Lambda(l) => execute_lambda(l, args),
+        Value::Function(ref fun) => execute_function(scope, fun, args),
+        Value::Lambda(ref l) => Machine::new().execute_lambda(l, args),
+        Value::Foreign(ref fv) => {
+            scope.get_metrics().add(&Metrics{calls: 1, ..Metrics::new()});
+            let v = try!(fv.call_value(scope, &mut args));
+            try!(scope.get_restrictions().check_value(&v));
+            try!(charge_output(scope, &v));
+            Ok(v)
+        }
         ref v => Err(From::from(ExecError::expected("function", v)))
     }
 }
 
 /// Executes a `Function` in the given scope and returns the value.
-pub fn execute_function(scope: &Scope, fun: Function, mut args: Vec<Value>)
+pub fn execute_function(scope: &Scope, fun: &Function, mut args: Vec<Value>)
         -> Result<Value, Error> {
     let n_args = args.len() as u32;
 
@@ -301,18 +369,47 @@ pub fn execute_function(scope: &Scope, fun: Function, mut args: Vec<Value>)
             found: n_args,
         }))
     } else {
-        (fun.sys_fn.callback)(scope, &mut args)
+        scope.get_metrics().add(&Metrics{calls: 1, ..Metrics::new()});
+        let v = try!((fun.sys_fn.callback)(scope, &mut args));
+        try!(scope.get_restrictions().check_value(&v));
+        try!(charge_output(scope, &v));
+        Ok(v)
+    }
+}
+
+/// Charges the instruction budget of a scope for a value
+/// returned by a function implemented in Rust.
+fn charge_output(scope: &Scope, v: &Value) -> Result<(), ExecError> {
+    if scope.get_restrictions().max_instructions.is_some() {
+        scope.consume_instructions(output_cost(v))
+    } else {
+        Ok(())
     }
 }
 
 /// Executes a `Lambda` in the given scope and returns the value.
 pub fn execute_lambda(lambda: Lambda, args: Vec<Value>) -> Result<Value, Error> {
-    Machine::new().execute_lambda(lambda, args)
+    Machine::new().execute_lambda(&lambda, args)
+}
+
+/// Location within compiled code at which a runtime error was produced
+///
+/// The location of the most recent error is available from
+/// `GlobalScope::error_location`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ErrorLocation {
+    /// Name of the function which produced the error; `None` if the error
+    /// was produced by top-level code or by an anonymous lambda
+    pub function: Option<Name>,
+    /// Span of the source expression which produced the error, if known
+    pub span: Option<Span>,
 }
 
 struct StackFrame {
     /// Code object
     code: Rc<Code>,
+    /// Decoded instructions of code object
+    instrs: DecodedCode,
     /// Code scope
     scope: Scope,
     /// Closure values
@@ -325,72 +422,142 @@ struct StackFrame {
     fn_on_stack: bool,
 }
 
+/// Maximum number of values held on the value stack
+const MAX_STACK: usize = 10240;
+
 struct Machine {
     stack: Vec<Value>,
     call_stack: Vec<StackFrame>,
     value: Value,
+    /// Buffer reused for arguments to system and foreign functions.
+    /// Because such functions cannot reenter the `Machine` which called
+    /// them, one buffer suffices and no shared borrow is required.
+    args: Vec<Value>,
+    /// Counters for work performed by this `Machine`
+    metrics: Metrics,
+    /// Error handlers installed by `try` expressions, innermost last
+    handlers: Vec<Handler>,
+    /// Instructions which may be executed before more are taken
+    /// from the budget of the executing scope
+    fuel: u64,
+    /// Call depth of the scope when execution began
+    base_depth: usize,
+    /// Maximum call depth, including `base_depth`
+    max_call_depth: usize,
+    /// Offset of the instruction being executed in the current frame
+    instr_offset: u32,
+}
+
+/// Saved state for resuming execution when an error is caught
+struct Handler {
+    /// Length of the call stack when the handler was installed
+    call_depth: usize,
+    /// Length of the value stack when the handler was installed
+    stack_len: usize,
+    /// Label of handler code within the installing frame
+    label: u32,
 }
 
 impl Machine {
     fn new() -> Machine {
         Machine{
-            // TODO: Configurable stack limits
-            stack: Vec::with_capacity(10240),
-            call_stack: Vec::with_capacity(1024),
+            // Stacks are sized as code is entered, using `Code::max_stack`
+            stack: Vec::new(),
+            call_stack: Vec::new(),
             value: Value::Unit,
+            args: Vec::new(),
+            metrics: Metrics::new(),
+            handlers: Vec::new(),
+            fuel: 0,
+            base_depth: 0,
+            max_call_depth: 0,
+            instr_offset: 0,
         }
     }
 
+    /// Begins execution of the outermost frame within the given scope.
+    fn enter(&mut self, scope: &Scope) -> Result<(), ExecError> {
+        self.base_depth = scope.call_depth();
+        self.max_call_depth = scope.get_restrictions().max_call_depth;
+
+        if self.base_depth == 0 {
+            scope.set_error_location(None);
+        }
+
+        let native_depth = scope.native_depth();
+
+        if self.base_depth >= self.max_call_depth ||
+                native_depth >= scope.get_restrictions().max_native_depth {
+            Err(ExecError::CallStackOverflow)
+        } else {
+            scope.set_native_depth(native_depth + 1);
+            Ok(())
+        }
+    }
+
+    /// Ends execution within the given scope, returning unused resources.
+    fn leave(&mut self, scope: &Scope) {
+        scope.get_metrics().add(&self.metrics);
+        scope.return_instructions(replace(&mut self.fuel, 0));
+        scope.set_call_depth(self.base_depth);
+        scope.set_native_depth(scope.native_depth() - 1);
+    }
+
+    /// Returns the current call depth, including the current frame.
+    fn call_depth(&self) -> usize {
+        self.base_depth + self.call_stack.len() + 1
+    }
+
+    /// Publishes the current call depth to the given scope,
+    /// before calling a function which may reenter execution.
+    fn publish_depth(&self, scope: &Scope) {
+        scope.set_call_depth(self.call_depth());
+    }
+
     fn execute(&mut self, scope: &Scope, code: Rc<Code>) -> Result<Value, Error> {
-        self.run(StackFrame{
+        try!(self.enter(scope));
+        self.reserve_stack(code.max_stack);
+
+        let r = self.run(StackFrame{
+            instrs: code.decoded(),
             code: code,
             scope: scope.clone(),
             values: None,
             iptr: 0,
             sptr: 0,
             fn_on_stack: false,
-        })
+        });
+
+        self.leave(scope);
+        r
     }
 
-    fn execute_lambda(&mut self, lambda: Lambda, args: Vec<Value>)
+    fn execute_lambda(&mut self, lambda: &Lambda, args: Vec<Value>)
             -> Result<Value, Error> {
         let scope = lambda.scope.upgrade()
             .expect("Lambda scope has been destroyed");
 
+        try!(self.enter(&scope));
+        let r = self.run_lambda(&scope, lambda, args);
+        self.leave(&scope);
+        r
+    }
+
+    fn run_lambda(&mut self, scope: &Scope, lambda: &Lambda, args: Vec<Value>)
+            -> Result<Value, Error> {
+        self.reserve_stack(lambda.code.max_stack.saturating_add(args.len() as u32));
         try!(self.push_iter(args));
 
         let n_args = self.stack.len() as u32;
-        let arity = lambda.code.arity();
-
-        if !arity.accepts(n_args) {
-            return Err(From::from(ExecError::ArityError{
-                name: lambda.code.name,
-                expected: arity,
-                found: n_args,
-            }));
-        }
+        try!(self.setup_call(scope, &lambda.code, n_args, false));
 
-        let n_params = lambda.code.n_params;
-
-        if n_params > n_args {
-            try!(self.push_unbound(lambda.code.n_params - n_args));
-        }
-        if !lambda.code.kw_params.is_empty() {
-            try!(self.push_unbound(lambda.code.kw_params.len() as u32));
-        }
-        if lambda.code.has_rest_params() {
-            if n_params >= n_args {
-                try!(self.push(Value::Unit));
-            } else {
-                try!(self.build_list(n_args - n_params));
-                try!(self.push_value());
-            }
-        }
+        self.metrics.calls += 1;
 
         self.run(StackFrame{
-            code: lambda.code,
-            scope: scope,
-            values: lambda.values,
+            code: lambda.code.clone(),
+            instrs: lambda.code.decoded(),
+            scope: scope.clone(),
+            values: lambda.values.clone(),
             iptr: 0,
             sptr: 0,
             fn_on_stack: false,
@@ -398,21 +565,112 @@ impl Machine {
     }
 
     fn run(&mut self, mut frame: StackFrame) -> Result<Value, Error> {
+        loop {
+            match self.run_frame(&mut frame) {
+                Ok(v) => return Ok(v),
+                Err(e) => match self.catch_error(&mut frame, e) {
+                    Ok(()) => frame.scope.set_error_location(None),
+                    Err(e) => {
+                        self.record_error_location(&frame);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records the location of the instruction which produced an error,
+    /// unless a nested execution has already recorded a location,
+    /// and adds each active call to the error trace.
+    fn record_error_location(&self, frame: &StackFrame) {
+        let scope = &frame.scope;
+        let loc = ErrorLocation{
+            function: frame.code.name,
+            span: frame.code.span_at(self.instr_offset),
+        };
+
+        if scope.error_location().is_none() {
+            scope.set_error_location(Some(loc));
+        }
+
+        scope.push_error_trace(loc);
+
+        // Caller frames point to the instruction following the call
+        for f in self.call_stack.iter().rev() {
+            scope.push_error_trace(ErrorLocation{
+                function: f.code.name,
+                span: f.code.span_at(f.iptr.saturating_sub(1)),
+            });
+        }
+    }
+
+    /// Runs code until the outermost frame returns or an error is produced.
+    /// When an error is produced, `frame` remains the frame which produced it.
+    fn run_frame(&mut self, frame: &mut StackFrame) -> Result<Value, Error> {
+        loop {
+            let scope = frame.scope.clone();
+
+            if try!(self.run_scope(frame, &scope)) {
+                return Ok(self.value.take());
+            }
+        }
+    }
+
+    /// Runs code while the executing frame belongs to the given scope.
+    ///
+    /// Returns `Ok(true)` when the outermost frame returns or `Ok(false)`
+    /// when control passes to a frame belonging to another scope.
+    ///
+    /// Global values are borrowed once and the borrow is held across
+    /// instructions, rather than borrowing the scope for each lookup.
+    /// The borrow is released before any instruction which may modify the
+    /// scope or execute other code.
+    fn run_scope(&mut self, frame: &mut StackFrame, scope: &Scope)
+            -> Result<bool, Error> {
         use bytecode::Instruction::*;
 
+        let mut globals = None;
+
         loop {
-            let instr = {
-                let mut r = CodeReader::new(&frame.code.code, frame.iptr as usize);
-                let instr = try!(r.read_instruction());
-                frame.iptr = r.get_offset() as u32;
-                instr
+            self.metrics.instructions += 1;
+
+            if self.metrics.instructions % INTERRUPT_INTERVAL == 0 {
+                try!(frame.scope.check_interrupt());
+            }
+
+            if self.fuel == 0 {
+                self.fuel = try!(frame.scope.take_instructions(INTERRUPT_INTERVAL));
+            }
+            self.fuel -= 1;
+            self.instr_offset = frame.iptr;
+
+            let instr = match frame.instrs.get(frame.iptr as usize) {
+                Some(&Some((instr, next))) => {
+                    frame.iptr = next;
+                    instr
+                }
+                _ => {
+                    let mut r = CodeReader::new(&frame.code.code, frame.iptr as usize);
+                    let instr = try!(r.read_instruction());
+                    frame.iptr = r.get_offset() as u32;
+                    instr
+                }
             };
 
+            let reenters = may_reenter(instr);
+
+            if reenters {
+                globals = None;
+            }
+
             match instr {
                 Load(n) => try!(self.load(frame.sptr + n)),
-                LoadC(n) => try!(self.load_c(&frame, n)),
+                LoadC(n) => try!(self.load_c(frame, n)),
                 UnboundToUnit(n) => try!(self.unbound_to_unit(frame.sptr + n)),
-                GetDef(n) => try!(self.get_def(&frame, n)),
+                GetDef(n) => {
+                    let globals = borrow_globals(&mut globals, frame, scope);
+                    try!(self.get_def(frame, globals, n))
+                }
                 Push => try!(self.push_value()),
                 Unit => self.value = Value::Unit,
                 True => self.value = Value::Bool(true),
@@ -420,35 +678,42 @@ impl Machine {
                 Const(n) => try!(self.load_const(&frame.code, n)),
                 Store(n) => try!(self.store(frame.sptr + n)),
                 LoadPush(n) => try!(self.load_push(frame.sptr + n)),
-                LoadCPush(n) => try!(self.load_c_push(&frame, n)),
-                GetDefPush(n) => try!(self.get_def_push(&frame, n)),
+                LoadCPush(n) => try!(self.load_c_push(frame, n)),
+                GetDefPush(n) => {
+                    let globals = borrow_globals(&mut globals, frame, scope);
+                    try!(self.get_def_push(frame, globals, n))
+                }
                 UnitPush => try!(self.push(Value::Unit)),
                 TruePush => try!(self.push(Value::Bool(true))),
                 FalsePush => try!(self.push(Value::Bool(false))),
                 ConstPush(n) => try!(self.push_const(&frame.code, n)),
-                SetDef(n) => try!(self.set_def(&frame, n)),
+                SetDef(n) => try!(self.set_def(frame, n)),
                 List(n) => try!(self.build_list(n)),
+                Unpack(n) => try!(self.unpack(n, false)),
+                UnpackRest(n) => try!(self.unpack(n, true)),
+                Try(label) => self.try_handler(label),
+                EndTry => { self.handlers.pop(); }
                 Quote(n) => try!(self.quote_value(n)),
                 Quasiquote(n) => try!(self.quasiquote_value(n)),
                 Comma(n) => try!(self.comma_value(n)),
                 CommaAt(n) => try!(self.comma_at_value(n)),
                 BuildClosure(n_const, n_values) =>
                     try!(self.build_closure(&frame.code, n_const, n_values)),
-                Jump(label) => try!(self.jump(&mut frame, label)),
-                JumpIf(label) => try!(self.jump_if(&mut frame, label)),
+                Jump(label) => try!(self.jump(frame, label)),
+                JumpIf(label) => try!(self.jump_if(frame, label)),
                 JumpIfBound(label, n) => {
                     let n = frame.sptr + n;
-                    try!(self.jump_if_bound(&mut frame, label, n))
+                    try!(self.jump_if_bound(frame, label, n))
                 }
-                JumpIfNot(label) => try!(self.jump_if_not(&mut frame, label)),
-                JumpIfEq(label) => try!(self.jump_if_eq(&mut frame, label)),
-                JumpIfNotEq(label) => try!(self.jump_if_not_eq(&mut frame, label)),
-                JumpIfNull(label) => try!(self.jump_if_null(&mut frame, label)),
-                JumpIfNotNull(label) => try!(self.jump_if_not_null(&mut frame, label)),
+                JumpIfNot(label) => try!(self.jump_if_not(frame, label)),
+                JumpIfEq(label) => try!(self.jump_if_eq(frame, label)),
+                JumpIfNotEq(label) => try!(self.jump_if_not_eq(frame, label)),
+                JumpIfNull(label) => try!(self.jump_if_null(frame, label)),
+                JumpIfNotNull(label) => try!(self.jump_if_not_null(frame, label)),
                 JumpIfEqConst(label, n) =>
-                    try!(self.jump_if_eq_const(&mut frame, label, n)),
+                    try!(self.jump_if_eq_const(frame, label, n)),
                 JumpIfNotEqConst(label, n) =>
-                    try!(self.jump_if_not_eq_const(&mut frame, label, n)),
+                    try!(self.jump_if_not_eq_const(frame, label, n)),
                 Null => self.is_null(),
                 NotNull => self.is_not_null(),
                 Eq => try!(self.equal()),
@@ -458,7 +723,15 @@ impl Machine {
                 Not => try!(self.negate()),
                 Inc => try!(self.increment()),
                 Dec => try!(self.decrement()),
-                Append => try!(self.append_value()),
+                IncPush => {
+                    try!(self.increment());
+                    try!(self.push_value());
+                }
+                DecPush => {
+                    try!(self.decrement());
+                    try!(self.push_value());
+                }
+                Append => try!(self.append_value(frame)),
                 First => try!(self.first()),
                 Tail => try!(self.tail()),
                 Init => try!(self.init()),
@@ -467,33 +740,93 @@ impl Machine {
                 TailPush => try!(self.tail_push()),
                 InitPush => try!(self.init_push()),
                 LastPush => try!(self.last_push()),
-                CallSys(n) => try!(self.call_sys(&mut frame, n)),
+                CallSys(n) => try!(self.call_sys(frame, n)),
                 CallSysArgs(n, n_args) =>
-                    try!(self.call_sys_args(&mut frame, n, n_args)),
+                    try!(self.call_sys_args(frame, n, n_args)),
+                CallSysPush(n) => {
+                    try!(self.call_sys(frame, n));
+                    try!(self.push_value());
+                }
+                CallSysArgsPush(n, n_args) => {
+                    try!(self.call_sys_args(frame, n, n_args));
+                    try!(self.push_value());
+                }
                 CallConst(n, n_args) =>
-                    try!(self.call_const(&mut frame, n, n_args)),
-                Call(n) => try!(self.call_function(&mut frame, n)),
-                Apply(n) => try!(self.apply(&mut frame, n)),
-                CallSelf(n) => try!(self.call_self(&mut frame, n)),
-                TailCall(n) => try!(self.tail_call(&mut frame, n)),
+                    try!(self.call_const(frame, n, n_args)),
+                Call(n) => try!(self.call_function(frame, n)),
+                Apply(n) => try!(self.apply(frame, n)),
+                CallSelf(n) => try!(self.call_self(frame, n)),
+                TailCall(n) => try!(self.tail_call(frame, n)),
+                TailCallConst(n, n_args) => {
+                    if !try!(self.tail_call_const(frame, n, n_args)) &&
+                            try!(self.return_frame(frame)) {
+                        return Ok(true);
+                    }
+                }
+                TailCallFunction(n) => {
+                    if !try!(self.tail_call_function(frame, n)) &&
+                            try!(self.return_frame(frame)) {
+                        return Ok(true);
+                    }
+                }
+                TailApply(n) => {
+                    if !try!(self.tail_apply(frame, n)) &&
+                            try!(self.return_frame(frame)) {
+                        return Ok(true);
+                    }
+                }
                 Skip(n) => try!(self.skip_stack(n as usize)),
                 Return => {
-                    match self.call_stack.pop() {
-                        None => break,
-                        Some(call) => {
-                            self.clean_stack(frame.sptr as usize);
-                            if frame.fn_on_stack {
-                                // Pop one more value for the function
-                                try!(self.pop());
-                            }
-                            frame = call;
-                        }
+                    if try!(self.return_frame(frame)) {
+                        return Ok(true);
                     }
                 }
             }
+
+            if reenters && !Rc::ptr_eq(&frame.scope, scope) {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn try_handler(&mut self, label: u32) {
+        self.handlers.push(Handler{
+            call_depth: self.call_stack.len(),
+            stack_len: self.stack.len(),
+            label: label,
+        });
+    }
+
+    /// Resumes execution at the innermost error handler, if there is one
+    /// and the error may be caught. Otherwise, returns the error.
+    fn catch_error(&mut self, frame: &mut StackFrame, err: Error) -> Result<(), Error> {
+        let catchable = match *err.root() {
+            Error::ExecError(ExecError::Interrupted) |
+            Error::ExecError(ExecError::InstructionLimit) |
+            Error::ExecError(ExecError::IntegerLimit) |
+            Error::ExecError(ExecError::ListLimit) |
+            Error::ExecError(ExecError::StringLimit) |
+            Error::ExecError(ExecError::NamespaceLimit) |
+            Error::ExecError(ExecError::CallStackOverflow) |
+            Error::ExecError(ExecError::Restricted(_)) |
+            Error::ExecError(ExecError::Panic(_)) => false,
+            _ => true
+        };
+
+        let handler = match self.handlers.pop() {
+            Some(h) if catchable => h,
+            _ => return Err(err)
+        };
+
+        if self.call_stack.len() > handler.call_depth {
+            let mut frames = self.call_stack.drain(handler.call_depth..);
+            *frame = frames.next().expect("missing handler frame");
         }
 
-        Ok(self.value.take())
+        self.clean_stack(handler.stack_len);
+        frame.iptr = handler.label;
+        self.value = error_value(&frame.scope, err);
+        Ok(())
     }
 
     fn build_closure(&mut self, code: &Code, n_const: u32, n_values: u32)
@@ -506,13 +839,49 @@ impl Machine {
         let values = try!(self.drain_stack_top(n_values))
             .collect::<Vec<_>>().into_boxed_slice();
 
-        self.value = Value::Lambda(Lambda::new_closure(code, scope, values));
+        self.value = Value::Lambda(Rc::new(Lambda::new_closure(code, scope, values)));
+        self.metrics.allocations += 1;
         Ok(())
     }
 
     fn build_list(&mut self, n: u32) -> Result<(), ExecError> {
         let v = try!(self.drain_stack_top(n)).collect::<Vec<_>>().into();
         self.value = v;
+        self.metrics.allocations += 1;
+        Ok(())
+    }
+
+    /// Pushes the elements of a list value onto the stack.
+    /// If `rest` is `true`, elements beyond the first `n` are pushed as a list.
+    fn unpack(&mut self, n: u32, rest: bool) -> Result<(), ExecError> {
+        let v = self.value.take();
+
+        let li = match v {
+            Value::Unit => Vec::new(),
+            Value::List(li) => li.into_vec(),
+            ref v => return Err(ExecError::expected("list", v))
+        };
+
+        let found = li.len() as u32;
+
+        if found < n || (!rest && found > n) {
+            return Err(ExecError::PatternMismatch{
+                expected: n,
+                rest: rest,
+                found: found,
+            });
+        }
+
+        let mut iter = li.into_iter();
+
+        for v in iter.by_ref().take(n as usize) {
+            try!(self.push(v));
+        }
+
+        if rest {
+            try!(self.push(iter.collect::<Vec<_>>().into()));
+        }
+
         Ok(())
     }
 
@@ -535,28 +904,35 @@ impl Machine {
     fn call_sys_args(&mut self, frame: &mut StackFrame, sys_fn: u32, n_args: u32)
             -> Result<(), Error> {
         let (name, sys_fn) = try!(self.get_sys_fn(sys_fn));
-        self.call_sys_fn(frame, name, sys_fn, n_args, false)
+        self.call_sys_fn(frame, name, sys_fn, n_args, false, false)
     }
 
+    /// Calls a system function; if `checked` is `true`, arity is assumed
+    /// to have been validated by a previous call.
     fn call_sys_fn(&mut self, frame: &mut StackFrame, name: Name,
-            sys_fn: &SystemFn, n_args: u32, fn_on_stack: bool)
+            sys_fn: &SystemFn, n_args: u32, fn_on_stack: bool, checked: bool)
             -> Result<(), Error> {
-        if !sys_fn.arity.accepts(n_args) {
+        if !checked && !sys_fn.arity.accepts(n_args) {
             Err(From::from(ExecError::ArityError{
                 name: Some(name),
                 expected: sys_fn.arity,
                 found: n_args,
             }))
         } else {
-                let mut args = try!(self.drain_stack_top(n_args))
-                    .collect::<Vec<_>>();
+                self.metrics.calls += 1;
+
+                let mut args = try!(self.take_args(n_args));
 
                 if fn_on_stack {
                     try!(self.pop());
                 }
 
+                self.publish_depth(&frame.scope);
                 let v = try!((sys_fn.callback)(&frame.scope, &mut args));
+                try!(frame.scope.get_restrictions().check_value(&v));
+                try!(self.charge_output(&frame.scope, &v));
                 self.value = v;
+                self.return_args(args);
 
                 Ok(())
         }
@@ -564,11 +940,34 @@ impl Machine {
 
     fn call_const(&mut self, frame: &mut StackFrame,
             n: u32, n_args: u32) -> Result<(), Error> {
-        let name = try!(get_const_name(&frame.code, n));
-        let v = try!(self.get_value(frame, name));
+        let (v, checked) = try!(self.get_const_fn(frame, n, n_args));
 
         self.value = Value::Unit;
-        self.call_value(frame, v, n_args, false)
+
+        if checked {
+            self.call_value(frame, v, n_args, false, true)
+        } else {
+            // A lambda call replaces `frame`; keep the caller's code
+            let code = frame.code.clone();
+            try!(self.call_value(frame, v, n_args, false, false));
+            code.calls.set_checked(n, n_args);
+            Ok(())
+        }
+    }
+
+    /// Returns the function named by const *n*, using the call cache,
+    /// and whether a call with `n_args` arguments was previously accepted.
+    fn get_const_fn(&mut self, frame: &StackFrame, n: u32, n_args: u32)
+            -> Result<(Value, bool), Error> {
+        match frame.code.calls.get(n, &frame.scope, n_args) {
+            Some(r) => Ok(r),
+            None => {
+                let name = try!(get_const_name(&frame.code, n));
+                let v = try!(self.get_value(frame, name));
+                frame.code.calls.insert(n, &frame.scope, &v);
+                Ok((v, false))
+            }
+        }
     }
 
     /// Calls a function on the stack with `n_args` arguments.
@@ -576,26 +975,33 @@ impl Machine {
     fn call_function(&mut self, frame: &mut StackFrame, n_args: u32)
             -> Result<(), Error> {
         let v = try!(self.get_stack_top(n_args)).clone();
-        self.call_value(frame, v, n_args, true)
+        self.call_value(frame, v, n_args, true, false)
     }
 
+    /// Calls a function value; if `checked` is `true`, arity is assumed
+    /// to have been validated by a previous call.
     fn call_value(&mut self, frame: &mut StackFrame, value: Value,
-            n_args: u32, fn_on_stack: bool) -> Result<(), Error> {
+            n_args: u32, fn_on_stack: bool, checked: bool) -> Result<(), Error> {
         match value {
-            Value::Function(fun) =>
-                self.call_sys_fn(frame, fun.name, &fun.sys_fn, n_args, fn_on_stack),
-            Value::Lambda(fun) =>
-                self.call_lambda(frame, fun, n_args, fn_on_stack),
+            Value::Function(ref fun) =>
+                self.call_sys_fn(frame, fun.name, &fun.sys_fn, n_args, fn_on_stack, checked),
+            Value::Lambda(ref fun) =>
+                self.call_lambda(frame, fun, n_args, fn_on_stack, checked),
             Value::Foreign(ref fv) => {
-                let mut args = try!(self.drain_stack_top(n_args))
-                    .collect::<Vec<_>>();
+                self.metrics.calls += 1;
+
+                let mut args = try!(self.take_args(n_args));
 
                 if fn_on_stack {
                     try!(self.pop());
                 }
 
+                self.publish_depth(&frame.scope);
                 let v = try!(fv.call_value(&frame.scope, &mut args));
+                try!(frame.scope.get_restrictions().check_value(&v));
+                try!(self.charge_output(&frame.scope, &v));
                 self.value = v;
+                self.return_args(args);
 
                 Ok(())
             }
@@ -603,27 +1009,80 @@ impl Machine {
         }
     }
 
-    fn call_lambda(&mut self, frame: &mut StackFrame, lambda: Lambda,
-            n_args: u32, fn_on_stack: bool) -> Result<(), Error> {
+    /// Charges instructions for a value returned by a function implemented
+    /// in Rust, first from this `Machine`'s fuel, then from the scope.
+    fn charge_output(&mut self, scope: &Scope, v: &Value) -> Result<(), ExecError> {
+        if scope.get_restrictions().max_instructions.is_none() {
+            return Ok(());
+        }
+
+        let cost = output_cost(v);
+
+        if cost <= self.fuel {
+            self.fuel -= cost;
+            Ok(())
+        } else {
+            let rest = cost - self.fuel;
+            self.fuel = 0;
+            scope.consume_instructions(rest)
+        }
+    }
+
+    /// Moves `n` values from the top of the stack into the argument buffer.
+    fn take_args(&mut self, n: u32) -> Result<Vec<Value>, ExecError> {
+        let mut args = replace(&mut self.args, Vec::new());
+        debug_assert!(args.is_empty());
+        args.extend(try!(self.drain_stack_top(n)));
+        Ok(args)
+    }
+
+    /// Returns the argument buffer for reuse.
+    fn return_args(&mut self, mut args: Vec<Value>) {
+        args.clear();
+        self.args = args;
+    }
+
+    fn call_lambda(&mut self, frame: &mut StackFrame, lambda: &Lambda,
+            n_args: u32, fn_on_stack: bool, checked: bool) -> Result<(), Error> {
         let scope = lambda.scope.upgrade()
             .expect("Lambda scope has been destroyed");
 
+        self.enter_code(frame, StackFrame{
+            code: lambda.code.clone(),
+            instrs: lambda.code.decoded(),
+            scope: scope,
+            values: lambda.values.clone(),
+            iptr: 0,
+            sptr: 0,
+            fn_on_stack: fn_on_stack,
+        }, n_args, checked)
+    }
+
+    /// Sets up a call and replaces the current frame with `new_frame`,
+    /// saving the current frame to the call stack.
+    ///
+    /// Closure values are shared with the callee through `Rc`;
+    /// the values themselves are never copied.
+    fn enter_code(&mut self, frame: &mut StackFrame, mut new_frame: StackFrame,
+            n_args: u32, checked: bool) -> Result<(), Error> {
         if self.stack.len() < n_args as usize {
             return Err(From::from(ExecError::StackOverflow));
         }
 
-        let n_args = try!(self.setup_call(&lambda.code, n_args));
+        self.metrics.calls += 1;
 
-        let old_frame = replace(frame, StackFrame{
-            code: lambda.code,
-            scope: scope,
-            values: lambda.values,
-            iptr: 0,
-            sptr: self.stack.len() as u32 - n_args,
-            fn_on_stack: fn_on_stack,
-        });
+        let n_args = try!(self.setup_call(&new_frame.scope, &new_frame.code,
+            n_args, checked));
+        self.reserve_stack(new_frame.code.max_stack.saturating_sub(n_args));
+
+        new_frame.sptr = self.stack.len() as u32 - n_args;
 
-        try!(self.save_frame(old_frame));
+        // Check depth before replacing the frame, so that an error
+        // is attributed to the calling instruction.
+        try!(self.check_call_depth());
+
+        let old_frame = replace(frame, new_frame);
+        self.call_stack.push(old_frame);
         Ok(())
     }
 
@@ -632,14 +1091,14 @@ impl Machine {
     /// Keyword and rest arguments are processed and `Unbound` values
     /// are pushed to the stack, if necessary.
     ///
+    /// If `checked` is `true`, the argument count is assumed to have been
+    /// validated by a previous call; keyword arguments are still validated.
+    ///
     /// Returns the final count of stack argument values.
-    fn setup_call(&mut self, code: &Code, mut n_args: u32) -> Result<u32, Error> {
-        if n_args < code.req_params {
-            return Err(From::from(ExecError::ArityError{
-                name: code.name,
-                expected: code.arity(),
-                found: n_args,
-            }));
+    fn setup_call(&mut self, scope: &Scope, code: &Code, mut n_args: u32,
+            checked: bool) -> Result<u32, Error> {
+        if !checked {
+            try!(check_arity(code, n_args));
         }
 
         if n_args < code.n_params {
@@ -656,8 +1115,10 @@ impl Machine {
                 try!(self.push(Value::Unit));
                 n_args += 1;
             };
-        } else if code.has_kw_params() {
-            let mut kw_values = vec![Value::Unbound; code.kw_params.len()];
+        } else if code.has_kw_params() || code.has_rest_keys() {
+            let mut kw_values = scope.get_arena().list();
+            kw_values.resize(code.kw_params.len(), Value::Unbound);
+            let mut rest_keys = Vec::new();
 
             if n_args > code.n_params {
                 let n_kw_args = n_args - code.n_params;
@@ -678,21 +1139,24 @@ impl Machine {
                                 return Err(From::from(ExecError::DuplicateKeyword(kw)));
                             }
                         }
+                        None if code.has_rest_keys() => {
+                            rest_keys.push(Value::Keyword(kw));
+                            rest_keys.push(v);
+                        }
                         None => return Err(From::from(ExecError::UnrecognizedKeyword(kw)))
                     }
                 }
             }
 
             n_args = code.n_params + kw_values.len() as u32;
-            for v in kw_values {
+            for v in kw_values.drain(..) {
                 try!(self.push(v));
             }
-        } else if n_args != code.n_params {
-            return Err(From::from(ExecError::ArityError{
-                name: code.name,
-                expected: code.arity(),
-                found: n_args,
-            }));
+
+            if code.has_rest_keys() {
+                try!(self.push(Value::from(rest_keys)));
+                n_args += 1;
+            }
         }
 
         Ok(n_args)
@@ -705,7 +1169,7 @@ impl Machine {
             Value::Unit => (),
             Value::List(li) => {
                 n_args += li.len() as u32;
-                try!(self.push_iter(li.to_vec()));
+                try!(self.push_iter(li.iter().cloned()));
             }
             ref v => return Err(From::from(ExecError::expected("list", v)))
         }
@@ -714,13 +1178,17 @@ impl Machine {
     }
 
     fn call_self(&mut self, frame: &mut StackFrame, n: u32) -> Result<(), Error> {
-        let lambda = Lambda{
+        let new_frame = StackFrame{
             code: frame.code.clone(),
-            scope: Rc::downgrade(&frame.scope),
+            instrs: frame.instrs.clone(),
+            scope: frame.scope.clone(),
             values: frame.values.clone(),
+            iptr: 0,
+            sptr: 0,
+            fn_on_stack: false,
         };
 
-        self.call_lambda(frame, lambda, n, false)
+        self.enter_code(frame, new_frame, n, false)
     }
 
     fn tail_call(&mut self, frame: &mut StackFrame, n_args: u32) -> Result<(), Error> {
@@ -735,12 +1203,114 @@ impl Machine {
 
         let _ = self.stack.drain(start..end);
         frame.iptr = 0;
+        self.metrics.calls += 1;
 
-        try!(self.setup_call(&frame.code, n_args));
+        try!(self.setup_call(&frame.scope, &frame.code, n_args, false));
 
         Ok(())
     }
 
+    fn tail_call_const(&mut self, frame: &mut StackFrame,
+            n: u32, n_args: u32) -> Result<bool, Error> {
+        let (v, checked) = try!(self.get_const_fn(frame, n, n_args));
+
+        self.value = Value::Unit;
+
+        if checked {
+            self.tail_call_value(frame, v, n_args, false, true)
+        } else {
+            // A lambda call replaces `frame`; keep the caller's code
+            let code = frame.code.clone();
+            let r = try!(self.tail_call_value(frame, v, n_args, false, false));
+            code.calls.set_checked(n, n_args);
+            Ok(r)
+        }
+    }
+
+    fn tail_call_function(&mut self, frame: &mut StackFrame, n_args: u32)
+            -> Result<bool, Error> {
+        let v = try!(self.get_stack_top(n_args)).clone();
+        self.tail_call_value(frame, v, n_args, true, false)
+    }
+
+    fn tail_apply(&mut self, frame: &mut StackFrame, mut n_args: u32)
+            -> Result<bool, Error> {
+        let v = self.value.take();
+
+        match v {
+            Value::Unit => (),
+            Value::List(li) => {
+                n_args += li.len() as u32;
+                try!(self.push_iter(li.iter().cloned()));
+            }
+            ref v => return Err(From::from(ExecError::expected("list", v)))
+        }
+
+        self.tail_call_function(frame, n_args)
+    }
+
+    /// Calls a value in tail position.
+    ///
+    /// If the value is a lambda, its code replaces the current frame,
+    /// which is removed from the stack, and `true` is returned.
+    /// Otherwise, the value is called normally and `false` is returned;
+    /// the caller must then return from the current frame.
+    ///
+    /// If `checked` is `true`, arity is assumed to have been validated
+    /// by a previous call.
+    fn tail_call_value(&mut self, frame: &mut StackFrame, value: Value,
+            n_args: u32, fn_on_stack: bool, checked: bool) -> Result<bool, Error> {
+        let lambda = match value {
+            Value::Lambda(lambda) => lambda,
+            v => {
+                try!(self.call_value(frame, v, n_args, fn_on_stack, checked));
+                return Ok(false);
+            }
+        };
+
+        let scope = lambda.scope.upgrade()
+            .expect("Lambda scope has been destroyed");
+
+        let len = self.stack.len();
+        let start = frame.sptr as usize;
+
+        if len < start + n_args as usize + fn_on_stack as usize {
+            return Err(From::from(ExecError::InvalidStack(len as u32)));
+        }
+
+        // Remove values of the current frame, including the function value
+        let _ = self.stack.drain(start..len - n_args as usize);
+        self.metrics.calls += 1;
+
+        let n_args = try!(self.setup_call(&scope, &lambda.code, n_args, checked));
+        self.reserve_stack(lambda.code.max_stack.saturating_sub(n_args));
+
+        frame.instrs = lambda.code.decoded();
+        frame.code = lambda.code.clone();
+        frame.scope = scope;
+        frame.values = lambda.values.clone();
+        frame.iptr = 0;
+
+        Ok(true)
+    }
+
+    /// Returns from the current frame to its caller.
+    /// Returns `true` if the current frame is the outermost frame.
+    fn return_frame(&mut self, frame: &mut StackFrame) -> Result<bool, ExecError> {
+        match self.call_stack.pop() {
+            None => Ok(true),
+            Some(call) => {
+                self.clean_stack(frame.sptr as usize);
+                if frame.fn_on_stack {
+                    // Pop one more value for the function
+                    try!(self.pop());
+                }
+                *frame = call;
+                Ok(false)
+            }
+        }
+    }
+
     /// Cleans the stack when returning from a function.
     /// All values `stack[pos..]` are removed.
     fn clean_stack(&mut self, pos: usize) {
@@ -760,13 +1330,13 @@ impl Machine {
     }
 
     /// Saves the current call state to the call stack.
-    fn save_frame(&mut self, frame: StackFrame) -> Result<(), ExecError> {
-        if self.call_stack.len() == self.call_stack.capacity() {
-            return Err(ExecError::StackOverflow);
+    /// Returns an error if another frame would exceed the maximum call depth.
+    fn check_call_depth(&self) -> Result<(), ExecError> {
+        if self.call_depth() >= self.max_call_depth {
+            Err(ExecError::CallStackOverflow)
+        } else {
+            Ok(())
         }
-
-        self.call_stack.push(frame);
-        Ok(())
     }
 
     /// Load a value from the stack.
@@ -839,9 +1409,10 @@ impl Machine {
     }
 
     /// Load a value from the global scope named by a const value.
-    fn get_def(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+    fn get_def(&mut self, frame: &StackFrame, globals: &NameMap<Value>, n: u32)
+            -> Result<(), ExecError> {
         let name = try!(get_const_name(&frame.code, n));
-        self.value = try!(self.get_value(frame, name));
+        self.value = try!(get_global(globals, name));
 
         Ok(())
     }
@@ -853,9 +1424,10 @@ impl Machine {
             .ok_or(ExecError::NameError(name))
     }
 
-    fn get_def_push(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+    fn get_def_push(&mut self, frame: &StackFrame, globals: &NameMap<Value>, n: u32)
+            -> Result<(), ExecError> {
         let name = try!(get_const_name(&frame.code, n));
-        let v = try!(self.get_value(frame, name));
+        let v = try!(get_global(globals, name));
         self.push(v)
     }
 
@@ -866,6 +1438,11 @@ impl Machine {
             return Err(ExecError::CannotDefine(name));
         }
 
+        if !frame.scope.contains_value(name) {
+            let n = frame.scope.with_values(|v| v.len());
+            try!(frame.scope.get_restrictions().check_namespace_size(n + 1));
+        }
+
         // Resulting value is the definition name
         let v = replace(&mut self.value, Value::Name(name));
 
@@ -883,28 +1460,45 @@ impl Machine {
     /// # Note
     ///
     /// If `ExactSizeIterator::len` misrepresents the number of items in the
-    /// iterator, the stack's capacity may be exceeded without causing an error.
+    /// iterator, the stack limit may be exceeded without causing an error.
     fn push_iter<I>(&mut self, iter: I) -> Result<(), ExecError>
             where I: IntoIterator<Item=Value>, I::IntoIter: ExactSizeIterator {
         let iter = iter.into_iter();
 
-        if self.stack.len() + iter.len() > self.stack.capacity() {
+        if self.stack.len() + iter.len() > MAX_STACK {
             return Err(ExecError::StackOverflow);
         }
 
         self.stack.extend(iter);
+        self.update_peak_stack();
         Ok(())
     }
 
+    /// Reserves space for `n` additional values on the stack,
+    /// up to the stack limit.
+    fn reserve_stack(&mut self, n: u32) {
+        let n = min(n as usize, MAX_STACK.saturating_sub(self.stack.len()));
+        self.stack.reserve(n);
+    }
+
     fn push(&mut self, v: Value) -> Result<(), ExecError> {
-        if self.stack.len() == self.stack.capacity() {
+        if self.stack.len() >= MAX_STACK {
             Err(ExecError::StackOverflow)
         } else {
             self.stack.push(v);
+            self.update_peak_stack();
             Ok(())
         }
     }
 
+    fn update_peak_stack(&mut self) {
+        let n = self.stack.len() as u32;
+
+        if n > self.metrics.peak_stack {
+            self.metrics.peak_stack = n;
+        }
+    }
+
     fn push_const(&mut self, code: &Code, n: u32) -> Result<(), ExecError> {
         self.push(try!(get_const(code, n)).clone())
     }
@@ -1129,13 +1723,16 @@ impl Machine {
         Ok(())
     }
 
-    fn append_value(&mut self) -> Result<(), ExecError> {
+    fn append_value(&mut self, frame: &StackFrame) -> Result<(), ExecError> {
         let mut li = try!(self.pop());
         let v = self.value.take();
 
         match li {
             Value::Unit => li = vec![v].into(),
-            Value::List(ref mut li) => li.push(v),
+            Value::List(ref mut li) => {
+                try!(frame.scope.get_restrictions().check_list_len(li.len() + 1));
+                li.push(v)
+            }
             ref v => return Err(ExecError::expected("list", v))
         }
 
@@ -1235,9 +1832,77 @@ fn get_keyword(v: &Value) -> Result<Name, ExecError> {
     }
 }
 
+/// Returns whether an instruction may modify the executing scope,
+/// execute other code, or pass control to another frame.
+fn may_reenter(instr: Instruction) -> bool {
+    use bytecode::Instruction::*;
+
+    match instr {
+        SetDef(_) |
+        CallSys(_) | CallSysArgs(..) | CallSysPush(_) | CallSysArgsPush(..) |
+        CallConst(..) | Call(_) | Apply(_) | CallSelf(_) |
+        TailCall(_) | TailCallConst(..) | TailCallFunction(_) | TailApply(_) |
+        Return => true,
+        _ => false
+    }
+}
+
+/// Returns the global values of the scope of the executing frame,
+/// borrowing them if they are not already borrowed.
+fn borrow_globals<'a, 's>(globals: &'a mut Option<Ref<'s, NameMap<Value>>>,
+        frame: &StackFrame, scope: &'s Scope) -> &'a NameMap<Value> {
+    debug_assert!(Rc::ptr_eq(&frame.scope, scope),
+        "global values borrowed from a scope other than that of the executing frame");
+    globals.get_or_insert_with(|| scope.borrow_values())
+}
+
+/// Returns a named value from master scope or the given global values.
+fn get_global(globals: &NameMap<Value>, name: Name) -> Result<Value, ExecError> {
+    match MasterScope::get(name) {
+        Some(v) => Ok(v),
+        None => globals.get(name).cloned().ok_or(ExecError::NameError(name))
+    }
+}
+
+/// Returns an error if `n_args` positional arguments are not accepted
+/// by a call to `code`. Keyword arguments are validated by `setup_call`.
+fn check_arity(code: &Code, n_args: u32) -> Result<(), ExecError> {
+    let too_many = n_args > code.n_params && !code.has_rest_params() &&
+        !code.has_kw_params() && !code.has_rest_keys();
+
+    if n_args < code.req_params || too_many {
+        Err(ExecError::ArityError{
+            name: code.name,
+            expected: code.arity(),
+            found: n_args,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 fn get_const_name(code: &Code, n: u32) -> Result<Name, ExecError> {
     match *try!(get_const(code, n)) {
         Value::Name(name) => Ok(name),
         ref v => Err(ExecError::expected("name", v))
     }
 }
+
+/// Returns the value bound to the name in a `catch` clause.
+///
+/// A value given to `raise` is returned as is. Any other error is described
+/// by a map containing its identifier, `:code`, and its message, `:message`.
+fn error_value(scope: &Scope, err: Error) -> Value {
+    if let Error::ExecError(ExecError::Raised(ref v)) = *err.root() {
+        return v.clone();
+    }
+
+    let message = display_names(&scope.borrow_names(), &err).to_string();
+
+    let mut map = Map::new();
+    map.insert(Value::Keyword(standard_names::CODE), err.code().into())
+        .expect("invalid map key");
+    map.insert(Value::Keyword(standard_names::MESSAGE), message.into())
+        .expect("invalid map key");
+    map.into()
+}
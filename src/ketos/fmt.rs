@@ -0,0 +1,498 @@
+//! Formats Ketos source code with a canonical layout.
+//!
+//! Source is read as a concrete syntax tree, rather than values, so that
+//! comments and the literal text of each token are preserved.
+
+use std::mem::replace;
+
+use cst::{parse, SyntaxNode, Trivia, TriviaKind};
+use parser::ParseError;
+
+/// Options controlling the layout of formatted source code.
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    /// Maximum width of a line; expressions which do not fit within this
+    /// width are broken across multiple lines.
+    pub width: usize,
+    /// Number of spaces by which the body of a form is indented
+    pub indent: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions{
+            width: 80,
+            indent: 2,
+        }
+    }
+}
+
+/// Formats Ketos source code using the given options.
+///
+/// Comments are preserved. A comment which follows an expression on the same
+/// line remains on that line; any other comment is placed on its own line.
+/// A single blank line is preserved wherever one or more blank lines appear
+/// between expressions.
+pub fn format_source(input: &str, options: &FormatOptions) -> Result<String, ParseError> {
+    let tree = try!(parse(input));
+
+    let mut items = Items::new();
+
+    for node in &tree.nodes {
+        items.add_node(node);
+    }
+    items.add_trivia(&tree.trailing);
+
+    let mut printer = Printer{
+        out: String::with_capacity(input.len()),
+        options: options,
+        col: 0,
+        heredoc: false,
+    };
+
+    let leading = tree.nodes.first().map_or(&tree.trailing[..], |node| node.leading());
+
+    if let Some(t) = leading.first() {
+        if t.kind == TriviaKind::Shebang {
+            printer.push_str(t.text.trim_right());
+            printer.newline(0, false);
+        }
+    }
+
+    printer.write_top_level(&items.items);
+
+    Ok(printer.out)
+}
+
+/// Number of arguments to a form which are written on the same line as the
+/// form's name; remaining arguments are written as an indented body.
+fn special_form_args(name: &str) -> Option<usize> {
+    match name {
+        "do" | "cond" => Some(0),
+        "case" | "const" | "define" | "if" | "lambda" | "let" |
+        "macro" | "struct" | "try" | "use" => Some(1),
+        _ => None
+    }
+}
+
+enum Node<'a> {
+    /// Literal value or name
+    Atom(&'a str),
+    /// Comment, including leading `;` or enclosing `#|` and `|#`
+    Comment(&'a str),
+    /// List of items and its enclosing delimiters, e.g. `(` and `)`
+    List(Vec<Item<'a>>, &'a str, &'a str),
+    /// Value preceded by quote, comma, or datum label tokens
+    Prefix(String, Box<Node<'a>>),
+}
+
+struct Item<'a> {
+    node: Node<'a>,
+    /// Whether one or more blank lines preceded the item
+    blank_before: bool,
+    /// Whether the item began on the same line as the preceding item
+    same_line: bool,
+}
+
+impl<'a> Item<'a> {
+    fn is_comment(&self) -> bool {
+        match self.node {
+            Node::Comment(_) => true,
+            _ => false
+        }
+    }
+
+    fn is_line_comment(&self) -> bool {
+        match self.node {
+            Node::Comment(s) => s.starts_with(';'),
+            _ => false
+        }
+    }
+}
+
+/// Collects the items of a list, or of the top level, from syntax tree nodes,
+/// along with the comments and line breaks found in their leading trivia.
+struct Items<'a> {
+    items: Vec<Item<'a>>,
+    /// Number of newlines since the last item
+    lines: usize,
+}
+
+impl<'a> Items<'a> {
+    fn new() -> Items<'a> {
+        Items{
+            items: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    fn push(&mut self, node: Node<'a>) {
+        let lines = replace(&mut self.lines, 0);
+        let blank_before = lines > 1 && !self.items.is_empty();
+
+        self.items.push(Item{
+            node: node,
+            blank_before: blank_before,
+            same_line: lines == 0,
+        });
+    }
+
+    fn add_trivia(&mut self, trivia: &[Trivia<'a>]) {
+        for t in trivia {
+            match t.kind {
+                TriviaKind::Whitespace =>
+                    self.lines += t.text.chars().filter(|&ch| ch == '\n').count(),
+                TriviaKind::Comment => self.push(Node::Comment(t.text)),
+                TriviaKind::DocComment => {
+                    for (i, line) in t.text.lines().enumerate() {
+                        if i != 0 {
+                            self.lines = 1;
+                        }
+                        self.push(Node::Comment(line.trim()));
+                    }
+                    // Doc comments include the final newline
+                    if t.text.ends_with('\n') {
+                        self.lines = 1;
+                    }
+                }
+                TriviaKind::Shebang => ()
+            }
+        }
+    }
+
+    fn add_node(&mut self, node: &SyntaxNode<'a>) {
+        self.add_trivia(node.leading());
+        let node = self.convert(node);
+        self.push(node);
+    }
+
+    fn convert(&mut self, node: &SyntaxNode<'a>) -> Node<'a> {
+        match *node {
+            SyntaxNode::Atom(ref tok) => Node::Atom(tok.text),
+            SyntaxNode::List{ref open, ref items, ref close} => {
+                let mut inner = Items::new();
+
+                for item in items {
+                    inner.add_node(item);
+                }
+                inner.add_trivia(&close.leading);
+
+                Node::List(inner.items, open.text, close.text)
+            }
+            SyntaxNode::Prefix{ref prefix, ref node} => {
+                let lines = self.lines;
+                let mut text = prefix.text.to_owned();
+                let mut node = &**node;
+
+                loop {
+                    // Comments between a prefix and its expression
+                    // are written before the prefixed expression
+                    self.add_trivia(node.leading());
+                    self.lines = lines;
+
+                    match *node {
+                        SyntaxNode::Prefix{ref prefix, node: ref inner} => {
+                            text.push_str(prefix.text);
+                            node = inner;
+                        }
+                        _ => return Node::Prefix(text, Box::new(self.convert(node)))
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Printer<'o> {
+    out: String,
+    options: &'o FormatOptions,
+    col: usize,
+    /// Whether the last token written was a heredoc string, whose closing
+    /// delimiter must be followed by the end of the line
+    heredoc: bool,
+}
+
+impl<'o> Printer<'o> {
+    fn push_str(&mut self, s: &str) {
+        self.out.push_str(s);
+
+        match s.rfind('\n') {
+            Some(pos) => self.col = s[pos + 1..].chars().count(),
+            None => self.col += s.chars().count()
+        }
+    }
+
+    fn newline(&mut self, col: usize, blank: bool) {
+        self.heredoc = false;
+        self.out.push('\n');
+        if blank {
+            self.out.push('\n');
+        }
+        for _ in 0..col {
+            self.out.push(' ');
+        }
+        self.col = col;
+    }
+
+    fn write_top_level(&mut self, items: &[Item]) {
+        let mut first = true;
+
+        for item in items {
+            if item.same_line && item.is_comment() && !first {
+                self.push_str(" ");
+            } else if !first {
+                self.newline(0, item.blank_before);
+            }
+
+            first = false;
+            self.write_node(&item.node);
+        }
+
+        if !first {
+            self.out.push('\n');
+        }
+    }
+
+    fn write_node(&mut self, node: &Node) {
+        match *node {
+            Node::Atom(s) => {
+                self.push_str(s);
+                self.heredoc = is_heredoc(s);
+            }
+            Node::Comment(s) => self.push_str(s),
+            Node::Prefix(ref p, ref node) => {
+                self.push_str(p);
+                self.write_node(node);
+            }
+            Node::List(ref items, open, close) => {
+                if let Some(s) = flat_list(items, open, close) {
+                    if self.col + s.chars().count() <= self.options.width {
+                        self.push_str(&s);
+                        return;
+                    }
+                }
+
+                self.write_list(items, open, close);
+            }
+        }
+    }
+
+    fn write_list(&mut self, items: &[Item], open: &str, close: &str) {
+        let open_col = self.col;
+        self.push_str(open);
+
+        // Only parenthesized lists are function calls or special forms
+        let head = match items.first() {
+            Some(&Item{node: Node::Atom(name), ..}) if open == "(" => Some(name),
+            _ => None
+        };
+
+        let body_col = open_col + self.options.indent;
+
+        match head {
+            Some(name) => {
+                self.push_str(name);
+
+                let rest = &items[1..];
+
+                match special_form_args(name) {
+                    Some(n) => {
+                        let n = leading_args(rest, n);
+                        let args_col = self.col + 1;
+
+                        self.write_items(&rest[..n], args_col, true);
+                        self.write_items(&rest[n..], body_col, false);
+                    }
+                    None => {
+                        let args_col = self.col + 1;
+                        let fits = rest.first().map_or(true, |item| {
+                            is_heredoc_node(&item.node) ||
+                                !item.is_comment() && flat_item(item).map_or(false,
+                                    |s| args_col + s.chars().count() <= self.options.width)
+                        });
+
+                        if fits {
+                            self.write_items(rest, args_col, true);
+                        } else {
+                            self.write_items(rest, body_col, false);
+                        }
+                    }
+                }
+            }
+            None => {
+                let col = open_col + 1;
+                self.write_items(items, col, true);
+            }
+        }
+
+        if self.heredoc || items.last().map_or(false, |item| item.is_line_comment()) {
+            self.newline(open_col, false);
+        }
+
+        self.push_str(close);
+    }
+
+    /// Writes each item on its own line at the given column.
+    /// If `inline` is `true`, the first item is written on the current line.
+    fn write_items(&mut self, items: &[Item], col: usize, inline: bool) {
+        let mut inline = inline;
+        let mut after_comment = false;
+
+        for item in items {
+            if self.heredoc {
+                self.newline(col, item.blank_before);
+            } else if item.same_line && item.is_comment() && !after_comment {
+                self.push_str(" ");
+            } else if inline && !after_comment {
+                if !self.out.ends_with(|ch| ch == '(' || ch == '[' || ch == '{') {
+                    self.push_str(" ");
+                }
+            } else {
+                self.newline(col, item.blank_before);
+            }
+
+            inline = false;
+            after_comment = item.is_line_comment();
+            self.write_node(&item.node);
+        }
+    }
+}
+
+/// Returns the number of items which may be written on the same line
+/// as a form name, up to `n`. Comments end the leading arguments.
+fn leading_args(items: &[Item], n: usize) -> usize {
+    items.iter().take(n).take_while(|item| !item.is_comment()).count()
+}
+
+/// Returns the single-line representation of a list,
+/// if it contains no comments.
+fn flat_list(items: &[Item], open: &str, close: &str) -> Option<String> {
+    let mut s = String::from(open);
+
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            s.push(' ');
+        }
+        match flat_item(item) {
+            Some(item) => s.push_str(&item),
+            None => return None
+        }
+    }
+
+    s.push_str(close);
+    Some(s)
+}
+
+fn flat_item(item: &Item) -> Option<String> {
+    flat_node(&item.node)
+}
+
+fn flat_node(node: &Node) -> Option<String> {
+    match *node {
+        Node::Atom(s) if is_heredoc(s) => None,
+        Node::Atom(s) => Some(s.to_owned()),
+        Node::Comment(_) => None,
+        Node::List(ref items, open, close) => flat_list(items, open, close),
+        Node::Prefix(ref p, ref node) => flat_node(node).map(|s| format!("{}{}", p, s)),
+    }
+}
+
+/// Returns whether a token is a heredoc string, which spans several lines
+/// and ends with a delimiter on a line of its own.
+fn is_heredoc(s: &str) -> bool {
+    s.starts_with("#<<")
+}
+
+fn is_heredoc_node(node: &Node) -> bool {
+    match *node {
+        Node::Atom(s) => is_heredoc(s),
+        Node::Prefix(_, ref node) => is_heredoc_node(node),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_source, FormatOptions};
+    use lexer::Lexer;
+    use name::NameStore;
+    use parser::Parser;
+
+    fn format(s: &str, width: usize) -> String {
+        format_source(s, &FormatOptions{width: width, indent: 2}).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("(define  (foo a)\n(+ a   1))", 80),
+            "(define (foo a) (+ a 1))\n");
+        assert_eq!(format("(define (foo a)\n(bar a 'b `(c ,d)))", 20),
+            "(define (foo a)\n  (bar a 'b `(c ,d)))\n");
+        assert_eq!(format("(if (< a b) a-is-less b-is-less)", 20),
+            "(if (< a b)\n  a-is-less\n  b-is-less)\n");
+        assert_eq!(format("(foo alpha beta gamma)", 16),
+            "(foo alpha\n     beta\n     gamma)\n");
+        assert_eq!(format("(foo [ 1 2 ] {:a  1})", 80),
+            "(foo [1 2] {:a 1})\n");
+        assert_eq!(format("[alpha beta gamma]", 12),
+            "[alpha\n beta\n gamma]\n");
+        assert_eq!(format("(foo #0= (a b) #0#)", 80),
+            "(foo #0=(a b) #0#)\n");
+        assert_eq!(format("(let ((a 1) (b 2)) (+ a b))", 16),
+            "(let ((a 1)\n      (b 2))\n  (+ a b))\n");
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(format("; head\n\n\n(foo) ; trailing\n;; doc\n(bar)", 80),
+            "; head\n\n(foo) ; trailing\n;; doc\n(bar)\n");
+        assert_eq!(format("(do (foo) ; note\n (bar) #| block |#)", 80),
+            "(do\n  (foo) ; note\n  (bar) #| block |#)\n");
+        assert_eq!(format("(foo\n; own line\nbar)", 80),
+            "(foo\n  ; own line\n  bar)\n");
+        assert_eq!(format("#!/usr/bin/env ketos\n\n(foo   a)", 80),
+            "#!/usr/bin/env ketos\n(foo a)\n");
+        assert_eq!(format("(foo ' ; note\n  bar)", 80),
+            "(foo ; note\n  'bar)\n");
+    }
+
+    #[test]
+    fn test_heredoc() {
+        assert_eq!(format("(foo #<<END\nsome text\nEND\n)", 80),
+            "(foo #<<END\nsome text\nEND\n)\n");
+        assert_eq!(format("(foo #<<END\na\nEND\n bar)", 80),
+            "(foo #<<END\na\nEND\n     bar)\n");
+        assert_eq!(format("(foo '#<<END\na\nEND\n)", 80),
+            "(foo '#<<END\na\nEND\n)\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for src in &[
+            "(foo #<<END\nsome text\nEND\n)",
+            "(define (foo a)\n  (bar #<<X\n  (not code)\nX\n  a) ; done\n  [a b])",
+            "(let ((a #<<A\nx\nA\n)) `(a ,a {:b 1}))",
+            "(foo ; note\n  'bar #| block |# \"baz\")",
+        ] {
+            let out = format(src, 20);
+
+            let mut names = NameStore::new();
+            let a = Parser::new(&mut names, Lexer::new(src, 0))
+                .parse_exprs().unwrap();
+            let b = Parser::new(&mut names, Lexer::new(&out, 0))
+                .parse_exprs().unwrap();
+
+            assert_eq!(a.len(), b.len(), "{:?} => {:?}", src, out);
+            for (a, b) in a.iter().zip(&b) {
+                assert!(a.is_identical(b), "{:?} => {:?}", src, out);
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(format_source("(foo", &FormatOptions::default()).is_err());
+        assert!(format_source("foo)", &FormatOptions::default()).is_err());
+        assert!(format_source("(foo]", &FormatOptions::default()).is_err());
+    }
+}
@@ -4,17 +4,23 @@ use std::borrow::Cow::{self, Borrowed, Owned};
 use std::cmp::{min, Ordering};
 use std::f64;
 use std::fmt;
+use std::io;
+use std::path::Path;
 use std::rc::Rc;
 
 use num::{Float, Zero};
 
 use bytecode::Code;
+use compile::{compile, macroexpand, macroexpand_1};
 use error::Error;
-use exec::ExecError;
+use exec::{execute, ExecError};
 use integer::{Integer, Ratio};
-use name::{Name, NameMap, NUM_SYSTEM_FNS};
-use scope::{Scope, WeakScope};
-use string_fmt::format_string;
+use map::{Map, Set};
+use io::{IoError, IoMode, Port, SharedRead, SharedWrite};
+use lexer::Lexer;
+use name::{standard_names, Name, NameMap, NUM_SYSTEM_FNS};
+use parser::Parser;
+use scope::{FlushPolicy, Scope, WeakScope};
 use value::{FromValueRef, Struct, StructDef, Value};
 
 use self::Arity::*;
@@ -26,6 +32,10 @@ pub struct SystemFn {
     pub arity: Arity,
     /// Function implementation
     pub callback: FunctionImpl,
+    /// Human-readable parameter signature, e.g. `(foo a b)`
+    pub signature: Option<&'static str>,
+    /// Documentation string
+    pub doc: Option<&'static str>,
 }
 
 impl Clone for SystemFn {
@@ -43,7 +53,7 @@ pub type FunctionImpl = fn(&Scope, &mut [Value]) -> Result<Value, Error>;
 
 macro_rules! sys_fn {
     ( $callback:path, $arity:expr ) => {
-        SystemFn{arity: $arity, callback: $callback}
+        SystemFn{arity: $arity, callback: $callback, signature: None, doc: None}
     };
 }
 
@@ -110,9 +120,33 @@ pub static SYSTEM_FNS: [SystemFn; NUM_SYSTEM_FNS] = [
     sys_fn!(fn_format,      Min(1)),
     sys_fn!(fn_print,       Min(1)),
     sys_fn!(fn_println,     Min(1)),
+    sys_fn!(fn_eprint,      Min(1)),
+    sys_fn!(fn_eprintln,    Min(1)),
+    sys_fn!(fn_read_line,   Range(0, 1)),
+    sys_fn!(fn_read_char,   Range(0, 1)),
+    sys_fn!(fn_flush,       Range(0, 1)),
+    sys_fn!(fn_close,       Exact(1)),
     sys_fn!(fn_panic,       Range(0, 1)),
     sys_fn!(fn_xor,         Exact(2)),
     sys_fn!(fn_not,         Exact(1)),
+    sys_fn!(fn_loaded_modules, Exact(0)),
+    sys_fn!(fn_hash_map,    Min(0)),
+    sys_fn!(fn_get,         Range(2, 3)),
+    sys_fn!(fn_insert,      Min(2)),
+    sys_fn!(fn_remove,      Min(1)),
+    sys_fn!(fn_keys,        Exact(1)),
+    sys_fn!(fn_values,      Exact(1)),
+    sys_fn!(fn_contains,    Exact(2)),
+    sys_fn!(fn_set,         Min(0)),
+    sys_fn!(fn_union,       Min(1)),
+    sys_fn!(fn_intersection, Min(1)),
+    sys_fn!(fn_difference,  Min(1)),
+    sys_fn!(fn_is_subset,   Exact(2)),
+    sys_fn!(fn_raise,       Exact(1)),
+    sys_fn!(fn_read,        Exact(1)),
+    sys_fn!(fn_eval,        Exact(1)),
+    sys_fn!(fn_macroexpand, Exact(1)),
+    sys_fn!(fn_macroexpand_1, Exact(1)),
 ];
 
 /// Describes the number of arguments a function may accept.
@@ -443,16 +477,57 @@ pub fn mul_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
 }
 
 /// `^` returns a base value raised to an exponent.
-fn fn_pow(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_pow(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let a = args[0].take();
     let b = args[1].take();
 
     try!(expect_number(&a));
     try!(expect_number(&b));
+    try!(check_pow_limit(scope, &a, &b));
 
     pow_number(a, b)
 }
 
+/// Returns an error if raising `lhs` to the power `rhs` would produce
+/// an integer exceeding the scope's size limit.
+///
+/// The check is performed before the result is computed, so that the
+/// limit may prevent a large allocation.
+fn check_pow_limit(scope: &Scope, lhs: &Value, rhs: &Value) -> Result<(), ExecError> {
+    let restrict = scope.get_restrictions();
+
+    if restrict.max_integer_bits.is_none() {
+        return Ok(());
+    }
+
+    let bits = match *lhs {
+        Value::Integer(ref a) => a.bits(),
+        Value::Ratio(ref a) => a.numer().bits().max(a.denom().bits()),
+        _ => return Ok(())
+    };
+
+    let exp = match (lhs, rhs) {
+        (&Value::Integer(_), &Value::Integer(ref b)) if !b.is_negative() => b.clone(),
+        (&Value::Ratio(_), &Value::Integer(ref b)) => b.abs(),
+        (&Value::Ratio(_), &Value::Ratio(ref b)) if b.is_integer() => b.numer().abs(),
+        _ => return Ok(())
+    };
+
+    if bits <= 1 {
+        return Ok(());
+    }
+
+    // The result requires at least `(bits - 1) * exp + 1` bits
+    let n = exp.to_usize()
+        .and_then(|exp| (bits - 1).checked_mul(exp))
+        .and_then(|n| n.checked_add(1));
+
+    match n {
+        Some(n) => restrict.check_integer_bits(n),
+        None => Err(ExecError::IntegerLimit)
+    }
+}
+
 fn pow_number(lhs: Value, rhs: Value) -> Result<Value, Error> {
     match (&lhs, &rhs) {
         (&Value::Ratio(ref a), &Value::Integer(ref b)) =>
@@ -610,10 +685,19 @@ fn rem_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
 }
 
 /// `<<` returns an integer, bit shifted left by a given number.
-fn fn_shl(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_shl(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let a = &args[0];
     let b = &args[1];
 
+    if let (&Value::Integer(ref a), &Value::Integer(ref b)) = (a, b) {
+        if let Some(n) = b.to_usize() {
+            if !a.is_zero() {
+                try!(scope.get_restrictions().check_integer_bits(
+                    a.bits().saturating_add(n)));
+            }
+        }
+    }
+
     shl_integer(a, b)
 }
 
@@ -882,6 +966,8 @@ fn type_of(scope: &Scope, v: &Value) -> Name {
         Value::Char(_) => CHAR,
         Value::String(_) => STRING,
         Value::List(_) => LIST,
+        Value::Map(_) => MAP,
+        Value::Set(_) => SET,
         Value::Function(_) => FUNCTION,
         Value::Lambda(_) => LAMBDA,
         Value::Quasiquote(_, _) |
@@ -1028,41 +1114,218 @@ fn fn_new(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     Ok(Value::Struct(Rc::new(Struct::new(def, fields.into_slice()))))
 }
 
+/// Formats arguments to `format` and the printing functions into a buffer.
+///
+/// The format string is parsed once and held in the scope's `FormatCache`.
+fn format_args(scope: &Scope, args: &[Value], buf: &mut String) -> Result<(), Error> {
+    let fmt = match args.first() {
+        Some(&Value::String(ref s)) => scope.get_format_cache().get(s),
+        Some(v) => return Err(From::from(ExecError::expected("string", v))),
+        None => return Err(From::from(ExecError::ArityError{
+            name: None,
+            expected: Min(2),
+            found: 1,
+        }))
+    };
+
+    try!(fmt.format_to_with(buf, &scope.borrow_names(),
+        Some(scope.get_format_directives()), &args[1..]));
+    Ok(())
+}
+
+/// Separates an optional output stream, either a `Port` or a stream named
+/// by a keyword such as `:stderr`, from the remaining arguments to `format`,
+/// `print`, or `println`.
+fn split_stream<'a>(scope: &Scope, args: &'a [Value])
+        -> Result<(Option<Rc<SharedWrite>>, &'a [Value]), Error> {
+    let name = match args[0] {
+        Value::Keyword(name) => name,
+        Value::Foreign(ref fv) => match fv.downcast_ref::<Port>() {
+            Some(port) => return Ok((Some(try!(port.writer())), &args[1..])),
+            None => return Ok((None, args))
+        },
+        _ => return Ok((None, args))
+    };
+
+    let io = scope.get_io();
+    let stream = scope.with_name(name, |name| match name {
+        "stdout" => Some(io.stdout.clone()),
+        "stderr" => Some(io.stderr.clone()),
+        _ => None
+    });
+
+    match stream {
+        Some(stream) => Ok((Some(stream), &args[1..])),
+        None => Err(From::from(ExecError::expected("stream", &args[0])))
+    }
+}
+
+/// Writes output of `format`, `print`, or `println` to a stream,
+/// flushing the stream according to the scope's `FlushPolicy`.
+fn write_stream(scope: &Scope, stream: &SharedWrite, s: &str) -> Result<(), Error> {
+    try!(stream.write_all(s.as_bytes()));
+
+    let flush = match scope.get_io().flush {
+        FlushPolicy::Always => true,
+        FlushPolicy::Line => s.contains('\n'),
+        FlushPolicy::Manual => false,
+    };
+
+    if flush {
+        try!(stream.flush());
+    }
+    Ok(())
+}
+
 /// `format` returns a formatted string.
+/// If an output stream is given, the string is instead written to the stream.
 fn fn_format(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
-    let fmt = try!(get_string(&args[0]));
-
-    let s = try!(format_string(&scope.borrow_names(), fmt, &args[1..]));
-    Ok(s.into())
+    let (stream, args) = try!(split_stream(scope, args));
+
+    match stream {
+        Some(stream) => {
+            let mut s = scope.get_arena().string();
+            try!(format_args(scope, args, &mut s));
+            try!(write_stream(scope, &*stream, &s));
+            Ok(Value::Unit)
+        }
+        None => {
+            let mut s = String::new();
+            try!(format_args(scope, args, &mut s));
+            Ok(try!(Value::new_string(s)))
+        }
+    }
 }
 
-/// `print` prints a formatted string to `stdout`.
+/// `print` prints a formatted string to `stdout` or a given output stream.
 fn fn_print(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
-    let fmt = try!(get_string(&args[0]));
+    let (stream, args) = try!(split_stream(scope, args));
 
-    let s = try!(format_string(&scope.borrow_names(), fmt, &args[1..]));
+    let mut s = scope.get_arena().string();
+    try!(format_args(scope, args, &mut s));
 
-    try!(scope.get_io().stdout.write_all(s.as_bytes()));
-    try!(scope.get_io().stdout.flush());
+    let stream = stream.unwrap_or_else(|| scope.get_io().stdout.clone());
+    try!(write_stream(scope, &*stream, &s));
 
     Ok(Value::Unit)
 }
 
-/// `println` prints a formatted string to `stdout`, followed by a newline.
+/// `println` prints a formatted string to `stdout` or a given output stream,
+/// followed by a newline.
 fn fn_println(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
-    let fmt = try!(get_string(&args[0]));
+    let (stream, args) = try!(split_stream(scope, args));
 
-    let mut s = try!(format_string(&scope.borrow_names(), fmt, &args[1..]));
+    let mut s = scope.get_arena().string();
+    try!(format_args(scope, args, &mut s));
     if !s.ends_with('\n') {
         s.push('\n');
     }
 
-    try!(scope.get_io().stdout.write_all(s.as_bytes()));
-    try!(scope.get_io().stdout.flush());
+    let stream = stream.unwrap_or_else(|| scope.get_io().stdout.clone());
+    try!(write_stream(scope, &*stream, &s));
 
     Ok(Value::Unit)
 }
 
+/// `eprint` prints a formatted string to `stderr`.
+fn fn_eprint(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut s = scope.get_arena().string();
+    try!(format_args(scope, args, &mut s));
+
+    try!(write_stream(scope, &*scope.get_io().stderr, &s));
+
+    Ok(Value::Unit)
+}
+
+/// `eprintln` prints a formatted string to `stderr`, followed by a newline.
+fn fn_eprintln(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut s = scope.get_arena().string();
+    try!(format_args(scope, args, &mut s));
+    if !s.ends_with('\n') {
+        s.push('\n');
+    }
+
+    try!(write_stream(scope, &*scope.get_io().stderr, &s));
+
+    Ok(Value::Unit)
+}
+
+/// Returns the input source given as an optional port argument or,
+/// if none is given, the input source of the scope.
+/// Returns an error if scripts are not permitted to read input.
+fn get_input(scope: &Scope, args: &[Value], name: Name) -> Result<Rc<SharedRead>, Error> {
+    if let Some(v) = args.first() {
+        let port = try!(<&Port>::from_value_ref(v));
+        return Ok(try!(port.reader()));
+    }
+
+    if !scope.get_restrictions().allow_stdin {
+        return Err(From::from(ExecError::Restricted(name)));
+    }
+
+    match scope.get_io().stdin {
+        Some(ref stdin) => Ok(stdin.clone()),
+        None => Err(From::from(IoError::new(IoMode::Read, Path::new("<stdin>"),
+            io::Error::new(io::ErrorKind::PermissionDenied, "input is not available"))))
+    }
+}
+
+/// `flush` flushes the given output stream or, if none is given,
+/// both `stdout` and `stderr`.
+fn fn_flush(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if args.is_empty() {
+        let io = scope.get_io();
+        try!(io.stdout.flush());
+        try!(io.stderr.flush());
+        return Ok(Value::Unit);
+    }
+
+    match try!(split_stream(scope, args)) {
+        (Some(stream), _) => try!(stream.flush()),
+        (None, _) => return Err(From::from(ExecError::expected("stream", &args[0])))
+    }
+
+    Ok(Value::Unit)
+}
+
+/// `close` flushes and closes a port.
+fn fn_close(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let port = try!(<&Port>::from_value_ref(&args[0]));
+    try!(port.close());
+    Ok(Value::Unit)
+}
+
+/// `read-line` reads a line of input from `stdin` or a given port,
+/// returning a string without the trailing newline or `()` at the end of input.
+fn fn_read_line(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let stdin = try!(get_input(scope, args, standard_names::READ_LINE));
+    let mut s = String::new();
+
+    if try!(stdin.read_line(&mut s)) == 0 {
+        return Ok(Value::Unit);
+    }
+
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+
+    Ok(try!(Value::new_string(s)))
+}
+
+/// `read-char` reads a single character of input from `stdin` or a given
+/// port, returning `()` at the end of input.
+fn fn_read_char(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let stdin = try!(get_input(scope, args, standard_names::READ_CHAR));
+
+    match try!(stdin.read_char()) {
+        Some(c) => Ok(c.into()),
+        None => Ok(Value::Unit)
+    }
+}
+
 /// `append` append a series of elements to a given list.
 ///
 /// ```lisp
@@ -1105,18 +1368,20 @@ fn fn_elt(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 /// (concat "foo" "bar")
 /// (concat "foo" #'/' "bar")
 /// ```
-fn fn_concat(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_concat(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     match args[0] {
-        Value::Unit | Value::List(_) => concat_list(args),
-        Value::Char(_) | Value::String(_) => concat_string(args),
+        Value::Unit | Value::List(_) => concat_list(scope, args),
+        Value::Char(_) | Value::String(_) => concat_string(scope, args),
         ref v => Err(From::from(ExecError::expected("list or string", v)))
     }
 }
 
-fn concat_list(args: &mut [Value]) -> Result<Value, Error> {
+fn concat_list(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let mut v = Vec::new();
 
     for arg in args {
+        try!(scope.check_interrupt());
+
         match arg.take() {
             Value::Unit => (),
             Value::List(li) => v.extend(li.into_vec()),
@@ -1127,10 +1392,12 @@ fn concat_list(args: &mut [Value]) -> Result<Value, Error> {
     Ok(v.into())
 }
 
-fn concat_string(args: &[Value]) -> Result<Value, Error> {
+fn concat_string(scope: &Scope, args: &[Value]) -> Result<Value, Error> {
     let mut res = String::new();
 
     for arg in args {
+        try!(scope.check_interrupt());
+
         match *arg {
             Value::Char(ch) => res.push(ch),
             Value::String(ref s) => res.push_str(s),
@@ -1138,7 +1405,7 @@ fn concat_string(args: &[Value]) -> Result<Value, Error> {
         }
     }
 
-    Ok(res.into())
+    Ok(try!(Value::new_string(res)))
 }
 
 /// `join` joins a series of lists or strings and chars using a separator value.
@@ -1147,24 +1414,23 @@ fn concat_string(args: &[Value]) -> Result<Value, Error> {
 /// (join '(0) '(1 2 3) '(4 5 6))
 /// (join ":" "foo" "bar")
 /// ```
-fn fn_join(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_join(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let (first, rest) = args.split_first_mut().unwrap();
 
     match *first {
-        Value::Unit => concat_list(rest),
-        Value::List(ref li) => join_list(li, rest),
+        Value::Unit => concat_list(scope, rest),
+        Value::List(ref li) => join_list(scope, li, rest),
         Value::Char(ch) => {
-            let mut s = String::new();
-            s.push(ch);
-            join_string(&s, rest)
+            let mut buf = [0; 4];
+            join_string(scope, ch.encode_utf8(&mut buf), rest)
         }
-        Value::String(ref s) if s.is_empty() => concat_string(rest),
-        Value::String(ref s) => join_string(s, rest),
+        Value::String(ref s) if s.is_empty() => concat_string(scope, rest),
+        Value::String(ref s) => join_string(scope, s, rest),
         ref v => Err(From::from(ExecError::expected("list or string", v)))
     }
 }
 
-fn join_list(sep: &[Value], args: &mut [Value]) -> Result<Value, Error> {
+fn join_list(scope: &Scope, sep: &[Value], args: &mut [Value]) -> Result<Value, Error> {
     let mut v = Vec::new();
 
     if let Some((first, rest)) = args.split_first_mut() {
@@ -1175,6 +1441,7 @@ fn join_list(sep: &[Value], args: &mut [Value]) -> Result<Value, Error> {
         }
 
         for arg in rest {
+            try!(scope.check_interrupt());
             v.extend(sep.iter().cloned());
 
             match arg.take() {
@@ -1188,7 +1455,7 @@ fn join_list(sep: &[Value], args: &mut [Value]) -> Result<Value, Error> {
     Ok(v.into())
 }
 
-fn join_string(sep: &str, args: &[Value]) -> Result<Value, Error> {
+fn join_string(scope: &Scope, sep: &str, args: &[Value]) -> Result<Value, Error> {
     let mut res = String::new();
 
     if let Some(value) = args.first() {
@@ -1199,6 +1466,7 @@ fn join_string(sep: &str, args: &[Value]) -> Result<Value, Error> {
         }
 
         for arg in &args[1..] {
+            try!(scope.check_interrupt());
             res.push_str(sep);
             match *arg {
                 Value::Char(ch) => res.push(ch),
@@ -1208,7 +1476,7 @@ fn join_string(sep: &str, args: &[Value]) -> Result<Value, Error> {
         }
     }
 
-    Ok(res.into())
+    Ok(try!(Value::new_string(res)))
 }
 
 /// `len` returns the length of the given list or string.
@@ -1216,6 +1484,8 @@ fn fn_len(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let n = match args[0] {
         Value::Unit => 0,
         Value::List(ref li) => li.len(),
+        Value::Map(ref m) => m.len(),
+        Value::Set(ref s) => s.len(),
         Value::String(ref s) => s.len(),
         ref v => return Err(From::from(ExecError::expected("list", v)))
     };
@@ -1263,7 +1533,7 @@ fn fn_slice(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
             } else if !is_char_boundary(s, end) {
                 Err(From::from(ExecError::NotCharBoundary(end)))
             } else {
-                Ok(s[begin..end].into())
+                Ok(s.slice(begin..end).into())
             }
         }
         ref v => Err(From::from(ExecError::expected("list or string", v)))
@@ -1496,7 +1766,7 @@ fn fn_fract(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 fn fn_numer(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     match args[0].take() {
         i @ Value::Integer(_) => Ok(i),
-        Value::Ratio(r) => Ok(r.numer().clone().into()),
+        Value::Ratio(r) => Ok(r.into_numer().into()),
         ref v => Err(From::from(ExecError::expected("integer or ratio", v)))
     }
 }
@@ -1589,6 +1859,291 @@ fn fn_min(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     Ok(v)
 }
 
+/// `loaded-modules` returns a list of the names of loaded modules,
+/// in the order in which they were loaded.
+fn fn_loaded_modules(scope: &Scope, _args: &mut [Value]) -> Result<Value, Error> {
+    Ok(scope.get_modules().iter()
+        .map(|m| Value::Name(m.name)).collect::<Vec<_>>().into())
+}
+
+/// `hash-map` returns a map constructed from alternating keys and values.
+///
+/// ```lisp
+/// (hash-map :a 1 :b 2)
+/// ```
+fn fn_hash_map(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if args.len() % 2 != 0 {
+        return Err(From::from(ExecError::OddKeywordParams));
+    }
+
+    let mut m = Map::new();
+
+    for pair in args.chunks_mut(2) {
+        try!(m.insert(pair[0].take(), pair[1].take()));
+    }
+
+    Ok(m.into())
+}
+
+/// `get` returns the value associated with a key in a map, or a default
+/// value if none is found. If no default value is given, `()` is returned.
+///
+/// A list is treated as a list of alternating keywords and values,
+/// if the key is a keyword, or as a sequence indexed by integer keys.
+///
+/// ```lisp
+/// (get {:a 1 :b 2} :b)
+/// (get '(:a 1 :b 2) :c 0)
+/// (get '(1 2 3) 5 :missing)
+/// ```
+fn fn_get(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let default = args.get_mut(2).map_or(Value::Unit, |v| v.take());
+
+    let found = match args[0] {
+        Value::Unit => None,
+        Value::Map(ref m) => m.get(&args[1]).cloned(),
+        Value::List(ref li) => match args[1] {
+            Value::Keyword(k) => keyword_position(li, k).map(|pos| li[pos + 1].clone()),
+            ref v => li.get(try!(usize::from_value_ref(v))).cloned()
+        },
+        ref v => return Err(From::from(ExecError::expected("map or list", v)))
+    };
+
+    Ok(found.unwrap_or(default))
+}
+
+/// Returns the position of a keyword in a list of alternating keywords
+/// and values.
+pub fn keyword_position(li: &[Value], key: Name) -> Option<usize> {
+    li.chunks(2).position(|pair| match pair[0] {
+        Value::Keyword(k) => k == key && pair.len() == 2,
+        _ => false
+    }).map(|n| n * 2)
+}
+
+/// `insert` returns a map with one or more keys associated with new values,
+/// or a set with one or more values added.
+///
+/// ```lisp
+/// (insert {:a 1} :a 2 :b 3)
+/// (insert (set 1 2) 3)
+/// ```
+fn fn_insert(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let (first, rest) = args.split_at_mut(1);
+
+    let mut m = match first[0].take() {
+        Value::Map(m) => m,
+        Value::Set(mut s) => {
+            {
+                let s = Rc::make_mut(&mut s);
+
+                for v in rest.iter_mut() {
+                    try!(s.insert(v.take()));
+                }
+            }
+
+            return Ok(Value::Set(s));
+        }
+        ref v => return Err(From::from(ExecError::expected("map or set", v)))
+    };
+
+    if rest.len() % 2 != 0 {
+        return Err(From::from(ExecError::OddKeywordParams));
+    }
+
+    {
+        let m = Rc::make_mut(&mut m);
+
+        for pair in rest.chunks_mut(2) {
+            try!(m.insert(pair[0].take(), pair[1].take()));
+        }
+    }
+
+    Ok(Value::Map(m))
+}
+
+/// `remove` returns a map with zero or more keys removed,
+/// or a set with zero or more values removed.
+///
+/// ```lisp
+/// (remove {:a 1 :b 2} :a)
+/// (remove (set 1 2) 1)
+/// ```
+fn fn_remove(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let (first, rest) = args.split_at_mut(1);
+
+    let mut m = match first[0].take() {
+        Value::Map(m) => m,
+        Value::Set(mut s) => {
+            if rest.iter().any(|v| s.contains(v)) {
+                let s = Rc::make_mut(&mut s);
+
+                for v in rest.iter() {
+                    s.remove(v);
+                }
+            }
+
+            return Ok(Value::Set(s));
+        }
+        ref v => return Err(From::from(ExecError::expected("map or set", v)))
+    };
+
+    if rest.iter().any(|k| m.contains_key(k)) {
+        let m = Rc::make_mut(&mut m);
+
+        for k in rest.iter() {
+            m.remove(k);
+        }
+    }
+
+    Ok(Value::Map(m))
+}
+
+/// `keys` returns a list of the keys of a map, in insertion order.
+fn fn_keys(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let m = try!(<&Map>::from_value_ref(&args[0]));
+    Ok(m.keys().into())
+}
+
+/// `values` returns a list of the values of a map, in the order of their keys,
+/// or a list of the values of a set, in insertion order.
+fn fn_values(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0] {
+        Value::Map(ref m) => Ok(m.values().into()),
+        Value::Set(ref s) => Ok(s.values().into()),
+        ref v => Err(From::from(ExecError::expected("map or set", v)))
+    }
+}
+
+/// `contains` returns whether a map contains the given key
+/// or a set contains the given value.
+fn fn_contains(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0] {
+        Value::Map(ref m) => Ok(m.contains_key(&args[1]).into()),
+        Value::Set(ref s) => Ok(s.contains(&args[1]).into()),
+        ref v => Err(From::from(ExecError::expected("map or set", v)))
+    }
+}
+
+/// `set` returns a set of the given values, with duplicates removed.
+///
+/// ```lisp
+/// (set 1 2 2 3)
+/// ```
+fn fn_set(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut s = Set::new();
+
+    for v in args.iter_mut() {
+        try!(s.insert(v.take()));
+    }
+
+    Ok(s.into())
+}
+
+/// `union` returns a set of values contained in any of the given sets.
+///
+/// ```lisp
+/// (union (set 1 2) (set 2 3))
+/// ```
+fn fn_union(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut s = try!(<&Set>::from_value_ref(&args[0])).clone();
+
+    for arg in &args[1..] {
+        s = s.union(try!(<&Set>::from_value_ref(arg)));
+    }
+
+    Ok(s.into())
+}
+
+/// `intersection` returns a set of values contained in all of the given sets.
+///
+/// ```lisp
+/// (intersection (set 1 2) (set 2 3))
+/// ```
+fn fn_intersection(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut s = try!(<&Set>::from_value_ref(&args[0])).clone();
+
+    for arg in &args[1..] {
+        s = s.intersection(try!(<&Set>::from_value_ref(arg)));
+    }
+
+    Ok(s.into())
+}
+
+/// `difference` returns a set of values contained in the first set
+/// and in none of the remaining sets.
+///
+/// ```lisp
+/// (difference (set 1 2 3) (set 2) (set 3))
+/// ```
+fn fn_difference(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut s = try!(<&Set>::from_value_ref(&args[0])).clone();
+
+    for arg in &args[1..] {
+        s = s.difference(try!(<&Set>::from_value_ref(arg)));
+    }
+
+    Ok(s.into())
+}
+
+/// `subset?` returns whether every value in the first set
+/// is contained in the second set.
+fn fn_is_subset(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let a = try!(<&Set>::from_value_ref(&args[0]));
+    let b = try!(<&Set>::from_value_ref(&args[1]));
+
+    Ok(a.is_subset(b).into())
+}
+
+/// `raise` produces an error carrying the given value, which may be
+/// caught by an enclosing `try` expression.
+fn fn_raise(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    Err(From::from(ExecError::Raised(args[0].take())))
+}
+
+/// `read` parses a string containing a single expression and returns
+/// the expression as a value, without evaluating it.
+fn fn_read(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if !scope.get_restrictions().allow_eval {
+        return Err(From::from(ExecError::Restricted(standard_names::READ)));
+    }
+
+    let input = try!(<&str>::from_value_ref(&args[0]));
+    let offset = scope.borrow_codemap_mut().reserve_source(input);
+
+    let mut names = scope.borrow_names_mut();
+    let mut p = Parser::new(&mut names, Lexer::new(input, offset));
+    scope.configure_parser(&mut p);
+
+    p.parse_single_expr().map_err(From::from)
+}
+
+/// `eval` compiles and executes a value as an expression
+/// in the current scope.
+fn fn_eval(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if !scope.get_restrictions().allow_eval {
+        return Err(From::from(ExecError::Restricted(standard_names::EVAL)));
+    }
+
+    let code = try!(compile(scope, &args[0]));
+    execute(scope, Rc::new(code))
+}
+
+/// `macroexpand` repeatedly expands a macro call until the result
+/// is not a macro call.
+fn fn_macroexpand(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    macroexpand(scope, &args[0])
+}
+
+/// `macroexpand-1` expands a macro call once. Other values are returned
+/// unchanged.
+fn fn_macroexpand_1(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match try!(macroexpand_1(scope, &args[0])) {
+        Some(v) => Ok(v),
+        None => Ok(args[0].take())
+    }
+}
+
 /// `panic` immediately interrupts execution upon evaluation.
 /// It accepts an optional parameter describing the reason for the panic.
 fn fn_panic(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
@@ -0,0 +1,341 @@
+//! Generates random values and programs, for use in property testing.
+//!
+//! ```
+//! extern crate ketos;
+//! extern crate rand;
+//!
+//! use ketos::Interpreter;
+//! use ketos::generate::ValueGenerator;
+//!
+//! # fn main() {
+//! let interp = Interpreter::new();
+//! let mut gen = ValueGenerator::new(rand::thread_rng());
+//!
+//! for _ in 0..10 {
+//!     let v = gen.gen_value(&mut interp.get_scope().borrow_names_mut());
+//!     let _ = interp.format_value(&v);
+//!
+//!     let src = gen.gen_program();
+//!     interp.run_code(&src, None).unwrap();
+//! }
+//! # }
+//! ```
+
+use rand::Rng;
+
+use integer::{Integer, Ratio, Sign};
+use name::{Name, NameStore};
+use value::Value;
+
+/// Controls the size and shape of generated values and programs
+#[derive(Clone, Debug)]
+pub struct GenConfig {
+    /// Maximum nesting depth of lists, quoted values, and expressions
+    pub max_depth: u32,
+    /// Maximum number of elements in a generated list
+    pub max_list_len: usize,
+    /// Maximum number of characters in a generated string
+    pub max_string_len: usize,
+    /// Whether to generate integers outside the range of `i64`
+    pub big_integers: bool,
+    /// Whether to generate float values
+    pub floats: bool,
+    /// Whether to generate ratio values
+    pub ratios: bool,
+    /// Whether to generate name and keyword values
+    pub names: bool,
+    /// Whether to generate quoted values
+    pub quotes: bool,
+    /// Whether to generate non-ASCII characters in chars and strings
+    pub unicode: bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> GenConfig {
+        GenConfig{
+            max_depth: 4,
+            max_list_len: 6,
+            max_string_len: 12,
+            big_integers: true,
+            floats: true,
+            ratios: true,
+            names: true,
+            quotes: true,
+            unicode: true,
+        }
+    }
+}
+
+/// Names used for generated name and keyword values
+const NAMES: &'static [&'static str] = &[
+    "a", "b", "foo", "bar", "baz", "x-y", "list", "if", "+", "set!", "*quux*",
+];
+
+/// Generates random values and programs
+pub struct ValueGenerator<R> {
+    rng: R,
+    config: GenConfig,
+}
+
+impl<R: Rng> ValueGenerator<R> {
+    /// Creates a new `ValueGenerator` using the default configuration.
+    pub fn new(rng: R) -> ValueGenerator<R> {
+        ValueGenerator::with_config(rng, GenConfig::default())
+    }
+
+    /// Creates a new `ValueGenerator` using the given configuration.
+    pub fn with_config(rng: R, config: GenConfig) -> ValueGenerator<R> {
+        ValueGenerator{
+            rng: rng,
+            config: config,
+        }
+    }
+
+    /// Returns the generator configuration.
+    pub fn config(&self) -> &GenConfig {
+        &self.config
+    }
+
+    /// Returns a mutable reference to the generator configuration.
+    pub fn config_mut(&mut self) -> &mut GenConfig {
+        &mut self.config
+    }
+
+    /// Returns a mutable reference to the random number generator.
+    pub fn rng(&mut self) -> &mut R {
+        &mut self.rng
+    }
+
+    /// Generates a random value.
+    ///
+    /// Generated values never include functions, lambdas, struct values,
+    /// or foreign values. `NameStore` is used to create names and keywords.
+    pub fn gen_value(&mut self, names: &mut NameStore) -> Value {
+        let depth = self.config.max_depth;
+        self.value(names, depth)
+    }
+
+    /// Generates a random list of values.
+    pub fn gen_list(&mut self, names: &mut NameStore) -> Vec<Value> {
+        let depth = self.config.max_depth;
+        self.list(names, depth)
+    }
+
+    /// Generates a random integer value.
+    pub fn gen_integer(&mut self) -> Integer {
+        if self.config.big_integers && self.rng.gen_weighted_bool(4) {
+            let n = self.rng.gen_range(9, 33);
+            let bytes = (0..n).map(|_| self.rng.gen()).collect::<Vec<u8>>();
+            let sign = if self.rng.gen() { Sign::Plus } else { Sign::Minus };
+            Integer::from_bytes_le(sign, &bytes)
+        } else if self.rng.gen() {
+            Integer::from_i64(self.rng.gen_range(-100, 100))
+        } else {
+            Integer::from_i64(self.rng.gen())
+        }
+    }
+
+    /// Generates a random string.
+    pub fn gen_string(&mut self) -> String {
+        let n = self.rng.gen_range(0, self.config.max_string_len + 1);
+        (0..n).map(|_| self.gen_char()).collect()
+    }
+
+    /// Generates a random character.
+    pub fn gen_char(&mut self) -> char {
+        if self.config.unicode && self.rng.gen_weighted_bool(4) {
+            self.rng.gen()
+        } else {
+            self.rng.gen_range(b' ', b'~' + 1) as char
+        }
+    }
+
+    /// Generates the source of a random program.
+    ///
+    /// The program consists of zero or more function definitions followed by
+    /// an expression. Programs are free of side effects and always run
+    /// successfully, producing an integer value.
+    pub fn gen_program(&mut self) -> String {
+        let mut src = String::new();
+        let mut funcs = Vec::new();
+
+        for i in 0..self.rng.gen_range(0, 3) {
+            let n_params = self.rng.gen_range(0, 3);
+            let params = (0..n_params).map(|n| format!("p{}", n)).collect::<Vec<_>>();
+            let depth = self.config.max_depth;
+
+            let body = ExprGen{
+                gen: self,
+                vars: params.clone(),
+                funcs: &funcs,
+                n_vars: 0,
+            }.int_expr(depth);
+
+            src.push_str(&format!("(define (f{}{}) {})\n",
+                i, params.iter().map(|p| format!(" {}", p)).collect::<String>(), body));
+            funcs.push(n_params);
+        }
+
+        let depth = self.config.max_depth;
+
+        let expr = ExprGen{
+            gen: self,
+            vars: Vec::new(),
+            funcs: &funcs,
+            n_vars: 0,
+        }.int_expr(depth);
+
+        src.push_str(&expr);
+        src.push('\n');
+        src
+    }
+
+    fn value(&mut self, names: &mut NameStore, depth: u32) -> Value {
+        loop {
+            match self.rng.gen_range(0, 12) {
+                0 => return Value::Unit,
+                1 => return Value::Bool(self.rng.gen()),
+                2 | 3 => return Value::Integer(self.gen_integer()),
+                4 if self.config.floats => return Value::Float(self.gen_float()),
+                5 if self.config.ratios => return Value::Ratio(self.gen_ratio()),
+                6 => return Value::Char(self.gen_char()),
+                7 | 8 => return self.gen_string().into(),
+                9 if self.config.names => return Value::Name(self.gen_name(names)),
+                10 if self.config.names => return Value::Keyword(self.gen_name(names)),
+                11 if depth > 0 => {
+                    if self.config.quotes && self.rng.gen_weighted_bool(4) {
+                        let v = self.value(names, depth - 1);
+                        return Value::Quote(Box::new(v), self.rng.gen_range(1, 3));
+                    }
+                    return self.list(names, depth - 1).into();
+                }
+                _ => ()
+            }
+        }
+    }
+
+    fn list(&mut self, names: &mut NameStore, depth: u32) -> Vec<Value> {
+        let n = self.rng.gen_range(0, self.config.max_list_len + 1);
+        (0..n).map(|_| self.value(names, depth)).collect()
+    }
+
+    fn gen_float(&mut self) -> f64 {
+        match self.rng.gen_range(0, 4) {
+            0 => 0.0,
+            1 => self.rng.gen_range(-100.0, 100.0),
+            _ => {
+                let f: f64 = self.rng.gen();
+                f * (self.rng.gen_range(-300, 300) as f64).exp2()
+            }
+        }
+    }
+
+    fn gen_ratio(&mut self) -> Ratio {
+        let numer = self.gen_integer();
+        let mut denom = self.gen_integer();
+
+        if denom.is_zero() {
+            denom = Integer::one();
+        }
+
+        Ratio::new(numer, denom)
+    }
+
+    fn gen_name(&mut self, names: &mut NameStore) -> Name {
+        let s = self.rng.choose(NAMES).expect("empty names");
+        names.add(s)
+    }
+}
+
+struct ExprGen<'a, R: 'a> {
+    gen: &'a mut ValueGenerator<R>,
+    vars: Vec<String>,
+    funcs: &'a [usize],
+    n_vars: usize,
+}
+
+impl<'a, R: Rng> ExprGen<'a, R> {
+    fn int_expr(&mut self, depth: u32) -> String {
+        if depth == 0 {
+            return self.int_atom();
+        }
+
+        let depth = depth - 1;
+
+        match self.gen.rng.gen_range(0, 9) {
+            0 => self.int_atom(),
+            1 => {
+                let op = *self.gen.rng.choose(&["+", "-", "*", "max", "min"]).unwrap();
+                let n = self.gen.rng.gen_range(1, 4);
+                self.call(op, n, depth)
+            }
+            2 => format!("(abs {})", self.int_expr(depth)),
+            3 => format!("(if {} {} {})", self.bool_expr(depth),
+                self.int_expr(depth), self.int_expr(depth)),
+            4 => {
+                let name = format!("v{}", self.n_vars);
+                self.n_vars += 1;
+
+                let value = self.int_expr(depth);
+                self.vars.push(name.clone());
+                let body = self.int_expr(depth);
+                self.vars.pop();
+
+                format!("(let (({} {})) {})", name, value, body)
+            }
+            5 => self.call("len (list", self.gen.config.max_list_len, depth) + ")",
+            6 if !self.funcs.is_empty() => {
+                let i = self.gen.rng.gen_range(0, self.funcs.len());
+                let n_params = self.funcs[i];
+                let mut s = format!("(f{}", i);
+
+                for _ in 0..n_params {
+                    s.push(' ');
+                    s.push_str(&self.int_expr(depth));
+                }
+
+                s.push(')');
+                s
+            }
+            _ => self.int_atom()
+        }
+    }
+
+    fn call(&mut self, op: &str, max_args: usize, depth: u32) -> String {
+        let n = self.gen.rng.gen_range(1, max_args.max(1) + 1);
+        let mut s = format!("({}", op);
+
+        for _ in 0..n {
+            s.push(' ');
+            s.push_str(&self.int_expr(depth));
+        }
+
+        s.push(')');
+        s
+    }
+
+    fn bool_expr(&mut self, depth: u32) -> String {
+        if depth == 0 {
+            return self.gen.rng.choose(&["true", "false"]).unwrap().to_string();
+        }
+
+        let depth = depth - 1;
+
+        match self.gen.rng.gen_range(0, 4) {
+            0 => format!("(not {})", self.bool_expr(depth)),
+            1 => format!("(and {} {})", self.bool_expr(depth), self.bool_expr(depth)),
+            _ => {
+                let op = *self.gen.rng.choose(&["<", ">", "=", "<=", ">="]).unwrap();
+                format!("({} {} {})", op, self.int_expr(depth), self.int_expr(depth))
+            }
+        }
+    }
+
+    fn int_atom(&mut self) -> String {
+        if !self.vars.is_empty() && self.gen.rng.gen() {
+            self.gen.rng.choose(&self.vars).unwrap().clone()
+        } else {
+            self.gen.rng.gen_range(-100i32, 100).to_string()
+        }
+    }
+}
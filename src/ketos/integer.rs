@@ -1,7 +1,7 @@
 //! Arbitrary precision integer and ratio types.
 
+use std::cmp::Ordering;
 use std::fmt;
-use std::mem::transmute;
 use std::ops;
 use std::str::FromStr;
 
@@ -11,12 +11,17 @@ use num::{self, BigInt, BigRational};
 use num::{FromPrimitive, ToPrimitive, Integer as NumInteger, Signed, Num, Zero, One};
 
 /// Arbitrary precision signed integer
-#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
-pub struct Integer(BigInt);
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub struct Integer(Box<BigInt>);
 
 /// Arbitrary precision signed integer ratio
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
-pub struct Ratio(BigRational);
+///
+/// The value is kept in lowest terms, with a positive denominator.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Ratio {
+    numer: Integer,
+    denom: Integer,
+}
 
 /// Error produced when failing to parse an `Integer` from `&str`.
 #[derive(Debug, PartialEq)]
@@ -52,83 +57,98 @@ impl fmt::Display for FromStrRadixError {
 }
 
 impl Integer {
+    #[inline]
+    fn wrap(i: BigInt) -> Integer {
+        Integer(Box::new(i))
+    }
+
+    #[inline]
+    fn to_big(&self) -> BigInt {
+        (*self.0).clone()
+    }
+
+    #[inline]
+    fn into_big(self) -> BigInt {
+        *self.0
+    }
+
     /// Creates an `Integer` from a sign and a series of big-endian bytes.
     #[inline]
     pub fn from_bytes_be(sign: Sign, bytes: &[u8]) -> Integer {
-        Integer(BigInt::from_bytes_be(sign, bytes))
+        Integer::wrap(BigInt::from_bytes_be(sign, bytes))
     }
 
     /// Creates an `Integer` from a sign and a series of little-endian bytes.
     #[inline]
     pub fn from_bytes_le(sign: Sign, bytes: &[u8]) -> Integer {
-        Integer(BigInt::from_bytes_le(sign, bytes))
+        Integer::wrap(BigInt::from_bytes_le(sign, bytes))
     }
 
     /// Creates an `Integer` with the value of the given `f64`.
     /// Returns `None` if the value cannot be converted.
     #[inline]
     pub fn from_f64(f: f64) -> Option<Integer> {
-        BigInt::from_f64(f).map(Integer)
+        BigInt::from_f64(f).map(Integer::wrap)
     }
 
     /// Creates an `Integer` with the value of the given `i8`.
     #[inline]
     pub fn from_i8(i: i8) -> Integer {
-        Integer(BigInt::from_i8(i).unwrap())
+        Integer::wrap(BigInt::from_i8(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `i16`.
     #[inline]
     pub fn from_i16(i: i16) -> Integer {
-        Integer(BigInt::from_i16(i).unwrap())
+        Integer::wrap(BigInt::from_i16(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `i32`.
     #[inline]
     pub fn from_i32(i: i32) -> Integer {
-        Integer(BigInt::from_i32(i).unwrap())
+        Integer::wrap(BigInt::from_i32(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `i64`.
     #[inline]
     pub fn from_i64(i: i64) -> Integer {
-        Integer(BigInt::from_i64(i).unwrap())
+        Integer::wrap(BigInt::from_i64(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `isize`.
     #[inline]
     pub fn from_isize(i: isize) -> Integer {
-        Integer(BigInt::from_isize(i).unwrap())
+        Integer::wrap(BigInt::from_isize(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `u8`.
     #[inline]
     pub fn from_u8(i: u8) -> Integer {
-        Integer(BigInt::from_u8(i).unwrap())
+        Integer::wrap(BigInt::from_u8(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `u16`.
     #[inline]
     pub fn from_u16(i: u16) -> Integer {
-        Integer(BigInt::from_u16(i).unwrap())
+        Integer::wrap(BigInt::from_u16(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `u32`.
     #[inline]
     pub fn from_u32(i: u32) -> Integer {
-        Integer(BigInt::from_u32(i).unwrap())
+        Integer::wrap(BigInt::from_u32(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `u64`.
     #[inline]
     pub fn from_u64(i: u64) -> Integer {
-        Integer(BigInt::from_u64(i).unwrap())
+        Integer::wrap(BigInt::from_u64(i).unwrap())
     }
 
     /// Creates an `Integer` with the value of the given `usize`.
     #[inline]
     pub fn from_usize(u: usize) -> Integer {
-        Integer(BigInt::from_usize(u).unwrap())
+        Integer::wrap(BigInt::from_usize(u).unwrap())
     }
 
     /// Returns an `Integer` represented by a string in the given radix.
@@ -136,7 +156,7 @@ impl Integer {
     #[inline]
     pub fn from_str_radix(s: &str, radix: u32) -> Result<Integer, FromStrRadixError> {
         BigInt::from_str_radix(s, radix)
-            .map(Integer).map_err(FromStrRadixError)
+            .map(Integer::wrap).map_err(FromStrRadixError)
     }
 
     /// Returns integer sign and a series of big-endian bytes.
@@ -232,13 +252,20 @@ impl Integer {
     /// Raises the value to the power of `exp`.
     #[inline]
     pub fn pow(self, exp: usize) -> Integer {
-        Integer(num::pow(self.0, exp))
+        Integer::wrap(num::pow(*self.0, exp))
     }
 
     /// Returns the absolute value of an `Integer`.
     #[inline]
     pub fn abs(&self) -> Integer {
-        Integer(self.0.abs())
+        Integer::wrap(self.0.abs())
+    }
+
+    /// Returns the number of bits required to represent the magnitude
+    /// of the `Integer`.
+    #[inline]
+    pub fn bits(&self) -> usize {
+        self.0.bits()
     }
 
     /// Returns whether `self` is a multiple of `rhs`.
@@ -268,7 +295,7 @@ impl Integer {
     /// Returns an `Integer` of the value zero.
     #[inline]
     pub fn zero() -> Integer {
-        Integer(BigInt::zero())
+        Integer::wrap(BigInt::zero())
     }
 
     /// Returns whether the `Integer` is equal to one.
@@ -280,11 +307,29 @@ impl Integer {
     /// Returns an `Integer` of the value one.
     #[inline]
     pub fn one() -> Integer {
-        Integer(BigInt::one())
+        Integer::wrap(BigInt::one())
     }
 }
 
 impl Ratio {
+    #[inline]
+    fn wrap(r: BigRational) -> Ratio {
+        Ratio{
+            numer: Integer::wrap(r.numer().clone()),
+            denom: Integer::wrap(r.denom().clone()),
+        }
+    }
+
+    #[inline]
+    fn to_big(&self) -> BigRational {
+        BigRational::new_raw((*self.numer.0).clone(), (*self.denom.0).clone())
+    }
+
+    #[inline]
+    fn into_big(self) -> BigRational {
+        BigRational::new_raw(*self.numer.0, *self.denom.0)
+    }
+
     /// Constructs a `Ratio` from numerator and denominator.
     ///
     /// # Panics
@@ -292,90 +337,90 @@ impl Ratio {
     /// Panics if `denom` is zero.
     #[inline]
     pub fn new(numer: Integer, denom: Integer) -> Ratio {
-        Ratio(BigRational::new(numer.0, denom.0))
+        Ratio::wrap(BigRational::new(*numer.0, *denom.0))
     }
 
     /// Creates a `Ratio` with the value of the given `f32`.
     /// Returns `None` if the value cannot be converted.
     #[inline]
     pub fn from_f32(f: f32) -> Option<Ratio> {
-        BigRational::from_float(f).map(Ratio)
+        BigRational::from_float(f).map(Ratio::wrap)
     }
 
     /// Creates a `Ratio` with the value of the given `f64`.
     /// Returns `None` if the value cannot be converted.
     #[inline]
     pub fn from_f64(f: f64) -> Option<Ratio> {
-        BigRational::from_float(f).map(Ratio)
+        BigRational::from_float(f).map(Ratio::wrap)
     }
 
     /// Creates a `Ratio` from an `Integer` value.
     #[inline]
     pub fn from_integer(i: Integer) -> Ratio {
-        Ratio(BigRational::from_integer(i.0))
+        Ratio{numer: i, denom: Integer::one()}
     }
 
     /// Returns the `Ratio` as an `f32` value.
     #[inline]
     pub fn to_f32(&self) -> Option<f32> {
-        self.numer().to_f32().and_then(
-            |n| self.denom().to_f32().map(|d| n / d))
+        self.numer.0.to_f32().and_then(
+            |n| self.denom.0.to_f32().map(|d| n / d))
     }
 
     /// Returns the `Ratio` as an `f64` value.
     #[inline]
     pub fn to_f64(&self) -> Option<f64> {
-        self.numer().to_f64().and_then(
-            |n| self.denom().to_f64().map(|d| n / d))
+        self.numer.0.to_f64().and_then(
+            |n| self.denom.0.to_f64().map(|d| n / d))
     }
 
     /// Truncates a `Ratio` and returns the whole portion as an `Integer`.
     #[inline]
     pub fn to_integer(&self) -> Integer {
-        Integer(self.0.to_integer())
+        Integer::wrap(&*self.numer.0 / &*self.denom.0)
     }
 
     /// Returns whether the `Ratio` is an integer; i.e. its denominator is `1`.
     #[inline]
     pub fn is_integer(&self) -> bool {
-        self.denom().is_one()
+        self.denom.0.is_one()
     }
 
     /// Returns the absolute value of the `Ratio`.
     #[inline]
     pub fn abs(&self) -> Ratio {
-        Ratio(self.0.abs())
+        Ratio{numer: self.numer.abs(), denom: self.denom.clone()}
     }
 
     /// Returns the `Ratio` rounded towards positive infinity.
     #[inline]
     pub fn ceil(&self) -> Ratio {
-        Ratio(self.0.ceil())
+        Ratio::wrap(self.to_big().ceil())
     }
 
     /// Returns the `Ratio` rounded towards negative infinity.
     #[inline]
     pub fn floor(&self) -> Ratio {
-        Ratio(self.0.floor())
+        Ratio::wrap(self.to_big().floor())
     }
 
     /// Returns the fractional portion of a `Ratio`.
     #[inline]
     pub fn fract(&self) -> Ratio {
-        Ratio(self.0.fract())
+        Ratio::wrap(self.to_big().fract())
     }
 
     /// Returns the `Ratio` rounded to the nearest integer.
     /// Rounds half-way cases away from zero.
     #[inline]
     pub fn round(&self) -> Ratio {
-        Ratio(self.0.round())
+        Ratio::wrap(self.to_big().round())
     }
 
     /// Returns the `Ratio` rounded towards zero.
     #[inline]
     pub fn trunc(&self) -> Ratio {
-        Ratio(self.0.trunc())
+        Ratio::from_integer(self.to_integer())
     }
 
     /// Returns the reciprocal of a `Ratio`.
@@ -385,54 +430,86 @@ impl Ratio {
     /// Panics if the numerator is zero.
     #[inline]
     pub fn recip(&self) -> Ratio {
-        Ratio(self.0.recip())
+        Ratio::wrap(self.to_big().recip())
+    }
+
+    /// Returns the total number of bits required to represent the magnitude
+    /// of the `Ratio`'s numerator and denominator.
+    #[inline]
+    pub fn bits(&self) -> usize {
+        self.numer.0.bits() + self.denom.0.bits()
     }
 
     /// Returns the `Ratio`'s numerator.
     #[inline]
     pub fn numer(&self) -> &Integer {
-        unsafe { transmute(self.0.numer()) }
+        &self.numer
     }
 
     /// Returns the `Ratio`'s denominator.
     #[inline]
     pub fn denom(&self) -> &Integer {
-        unsafe { transmute(self.0.denom()) }
+        &self.denom
+    }
+
+    /// Consumes the `Ratio` and returns its numerator.
+    #[inline]
+    pub fn into_numer(self) -> Integer {
+        self.numer
+    }
+
+    /// Consumes the `Ratio` and returns its denominator.
+    #[inline]
+    pub fn into_denom(self) -> Integer {
+        self.denom
     }
 
     /// Returns whether the `Ratio` is equal to zero.
     pub fn is_zero(&self) -> bool {
-        self.numer().is_zero()
+        self.numer.0.is_zero()
     }
 
     /// Returns whether the `Ratio` is less than zero.
     pub fn is_negative(&self) -> bool {
-        self.numer().is_negative()
+        self.numer.0.is_negative()
     }
 
     /// Returns whether the `Ratio` is greater than zero.
     pub fn is_positive(&self) -> bool {
-        self.numer().is_positive()
+        self.numer.0.is_positive()
     }
 
     /// Returns a `Ratio` of value zero.
     pub fn zero() -> Ratio {
-        Ratio(BigRational::zero())
+        Ratio::from_integer(Integer::zero())
     }
 
     /// Returns a `Ratio` of value one.
     pub fn one() -> Ratio {
-        Ratio(BigRational::one())
+        Ratio::from_integer(Integer::one())
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, rhs: &Ratio) -> Ordering {
+        // Denominators are positive, so cross-multiplying preserves order
+        (&*self.numer.0 * &*rhs.denom.0).cmp(&(&*rhs.numer.0 * &*self.denom.0))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, rhs: &Ratio) -> Option<Ordering> {
+        Some(self.cmp(rhs))
     }
 }
 
 impl PartialEq<Integer> for Ratio {
     fn eq(&self, rhs: &Integer) -> bool {
-        self.denom().is_one() && self.numer() == rhs
+        self.is_integer() && self.numer == *rhs
     }
 
     fn ne(&self, rhs: &Integer) -> bool {
-        !self.denom().is_one() || self.numer() != rhs
+        !self.is_integer() || self.numer != *rhs
     }
 }
 
@@ -452,7 +529,7 @@ impl FromStr for Integer {
 
     #[inline]
     fn from_str(s: &str) -> Result<Integer, FromStrIntError> {
-        s.parse().map(Integer).map_err(FromStrIntError)
+        s.parse().map(Integer::wrap).map_err(FromStrIntError)
     }
 }
 
@@ -461,7 +538,7 @@ impl ops::Shl<usize> for Integer {
 
     #[inline]
     fn shl(self, rhs: usize) -> Integer {
-        Integer(self.0.shl(rhs))
+        Integer::wrap((*self.0).shl(rhs))
     }
 }
 
@@ -470,7 +547,7 @@ impl<'a> ops::Shl<usize> for &'a Integer {
 
     #[inline]
     fn shl(self, rhs: usize) -> Integer {
-        Integer(self.0.clone().shl(rhs))
+        Integer::wrap((*self.0).clone().shl(rhs))
     }
 }
 
@@ -479,7 +556,7 @@ impl ops::Shr<usize> for Integer {
 
     #[inline]
     fn shr(self, rhs: usize) -> Integer {
-        Integer(self.0.shr(rhs))
+        Integer::wrap((*self.0).shr(rhs))
     }
 }
 
@@ -488,7 +565,7 @@ impl<'a> ops::Shr<usize> for &'a Integer {
 
     #[inline]
     fn shr(self, rhs: usize) -> Integer {
-        Integer(self.0.clone().shr(rhs))
+        Integer::wrap((*self.0).clone().shr(rhs))
     }
 }
 
@@ -499,7 +576,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn add(self, rhs: $ty) -> $ty {
-                $ty(self.0.add(rhs.0))
+                $ty::wrap(self.into_big().add(rhs.into_big()))
             }
         }
 
@@ -508,7 +585,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn add(self, rhs: &$ty) -> $ty {
-                $ty(self.0.add(rhs.0.clone()))
+                $ty::wrap(self.into_big().add(rhs.to_big()))
             }
         }
 
@@ -517,7 +594,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn add(self, rhs: $ty) -> $ty {
-                $ty(self.0.clone().add(rhs.0))
+                $ty::wrap(self.to_big().add(rhs.into_big()))
             }
         }
 
@@ -526,7 +603,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn add(self, rhs: &$ty) -> $ty {
-                $ty(self.0.clone().add(rhs.0.clone()))
+                $ty::wrap(self.to_big().add(rhs.to_big()))
             }
         }
 
@@ -535,7 +612,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn sub(self, rhs: $ty) -> $ty {
-                $ty(self.0.sub(rhs.0))
+                $ty::wrap(self.into_big().sub(rhs.into_big()))
             }
         }
 
@@ -544,7 +621,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn sub(self, rhs: &$ty) -> $ty {
-                $ty(self.0.sub(rhs.0.clone()))
+                $ty::wrap(self.into_big().sub(rhs.to_big()))
             }
         }
 
@@ -553,7 +630,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn sub(self, rhs: $ty) -> $ty {
-                $ty(self.0.clone().sub(rhs.0))
+                $ty::wrap(self.to_big().sub(rhs.into_big()))
             }
         }
 
@@ -562,7 +639,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn sub(self, rhs: &$ty) -> $ty {
-                $ty(self.0.clone().sub(rhs.0.clone()))
+                $ty::wrap(self.to_big().sub(rhs.to_big()))
             }
         }
 
@@ -571,7 +648,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn mul(self, rhs: $ty) -> $ty {
-                $ty(self.0.mul(rhs.0))
+                $ty::wrap(self.into_big().mul(rhs.into_big()))
             }
         }
 
@@ -580,7 +657,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn mul(self, rhs: &$ty) -> $ty {
-                $ty(self.0.mul(rhs.0.clone()))
+                $ty::wrap(self.into_big().mul(rhs.to_big()))
             }
         }
 
@@ -589,7 +666,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn mul(self, rhs: $ty) -> $ty {
-                $ty(self.0.clone().mul(rhs.0))
+                $ty::wrap(self.to_big().mul(rhs.into_big()))
             }
         }
 
@@ -598,7 +675,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn mul(self, rhs: &$ty) -> $ty {
-                $ty(self.0.clone().mul(rhs.0.clone()))
+                $ty::wrap(self.to_big().mul(rhs.to_big()))
             }
         }
 
@@ -607,7 +684,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn div(self, rhs: $ty) -> $ty {
-                $ty(self.0.div(rhs.0))
+                $ty::wrap(self.into_big().div(rhs.into_big()))
             }
         }
 
@@ -616,7 +693,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn div(self, rhs: &$ty) -> $ty {
-                $ty(self.0.div(rhs.0.clone()))
+                $ty::wrap(self.into_big().div(rhs.to_big()))
             }
         }
 
@@ -625,7 +702,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn div(self, rhs: $ty) -> $ty {
-                $ty(self.0.clone().div(rhs.0))
+                $ty::wrap(self.to_big().div(rhs.into_big()))
             }
         }
 
@@ -634,7 +711,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn div(self, rhs: &$ty) -> $ty {
-                $ty(self.0.clone().div(rhs.0.clone()))
+                $ty::wrap(self.to_big().div(rhs.to_big()))
             }
         }
 
@@ -643,7 +720,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn rem(self, rhs: $ty) -> $ty {
-                $ty(self.0.rem(rhs.0))
+                $ty::wrap(self.into_big().rem(rhs.into_big()))
             }
         }
 
@@ -652,7 +729,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn rem(self, rhs: &$ty) -> $ty {
-                $ty(self.0.rem(rhs.0.clone()))
+                $ty::wrap(self.into_big().rem(rhs.to_big()))
             }
         }
 
@@ -661,7 +738,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn rem(self, rhs: $ty) -> $ty {
-                $ty(self.0.clone().rem(rhs.0))
+                $ty::wrap(self.to_big().rem(rhs.into_big()))
             }
         }
 
@@ -670,7 +747,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn rem(self, rhs: &$ty) -> $ty {
-                $ty(self.0.clone().rem(rhs.0.clone()))
+                $ty::wrap(self.to_big().rem(rhs.to_big()))
             }
         }
 
@@ -679,7 +756,7 @@ macro_rules! impl_ops {
 
             #[inline]
             fn neg(self) -> $ty {
-                $ty(self.0.neg())
+                $ty::wrap(self.into_big().neg())
             }
         }
 
@@ -688,15 +765,15 @@ macro_rules! impl_ops {
 
             #[inline]
             fn neg(self) -> $ty {
-                $ty(self.0.clone().neg())
+                $ty::wrap(self.to_big().neg())
             }
         }
 
         impl ::num::Zero for $ty {
             #[inline]
-            fn is_zero(&self) -> bool { self.0.is_zero() }
+            fn is_zero(&self) -> bool { $ty::is_zero(self) }
             #[inline]
-            fn zero() -> $ty { $ty(Zero::zero()) }
+            fn zero() -> $ty { $ty::wrap(Zero::zero()) }
         }
     }
 }
@@ -706,7 +783,11 @@ impl_ops!{Ratio}
 
 impl fmt::Display for Ratio {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.0, f)
+        if self.is_integer() {
+            fmt::Display::fmt(&self.numer, f)
+        } else {
+            write!(f, "{}/{}", self.numer, self.denom)
+        }
     }
 }
 
@@ -715,6 +796,6 @@ impl FromStr for Ratio {
 
     #[inline]
     fn from_str(s: &str) -> Result<Ratio, FromStrRatioError> {
-        s.parse().map(Ratio).map_err(FromStrRatioError)
+        s.parse().map(Ratio::wrap).map_err(FromStrRatioError)
     }
 }
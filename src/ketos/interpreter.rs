@@ -1,22 +1,30 @@
 //! Provides a context in which to compile and execute code.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt::Write as FmtWrite;
 use std::fs::File;
 use std::io::{stderr, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 use bytecode::Code;
-use compile::compile;
-use error::Error;
-use exec::{call_function, execute, ExecError};
+use callable::{Callable, IntoArgs, IntoFn};
+use compile::{compile_with_spans, macroexpand};
+use error::{Error, ErrorReport};
+use exec::{call_function, execute, ErrorLocation, ExecError};
 use io::{IoError, IoMode};
-use lexer::{CodeMap, Lexer};
-use module::{FileModuleLoader, ModuleLoader, ModuleRegistry};
-use name::{debug_names, display_names, Name, NameStore};
-use parser::{ParseError, Parser};
+use lexer::{CodeMap, Lexer, Span};
+use metrics::Metrics;
+use module::{FileModuleLoader, Module, ModuleLoader, ModuleRegistry};
+use name::{debug_names, display_names, DisplayNames, Name, NameDisplay, NameStore};
+use parser::{ParseError, ParseErrorKind, Parser, SpanMap};
+use repl::complete_name;
+use restrict::RestrictConfig;
 use scope::{GlobalIo, GlobalScope, MasterScope, Scope};
-use value::Value;
+use trace::Trace;
+use value::{FromValue, Readable, Value};
 
 /// Provides a context in which to compile and execute code.
 ///
@@ -29,12 +37,19 @@ use value::Value;
 #[derive(Clone)]
 pub struct Interpreter {
     scope: Scope,
+    error_span: Rc<Cell<Option<Span>>>,
+    /// Scope of the interpreter from which shared state was taken, if any
+    base: Option<Scope>,
 }
 
 impl Interpreter {
     /// Creates a new `Interpreter`.
+    ///
+    /// Modules are loaded from files in the current directory or,
+    /// on platforms without a filesystem, such as `wasm32-unknown-unknown`,
+    /// only builtin modules are available.
     pub fn new() -> Interpreter {
-        Interpreter::with_loader(Box::new(FileModuleLoader::new()))
+        Interpreter::with_loader(default_loader())
     }
 
     /// Creates a new `Interpreter` using the given `ModuleLoader` instance.
@@ -44,15 +59,109 @@ impl Interpreter {
         let modules = Rc::new(ModuleRegistry::new(loader));
         let io = Rc::new(GlobalIo::default());
 
+        Interpreter::with_scope(Rc::new(GlobalScope::new(
+            names,
+            codemap,
+            modules,
+            io)))
+    }
+
+    /// Creates a new `Interpreter` which places the given restrictions
+    /// on executed code.
+    ///
+    /// See the `restrict` module for details.
+    pub fn with_restrictions(restrict: RestrictConfig) -> Interpreter {
+        let names = Rc::new(RefCell::new(NameStore::new()));
+        let codemap = Rc::new(RefCell::new(CodeMap::new()));
+        let modules = Rc::new(ModuleRegistry::new(default_loader()));
+        let io = Rc::new(GlobalIo::default());
+
+        Interpreter::with_scope(Rc::new(GlobalScope::new(
+            names,
+            codemap,
+            modules,
+            io).with_restrictions(restrict)))
+    }
+
+    /// Creates a new `Interpreter` using the given `Scope`.
+    ///
+    /// This allows an `Interpreter` to be constructed with a custom
+    /// `GlobalIo` or other shared state.
+    pub fn with_scope(scope: Scope) -> Interpreter {
+        Interpreter{
+            scope: scope,
+            error_span: Rc::new(Cell::new(None)),
+            base: None,
+        }
+    }
+
+    /// Creates a new `Interpreter` which shares the `NameStore`, `CodeMap`,
+    /// `ModuleRegistry`, and `GlobalIo` of this interpreter.
+    ///
+    /// The new interpreter has its own global scope, so definitions made in
+    /// one interpreter are not visible in the other, as well as its own
+    /// metrics and interrupt flag. Restrictions placed on this interpreter
+    /// also apply to the new interpreter. Modules loaded by either interpreter
+    /// are available to both without being loaded again, which makes it cheap
+    /// to create many interpreters from one which has been prepared in advance.
+    /// The registry may be frozen after setup, using `ModuleRegistry::freeze`,
+    /// to prevent scripts from loading further modules into it.
+    ///
+    /// Because names are shared, `Name` values may be passed between
+    /// such interpreters. Other values should not be shared.
+    /// Code defined within a shared module runs in the scope of that module,
+    /// so it observes the interrupt flag and instruction limit of the
+    /// interpreter which loaded it.
+    /// Loaded modules are released only when this interpreter and all
+    /// interpreters sharing its state have been dropped.
+    ///
+    /// ```
+    /// use ketos::Interpreter;
+    ///
+    /// let base = Interpreter::new();
+    /// base.load_module("math").unwrap();
+    /// base.get_scope().get_modules().freeze();
+    ///
+    /// for i in 0..10 {
+    ///     let interp = base.new_sharing();
+    ///     let src = format!("(use math (sqrt)) (int (sqrt (float {})))", i * i);
+    ///     let v = interp.run_code(&src, None).unwrap();
+    ///     assert_eq!(interp.format_value(&v), i.to_string());
+    /// }
+    ///
+    /// assert!(base.new_sharing().run_code("(use random (random))", None).is_err());
+    /// ```
+    pub fn new_sharing(&self) -> Interpreter {
+        self.new_sharing_with_io(self.scope.get_io().clone())
+    }
+
+    /// Creates a new `Interpreter` which shares the `NameStore`, `CodeMap`,
+    /// and `ModuleRegistry` of this interpreter, using the given `GlobalIo`.
+    ///
+    /// See `new_sharing` for details.
+    pub fn new_sharing_with_io(&self, io: Rc<GlobalIo>) -> Interpreter {
+        let scope = Rc::new(GlobalScope::new(
+            self.scope.get_names().clone(),
+            self.scope.get_codemap().clone(),
+            self.scope.get_modules().clone(),
+            io).with_restrictions(self.scope.get_restrictions().clone()));
+
         Interpreter{
-            scope: Rc::new(GlobalScope::new(
-                names.clone(),
-                codemap.clone(),
-                modules,
-                io)),
+            scope: scope,
+            error_span: Rc::new(Cell::new(None)),
+            base: Some(self.base.as_ref().unwrap_or(&self.scope).clone()),
         }
     }
 
+    /// Loads the named module into the contained `ModuleRegistry`,
+    /// if it has not already been loaded, and returns it.
+    ///
+    /// No names are imported into the global scope.
+    pub fn load_module(&self, name: &str) -> Result<Module, Error> {
+        let name = self.scope.add_name(name);
+        self.scope.get_modules().get_module(name, &self.scope)
+    }
+
     /// Creates a new `Interpreter` that searches for module files in a given
     /// series of directories.
     pub fn with_search_paths(paths: Vec<PathBuf>) -> Interpreter {
@@ -60,6 +169,16 @@ impl Interpreter {
             FileModuleLoader::with_search_paths(paths)))
     }
 
+    /// Sets whether source code is recorded in the contained `CodeMap`.
+    ///
+    /// Hosts which compile large amounts of code and have no need for
+    /// source locations in error messages may disable recording to save
+    /// memory. Errors in code compiled while recording is disabled are
+    /// displayed without source locations.
+    pub fn set_codemap_enabled(&self, enabled: bool) {
+        self.scope.borrow_codemap_mut().set_enabled(enabled);
+    }
+
     /// Clears cached source from the contained `CodeMap`.
     ///
     /// # Note
@@ -69,42 +188,222 @@ impl Interpreter {
         self.scope.borrow_codemap_mut().clear();
     }
 
+    /// Returns the span of the source expression which produced the most
+    /// recent compilation error, if it is known.
+    ///
+    /// The span is reset each time source code is compiled by the methods
+    /// `run_code`, `run_file`, `run_single_expr`, `compile_exprs`,
+    /// and `compile_single_expr`.
+    pub fn error_span(&self) -> Option<Span> {
+        self.error_span.get()
+    }
+
+    /// Returns the location at which the most recent execution error
+    /// was produced, if it is known.
+    ///
+    /// The location is reset at the start of each top-level execution.
+    pub fn error_location(&self) -> Option<ErrorLocation> {
+        self.scope.error_location()
+    }
+
+    /// Returns all names beginning with `prefix` which are known to the
+    /// interpreter, sorted and without duplicates; see `repl::complete_name`.
+    ///
+    /// ```
+    /// use ketos::Interpreter;
+    ///
+    /// let interp = Interpreter::new();
+    /// interp.run_code("(define (display-all) ())", None).unwrap();
+    ///
+    /// assert_eq!(interp.complete_name("disp"), ["display-all"]);
+    /// ```
+    pub fn complete_name(&self, prefix: &str) -> Vec<String> {
+        complete_name(prefix, &self.scope)
+    }
+
+    /// Returns the calls which were active when the most recent execution
+    /// error was produced, innermost first.
+    ///
+    /// The trace is reset at the start of each top-level execution.
+    pub fn error_trace(&self) -> Trace {
+        self.scope.error_trace()
+    }
+
+    /// Returns a one-line description of each call in a trace,
+    /// as by `Trace::describe`.
+    pub fn describe_trace(&self, trace: &Trace) -> Vec<String> {
+        trace.describe(&self.scope.borrow_names(), &self.scope.borrow_codemap())
+    }
+
+    /// Formats a trace into a string, as by `Trace::render`.
+    pub fn format_trace(&self, trace: &Trace) -> String {
+        trace.render(&self.scope.borrow_names(), &self.scope.borrow_codemap())
+    }
+
+    /// Returns a plain representation of an error, suitable for serialization.
+    ///
+    /// Source locations are resolved using the contained `CodeMap`.
+    /// The span of a compilation error is known only if the error was
+    /// produced by the most recent compilation; likewise, the span of an
+    /// execution error is known only if it was produced by the most recent
+    /// execution.
+    pub fn error_report(&self, e: &Error) -> ErrorReport {
+        let root = e.root();
+
+        let span = match *root {
+            Error::CompileError(_) => self.error_span.get(),
+            Error::ParseError(ref e) => Some(e.span),
+            Error::ExecError(_) => self.error_location().and_then(|loc| loc.span),
+            _ => None
+        };
+
+        let backtrace = match *root {
+            Error::ExecError(_) => self.describe_trace(&self.error_trace()),
+            _ => Vec::new()
+        };
+
+        let mut report = ErrorReport{
+            code: e.code(),
+            message: display_names(&self.scope.borrow_names(), root).to_string(),
+            context: e.contexts().into_iter().map(|s| s.to_owned()).collect(),
+            file: None,
+            span: span,
+            line: None,
+            column: None,
+            backtrace: backtrace,
+        };
+
+        if let Some(span) = span {
+            let codemap = self.scope.borrow_codemap();
+
+            if let Some(hi) = codemap.try_highlight_span(span) {
+                report.file = hi.filename.map(|s| s.to_owned());
+                report.line = Some(hi.line);
+                report.column = Some(hi.col);
+            }
+        }
+
+        report
+    }
+
+    /// Renders an error as a diagnostic message in the style of `rustc`;
+    /// see `ErrorReport::render`.
+    pub fn render_error(&self, e: &Error) -> String {
+        self.error_report(e).render(&self.scope.borrow_codemap())
+    }
+
     /// Prints an error to `stderr`.
-    /// `input` is the source code which produced the error and `name`
-    /// is the optional filename of the program. These are used if the error
-    /// message refers to a span within the source code.
+    ///
+    /// The error is formatted as by `format_error`.
     pub fn display_error(&self, e: &Error) {
+        let _ = stderr().write_all(self.format_error(e).as_bytes());
+    }
+
+    /// Formats an error into a string, as printed by `display_error`.
+    ///
+    /// If the error refers to a span within source code held by the
+    /// contained `CodeMap`, the relevant source line is included.
+    /// Each line of the result, including the last, ends with a newline.
+    pub fn format_error(&self, e: &Error) -> String {
+        let mut buf = String::new();
+        self.write_error(&mut buf, e);
+        buf
+    }
+
+    fn write_error(&self, buf: &mut String, e: &Error) {
         match *e {
             Error::CompileError(ref e) => {
-                let _ = writeln!(stderr(), "compile error: {}",
-                    display_names(&self.scope.borrow_names(), e));
+                let codemap = self.scope.borrow_codemap();
+
+                match self.error_span.get().and_then(|sp| codemap.try_highlight_span(sp)) {
+                    Some(hi) => {
+                        let _ = writeln!(buf, "{}:{}:{}:compile error: {}",
+                            hi.filename.unwrap_or("<input>"), hi.line, hi.col,
+                            display_names(&self.scope.borrow_names(), e));
+                        let _ = writeln!(buf, "    {}", hi.source);
+                        let _ = writeln!(buf, "    {}", hi.highlight);
+                    }
+                    None => {
+                        let _ = writeln!(buf, "compile error: {}",
+                            display_names(&self.scope.borrow_names(), e));
+                    }
+                }
             }
             Error::DecodeError(ref e) => {
-                let _ = writeln!(stderr(), "decode error: {}", e);
+                let _ = writeln!(buf, "decode error: {}", e);
             }
             Error::EncodeError(ref e) => {
-                let _ = writeln!(stderr(), "encode error: {}", e);
+                let _ = writeln!(buf, "encode error: {}", e);
             }
             Error::ExecError(ref e) => {
-                let _ = writeln!(stderr(), "execution error: {}",
-                    display_names(&self.scope.borrow_names(), e));
+                let loc = self.error_location();
+                let codemap = self.scope.borrow_codemap();
+                let names = self.scope.borrow_names();
+
+                let func = match loc.and_then(|loc| loc.function) {
+                    Some(name) => format!(" (in function `{}`)", names.get(name)),
+                    None => String::new()
+                };
+
+                match loc.and_then(|loc| loc.span)
+                        .and_then(|sp| codemap.try_highlight_span(sp)) {
+                    Some(hi) => {
+                        let _ = writeln!(buf, "{}:{}:{}:execution error{}: {}",
+                            hi.filename.unwrap_or("<input>"), hi.line, hi.col,
+                            func, display_names(&names, e));
+                        let _ = writeln!(buf, "    {}", hi.source);
+                        let _ = writeln!(buf, "    {}", hi.highlight);
+                    }
+                    None => {
+                        let _ = writeln!(buf, "execution error{}: {}",
+                            func, display_names(&names, e));
+                    }
+                }
+
+                // A single call is already described by the location above
+                let trace = self.error_trace();
+
+                if trace.len() > 1 {
+                    buf.push_str(&trace.render(&names, &codemap));
+                }
             }
             Error::IoError(ref e) => {
-                let _ = writeln!(stderr(), "io error: {}", e);
+                let _ = writeln!(buf, "io error: {}", e);
+            }
+            Error::ParseError(ref e) => self.write_parse_error(buf, e),
+            Error::Context(ref e) => {
+                let _ = writeln!(buf, "{}:", e.context);
+                self.write_error(buf, &e.error);
             }
-            Error::ParseError(ref e) => self.display_parse_error(e),
         }
     }
 
-    fn display_parse_error(&self, e: &ParseError) {
+    fn write_parse_error(&self, buf: &mut String, e: &ParseError) {
         let codemap = self.scope.borrow_codemap();
-        let hi = codemap.highlight_span(e.span);
 
-        let mut stderr = stderr();
-        let _ = writeln!(stderr, "{}:{}:{}:parse error: {}",
-            hi.filename.unwrap_or("<input>"), hi.line, hi.col, e.kind);
-        let _ = writeln!(stderr, "    {}", hi.source);
-        let _ = writeln!(stderr, "    {}", hi.highlight);
+        match codemap.try_highlight_span(e.span) {
+            Some(hi) => {
+                let _ = writeln!(buf, "{}:{}:{}:parse error: {}",
+                    hi.filename.unwrap_or("<input>"), hi.line, hi.col, e.kind);
+                let _ = writeln!(buf, "    {}", hi.source);
+                let _ = writeln!(buf, "    {}", hi.highlight);
+            }
+            None => {
+                let _ = writeln!(buf, "parse error: {}", e.kind);
+            }
+        }
+
+        if let Some(hi) = e.open_span.and_then(|sp| codemap.try_highlight_span(sp)) {
+            let _ = writeln!(buf, "{}:{}:{}:note: list opened here",
+                hi.filename.unwrap_or("<input>"), hi.line, hi.col);
+            let _ = writeln!(buf, "    {}", hi.source);
+            let _ = writeln!(buf, "    {}", hi.highlight);
+        }
+
+        if let Some(ref sugg) = e.suggestion {
+            let _ = writeln!(buf, "help: {}",
+                display_names(&self.scope.borrow_names(), sugg));
+        }
     }
 
     /// Prints a string representation of a value to `stdout`.
@@ -117,41 +416,173 @@ impl Interpreter {
         debug_names(&self.scope.borrow_names(), value).to_string()
     }
 
+    /// Formats a value containing names, such as an `Error`, into a string.
+    ///
+    /// ```
+    /// use ketos::Interpreter;
+    ///
+    /// let interp = Interpreter::new();
+    /// let err = interp.run_code("(foo)", None).unwrap_err();
+    ///
+    /// assert_eq!(interp.display(&err), "name not found in global scope: foo");
+    /// ```
+    pub fn display<T: NameDisplay>(&self, t: &T) -> String {
+        self.display_names(t).to_string()
+    }
+
+    /// Returns a wrapper which displays a value containing names.
+    ///
+    /// The wrapper holds a borrow of the interpreter's `NameStore`;
+    /// names cannot be created while it exists.
+    pub fn display_names<'a, T: NameDisplay>(&'a self, t: &'a T) -> DisplayNames<'a, T> {
+        DisplayNames::new(self.scope.borrow_names(), t)
+    }
+
+    /// Returns a handle to the flag used to interrupt execution.
+    ///
+    /// Setting the flag, possibly from another thread, causes any code
+    /// running within the interpreter to stop promptly with an
+    /// `ExecError::Interrupted` error. The flag is cleared when the
+    /// interrupt is reported.
+    ///
+    /// ```
+    /// use std::sync::atomic::Ordering;
+    /// use ketos::{Error, ExecError, Interpreter};
+    ///
+    /// let interp = Interpreter::new();
+    ///
+    /// interp.interrupt_handle().store(true, Ordering::SeqCst);
+    ///
+    /// match interp.run_code("(define (loop) (loop)) (loop)", None) {
+    ///     Err(Error::ExecError(ExecError::Interrupted)) => (),
+    ///     _ => panic!("expected interrupt")
+    /// }
+    ///
+    /// let v = interp.run_code("(+ 1 2)", None).unwrap();
+    /// assert_eq!(interp.format_value(&v), "3");
+    /// ```
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.scope.get_interrupt().clone()
+    }
+
+    /// Returns counters for the resources used by the most recent top-level
+    /// invocation, such as `run_code`, `run_file`, `call`, or `execute`.
+    ///
+    /// For methods which compile source code, the counters include work
+    /// performed during compilation, such as macro expansions and module loads.
+    ///
+    /// ```
+    /// use ketos::Interpreter;
+    ///
+    /// let interp = Interpreter::new();
+    ///
+    /// interp.run_code(r#"
+    ///     (use math (sqrt))
+    ///     (macro (twice a) `(do ,a ,a))
+    ///     (twice (sqrt 4.0))
+    ///     "#, None).unwrap();
+    ///
+    /// let stats = interp.last_run_stats();
+    ///
+    /// assert_eq!(stats.module_loads, 1);
+    /// assert_eq!(stats.macro_expansions, 1);
+    /// assert!(stats.instructions > 0);
+    /// ```
+    pub fn last_run_stats(&self) -> Metrics {
+        self.scope.get_metrics().get()
+    }
+
     /// Executes a code object taking no parameters.
     pub fn execute(&self, code: Code) -> Result<Value, Error> {
-        let v = try!(execute(&self.scope, Rc::new(code)));
-        Ok(v)
+        self.begin_run();
+        self.execute_code(code)
     }
 
     /// Executes a series of code objects sequentially and returns the value
     /// of the final expression. If `code` is empty, the value `()` is returned.
     pub fn execute_program(&self, code: Vec<Code>) -> Result<Value, Error> {
+        self.begin_run();
+        self.execute_code_seq(code)
+    }
+
+    fn execute_code_seq(&self, code: Vec<Code>) -> Result<Value, Error> {
         let mut last_v = Value::Unit;
 
         for c in code {
-            last_v = try!(self.execute(c));
+            last_v = try!(self.execute_code(c));
         }
 
         Ok(last_v)
     }
 
+    fn execute_code(&self, code: Code) -> Result<Value, Error> {
+        self.scope.get_arena().reset();
+        let v = try!(execute(&self.scope, Rc::new(code)));
+        Ok(v)
+    }
+
     /// Calls a named function with the given arguments.
     pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
         let name = self.scope.borrow_names_mut().add(name);
+        self.scope.set_error_location(None);
 
         let v = try!(self.get_value_name(name).ok_or(ExecError::NameError(name)));
         self.call_value(v, args)
     }
 
-    /// Calls a function with the given arguments.
+    /// Returns a typed handle to a named function, which may be stored
+    /// and called later.
+    ///
+    /// ```
+    /// # use ketos::Interpreter;
+    /// let interp = Interpreter::new();
+    /// interp.run_code("(define (greet name) (format \"Hello, ~a!\" name))", None).unwrap();
+    ///
+    /// let greet = interp.get_callable::<(&str,), String>("greet").unwrap();
+    /// assert_eq!(greet.call(("world",)).unwrap(), "Hello, world!");
+    /// ```
+    pub fn get_callable<A, R>(&self, name: &str) -> Result<Callable<A, R>, Error>
+            where A: IntoArgs, R: FromValue {
+        let name = self.scope.borrow_names_mut().add(name);
+        self.scope.set_error_location(None);
+
+        let v = try!(self.get_value_name(name).ok_or(ExecError::NameError(name)));
+        Ok(try!(Callable::new(&self.scope, v)))
+    }
+
+    /// Returns a named function as a boxed closure, which converts
+    /// its arguments and result.
+    ///
+    /// ```
+    /// # use ketos::Interpreter;
+    /// let interp = Interpreter::new();
+    /// interp.run_code("(define (add a b) (+ a b))", None).unwrap();
+    ///
+    /// let add = interp.get_fn::<(i32, i32), i32>("add").unwrap();
+    /// assert_eq!(add(1, 2).unwrap(), 3);
+    /// ```
+    pub fn get_fn<A, R>(&self, name: &str) -> Result<Box<A::Closure>, Error>
+            where A: IntoFn<R>, R: FromValue {
+        self.get_callable::<A, R>(name).map(Callable::into_fn)
+    }
+
+    /// Calls a function value with the given arguments.
+    ///
+    /// The value may be a function, lambda, or foreign function.
+    /// This begins a new top-level invocation; foreign functions which
+    /// receive a function value as an argument should instead call it
+    /// using `exec::call_function` with the scope they are given.
     pub fn call_value(&self, value: Value, args: Vec<Value>) -> Result<Value, Error> {
+        self.scope.get_arena().reset();
+        self.begin_run();
         let v = try!(call_function(&self.scope, value, args));
         Ok(v)
     }
 
     fn call_main(&self) -> Result<(), Error> {
         if let Some(v) = self.get_value("main") {
-            try!(self.call_value(v, Vec::new()));
+            self.scope.get_arena().reset();
+            try!(call_function(&self.scope, v, Vec::new()));
         }
         Ok(())
     }
@@ -183,36 +614,98 @@ impl Interpreter {
 
     /// Compiles and executes the contents of a file.
     pub fn run_file(&self, path: &Path) -> Result<(), Error> {
-        let mut f = try!(File::open(path)
-            .map_err(|e| IoError::new(IoMode::Open, path, e)));
+        let buf = try!(read_file(path));
+        self.run_main(&buf, path.to_string_lossy().into_owned())
+    }
 
-        let mut buf = String::new();
+    /// Runs a script file and returns its exit status.
+    ///
+    /// The file is loaded into its own scope, as a module would be, and its
+    /// `main` function is called with a single argument: a list of the given
+    /// `args` as strings. The value returned by `main` determines the exit status:
+    ///
+    /// * `()` or `true` produces `0`
+    /// * `false` produces `1`
+    /// * an integer produces its own value
+    ///
+    /// Any other value produces an error, as does a script without
+    /// a `main` function.
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use ketos::Interpreter;
+    ///
+    /// let interp = Interpreter::new();
+    /// let args = std::env::args().skip(2).collect::<Vec<_>>();
+    ///
+    /// let status = match interp.run_script_file(Path::new("script.ket"), &args) {
+    ///     Ok(status) => status,
+    ///     Err(e) => {
+    ///         interp.display_error(&e);
+    ///         1
+    ///     }
+    /// };
+    ///
+    /// std::process::exit(status);
+    /// ```
+    pub fn run_script_file<T: AsRef<str>>(&self, path: &Path, args: &[T])
+            -> Result<i32, Error> {
+        let buf = try!(read_file(path));
+        let scope = GlobalScope::new_using(&self.scope);
 
-        try!(f.read_to_string(&mut buf)
-            .map_err(|e| IoError::new(IoMode::Read, path, e)));
+        self.begin_run();
 
-        self.run_main(&buf, path.to_string_lossy().into_owned())
+        let code = try!(self.compile_source_in(&scope, &buf,
+            Some(path.to_string_lossy().into_owned()), true));
+
+        for c in code {
+            scope.get_arena().reset();
+            try!(execute(&scope, Rc::new(c)));
+        }
+
+        let main = scope.borrow_names_mut().add("main");
+        let main = try!(scope.get_value(main).ok_or(ExecError::NameError(main)));
+
+        let args = args.iter()
+            .map(|s| s.as_ref().into())
+            .collect::<Vec<Value>>();
+
+        scope.get_arena().reset();
+        let v = try!(call_function(&scope, main, vec![args.into()]));
+
+        exit_status(v)
     }
 
     /// Compiles and executes an input expression.
     pub fn run_single_expr(&self, input: &str, path: Option<String>) -> Result<Value, Error> {
+        self.begin_run();
         let c = try!(self.compile_single_expr(input, path));
-        self.execute(c)
+        self.execute_code(c)
     }
 
     /// Parses and executes a series of expressions and return the last value.
     pub fn run_code(&self, input: &str, path: Option<String>) -> Result<Value, Error> {
+        self.begin_run();
         let code = try!(self.compile_code(input, path));
-        self.execute_program(code)
+        self.execute_code_seq(code)
     }
 
     /// Compiles and compiles a single expression and returns a code object.
     /// If the input string contains more than one expression, an error is returned.
     pub fn compile_single_expr(&self, input: &str, path: Option<String>) -> Result<Code, Error> {
-        let v = try!(self.parse_single_expr(input, path));
+        self.error_span.set(None);
 
-        let code = try!(compile(&self.scope, &v));
-        Ok(code)
+        let offset = self.scope.borrow_codemap_mut().add_source(input, path);
+
+        let (v, spans) = {
+            let mut ns = self.scope.borrow_names_mut();
+            let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+            self.scope.configure_parser(&mut p);
+            let v = try!(p.parse_single_expr());
+            (v, p.take_spans())
+        };
+
+        self.compile_spanned(&v, &spans)
     }
 
     /// Compiles and compiles a series of expressions.
@@ -220,6 +713,69 @@ impl Interpreter {
         self.compile_code(input, None)
     }
 
+    /// Expands a value, if it is a macro call, until the result is not a
+    /// macro call; see `compile::macroexpand`.
+    ///
+    /// ```
+    /// use ketos::Interpreter;
+    ///
+    /// let interp = Interpreter::new();
+    /// interp.run_code("(macro (unless c :rest body) `(if ,c () (do ,@body)))", None).unwrap();
+    ///
+    /// let v = interp.parse_value("(unless a (b))").unwrap();
+    /// let v = interp.expand_macros(&v).unwrap();
+    ///
+    /// assert_eq!(interp.format_value(&v), "(if a () (do (b)))");
+    /// ```
+    pub fn expand_macros(&self, value: &Value) -> Result<Value, Error> {
+        macroexpand(&self.scope, value)
+    }
+
+    /// Writes a value in a form which is parsed by `parse_value`
+    /// to produce an equal value.
+    ///
+    /// An error is returned if the value is not readable;
+    /// see `Value::find_unreadable_names`.
+    ///
+    /// ```
+    /// use ketos::{Interpreter, Value};
+    ///
+    /// let interp = Interpreter::new();
+    /// let v = interp.run_single_expr(r#"(list "a\nb" #'c' 1.0 :d)"#, None).unwrap();
+    ///
+    /// let s = interp.write_value(&v).unwrap();
+    /// assert_eq!(s, r#"("a\nb" #'c' 1.0 :d)"#);
+    ///
+    /// let v2 = interp.parse_value(&s).unwrap();
+    /// assert!(v.is_identical(&v2));
+    /// ```
+    pub fn write_value(&self, value: &Value) -> Result<String, Error> {
+        if let Some(v) = value.find_unreadable_names(&self.scope.borrow_names()) {
+            return Err(From::from(ExecError::expected("readable value", v)));
+        }
+
+        Ok(debug_names(&self.scope.borrow_names(), &Readable(value)).to_string())
+    }
+
+    /// Parses a single value written by `write_value`.
+    ///
+    /// Unlike `parse_single_expr`, reader macros are not applied and
+    /// the source is not recorded in the contained `CodeMap`.
+    pub fn parse_value(&self, input: &str) -> Result<Value, Error> {
+        let offset = self.scope.borrow_codemap_mut().reserve_source(input);
+
+        let mut ns = self.scope.borrow_names_mut();
+        let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+
+        if let Some(depth) = self.scope.get_restrictions().max_parse_depth {
+            p.set_max_depth(depth);
+        }
+
+        let v = try!(p.parse_single_expr());
+
+        Ok(v)
+    }
+
     /// Parses a single expression and returns it as a `Value`.
     /// If `input` contains more than one expression, an error is returned.
     pub fn parse_single_expr(&self, input: &str, path: Option<String>) -> Result<Value, Error> {
@@ -227,23 +783,78 @@ impl Interpreter {
 
         let mut ns = self.scope.borrow_names_mut();
         let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+        self.scope.configure_parser(&mut p);
         let v = try!(p.parse_single_expr());
 
         Ok(v)
     }
 
+    /// Parses a single expression from the beginning of `input`, returning
+    /// the value and the byte offset within `input` at which the expression
+    /// ended.
+    ///
+    /// Input following the expression is not examined, allowing expressions
+    /// to be embedded within other text. If `input` contains no expression,
+    /// an error is returned.
+    pub fn parse_expr_prefix(&self, input: &str, path: Option<String>)
+            -> Result<(Value, usize), Error> {
+        let offset = self.scope.borrow_codemap_mut().add_source(input, path);
+
+        let mut ns = self.scope.borrow_names_mut();
+        let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+        self.scope.configure_parser(&mut p);
+
+        match try!(p.parse_next_expr()) {
+            Some((v, end)) => Ok((v, (end - offset) as usize)),
+            None => Err(From::from(ParseError::new(
+                Span::empty(offset + input.len() as u32),
+                ParseErrorKind::UnexpectedEof)))
+        }
+    }
+
     /// Parses a series of expressions and returns them as `Value`s.
     pub fn parse_exprs(&self, input: &str, path: Option<String>) -> Result<Vec<Value>, Error> {
         let offset = self.scope.borrow_codemap_mut().add_source(input, path);
 
         let mut ns = self.scope.borrow_names_mut();
         let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+        self.scope.configure_parser(&mut p);
 
         let v = try!(p.parse_exprs());
 
         Ok(v)
     }
 
+    /// Parses a series of expressions, recovering from errors to report
+    /// all errors found in the input; see `Parser::parse_exprs_recover`.
+    ///
+    /// Each error may be formatted by `format_error`.
+    ///
+    /// ```
+    /// use ketos::{Error, Interpreter};
+    ///
+    /// let interp = Interpreter::new();
+    ///
+    /// let errs = interp.parse_exprs_recover(
+    ///     "(define a 1)\n(define b #x)\n(define c \"d)", None).unwrap_err();
+    ///
+    /// assert_eq!(errs.len(), 2);
+    /// assert_eq!(interp.format_error(&Error::ParseError(errs[0])),
+    ///     "<input>:2:10:parse error: unknown dispatch character: 'x'\n\
+    ///     \x20   (define b #x)\n\
+    ///     \x20             ^~\n");
+    /// ```
+    pub fn parse_exprs_recover(&self, input: &str, path: Option<String>)
+            -> Result<Vec<Value>, Vec<ParseError>> {
+        let offset = self.scope.borrow_codemap_mut().add_source(input, path);
+
+        let mut ns = self.scope.borrow_names_mut();
+        let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+        self.scope.configure_parser(&mut p);
+
+        p.parse_exprs_recover()
+    }
+
     /// Parses a series of expressions from the contents of a file and
     /// returns them as `Value`s.
     pub fn parse_file(&self, input: &str, path: Option<String>) -> Result<Vec<Value>, Error> {
@@ -251,6 +862,7 @@ impl Interpreter {
 
         let mut ns = self.scope.borrow_names_mut();
         let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+        self.scope.configure_parser(&mut p);
         p.skip_shebang();
 
         let v = try!(p.parse_exprs());
@@ -259,15 +871,107 @@ impl Interpreter {
     }
 
     fn compile_code(&self, input: &str, path: Option<String>) -> Result<Vec<Code>, Error> {
-        let v = try!(self.parse_exprs(input, path));
+        self.compile_source(input, path, false)
+    }
 
-        v.iter().map(|v| compile(&self.scope, v)).collect()
+    fn compile_source(&self, input: &str, path: Option<String>, shebang: bool)
+            -> Result<Vec<Code>, Error> {
+        self.compile_source_in(&self.scope, input, path, shebang)
+    }
+
+    fn compile_source_in(&self, scope: &Scope, input: &str, path: Option<String>,
+            shebang: bool) -> Result<Vec<Code>, Error> {
+        self.error_span.set(None);
+
+        let offset = scope.borrow_codemap_mut().add_source(input, path);
+
+        let (exprs, spans) = {
+            let mut ns = scope.borrow_names_mut();
+            let mut p = Parser::new(&mut ns, Lexer::new(input, offset));
+            scope.configure_parser(&mut p);
+
+            if shebang {
+                p.skip_shebang();
+            }
+
+            let exprs = try!(p.parse_exprs());
+            (exprs, p.take_spans())
+        };
+
+        exprs.iter().map(|v| self.compile_spanned_in(scope, v, &spans)).collect()
+    }
+
+    fn compile_spanned(&self, value: &Value, spans: &SpanMap) -> Result<Code, Error> {
+        self.compile_spanned_in(&self.scope, value, spans)
+    }
+
+    fn compile_spanned_in(&self, scope: &Scope, value: &Value, spans: &SpanMap)
+            -> Result<Code, Error> {
+        compile_with_spans(scope, value, spans).map_err(|(e, span)| {
+            self.error_span.set(span);
+            e
+        })
+    }
+
+    /// Resets state maintained for each top-level invocation.
+    fn begin_run(&self) {
+        self.scope.get_metrics().reset();
+        self.scope.reset_instructions();
+        self.scope.set_error_location(None);
     }
 
     fn run_main(&self, input: &str, path: String) -> Result<(), Error> {
-        let exprs = try!(self.parse_file(input, Some(path)));
-        let code = try!(exprs.iter().map(|v| compile(&self.scope, v)).collect());
-        try!(self.execute_program(code));
+        self.begin_run();
+        let code = try!(self.compile_source(input, Some(path), true));
+        try!(self.execute_code_seq(code));
         self.call_main()
     }
 }
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        // Loaded modules hold a reference to the registry, so it must be
+        // cleared explicitly when the last handle to this scope is released.
+        // A registry shared with other interpreters is cleared only when
+        // the last of them is released.
+        if Rc::strong_count(&self.scope) == 1 {
+            match self.base {
+                Some(ref base) if Rc::strong_count(base) > 1 => (),
+                _ => self.scope.get_modules().clear()
+            }
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut f = try!(File::open(path)
+        .map_err(|e| IoError::new(IoMode::Open, path, e)));
+
+    let mut buf = String::new();
+
+    try!(f.read_to_string(&mut buf)
+        .map_err(|e| IoError::new(IoMode::Read, path, e)));
+
+    Ok(buf)
+}
+
+/// Converts the value returned by a script's `main` function to an exit status.
+fn exit_status(v: Value) -> Result<i32, Error> {
+    match v {
+        Value::Unit | Value::Bool(true) => Ok(0),
+        Value::Bool(false) => Ok(1),
+        Value::Integer(ref i) => i.to_i32()
+            .ok_or_else(|| From::from(ExecError::Overflow)),
+        ref v => Err(From::from(ExecError::expected("integer", v)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_loader() -> Box<ModuleLoader> {
+    Box::new(FileModuleLoader::new())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_loader() -> Box<ModuleLoader> {
+    Box::new(::module::BuiltinModuleLoader)
+}
@@ -1,9 +1,16 @@
 //! Creates an abstraction layer to I/O operations
 
+use std::cell::RefCell;
 use std::fmt::{self, Arguments};
 use std::fs;
-use std::io::{self, Stdout, Stderr, Write};
+use std::io::{self, BufRead, Stdin, Stdout, Stderr, Write};
+use std::str;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use exec::ExecError;
+use name::NameStore;
+use value::{ForeignValue, FromValueRef, Value};
 
 /// Describes the cause of an `io::Error`.
 #[derive(Debug)]
@@ -105,6 +112,97 @@ macro_rules! shared_write {
 shared_write!{ Stdout => "<stdout>" }
 shared_write!{ Stderr => "<stderr>" }
 
+/// A reader object that can operate using shared references.
+pub trait SharedRead {
+    /// Analogous to `std::io::BufRead::read_line`; reads bytes into `buf`
+    /// until a newline is reached, returning the number of bytes read.
+    ///
+    /// A return value of `0` indicates the end of input.
+    fn read_line(&self, buf: &mut String) -> Result<usize, IoError>;
+
+    /// Reads a single character from the input stream.
+    ///
+    /// Returns `None` at the end of input.
+    fn read_char(&self) -> Result<Option<char>, IoError>;
+}
+
+impl SharedRead for Stdin {
+    fn read_line(&self, buf: &mut String) -> Result<usize, IoError> {
+        let mut lock = self.lock();
+        BufRead::read_line(&mut lock, buf)
+            .map_err(|e| IoError::new(IoMode::Read, Path::new("<stdin>"), e))
+    }
+
+    fn read_char(&self) -> Result<Option<char>, IoError> {
+        let mut lock = self.lock();
+        read_char(&mut lock)
+            .map_err(|e| IoError::new(IoMode::Read, Path::new("<stdin>"), e))
+    }
+}
+
+/// Wraps a buffered reader, such as an `io::Cursor`, as a shared reader.
+pub struct SharedReader<R> {
+    reader: RefCell<R>,
+    path: PathBuf,
+}
+
+impl<R: BufRead> SharedReader<R> {
+    /// Creates a new `SharedReader` from a reader.
+    pub fn new(reader: R) -> SharedReader<R> {
+        SharedReader::with_path(reader, PathBuf::from("<input>"))
+    }
+
+    /// Creates a new `SharedReader` from a reader and a path
+    /// used to describe the source in error values.
+    pub fn with_path(reader: R, path: PathBuf) -> SharedReader<R> {
+        SharedReader{
+            reader: RefCell::new(reader),
+            path: path,
+        }
+    }
+}
+
+impl<R: BufRead> SharedRead for SharedReader<R> {
+    fn read_line(&self, buf: &mut String) -> Result<usize, IoError> {
+        self.reader.borrow_mut().read_line(buf)
+            .map_err(|e| IoError::new(IoMode::Read, &self.path, e))
+    }
+
+    fn read_char(&self) -> Result<Option<char>, IoError> {
+        read_char(&mut *self.reader.borrow_mut())
+            .map_err(|e| IoError::new(IoMode::Read, &self.path, e))
+    }
+}
+
+/// Reads a single UTF-8 encoded character from a buffered reader.
+fn read_char<R: BufRead + ?Sized>(r: &mut R) -> io::Result<Option<char>> {
+    let first = match try!(r.fill_buf()).first() {
+        Some(&b) => b,
+        None => return Ok(None)
+    };
+    r.consume(1);
+
+    let width = match first {
+        0x00 ... 0x7f => 1,
+        0xc0 ... 0xdf => 2,
+        0xe0 ... 0xef => 3,
+        0xf0 ... 0xf7 => 4,
+        _ => return Err(invalid_utf8())
+    };
+
+    let mut buf = [first, 0, 0, 0];
+    try!(r.read_exact(&mut buf[1..width]));
+
+    match str::from_utf8(&buf[..width]) {
+        Ok(s) => Ok(s.chars().next()),
+        Err(_) => Err(invalid_utf8())
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
 /// A shared writer which sends all data into the void.
 pub struct Sink;
 
@@ -146,3 +244,126 @@ impl SharedWrite for File {
             .map_err(|e| IoError::new(IoMode::Write, &self.path, e))
     }
 }
+
+/// A named input or output stream which may be passed to scripts as a value.
+///
+/// Ports are created by the host program. Scripts may write to an output port
+/// using `format`, `print`, and `println`; read from an input port using
+/// `read-line` and `read-char`; and release a port using `close`.
+pub struct Port {
+    name: String,
+    reader: RefCell<Option<Rc<SharedRead>>>,
+    writer: RefCell<Option<Rc<SharedWrite>>>,
+}
+
+impl Port {
+    /// Creates a new `Port` with the given name and optional
+    /// reader and writer.
+    pub fn new(name: &str, reader: Option<Rc<SharedRead>>,
+            writer: Option<Rc<SharedWrite>>) -> Port {
+        Port{
+            name: name.to_owned(),
+            reader: RefCell::new(reader),
+            writer: RefCell::new(writer),
+        }
+    }
+
+    /// Creates a new `Port` which reads from the given source.
+    pub fn input(name: &str, reader: Rc<SharedRead>) -> Port {
+        Port::new(name, Some(reader), None)
+    }
+
+    /// Creates a new `Port` which writes to the given destination.
+    pub fn output(name: &str, writer: Rc<SharedWrite>) -> Port {
+        Port::new(name, None, Some(writer))
+    }
+
+    /// Returns the name of the port.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether the port has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.reader.borrow().is_none() && self.writer.borrow().is_none()
+    }
+
+    /// Returns the port's reader or an error if the port
+    /// is closed or does not support reading.
+    pub fn reader(&self) -> Result<Rc<SharedRead>, IoError> {
+        match *self.reader.borrow() {
+            Some(ref r) => Ok(r.clone()),
+            None => Err(self.unavailable(IoMode::Read))
+        }
+    }
+
+    /// Returns the port's writer or an error if the port
+    /// is closed or does not support writing.
+    pub fn writer(&self) -> Result<Rc<SharedWrite>, IoError> {
+        match *self.writer.borrow() {
+            Some(ref w) => Ok(w.clone()),
+            None => Err(self.unavailable(IoMode::Write))
+        }
+    }
+
+    /// Flushes any output and releases the port's reader and writer.
+    ///
+    /// Closing a port which is already closed has no effect.
+    pub fn close(&self) -> Result<(), IoError> {
+        self.reader.borrow_mut().take();
+
+        match self.writer.borrow_mut().take() {
+            Some(w) => w.flush(),
+            None => Ok(())
+        }
+    }
+
+    fn unavailable(&self, mode: IoMode) -> IoError {
+        let msg = if self.is_closed() {
+            "port is closed"
+        } else if mode == IoMode::Read {
+            "port does not support reading"
+        } else {
+            "port does not support writing"
+        };
+
+        IoError::new(mode, Path::new(&self.name),
+            io::Error::new(io::ErrorKind::Other, msg))
+    }
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Port {{ name: {:?}, ... }}", self.name)
+    }
+}
+
+impl ForeignValue for Port {
+    fn fmt_debug(&self, _names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_closed() {
+            write!(f, "<port {:?} (closed)>", self.name)
+        } else {
+            write!(f, "<port {:?}>", self.name)
+        }
+    }
+
+    fn type_name(&self) -> &'static str { "port" }
+}
+
+impl<'a> FromValueRef<'a> for &'a Port {
+    fn from_value_ref(v: &'a Value) -> Result<&'a Port, ExecError> {
+        if let Value::Foreign(ref fv) = *v {
+            if let Some(port) = fv.downcast_ref::<Port>() {
+                return Ok(port);
+            }
+        }
+
+        Err(ExecError::expected("port", v))
+    }
+}
+
+impl From<Port> for Value {
+    fn from(port: Port) -> Value {
+        Value::new_foreign(port)
+    }
+}
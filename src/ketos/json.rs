@@ -0,0 +1,136 @@
+//! Implements conversion between Ketos values and `serde_json` values.
+//!
+//! Ketos values are converted into JSON values as follows:
+//!
+//! * `()` becomes `null`
+//! * Booleans become booleans
+//! * Integers become numbers; integers outside the range of `i64` and `u64`
+//!   become strings of decimal digits
+//! * Floats become numbers; infinite and NaN values produce an error
+//! * Ratios become floating point numbers
+//! * Characters, strings, and names become strings
+//! * Keywords become strings, without the leading colon
+//! * Lists and sets become arrays
+//! * Maps become objects; keys must be strings, names, or keywords
+//! * Struct values become objects, mapping field names to field values
+//!
+//! All other values, such as functions and foreign values, produce an error.
+//!
+//! JSON values are converted into Ketos values as follows:
+//!
+//! * `null` becomes `()`
+//! * Booleans become booleans
+//! * Numbers become integers, if they have no fractional part,
+//!   or otherwise floats
+//! * Strings become strings
+//! * Arrays become lists; an empty array becomes `()`
+//! * Objects become maps with keyword keys,
+//!   e.g. `{"a": 1, "b": 2}` becomes `{:a 1 :b 2}`
+
+use serde_json::{self, Map, Number};
+
+use exec::ExecError;
+use integer::Integer;
+use map::Map as ValueMap;
+use name::NameStore;
+use value::Value;
+
+/// Converts a `Value` into a JSON value.
+///
+/// The given `NameStore` is used to resolve names, keywords,
+/// and struct field names into strings.
+pub fn to_json(names: &NameStore, value: &Value) -> Result<serde_json::Value, ExecError> {
+    Ok(match *value {
+        Value::Unit => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(b),
+        Value::Integer(ref i) => integer_to_json(i),
+        Value::Float(f) => try!(float_to_json(f, value)),
+        Value::Ratio(ref r) => match r.to_f64() {
+            Some(f) => try!(float_to_json(f, value)),
+            None => return Err(ExecError::Overflow)
+        },
+        Value::Char(c) => serde_json::Value::String(c.to_string()),
+        Value::String(ref s) => serde_json::Value::String(s.to_string()),
+        Value::Name(name) | Value::Keyword(name) =>
+            serde_json::Value::String(names.get(name).to_owned()),
+        Value::List(ref li) => serde_json::Value::Array(try!(li.iter()
+            .map(|v| to_json(names, v)).collect())),
+        Value::Set(ref s) => serde_json::Value::Array(try!(s.iter()
+            .map(|v| to_json(names, v)).collect())),
+        Value::Map(ref m) => {
+            let mut map = Map::new();
+
+            for (k, v) in m.iter() {
+                let key = match *k {
+                    Value::String(ref s) => s.to_string(),
+                    Value::Name(name) | Value::Keyword(name) =>
+                        names.get(name).to_owned(),
+                    ref k => return Err(ExecError::expected("string or keyword", k))
+                };
+
+                map.insert(key, try!(to_json(names, v)));
+            }
+
+            serde_json::Value::Object(map)
+        }
+        Value::Struct(ref s) => {
+            let mut map = Map::new();
+
+            for &(name, ref v) in &s.fields {
+                map.insert(names.get(name).to_owned(), try!(to_json(names, v)));
+            }
+
+            serde_json::Value::Object(map)
+        }
+        ref v => return Err(ExecError::expected("json value", v))
+    })
+}
+
+fn integer_to_json(i: &Integer) -> serde_json::Value {
+    if let Some(n) = i.to_i64() {
+        serde_json::Value::Number(n.into())
+    } else if let Some(n) = i.to_u64() {
+        serde_json::Value::Number(n.into())
+    } else {
+        serde_json::Value::String(i.to_string())
+    }
+}
+
+fn float_to_json(f: f64, value: &Value) -> Result<serde_json::Value, ExecError> {
+    match Number::from_f64(f) {
+        Some(n) => Ok(serde_json::Value::Number(n)),
+        None => Err(ExecError::expected("finite number", value))
+    }
+}
+
+/// Converts a JSON value into a `Value`.
+///
+/// The given `NameStore` is used to create keywords from object keys.
+pub fn from_json(names: &mut NameStore, json: &serde_json::Value) -> Value {
+    match *json {
+        serde_json::Value::Null => Value::Unit,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(ref n) => {
+            if let Some(i) = n.as_i64() {
+                i.into()
+            } else if let Some(u) = n.as_u64() {
+                u.into()
+            } else {
+                n.as_f64().unwrap_or(0.0).into()
+            }
+        }
+        serde_json::Value::String(ref s) => s[..].into(),
+        serde_json::Value::Array(ref arr) => arr.iter()
+            .map(|v| from_json(names, v)).collect::<Vec<_>>().into(),
+        serde_json::Value::Object(ref map) => {
+            let mut values = ValueMap::new();
+
+            for (k, v) in map {
+                let key = Value::Keyword(names.add(k));
+                values.insert(key, from_json(names, v)).expect("invalid map key");
+            }
+
+            values.into()
+        }
+    }
+}
@@ -3,6 +3,8 @@
 use std::iter::{once, repeat};
 use std::str::CharIndices;
 
+use unicode_xid::UnicodeXID;
+
 use parser::{ParseError, ParseErrorKind};
 use string;
 
@@ -13,15 +15,30 @@ pub enum Token<'lex> {
     LeftParen,
     /// Right parenthesis `)`
     RightParen,
+    /// Left bracket `[`
+    LeftBracket,
+    /// Right bracket `]`
+    RightBracket,
+    /// Left brace `{`
+    LeftBrace,
+    /// Right brace `}`
+    RightBrace,
+    /// Left brace opening a set literal `#{`
+    LeftSetBrace,
     /// A series of line comments beginning with `;;`,
     /// used to document declared values.
     DocComment(&'lex str),
+    /// A line comment or block comment; only produced when enabled
+    /// by `Lexer::set_comments`
+    Comment(&'lex str),
     /// Floating point literal
     Float(&'lex str),
     /// Integer literal in a given radix
     Integer(&'lex str, u32),
-    /// Ratio literal
-    Ratio(&'lex str),
+    /// Ratio literal in a given radix
+    Ratio(&'lex str, u32),
+    /// Exact decimal literal, excluding the `d` suffix
+    Decimal(&'lex str),
     /// Character literal
     Char(&'lex str),
     /// String literal
@@ -38,6 +55,12 @@ pub enum Token<'lex> {
     CommaAt,
     /// Single-quote `'`
     Quote,
+    /// Dispatch character following `#`, handled by a reader macro
+    Dispatch(char),
+    /// Datum label definition `#n=`; contains the label number
+    Label(&'lex str),
+    /// Datum label reference `#n#`; contains the label number
+    LabelRef(&'lex str),
     /// End of input stream
     End,
 }
@@ -48,10 +71,17 @@ impl<'lex> Token<'lex> {
         match *self {
             Token::LeftParen => "(",
             Token::RightParen => ")",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftSetBrace => "#{",
             Token::DocComment(_) => "doc-comment",
+            Token::Comment(_) => "comment",
             Token::Float(_) => "float",
             Token::Integer(_, _) => "integer",
-            Token::Ratio(_) => "ratio",
+            Token::Ratio(..) => "ratio",
+            Token::Decimal(_) => "decimal",
             Token::Name(_) => "name",
             Token::Char(_) => "char",
             Token::String(_) => "string",
@@ -60,6 +90,9 @@ impl<'lex> Token<'lex> {
             Token::Comma => ",",
             Token::CommaAt => ",@",
             Token::Quote => "'",
+            Token::Dispatch(_) => "dispatch",
+            Token::Label(_) => "label",
+            Token::LabelRef(_) => "label-reference",
             Token::End => "end-of-file",
         }
     }
@@ -84,6 +117,17 @@ pub struct SpanDisplay<'a> {
     pub highlight: String,
 }
 
+/// Location of the beginning of a span within a source recorded in a `CodeMap`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Position<'a> {
+    /// Filename
+    pub filename: Option<&'a str>,
+    /// Line number, beginning at `1`
+    pub line: usize,
+    /// Column offset, in characters, beginning at `0`
+    pub col: usize,
+}
+
 /// Returns a structure which helps in highlighting a span within a body of text.
 ///
 /// # Panics
@@ -124,16 +168,28 @@ pub fn highlight_span(text: &str, span: Span) -> SpanDisplay {
 }
 
 /// Contains source code of parsed programs
+///
+/// Recording of source may be disabled, for hosts which compile large amounts
+/// of code and do not require source locations in error messages. While
+/// disabled, sources are not retained, and spans within them cannot be
+/// highlighted.
 #[derive(Clone, Debug)]
 pub struct CodeMap {
     text: String,
     files: Vec<File>,
+    /// Offset at which the next source will begin,
+    /// including any sources which were not recorded
+    end: BytePos,
+    enabled: bool,
 }
 
 #[derive(Clone, Debug)]
 struct File {
     path: Option<String>,
+    /// Offset assigned to the beginning of the source
     begin: BytePos,
+    /// Position of the source within `text`
+    text_begin: usize,
 }
 
 impl CodeMap {
@@ -142,19 +198,47 @@ impl CodeMap {
         CodeMap{
             text: String::new(),
             files: Vec::new(),
+            end: 0,
+            enabled: true,
         }
     }
 
+    /// Returns whether sources added to the codemap are recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sets whether sources added to the codemap are recorded.
+    /// Sources recorded previously are retained.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
     /// Adds a source to the codemap, returning its offset in the internal buffer.
+    ///
+    /// If recording is disabled, the source is not retained, though it is
+    /// still assigned a distinct range of offsets.
     pub fn add_source(&mut self, text: &str, path: Option<String>) -> BytePos {
-        let begin = self.text.len() as BytePos;
+        let begin = self.end;
+        self.end += text.len() as BytePos;
+
+        if self.enabled {
+            self.files.push(File{
+                path: path,
+                begin: begin,
+                text_begin: self.text.len(),
+            });
+            self.text.push_str(text);
+        }
 
-        self.text.push_str(text);
-        self.files.push(File{
-            path: path,
-            begin: begin,
-        });
+        begin
+    }
 
+    /// Assigns a distinct range of offsets to a source without recording it,
+    /// regardless of whether recording is enabled.
+    pub fn reserve_source(&mut self, text: &str) -> BytePos {
+        let begin = self.end;
+        self.end += text.len() as BytePos;
         begin
     }
 
@@ -162,6 +246,7 @@ impl CodeMap {
     pub fn clear(&mut self) {
         self.text.clear();
         self.files.clear();
+        self.end = 0;
     }
 
     /// Highlights a span within the codemap.
@@ -170,29 +255,105 @@ impl CodeMap {
     ///
     /// Panics if `span` is not valid.
     pub fn highlight_span(&self, span: Span) -> SpanDisplay {
+        match self.try_highlight_span(span) {
+            Some(hi) => hi,
+            None => panic!("span {:?} does not refer to a recorded source", span)
+        }
+    }
+
+    /// Highlights a span within the codemap, if the span lies within
+    /// a single recorded source.
+    pub fn try_highlight_span(&self, span: Span) -> Option<SpanDisplay> {
+        self.find_source(span).map(|(path, text, span)| SpanDisplay{
+            filename: path,
+            ..highlight_span(text, span)
+        })
+    }
+
+    /// Returns the filename, line, and column at which a span begins,
+    /// if the span lies within a single recorded source.
+    ///
+    /// ```
+    /// use ketos::lexer::{CodeMap, Position, Span};
+    ///
+    /// let mut codemap = CodeMap::new();
+    /// codemap.add_source("(foo)\n(bar baz)", Some("a.ket".to_owned()));
+    ///
+    /// let span = Span{lo: 11, hi: 14};
+    ///
+    /// assert_eq!(codemap.position(span),
+    ///     Some(Position{filename: Some("a.ket"), line: 2, col: 5}));
+    /// assert_eq!(codemap.source_line(span), Some("(bar baz)"));
+    /// assert_eq!(codemap.source_text(span), Some("baz"));
+    /// ```
+    pub fn position(&self, span: Span) -> Option<Position> {
+        self.find_source(span).map(|(path, text, span)| {
+            let lo = span.lo as usize;
+            let line_start = text[..lo].rfind('\n').map_or(0, |pos| pos + 1);
+
+            Position{
+                filename: path,
+                line: text[..line_start].matches('\n').count() + 1,
+                col: text[line_start..lo].chars().count(),
+            }
+        })
+    }
+
+    /// Returns the line of source containing the beginning of a span,
+    /// excluding the newline, if the span lies within a single recorded source.
+    pub fn source_line(&self, span: Span) -> Option<&str> {
+        self.find_source(span).map(|(_, text, span)| {
+            let lo = span.lo as usize;
+            let line_start = text[..lo].rfind('\n').map_or(0, |pos| pos + 1);
+            let line_end = text[lo..].find('\n').map_or(text.len(), |pos| lo + pos);
+
+            &text[line_start..line_end]
+        })
+    }
+
+    /// Returns the source text of a span,
+    /// if the span lies within a single recorded source.
+    pub fn source_text(&self, span: Span) -> Option<&str> {
+        self.find_source(span).map(|(_, text, span)| &text[span.lo as usize..span.hi as usize])
+    }
+
+    /// Returns the path and text of the recorded source containing a span,
+    /// along with the span relative to the beginning of the text.
+    ///
+    /// Returns `None` if either end of the span lies within a character.
+    fn find_source(&self, span: Span) -> Option<(Option<&str>, &str, Span)> {
+        if span.lo > span.hi {
+            return None;
+        }
+
         let n = match self.files.binary_search_by(|f| f.begin.cmp(&span.lo)) {
             Ok(n) => n,
+            Err(0) => return None,
             Err(n) => n - 1
         };
 
-        let end = match self.files.get(n as usize + 1) {
-            Some(f) => f.begin,
-            None => self.text.len() as u32
+        let f = &self.files[n];
+
+        let text_end = match self.files.get(n + 1) {
+            Some(f) => f.text_begin,
+            None => self.text.len()
         };
 
-        if span.hi > end {
-            panic!("span {:?} spans multiple files", span);
-        }
+        let text = &self.text[f.text_begin..text_end];
 
-        let f = &self.files[n as usize];
+        if span.hi - f.begin > text.len() as BytePos {
+            return None;
+        }
 
         let Span{lo, hi} = span;
         let adj_span = Span{lo: lo - f.begin, hi: hi - f.begin};
 
-        SpanDisplay{
-            filename: f.path.as_ref().map(|s| &s[..]),
-            ..highlight_span(&self.text[f.begin as usize..end as usize], adj_span)
+        if !text.is_char_boundary(adj_span.lo as usize) ||
+                !text.is_char_boundary(adj_span.hi as usize) {
+            return None;
         }
+
+        Some((f.path.as_ref().map(|s| &s[..]), text, adj_span))
     }
 }
 
@@ -201,6 +362,14 @@ pub struct Lexer<'lex> {
     input: &'lex str,
     cur_pos: BytePos,
     code_offset: BytePos,
+    /// Whether the character preceding `input` is a newline
+    after_newline: bool,
+    /// Whether the last token scanned began a line
+    line_start: bool,
+    /// Whether the last token scanned was the first token on its line
+    first_on_line: bool,
+    /// Whether to produce `Comment` tokens
+    comments: bool,
 }
 
 /// Represents a beginning and end point within a body of text.
@@ -236,12 +405,72 @@ impl<'lex> Lexer<'lex> {
             input: input,
             cur_pos: 0,
             code_offset: offset,
+            after_newline: true,
+            line_start: false,
+            first_on_line: false,
+            comments: false,
         }
     }
 
+    /// Sets whether line comments and block comments are produced as
+    /// `Token::Comment` tokens. By default, these comments are skipped.
+    pub fn set_comments(&mut self, comments: bool) {
+        self.comments = comments;
+    }
+
+    /// Returns the current position of the lexer within the input,
+    /// including the code offset given when the lexer was created.
+    pub fn position(&self) -> BytePos {
+        self.code_offset + self.cur_pos
+    }
+
+    /// Returns the input which has not yet been scanned.
+    pub fn remaining(&self) -> &'lex str {
+        self.input
+    }
+
+    /// Returns whether the most recently scanned token began a line;
+    /// that is, whether it was preceded by a newline or the start of input.
+    pub fn line_start(&self) -> bool {
+        self.line_start
+    }
+
+    /// Returns whether the most recently scanned token was the first token
+    /// on its line; that is, whether only whitespace precedes it on the line.
+    pub fn first_on_line(&self) -> bool {
+        self.first_on_line
+    }
+
     /// Scans the input stream for the next token, returning the token and
     /// the span of input text from which it was scanned.
+    ///
+    /// If an error is encountered, the remainder of the offending line is
+    /// skipped, so that scanning may resume on the following line.
     pub fn next_token(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
+        let input = self.input;
+        let start = self.cur_pos;
+
+        self.scan_token().map_err(|e| {
+            self.input = &input[(self.cur_pos - start) as usize..];
+            self.skip_error(e.kind);
+            e
+        })
+    }
+
+    fn skip_error(&mut self, kind: ParseErrorKind) {
+        let n = match kind {
+            // These errors are only produced upon reaching the end of input
+            ParseErrorKind::UnterminatedComment |
+            ParseErrorKind::UnterminatedString => self.input.len(),
+            _ => self.input.find('\n').map_or(self.input.len(), |n| n + 1)
+        };
+
+        self.input = &self.input[n..];
+        self.cur_pos += n as BytePos;
+        self.after_newline = true;
+    }
+
+    fn scan_token(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
         let mut chars = self.input.char_indices();
 
         while let Some((ind, ch)) = chars.next() {
@@ -250,6 +479,10 @@ impl<'lex> Lexer<'lex> {
             let res = match ch {
                 '(' => Ok((Token::LeftParen, 1)),
                 ')' => Ok((Token::RightParen, 1)),
+                '[' => Ok((Token::LeftBracket, 1)),
+                ']' => Ok((Token::RightBracket, 1)),
+                '{' => Ok((Token::LeftBrace, 1)),
+                '}' => Ok((Token::RightBrace, 1)),
                 '\'' => Ok((Token::Quote, 1)),
                 '`' => Ok((Token::BackQuote, 1)),
                 ',' => match chars.next() {
@@ -260,14 +493,22 @@ impl<'lex> Lexer<'lex> {
                 '"' => Ok(try!(parse_string(&self.input[ind..], lo))),
                 '#' => match chars.next() {
                     Some((_, '\'')) => Ok(try!(parse_char(&self.input[ind..], lo))),
+                    Some((_, '<')) if self.input[ind..].starts_with("#<<") =>
+                        Ok(try!(parse_heredoc(&self.input[ind..], lo))),
                     Some((_, '|')) => match consume_block_comment(ind, &mut chars) {
+                        Ok(n) if self.comments =>
+                            Ok((Token::Comment(&self.input[ind..ind + n]), n)),
                         Ok(n) => {
                             self.cur_pos += n as u32;
                             continue;
                         }
                         Err(k) => Err(k)
                     },
-                    Some(_) => Err(ParseErrorKind::InvalidToken),
+                    Some((_, ch)) if ch.is_whitespace() =>
+                        Err(ParseErrorKind::InvalidToken),
+                    Some((_, '{')) => Ok((Token::LeftSetBrace, 2)),
+                    Some((_, '0' ... '9')) => Ok(parse_label(&self.input[ind..])),
+                    Some((_, ch)) => Ok((Token::Dispatch(ch), 1 + ch.len_utf8())),
                     None => Err(ParseErrorKind::UnexpectedEof)
                 },
                 'r' => match chars.next() {
@@ -280,8 +521,16 @@ impl<'lex> Lexer<'lex> {
                     match chars.clone().next() {
                         Some((_, ';')) => Ok(parse_doc_comment(&self.input[ind..])),
                         _ => {
-                            self.cur_pos += consume_line_comment(ind, &mut chars) as u32;
-                            continue;
+                            let n = consume_line_comment(ind, &mut chars);
+
+                            if self.comments {
+                                let text = self.input[ind..ind + n]
+                                    .trim_right_matches(|ch| ch == '\n' || ch == '\r');
+                                Ok((Token::Comment(text), text.len()))
+                            } else {
+                                self.cur_pos += n as u32;
+                                continue;
+                            }
                         }
                     }
                 }
@@ -296,7 +545,7 @@ impl<'lex> Lexer<'lex> {
                     self.cur_pos += ch.len_utf8() as u32;
                     continue;
                 }
-                _ if is_identifier(ch) => parse_name(&self.input[ind..]),
+                _ if is_identifier_start(ch) => parse_name(&self.input[ind..]),
                 ch => Err(ParseErrorKind::InvalidChar(ch))
             };
 
@@ -306,6 +555,14 @@ impl<'lex> Lexer<'lex> {
                     self.span(Span::one(lo, ch)), kind))
             };
 
+            self.line_start = if ind == 0 {
+                self.after_newline
+            } else {
+                self.input[..ind].ends_with('\n')
+            };
+            self.first_on_line = self.after_newline || self.input[..ind].contains('\n');
+            self.after_newline = self.input[..ind + size].ends_with('\n');
+
             self.cur_pos += size as BytePos;
             self.input = &self.input[ind + size..];
 
@@ -382,16 +639,16 @@ fn consume_block_comment(start: usize, chars: &mut CharIndices) -> Result<usize,
 }
 
 fn consume_line_comment(start: usize, chars: &mut CharIndices) -> usize {
-    let mut last = start;
+    let mut end = start + 1;
 
     while let Some((ind, ch)) = chars.next() {
-        last = ind;
+        end = ind + ch.len_utf8();
         if ch == '\n' {
             break;
         }
     }
 
-    last - start + 1
+    end - start
 }
 
 fn parse_doc_comment(input: &str) -> (Token, usize) {
@@ -434,15 +691,25 @@ fn parse_doc_comment(input: &str) -> (Token, usize) {
     (Token::DocComment(&input[..end]), end)
 }
 
-fn is_identifier(ch: char) -> bool {
+/// Returns whether a character may begin an identifier.
+fn is_identifier_start(ch: char) -> bool {
     match ch {
         '!' | '$' | '%' | '&' | '*' | '+' | '-' | '.' | '/' |
         '<' | '=' | '>' | '?' | '^' | '_' | '|' => true,
-        _ if ch.is_alphanumeric() => true,
-        _ => false
+        _ => ch.is_alphanumeric()
     }
 }
 
+/// Returns whether a character may appear within an identifier.
+///
+/// In addition to the characters which may begin an identifier, non-ASCII
+/// characters with the Unicode `XID_Continue` property, such as combining
+/// marks, may continue an identifier.
+fn is_identifier(ch: char) -> bool {
+    is_identifier_start(ch) ||
+        (!ch.is_ascii() && UnicodeXID::is_xid_continue(ch))
+}
+
 fn parse_identifier(input: &str) -> Result<(&str, usize), ParseErrorKind> {
     for (ind, ch) in input.char_indices() {
         if !is_identifier(ch) {
@@ -464,16 +731,33 @@ fn parse_keyword(input: &str) -> Result<(Token, usize), ParseErrorKind> {
         })
 }
 
+/// Parses a datum label `#n=` or reference `#n#`.
+/// If the digits are followed by neither, the first digit is a dispatch
+/// character.
+fn parse_label(input: &str) -> (Token, usize) {
+    let digits = &input[1..];
+    let n = digits.find(|ch: char| !ch.is_digit(10)).unwrap_or(digits.len());
+
+    match digits[n..].chars().next() {
+        Some('=') => (Token::Label(&digits[..n]), n + 2),
+        Some('#') => (Token::LabelRef(&digits[..n]), n + 2),
+        _ => (Token::Dispatch(input[1..].chars().next().unwrap()), 2)
+    }
+}
+
 fn parse_name(input: &str) -> Result<(Token, usize), ParseErrorKind> {
     parse_identifier(input).map(|(ident, size)| (Token::Name(ident), size))
 }
 
 fn parse_number(input: &str) -> Result<(Token, usize), ParseErrorKind> {
+    let mut digit = false;
     let mut dot = false;
     let mut exp = false;
+    let mut exp_sign = false;
     let mut exp_digit = false;
     let mut slash = false;
     let mut slash_digit = false;
+    let mut decimal = false;
 
     let (base, prefix_offset, rest) = if input.starts_with("0x") {
         (16, 2, &input[2..])
@@ -491,65 +775,87 @@ fn parse_number(input: &str) -> Result<(Token, usize), ParseErrorKind> {
         (10, 0, input)
     };
 
+    // Decimal literals use a decimal exponent, `e`;
+    // radix-prefixed literals use a binary exponent, `p`.
+    let exp_char = if base == 10 { 'e' } else { 'p' };
+
+    let mut end = input.len();
+
     for (ind, ch) in rest.char_indices() {
         match ch {
-            _ if ch.is_digit(base) => {
-                if exp {
-                    exp_digit = true;
-                } else if slash {
+            // Nothing may follow the decimal suffix
+            _ if decimal => {
+                if is_identifier(ch) {
+                    return Err(ParseErrorKind::InvalidLiteral);
+                }
+                end = prefix_offset + ind;
+                break;
+            }
+            // Exponent digits are always decimal
+            _ if exp && ch.is_digit(10) => exp_digit = true,
+            _ if !exp && ch.is_digit(base) => {
+                if slash {
                     slash_digit = true;
+                } else {
+                    digit = true;
                 }
             }
             '+' | '-' => {
-                if !(exp && !exp_digit) {
-                    return Err(ParseErrorKind::InvalidLiteral);
+                if !exp || exp_sign || exp_digit {
+                    return Err(if exp {
+                        ParseErrorKind::InvalidExponent
+                    } else {
+                        ParseErrorKind::InvalidLiteral
+                    });
                 }
-                exp_digit = true;
+                exp_sign = true;
             }
             '.' => {
-                if dot || slash || base != 10 {
+                if exp {
+                    return Err(ParseErrorKind::InvalidExponent);
+                }
+                if dot || slash {
                     return Err(ParseErrorKind::InvalidLiteral);
                 }
                 dot = true;
             }
-            'e' | 'E' => {
-                if exp || slash || base != 10 {
-                    return Err(ParseErrorKind::InvalidLiteral);
+            _ if ch.to_ascii_lowercase() == exp_char => {
+                if exp || slash {
+                    return Err(ParseErrorKind::InvalidExponent);
+                }
+                if !digit {
+                    return Err(ParseErrorKind::MissingDigits);
                 }
-                dot = true;
                 exp = true;
             }
             '/' => {
-                if dot || exp || slash || base != 10 {
+                if dot || exp || slash {
                     return Err(ParseErrorKind::InvalidLiteral);
                 }
                 slash = true;
             }
+            'd' | 'D' if base == 10 && !slash => decimal = true,
             '_' => (),
             _ if !is_identifier(ch) => {
-                let end = prefix_offset + ind;
-                if dot || exp {
-                    return Ok((Token::Float(&input[..end]), end));
-                } else if slash {
-                    return Ok((Token::Ratio(&input[..end]), end));
-                } else {
-                    return Ok((Token::Integer(&input[..end], base), end));
-                }
+                end = prefix_offset + ind;
+                break;
             }
+            _ if ch.is_digit(10) =>
+                return Err(ParseErrorKind::InvalidDigit{digit: ch, base: base}),
             _ => return Err(ParseErrorKind::InvalidLiteral)
         }
     }
 
-    if dot || exp {
-        Ok((Token::Float(input), input.len()))
+    if !digit || (exp && !exp_digit) || (slash && !slash_digit) {
+        Err(ParseErrorKind::MissingDigits)
+    } else if decimal {
+        Ok((Token::Decimal(&input[..end - 1]), end))
+    } else if dot || exp {
+        Ok((Token::Float(&input[..end]), end))
     } else if slash {
-        if !slash_digit {
-            Err(ParseErrorKind::InvalidLiteral)
-        } else {
-            Ok((Token::Ratio(input), input.len()))
-        }
+        Ok((Token::Ratio(&input[..end], base), end))
     } else {
-        Ok((Token::Integer(input, base), input.len()))
+        Ok((Token::Integer(&input[..end], base), end))
     }
 }
 
@@ -568,9 +874,14 @@ fn parse_raw_string(input: &str, pos: BytePos) -> Result<(Token, usize), ParseEr
     Ok((Token::String(&input[..size]), size))
 }
 
+fn parse_heredoc(input: &str, pos: BytePos) -> Result<(Token, usize), ParseError> {
+    let (_, size) = try!(string::parse_heredoc(input, pos));
+    Ok((Token::String(&input[..size]), size))
+}
+
 #[cfg(test)]
 mod test {
-    use super::{BytePos, Lexer, Span, Token};
+    use super::{BytePos, CodeMap, Lexer, Position, Span, Token};
     use parser::ParseErrorKind;
 
     fn sp(lo: BytePos, hi: BytePos) -> Span {
@@ -633,8 +944,12 @@ mod test {
              (sp(13, 23), Token::Integer("0xdeadBEEF", 16))]);
 
         assert_eq!(tokens("1/2 -10/3"),
-            [(sp(0, 3), Token::Ratio("1/2")),
-             (sp(4, 9), Token::Ratio("-10/3"))]);
+            [(sp(0, 3), Token::Ratio("1/2", 10)),
+             (sp(4, 9), Token::Ratio("-10/3", 10))]);
+
+        assert_eq!(tokens("0x1f/3 0b1_0/11"),
+            [(sp(0, 6), Token::Ratio("0x1f/3", 16)),
+             (sp(7, 15), Token::Ratio("0b1_0/11", 2))]);
 
         assert_eq!(tokens("-1 2.0 3e100 -4.1e-2 5e+1"),
             [(sp(0, 2), Token::Integer("-1", 10)),
@@ -643,6 +958,15 @@ mod test {
              (sp(13, 20), Token::Float("-4.1e-2")),
              (sp(21, 25), Token::Float("5e+1"))]);
 
+        assert_eq!(tokens("1_000 0xffff_ffff 1_0.0_1e1_0 1_0/3_0 0x1.8p3 0b1P-1 0o7.4"),
+            [(sp(0, 5), Token::Integer("1_000", 10)),
+             (sp(6, 17), Token::Integer("0xffff_ffff", 16)),
+             (sp(18, 29), Token::Float("1_0.0_1e1_0")),
+             (sp(30, 37), Token::Ratio("1_0/3_0", 10)),
+             (sp(38, 45), Token::Float("0x1.8p3")),
+             (sp(46, 52), Token::Float("0b1P-1")),
+             (sp(53, 58), Token::Float("0o7.4"))]);
+
         assert_eq!(tokens(r"#'a' #'\'' #'\x7f' #'\u{1234}'"),
             [(sp(0, 4), Token::Char("#'a'")),
              (sp(5, 10), Token::Char(r"#'\''")),
@@ -657,6 +981,11 @@ mod test {
              (sp(25, 31), Token::String(r#""\x7f""#)),
              (sp(32, 42), Token::String(r#""\u{1234}""#))]);
 
+        assert_eq!(tokens("(#<<END\n\"a\"\nEND\n)"),
+            [(sp(0, 1), Token::LeftParen),
+             (sp(1, 15), Token::String("#<<END\n\"a\"\nEND")),
+             (sp(16, 17), Token::RightParen)]);
+
         assert_eq!(tokens("foo"), [(sp(0, 3), Token::Name("foo"))]);
 
         assert_eq!(tokens("()"), [
@@ -771,19 +1100,146 @@ mod test {
              (sp(8, 15), Token::DocComment(";; bar\n"))]);
     }
 
+    #[test]
+    fn test_unicode_names() {
+        assert_eq!(tokens("(\u{3b1}\u{3b2} :n\u{e4}me e\u{301}t\u{e9})"),
+            [(sp(0, 1), Token::LeftParen),
+             (sp(1, 5), Token::Name("\u{3b1}\u{3b2}")),
+             (sp(6, 12), Token::Keyword("n\u{e4}me")),
+             (sp(13, 19), Token::Name("e\u{301}t\u{e9}")),
+             (sp(19, 20), Token::RightParen)]);
+
+        assert_eq!(error("\u{301}"), Err(ParseErrorKind::InvalidChar('\u{301}')));
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(tokens("1.50d -2D 1_000.5e-2d 1/2"),
+            [(sp(0, 5), Token::Decimal("1.50")),
+             (sp(6, 9), Token::Decimal("-2")),
+             (sp(10, 21), Token::Decimal("1_000.5e-2")),
+             (sp(22, 25), Token::Ratio("1/2", 10))]);
+
+        assert_eq!(error("1.5dx"), Err(ParseErrorKind::InvalidLiteral));
+        assert_eq!(error("1ed"), Err(ParseErrorKind::MissingDigits));
+        assert_eq!(error("1/2d"), Err(ParseErrorKind::InvalidLiteral));
+        assert_eq!(error("0x1d"), Ok(()));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert_eq!(tokens("[a]{b}"),
+            [(sp(0, 1), Token::LeftBracket),
+             (sp(1, 2), Token::Name("a")),
+             (sp(2, 3), Token::RightBracket),
+             (sp(3, 4), Token::LeftBrace),
+             (sp(4, 5), Token::Name("b")),
+             (sp(5, 6), Token::RightBrace)]);
+
+        assert_eq!(tokens("#{a}"),
+            [(sp(0, 2), Token::LeftSetBrace),
+             (sp(2, 3), Token::Name("a")),
+             (sp(3, 4), Token::RightBrace)]);
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(tokens("#0=(a) #12# #1x"),
+            [(sp(0, 3), Token::Label("0")),
+             (sp(3, 4), Token::LeftParen),
+             (sp(4, 5), Token::Name("a")),
+             (sp(5, 6), Token::RightParen),
+             (sp(7, 11), Token::LabelRef("12")),
+             (sp(12, 14), Token::Dispatch('1')),
+             (sp(14, 15), Token::Name("x"))]);
+    }
+
+    #[test]
+    fn test_comment_tokens() {
+        let mut lex = Lexer::new("1 ; foo\r\n#| bar |# ;; baz\n2 ;", 0);
+        lex.set_comments(true);
+
+        let mut res = Vec::new();
+
+        loop {
+            match lex.next_token().unwrap() {
+                (_, Token::End) => break,
+                tok => res.push(tok),
+            }
+        }
+
+        assert_eq!(res, [
+            (sp(0, 1), Token::Integer("1", 10)),
+            (sp(2, 7), Token::Comment("; foo")),
+            (sp(9, 18), Token::Comment("#| bar |#")),
+            (sp(19, 26), Token::DocComment(";; baz\n")),
+            (sp(26, 27), Token::Integer("2", 10)),
+            (sp(28, 29), Token::Comment(";")),
+        ]);
+    }
+
     #[test]
     fn test_errors() {
         assert_eq!(error("\rfoo"), Err(ParseErrorKind::InvalidChar('\r')));
         assert_eq!(error(":"), Err(ParseErrorKind::InvalidToken));
+        assert_eq!(error("# "), Err(ParseErrorKind::InvalidToken));
 
         assert_eq!(error("-0x1"), Err(ParseErrorKind::InvalidLiteral));
-        assert_eq!(error("0o78"), Err(ParseErrorKind::InvalidLiteral));
-        assert_eq!(error("0b012"), Err(ParseErrorKind::InvalidLiteral));
-        assert_eq!(error("1e2.0"), Err(ParseErrorKind::InvalidLiteral));
-        assert_eq!(error("1e2-0"), Err(ParseErrorKind::InvalidLiteral));
+        assert_eq!(error("0o78"), Err(ParseErrorKind::InvalidDigit{digit: '8', base: 8}));
+        assert_eq!(error("0b012"), Err(ParseErrorKind::InvalidDigit{digit: '2', base: 2}));
+        assert_eq!(error("1e2.0"), Err(ParseErrorKind::InvalidExponent));
+        assert_eq!(error("1e2-0"), Err(ParseErrorKind::InvalidExponent));
+        assert_eq!(error("1e+-2"), Err(ParseErrorKind::InvalidExponent));
         assert_eq!(error("-1/-2"), Err(ParseErrorKind::InvalidLiteral));
-        assert_eq!(error("0x10/2"), Err(ParseErrorKind::InvalidLiteral));
+        assert_eq!(error("0x10/0x2"), Err(ParseErrorKind::InvalidLiteral));
+        assert_eq!(error("0o7/8"), Err(ParseErrorKind::InvalidDigit{digit: '8', base: 8}));
         assert_eq!(error("10e1/2"), Err(ParseErrorKind::InvalidLiteral));
-        assert_eq!(error("10/2e3"), Err(ParseErrorKind::InvalidLiteral));
+        assert_eq!(error("10/2e3"), Err(ParseErrorKind::InvalidExponent));
+        assert_eq!(error("1.5f"), Err(ParseErrorKind::InvalidLiteral));
+
+        assert_eq!(error("0x"), Err(ParseErrorKind::MissingDigits));
+        assert_eq!(error("0x.p1"), Err(ParseErrorKind::MissingDigits));
+        assert_eq!(error("1e"), Err(ParseErrorKind::MissingDigits));
+        assert_eq!(error("1e-"), Err(ParseErrorKind::MissingDigits));
+        assert_eq!(error("1/ "), Err(ParseErrorKind::MissingDigits));
+    }
+
+    #[test]
+    fn test_codemap_disabled() {
+        let mut cm = CodeMap::new();
+
+        let a = cm.add_source("(foo)", Some("a.ket".to_owned()));
+        cm.set_enabled(false);
+        let b = cm.add_source("(bar)", None);
+        cm.set_enabled(true);
+        let c = cm.add_source("(baz)", None);
+
+        assert_eq!(cm.highlight_span(sp(a + 1, a + 4)).filename, Some("a.ket"));
+        assert_eq!(cm.highlight_span(sp(c + 1, c + 4)).source, "(baz)");
+        assert!(cm.try_highlight_span(sp(b + 1, b + 4)).is_none());
+        assert!(cm.try_highlight_span(sp(c + 1, c + 10)).is_none());
+    }
+
+    #[test]
+    fn test_codemap_position() {
+        let mut cm = CodeMap::new();
+
+        let a = cm.add_source("(foo)\n(bar)", Some("a.ket".to_owned()));
+        let b = cm.add_source("\n\n  (\u{e9}t\u{e9} x)\n", None);
+
+        assert_eq!(cm.position(sp(a + 7, a + 10)),
+            Some(Position{filename: Some("a.ket"), line: 2, col: 1}));
+        assert_eq!(cm.position(sp(b + 11, b + 12)),
+            Some(Position{filename: None, line: 3, col: 7}));
+        assert_eq!(cm.source_line(sp(b + 11, b + 12)), Some("  (\u{e9}t\u{e9} x)"));
+        assert_eq!(cm.source_line(sp(a, a)), Some("(foo)"));
+        assert_eq!(cm.source_text(sp(a + 7, a + 10)), Some("bar"));
+        assert_eq!(cm.position(sp(b + 20, b + 21)), None);
+        assert_eq!(cm.source_text(sp(a + 4, a + 2)), None);
+
+        // Spans beginning or ending within a character
+        assert_eq!(cm.position(sp(b + 6, b + 7)), None);
+        assert_eq!(cm.source_line(sp(b + 6, b + 7)), None);
+        assert_eq!(cm.source_text(sp(b + 5, b + 6)), None);
     }
 }
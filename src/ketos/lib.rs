@@ -29,41 +29,72 @@
 extern crate byteorder;
 extern crate num;
 extern crate rand;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(feature = "serde_json")] extern crate serde_json;
+extern crate unicode_normalization;
+extern crate unicode_xid;
 
+pub use callable::Callable;
 pub use compile::CompileError;
+#[cfg(feature = "serde")] pub use de::{from_value, DeError};
 pub use encode::{DecodeError, EncodeError};
-pub use error::Error;
-pub use exec::ExecError;
+pub use error::{Error, ErrorContext, ErrorReport, ResultExt};
+pub use exec::{ErrorLocation, ExecError};
 pub use function::Arity;
 pub use interpreter::Interpreter;
 pub use integer::{Integer, Ratio};
 pub use io::IoError;
-pub use module::{BuiltinModuleLoader, FileModuleLoader, Module, ModuleBuilder, ModuleLoader};
+pub use map::{Map, Set};
+pub use module::{BuiltinModuleLoader, FileModuleLoader, Module, ModuleBuilder,
+    ModuleLoader, ModuleVersion};
 pub use name::{Name, NameStore};
 pub use parser::{ParseError, ParseErrorKind};
+pub use restrict::RestrictConfig;
 pub use scope::{GlobalScope, Scope};
+pub use trace::Trace;
 pub use value::{ForeignValue, FromValue, FromValueRef, Value};
 
+pub mod arena;
 pub mod bytecode;
+pub mod callable;
 pub mod compile;
 mod const_fold;
+pub mod cst;
+#[cfg(feature = "serde")] pub mod de;
 pub mod encode;
 pub mod error;
 pub mod exec;
+pub mod fmt;
 pub mod function;
+pub mod generate;
 pub mod integer;
 pub mod interpreter;
 pub mod io;
+#[cfg(feature = "serde_json")] pub mod json;
 pub mod lexer;
+pub mod map;
+pub mod metadata;
+pub mod metrics;
 pub mod module;
 pub mod name;
+pub mod optimize;
 pub mod parser;
+pub mod pretty;
+pub mod rc_string;
 pub mod rc_vec;
+pub mod repl;
+pub mod restrict;
 pub mod scope;
 mod string;
 pub mod string_fmt;
+pub mod testing;
+pub mod trace;
 pub mod value;
 
+mod mod_clojure;
 mod mod_code;
 mod mod_math;
 mod mod_random;
+mod mod_scheme;
+mod mod_sb;
+mod mod_test;
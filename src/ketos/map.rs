@@ -0,0 +1,347 @@
+//! Implements the map and set value types, associative containers of values.
+//!
+//! A map is constructed by the `hash-map` function or by a literal
+//! enclosed in braces, `{:a 1 :b 2}`. A set is constructed by the `set`
+//! function.
+//!
+//! ```
+//! use ketos::Interpreter;
+//!
+//! let interp = Interpreter::new();
+//!
+//! let v = interp.run_code(r#"(get {"one" 1 "two" 2} "two")"#, None).unwrap();
+//! assert_eq!(interp.format_value(&v), "2");
+//! ```
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::Zip;
+use std::slice;
+
+use exec::ExecError;
+use value::Value;
+
+/// Associative container mapping keys to values
+///
+/// Entries are kept in insertion order; replacing the value of an existing
+/// key does not change its position. Maps with the same entries are equal,
+/// regardless of order.
+///
+/// A key may be unit, a boolean, character, integer, string, name, keyword,
+/// or a list of such values.
+#[derive(Clone, Default)]
+pub struct Map {
+    keys: Vec<Value>,
+    values: Vec<Value>,
+    index: Index,
+}
+
+/// Iterator over the entries of a `Map`
+pub type Iter<'a> = Zip<slice::Iter<'a, Value>, slice::Iter<'a, Value>>;
+
+impl Map {
+    /// Creates a new, empty `Map`.
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns whether the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the value associated with the given key.
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.index.find(&self.keys, key).map(|pos| &self.values[pos])
+    }
+
+    /// Returns whether the map contains an entry for the given key.
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.index.find(&self.keys, key).is_some()
+    }
+
+    /// Inserts a value into the map, returning the value previously
+    /// associated with the key, if any.
+    ///
+    /// Returns an error if the key is not of a valid key type.
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<Option<Value>, ExecError> {
+        if let Some(v) = find_invalid_key(&key) {
+            return Err(ExecError::expected("map key", v));
+        }
+
+        match self.index.find(&self.keys, &key) {
+            Some(pos) => Ok(Some(::std::mem::replace(&mut self.values[pos], value))),
+            None => {
+                self.index.insert(&key, self.keys.len());
+                self.keys.push(key);
+                self.values.push(value);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Removes the entry for the given key, returning its value, if any.
+    ///
+    /// Later entries are shifted to preserve insertion order,
+    /// so removal takes time proportional to the size of the map.
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        self.index.find(&self.keys, key).map(|pos| {
+            self.index.remove(key, pos);
+            self.keys.remove(pos);
+            self.values.remove(pos)
+        })
+    }
+
+    /// Returns the keys of the map, in insertion order.
+    pub fn keys(&self) -> &[Value] {
+        &self.keys
+    }
+
+    /// Returns the values of the map, in the order of their keys.
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// Returns an iterator over the entries of the map, in insertion order.
+    pub fn iter(&self) -> Iter {
+        self.keys.iter().zip(self.values.iter())
+    }
+
+    /// Consumes the map and returns its keys and values.
+    pub fn into_parts(self) -> (Vec<Value>, Vec<Value>) {
+        (self.keys, self.values)
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Collection of unique values
+///
+/// Values are kept in insertion order.
+/// Any value which may be used as a map key may be a member of a set.
+#[derive(Clone, Default)]
+pub struct Set {
+    values: Vec<Value>,
+    index: Index,
+}
+
+impl Set {
+    /// Creates a new, empty `Set`.
+    pub fn new() -> Set {
+        Set::default()
+    }
+
+    /// Returns the number of values in the set.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns whether the set contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns whether the set contains the given value.
+    pub fn contains(&self, value: &Value) -> bool {
+        self.index.find(&self.values, value).is_some()
+    }
+
+    /// Adds a value to the set, returning whether it was not already present.
+    ///
+    /// Returns an error if the value is not of a valid key type.
+    pub fn insert(&mut self, value: Value) -> Result<bool, ExecError> {
+        if let Some(v) = find_invalid_key(&value) {
+            return Err(ExecError::expected("set value", v));
+        }
+
+        Ok(self.insert_valid(value))
+    }
+
+    /// Removes a value from the set, returning whether it was present.
+    ///
+    /// Later values are shifted to preserve insertion order,
+    /// so removal takes time proportional to the size of the set.
+    pub fn remove(&mut self, value: &Value) -> bool {
+        match self.index.find(&self.values, value) {
+            Some(pos) => {
+                self.index.remove(value, pos);
+                self.values.remove(pos);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Returns the values of the set, in insertion order.
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// Returns an iterator over the values of the set, in insertion order.
+    pub fn iter(&self) -> slice::Iter<Value> {
+        self.values.iter()
+    }
+
+    /// Consumes the set and returns its values.
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    /// Returns a set of values contained in either set.
+    ///
+    /// Values of this set come first, followed by values only in `other`.
+    pub fn union(&self, other: &Set) -> Set {
+        let mut set = self.clone();
+
+        for v in other.iter() {
+            set.insert_valid(v.clone());
+        }
+
+        set
+    }
+
+    /// Returns a set of values contained in both sets.
+    pub fn intersection(&self, other: &Set) -> Set {
+        self.filter(|v| other.contains(v))
+    }
+
+    /// Returns a set of values contained in this set but not in `other`.
+    pub fn difference(&self, other: &Set) -> Set {
+        self.filter(|v| !other.contains(v))
+    }
+
+    /// Returns whether every value in this set is contained in `other`.
+    pub fn is_subset(&self, other: &Set) -> bool {
+        self.len() <= other.len() && self.iter().all(|v| other.contains(v))
+    }
+
+    fn filter<F: Fn(&Value) -> bool>(&self, f: F) -> Set {
+        let mut set = Set::new();
+
+        for v in self.iter().filter(|v| f(v)) {
+            set.insert_valid(v.clone());
+        }
+
+        set
+    }
+
+    fn insert_valid(&mut self, value: Value) -> bool {
+        if self.index.find(&self.values, &value).is_some() {
+            false
+        } else {
+            self.index.insert(&value, self.values.len());
+            self.values.push(value);
+            true
+        }
+    }
+}
+
+impl fmt::Debug for Set {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Maps the hash of each key to its positions within an entry vector
+#[derive(Clone, Default)]
+struct Index {
+    buckets: HashMap<u64, Vec<usize>>,
+}
+
+impl Index {
+    fn find(&self, entries: &[Value], key: &Value) -> Option<usize> {
+        if find_invalid_key(key).is_some() {
+            return None;
+        }
+
+        self.buckets.get(&key_hash(key)).and_then(|bucket| {
+            bucket.iter().cloned().find(|&pos| entries[pos].is_identical(key))
+        })
+    }
+
+    fn insert(&mut self, key: &Value, pos: usize) {
+        self.buckets.entry(key_hash(key)).or_insert_with(Vec::new).push(pos);
+    }
+
+    fn remove(&mut self, key: &Value, pos: usize) {
+        let hash = key_hash(key);
+
+        let empty = {
+            let bucket = self.buckets.get_mut(&hash)
+                .expect("removed key is not indexed");
+            bucket.retain(|&p| p != pos);
+            bucket.is_empty()
+        };
+
+        if empty {
+            self.buckets.remove(&hash);
+        }
+
+        for bucket in self.buckets.values_mut() {
+            for p in bucket.iter_mut() {
+                if *p > pos {
+                    *p -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the first value within `key` which may not be used as a map key.
+fn find_invalid_key(key: &Value) -> Option<&Value> {
+    let mut stack = vec![key];
+
+    while let Some(v) = stack.pop() {
+        match *v {
+            Value::Unit |
+            Value::Bool(_) |
+            Value::Char(_) |
+            Value::Integer(_) |
+            Value::String(_) |
+            Value::Name(_) |
+            Value::Keyword(_) => (),
+            Value::List(ref li) => stack.extend(li.iter()),
+            _ => return Some(v)
+        }
+    }
+
+    None
+}
+
+fn key_hash(key: &Value) -> u64 {
+    let mut state = DefaultHasher::new();
+    hash_key(key, &mut state);
+    state.finish()
+}
+
+/// Hashes a valid map key. Keys which are identical produce the same hash.
+fn hash_key<H: Hasher>(key: &Value, state: &mut H) {
+    match *key {
+        Value::Unit => 0u8.hash(state),
+        Value::Bool(b) => { 1u8.hash(state); b.hash(state); }
+        Value::Char(c) => { 2u8.hash(state); c.hash(state); }
+        Value::Integer(ref i) => { 3u8.hash(state); i.hash(state); }
+        Value::String(ref s) => { 4u8.hash(state); s.hash(state); }
+        Value::Name(n) => { 5u8.hash(state); n.hash(state); }
+        Value::Keyword(n) => { 6u8.hash(state); n.hash(state); }
+        Value::List(ref li) => {
+            7u8.hash(state);
+            li.len().hash(state);
+            for v in li.iter() {
+                hash_key(v, state);
+            }
+        }
+        _ => unreachable!("invalid map key")
+    }
+}
@@ -0,0 +1,137 @@
+//! Reads metadata declared in the header of a script file.
+//!
+//! A metadata header is a series of doc comment lines at the beginning of
+//! a file, following an optional shebang line. Each line of the form
+//! `;; :key value` declares an entry; other lines within the header are
+//! ignored.
+//!
+//! ```lisp
+//! #!/usr/bin/env ketos
+//! ;; :name my-plugin
+//! ;; :version 1.2.0
+//! ;; :permission fs
+//! ;; :permission net
+//!
+//! (define (main) ...)
+//! ```
+//!
+//! The header should be followed by a blank line; otherwise, it will also
+//! be taken as the documentation of the expression which follows it.
+
+use std::slice::Iter;
+
+/// Entries declared in the metadata header of a script file
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Creates an empty `Metadata`.
+    pub fn new() -> Metadata {
+        Metadata::default()
+    }
+
+    /// Returns the value of the first entry with the given key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| &v[..])
+    }
+
+    /// Returns the values of all entries with the given key,
+    /// in the order in which they were declared.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.entries.iter().filter(|&&(ref k, _)| k == key)
+            .map(|&(_, ref v)| &v[..]).collect()
+    }
+
+    /// Returns whether an entry with the given key was declared.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns whether no entries were declared.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the number of entries declared.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Iterates over all entries, in the order in which they were declared.
+    pub fn iter(&self) -> Iter<(String, String)> {
+        self.entries.iter()
+    }
+}
+
+/// Reads the metadata header from the source of a script file.
+///
+/// Reading stops at the first line which is not a doc comment, so no code
+/// is parsed or executed. If the input has no metadata header, the returned
+/// `Metadata` is empty.
+pub fn read_metadata(input: &str) -> Metadata {
+    let mut meta = Metadata::new();
+    let mut lines = input.lines().map(|line| line.trim());
+
+    if input.starts_with("#!") {
+        lines.next();
+    }
+
+    let lines = lines.skip_while(|line| line.is_empty())
+        .take_while(|line| line.starts_with(";;"));
+
+    for line in lines {
+        let line = line.trim_left_matches(';').trim_left();
+
+        if !line.starts_with(':') {
+            continue;
+        }
+
+        let line = &line[1..];
+        let (key, value) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, "")
+        };
+
+        if !key.is_empty() {
+            meta.entries.push((key.to_owned(), value.to_owned()));
+        }
+    }
+
+    meta
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_metadata;
+
+    #[test]
+    fn test_metadata() {
+        let meta = read_metadata("#!/usr/bin/env ketos\n\n\
+            ;; :name my-plugin\r\n\
+            ;; A plugin which does things.\n\
+            ;;; :version  1.2.0 \n\
+            ;; :permission fs\n\
+            ;; :permission net\n\
+            ;; :flag\n\
+            ;; :version 2\n\
+            \n\
+            ;; :after header\n\
+            (define foo 1)");
+
+        assert_eq!(meta.len(), 6);
+        assert_eq!(meta.get("name"), Some("my-plugin"));
+        assert_eq!(meta.get("version"), Some("1.2.0"));
+        assert_eq!(meta.get_all("permission"), ["fs", "net"]);
+        assert_eq!(meta.get("flag"), Some(""));
+        assert!(!meta.contains_key("after"));
+    }
+
+    #[test]
+    fn test_no_metadata() {
+        assert!(read_metadata("").is_empty());
+        assert!(read_metadata("(define foo 1)\n;; :name foo\n").is_empty());
+        assert!(read_metadata("; :name foo\n").is_empty());
+    }
+}
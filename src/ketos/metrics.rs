@@ -0,0 +1,88 @@
+//! Counters describing the work performed while executing code.
+//!
+//! Each top-level invocation of an `Interpreter`, such as `run_code` or
+//! `call`, resets the counters of its `GlobalScope`. Counters are then
+//! accumulated by every compilation and execution, including functions
+//! called from within system functions, until the invocation returns.
+//!
+//! ```
+//! use ketos::Interpreter;
+//!
+//! let interp = Interpreter::new();
+//!
+//! interp.run_code("(define (sq n) (* n n)) (sq 3)", None).unwrap();
+//!
+//! let stats = interp.last_run_stats();
+//! assert!(stats.instructions > 0);
+//! assert!(stats.calls > 0);
+//! ```
+
+use std::cell::Cell;
+use std::cmp::max;
+
+/// Execution counters for one or more invocations of bytecode
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// Number of bytecode instructions executed
+    pub instructions: u64,
+    /// Number of calls made to lambdas, system functions,
+    /// and foreign functions, including tail calls
+    pub calls: u64,
+    /// Number of lists and closures constructed by bytecode instructions;
+    /// values constructed within system functions are not counted.
+    pub allocations: u64,
+    /// Greatest number of values held on any single value stack
+    pub peak_stack: u32,
+    /// Number of macro expansions performed during compilation
+    pub macro_expansions: u64,
+    /// Number of modules loaded; imports of modules which were
+    /// previously loaded are not counted.
+    pub module_loads: u64,
+}
+
+impl Metrics {
+    /// Creates a new `Metrics` with all counters set to zero.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Adds counters from another `Metrics` to this one.
+    pub fn add(&mut self, other: &Metrics) {
+        self.instructions += other.instructions;
+        self.calls += other.calls;
+        self.allocations += other.allocations;
+        self.macro_expansions += other.macro_expansions;
+        self.module_loads += other.module_loads;
+        self.peak_stack = max(self.peak_stack, other.peak_stack);
+    }
+}
+
+/// Accumulates `Metrics` shared by all scopes of an `Interpreter`
+#[derive(Debug, Default)]
+pub struct MetricsCell {
+    metrics: Cell<Metrics>,
+}
+
+impl MetricsCell {
+    /// Creates a new `MetricsCell` with all counters set to zero.
+    pub fn new() -> MetricsCell {
+        MetricsCell::default()
+    }
+
+    /// Returns the accumulated counters.
+    pub fn get(&self) -> Metrics {
+        self.metrics.get()
+    }
+
+    /// Adds counters to the accumulated counters.
+    pub fn add(&self, metrics: &Metrics) {
+        let mut m = self.metrics.get();
+        m.add(metrics);
+        self.metrics.set(m);
+    }
+
+    /// Resets all accumulated counters to zero.
+    pub fn reset(&self) {
+        self.metrics.set(Metrics::new());
+    }
+}
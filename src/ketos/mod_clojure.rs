@@ -0,0 +1,139 @@
+//! Implements builtin `clojure` module.
+
+use error::Error;
+use exec::ExecError;
+use function::keyword_position;
+use function::Arity::{Exact, Min};
+use module::{Module, ModuleBuilder};
+use name::{get_system_fn, Name};
+use name::standard_names::{ADD, ELT, LEN, NULL, SUB};
+use scope::Scope;
+use value::{FromValueRef, Value};
+
+/// Loads the `clojure` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    ModuleBuilder::new("clojure", scope)
+        .doc("Provides list operations under their Clojure names.")
+        .add_function("assoc",    fn_assoc,     Min(3))
+        .add_function("conj",     fn_conj,      Min(1))
+        .add_alias("count",       LEN)
+        .add_function("dec",      fn_dec,       Exact(1))
+        .add_function("empty?",   fn_is_empty,  Exact(1))
+        .add_function("inc",      fn_inc,       Exact(1))
+        .add_alias("nil?",        NULL)
+        .add_alias("nth",         ELT)
+        .add_function("rest",     fn_rest,      Exact(1))
+        .finish()
+}
+
+/// `assoc` returns a list with one or more values replaced.
+///
+/// If keys are keywords, the list is treated as a list of alternating
+/// keywords and values, e.g. `(:a 1 :b 2)`; keys not present in the list
+/// are appended. If keys are integers, they are indices into the list;
+/// an index equal to the length of the list appends a value.
+///
+/// ```lisp
+/// (assoc '(:a 1 :b 2) :a 3 :c 4)
+/// (assoc '(1 2 3) 0 :x)
+/// ```
+fn fn_assoc(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let (first, rest) = args.split_at_mut(1);
+
+    let mut li = match first[0].take() {
+        Value::Unit => Vec::new(),
+        Value::List(li) => li.into_vec(),
+        ref v => return Err(From::from(ExecError::expected("list", v)))
+    };
+
+    if rest.len() % 2 != 0 {
+        return Err(From::from(ExecError::OddKeywordParams));
+    }
+
+    for pair in rest.chunks_mut(2) {
+        let value = pair[1].take();
+
+        match pair[0] {
+            Value::Keyword(k) => {
+                match keyword_position(&li, k) {
+                    Some(pos) => li[pos + 1] = value,
+                    None => {
+                        li.push(Value::Keyword(k));
+                        li.push(value);
+                    }
+                }
+            }
+            ref v => {
+                let idx = try!(usize::from_value_ref(v));
+
+                if idx < li.len() {
+                    li[idx] = value;
+                } else if idx == li.len() {
+                    li.push(value);
+                } else {
+                    return Err(From::from(ExecError::OutOfBounds(idx)));
+                }
+            }
+        }
+    }
+
+    Ok(li.into())
+}
+
+/// `conj` returns a list with one or more values added to the front,
+/// in the order given, so that the last value is the first element.
+///
+/// ```lisp
+/// (conj '(1 2) 3 4)
+/// ```
+fn fn_conj(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let (first, rest) = args.split_at_mut(1);
+
+    let tail = match first[0].take() {
+        Value::Unit => Vec::new(),
+        Value::List(li) => li.into_vec(),
+        ref v => return Err(From::from(ExecError::expected("list", v)))
+    };
+
+    let mut li = Vec::with_capacity(rest.len() + tail.len());
+
+    li.extend(rest.iter_mut().rev().map(|v| v.take()));
+    li.extend(tail);
+
+    Ok(li.into())
+}
+
+/// `dec` returns a number minus one.
+fn fn_dec(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    call_sys_fn(scope, SUB, &mut [args[0].take(), 1.into()])
+}
+
+/// `inc` returns a number plus one.
+fn fn_inc(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    call_sys_fn(scope, ADD, &mut [args[0].take(), 1.into()])
+}
+
+fn call_sys_fn(scope: &Scope, name: Name, args: &mut [Value]) -> Result<Value, Error> {
+    let sys_fn = get_system_fn(name).expect("not a system function");
+    (sys_fn.callback)(scope, args)
+}
+
+/// `empty?` returns whether a list or string is empty.
+fn fn_is_empty(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0] {
+        Value::Unit => Ok(true.into()),
+        Value::List(_) => Ok(false.into()),
+        Value::String(ref s) => Ok(s.is_empty().into()),
+        ref v => Err(From::from(ExecError::expected("list or string", v)))
+    }
+}
+
+/// `rest` returns all but the first element of a list;
+/// `(rest ())` returns `()`.
+fn fn_rest(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0].take() {
+        Value::Unit => Ok(Value::Unit),
+        Value::List(ref li) => Ok(li.slice(1..).into()),
+        ref v => Err(From::from(ExecError::expected("list", v)))
+    }
+}
@@ -2,31 +2,39 @@
 
 use std::rc::Rc;
 
-use bytecode::{CodeReader, Instruction};
+use bytecode::disassemble_lambda;
 use compile::compile;
 use error::Error;
 use exec::ExecError;
 use function::{plural, Lambda};
-use function::Arity::Exact;
+use function::Arity::{Exact, Range};
 use module::{Module, ModuleBuilder};
-use name::{debug_names, get_standard_name};
+use name::debug_names;
 use scope::Scope;
 use value::{FromValueRef, Value};
 
 /// Loads the `code` module into the given scope.
 pub fn load(scope: Scope) -> Module {
     ModuleBuilder::new("code", scope)
+        .doc("Provides facilities for inspecting compiled bytecode objects.")
         .add_function("compile",     fn_compile,     Exact(1))
         .add_function("disassemble", fn_disassemble, Exact(1))
+        .add_function_doc("doc",     fn_doc,         Range(1, 2),
+            "(doc value [field])",
+            "Returns the documentation string of a function, a named constant, \
+            a struct definition, or a field of a struct definition, or `()`.")
         .add_function("get-const",   fn_get_const,   Exact(2))
         .add_function("get-value",   fn_get_value,   Exact(2))
+        .add_function_doc("help",    fn_help,        Exact(1),
+            "(help function)",
+            "Prints the signature and documentation of a function.")
         .finish()
 }
 
 /// `compile` compiles an expression into a code object.
 fn fn_compile(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let code = try!(compile(scope, &args[0]));
-    Ok(Value::Lambda(Lambda::new(Rc::new(code), scope)))
+    Ok(Value::Lambda(Rc::new(Lambda::new(Rc::new(code), scope))))
 }
 
 /// `disassemble` prints information about a `Lambda` code object.
@@ -60,6 +68,10 @@ fn fn_disassemble(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
         println!("No rest parameter");
     }
 
+    if code.has_rest_keys() {
+        println!("Has rest keyword parameter");
+    }
+
     if code.consts.is_empty() {
         println!("0 const values");
     } else {
@@ -85,103 +97,68 @@ fn fn_disassemble(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
         println!("0 enclosed values");
     }
 
-    let instrs = try!(get_instructions(&code.code));
-    let mut jumps = Vec::with_capacity(16);
-
-    // Collect all jump labels
-    for &(_, ref instr) in &instrs {
-        if let Some(off) = instr.get_jump_label() {
-            match jumps.binary_search(&off) {
-                Ok(_) => (),
-                Err(pos) => jumps.insert(pos, off)
-            }
-        }
+    // Report invalid bytecode as an error rather than as part of the listing
+    for r in code.instructions() {
+        try!(r);
     }
 
-    println!("{} bytecode instruction{}:", instrs.len(), plural(instrs.len() as u32));
-
-    for (off, instr) in instrs {
-        let is_label = jumps.binary_search(&off).is_ok();
-        print_instruction(scope, l, off, instr, is_label);
-    }
+    print!("{}", disassemble_lambda(l, &scope.borrow_names()));
 
     Ok(().into())
 }
 
-fn get_instructions(code: &[u8]) -> Result<Vec<(u32, Instruction)>, ExecError> {
-    let mut res = Vec::new();
-    let mut r = CodeReader::new(code, 0);
-
-    loop {
-        let off = r.get_offset() as u32;
+/// `doc` returns the documentation string of a function, a named constant,
+/// a struct definition, or a field of a struct definition, or `()`.
+fn fn_doc(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if args.len() == 2 {
+        let def = match args[0] {
+            Value::StructDef(ref def) => def,
+            ref v => return Err(From::from(ExecError::expected("struct-def", v)))
+        };
 
-        let instr = match r.read_instruction() {
-            Ok(instr) => instr,
-            Err(ExecError::UnexpectedEnd) => break,
-            Err(e) => return Err(e)
+        let field = match args[1] {
+            Value::Name(name) => name,
+            ref v => return Err(From::from(ExecError::expected("name", v)))
         };
 
-        res.push((off, instr));
+        if !def.fields.contains_key(field) {
+            return Err(From::from(ExecError::FieldError{
+                struct_name: def.name,
+                field: field,
+            }));
+        }
+
+        return Ok(def.field_doc(field).map_or(Value::Unit, Value::from));
     }
 
-    Ok(res)
+    match args[0] {
+        Value::Function(ref f) => Ok(f.sys_fn.doc.map_or(Value::Unit, Value::from)),
+        Value::Name(name) => Ok(scope.get_doc(name).map_or(Value::Unit, Value::from)),
+        Value::StructDef(ref def) =>
+            Ok(def.doc.as_ref().map_or(Value::Unit, |s| Value::from(&s[..]))),
+        ref v => Err(From::from(ExecError::expected("function", v)))
+    }
 }
 
-fn print_instruction(scope: &Scope, lambda: &Lambda,
-        offset: u32, instr: Instruction, is_label: bool) {
-    use bytecode::Instruction::*;
-
-    let label_str = if is_label { ">>" } else { "  " };
-    let code = &lambda.code;
-
-    let extra = {
-        let names = scope.borrow_names();
-
-        match instr {
-            LoadC(n) |
-            LoadCPush(n) =>
-                lambda.values.as_ref().and_then(|v| v.get(n as usize))
-                    .map(|v| debug_names(&names, v).to_string()),
-            GetDef(n) |
-            Const(n) |
-            GetDefPush(n) |
-            ConstPush(n) |
-            SetDef(n) |
-            BuildClosure(n, _) |
-            CallConst(n, _)
-                => code.consts.get(n as usize).map(
-                    |c| debug_names(&names, c).to_string()),
-            Jump(l) |
-            JumpIf(l) |
-            JumpIfNull(l) |
-            JumpIfNotNull(l) |
-            JumpIfNot(l) |
-            JumpIfEq(l) |
-            JumpIfNotEq(l) |
-            JumpIfBound(l, _)
-                => Some(format!("L{}", l)),
-            JumpIfEqConst(l, n) |
-            JumpIfNotEqConst(l, n)
-                => Some(match code.consts.get(n as usize) {
-                    None => format!("L{}", l),
-                    Some(c) => format!("L{} {}", l, debug_names(&names, c))
-                }),
-            CallSys(n) |
-            CallSysArgs(n, _) =>
-                get_standard_name(n).map(|n| names.get(n).to_owned()),
-            _ => None
-        }
+/// `help` prints the signature and documentation of a function.
+fn fn_help(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = match args[0] {
+        Value::Function(ref f) => f,
+        ref v => return Err(From::from(ExecError::expected("function", v)))
     };
 
-    match extra {
-        Some(s) => {
-            // fmt::Debug does not honor "<n" formatting,
-            // so we make this string first and format again.
-            let instr = format!("{:?}", instr);
-            println!("  {} {:>4}  {:<30} ; {}", label_str, offset, instr, s);
-        }
-        None => println!("  {} {:>4}  {:?}", label_str, offset, instr)
+    match f.sys_fn.signature {
+        Some(sig) => println!("{}", sig),
+        None => scope.with_name(f.name,
+            |name| println!("({} ...) ; {}", name, f.sys_fn.arity))
+    }
+
+    match f.sys_fn.doc {
+        Some(doc) => println!("\n{}", doc),
+        None => println!("\nNo documentation available.")
     }
+
+    Ok(().into())
 }
 
 /// `get-const` returns the specified const value from a `Lambda` object.
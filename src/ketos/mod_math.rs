@@ -12,6 +12,7 @@ use value::{FromValueRef, Value};
 /// Loads the `math` module into the given scope.
 pub fn load(scope: Scope) -> Module {
     ModuleBuilder::new("math", scope)
+        .doc("Provides mathematical constants and functions.")
         .add_constant("e",     consts::E)
         .add_constant("pi",    consts::PI)
         .add_function("acos",  fn_acos,   Exact(1))
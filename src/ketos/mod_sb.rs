@@ -0,0 +1,92 @@
+//! Implements builtin `string-builder` module.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem::replace;
+
+use error::Error;
+use exec::ExecError;
+use function::Arity::{Exact, Range};
+use module::{Module, ModuleBuilder};
+use name::NameStore;
+use scope::Scope;
+use value::{ForeignValue, FromValueRef, Value};
+
+/// Loads the `string-builder` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    ModuleBuilder::new("string-builder", scope)
+        .doc("Provides a mutable buffer for constructing strings incrementally.")
+        .add_function("sb-new",      fn_sb_new,      Range(0, 1))
+        .add_function("sb-push",     fn_sb_push,     Exact(2))
+        .add_function("sb-push-str", fn_sb_push_str, Exact(2))
+        .add_function("sb-finish",   fn_sb_finish,   Exact(1))
+        .finish()
+}
+
+/// Mutable string buffer
+///
+/// Unlike `concat`, which copies its arguments into a new string each time
+/// it is called, appending to a `StringBuilder` modifies it in place.
+#[derive(Debug)]
+struct StringBuilder {
+    buf: RefCell<String>,
+}
+
+impl ForeignValue for StringBuilder {
+    fn fmt_debug(&self, _names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<string-builder {:?}>", *self.buf.borrow())
+    }
+
+    fn type_name(&self) -> &'static str { "string-builder" }
+}
+
+fn get_builder(v: &Value) -> Result<&StringBuilder, ExecError> {
+    if let Value::Foreign(ref fv) = *v {
+        if let Some(sb) = fv.downcast_ref::<StringBuilder>() {
+            return Ok(sb);
+        }
+    }
+
+    Err(ExecError::expected("string-builder", v))
+}
+
+/// `sb-new` returns a new string builder, optionally containing a string.
+fn fn_sb_new(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = match args.first() {
+        Some(v) => try!(<&str>::from_value_ref(v)).to_owned(),
+        None => String::new()
+    };
+
+    Ok(Value::new_foreign(StringBuilder{buf: RefCell::new(s)}))
+}
+
+/// `sb-push` appends a character to a string builder.
+fn fn_sb_push(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let sb = try!(get_builder(&args[0]));
+    let ch = try!(char::from_value_ref(&args[1]));
+    let mut buf = sb.buf.borrow_mut();
+
+    try!(scope.get_restrictions().check_string_len(buf.len() + ch.len_utf8()));
+    buf.push(ch);
+    Ok(Value::Unit)
+}
+
+/// `sb-push-str` appends a string to a string builder.
+fn fn_sb_push_str(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let sb = try!(get_builder(&args[0]));
+    let s = try!(<&str>::from_value_ref(&args[1]));
+    let mut buf = sb.buf.borrow_mut();
+
+    try!(scope.get_restrictions().check_string_len(buf.len() + s.len()));
+    buf.push_str(s);
+    Ok(Value::Unit)
+}
+
+/// `sb-finish` returns the contents of a string builder as a string,
+/// leaving the builder empty.
+fn fn_sb_finish(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let sb = try!(get_builder(&args[0]));
+    let s = replace(&mut *sb.buf.borrow_mut(), String::new());
+
+    Ok(try!(Value::new_string(s)))
+}
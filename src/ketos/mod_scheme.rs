@@ -0,0 +1,102 @@
+//! Implements builtin `scheme` module.
+
+use error::Error;
+use exec::ExecError;
+use function::Arity::Exact;
+use module::{Module, ModuleBuilder};
+use name::standard_names::{EQ, ELT, FIRST, LEN, NULL, SECOND, TAIL, ZERO};
+use scope::Scope;
+use value::Value;
+
+/// Loads the `scheme` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    ModuleBuilder::new("scheme", scope)
+        .doc("Provides list operations under their Scheme names.")
+        .add_alias("car",         FIRST)
+        .add_alias("cadr",        SECOND)
+        .add_alias("cdr",         TAIL)
+        .add_function("cons",     fn_cons,       Exact(2))
+        .add_alias("equal?",      EQ)
+        .add_alias("length",      LEN)
+        .add_alias("list-ref",    ELT)
+        .add_function("list?",    fn_is_list,    Exact(1))
+        .add_alias("null?",       NULL)
+        .add_function("number?",  fn_is_number,  Exact(1))
+        .add_function("pair?",    fn_is_pair,    Exact(1))
+        .add_function("procedure?", fn_is_procedure, Exact(1))
+        .add_function("string?",  fn_is_string,  Exact(1))
+        .add_function("symbol?",  fn_is_symbol,  Exact(1))
+        .add_alias("zero?",       ZERO)
+        .finish()
+}
+
+/// `cons` returns a list with a value prepended to a given list.
+///
+/// ```lisp
+/// (cons 1 '(2 3))
+/// ```
+fn fn_cons(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut v = vec![args[0].take()];
+
+    match args[1].take() {
+        Value::Unit => (),
+        Value::List(li) => v.extend(li.into_vec()),
+        ref v => return Err(From::from(ExecError::expected("list", v)))
+    }
+
+    Ok(v.into())
+}
+
+/// `list?` returns whether a value is a list, including the empty list `()`.
+fn fn_is_list(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let r = match args[0] {
+        Value::Unit | Value::List(_) => true,
+        _ => false
+    };
+    Ok(r.into())
+}
+
+/// `number?` returns whether a value is an integer, float, or ratio.
+fn fn_is_number(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let r = match args[0] {
+        Value::Float(_) | Value::Integer(_) | Value::Ratio(_) => true,
+        _ => false
+    };
+    Ok(r.into())
+}
+
+/// `pair?` returns whether a value is a non-empty list.
+fn fn_is_pair(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let r = match args[0] {
+        Value::List(_) => true,
+        _ => false
+    };
+    Ok(r.into())
+}
+
+/// `procedure?` returns whether a value is a function or lambda.
+fn fn_is_procedure(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let r = match args[0] {
+        Value::Function(_) | Value::Lambda(_) => true,
+        _ => false
+    };
+    Ok(r.into())
+}
+
+/// `string?` returns whether a value is a string.
+fn fn_is_string(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let r = match args[0] {
+        Value::String(_) => true,
+        _ => false
+    };
+    Ok(r.into())
+}
+
+/// `symbol?` returns whether a value is a name.
+fn fn_is_symbol(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let r = match args[0] {
+        Value::Name(_) => true,
+        _ => false
+    };
+    Ok(r.into())
+}
@@ -0,0 +1,149 @@
+//! Implements builtin `test` module.
+
+use std::rc::Rc;
+
+use compile::compile;
+use error::Error;
+use exec::{call_function, execute, ExecError};
+use function::Arity::{Exact, Range};
+use lexer::Lexer;
+use module::{Module, ModuleBuilder};
+use name::{debug_names, display_names, NameSetSlice};
+use parser::Parser;
+use scope::Scope;
+use value::{FromValueRef, Value};
+
+/// Prefix of names of test functions
+pub const TEST_PREFIX: &'static str = "test-";
+
+/// Macros defined by the `test` module
+const TEST_MACROS: &'static str = r#"
+;; Panics with a nice error message if the condition is false.
+(macro (assert pred)
+  `(if (not ,pred)
+     (panic ,(format "assertion `~s` failed" pred))))
+
+;; Panics with a nice error message if the condition is true.
+(macro (assert-not pred)
+  `(if ,pred
+     (panic ,(format "assertion `not ~s` failed" pred))))
+
+;; Panics with a nice error message if the two arguments are not equal.
+(macro (assert-eq a b)
+  `(let ((assert-lhs ,a)
+         (assert-rhs ,b))
+     (if (/= assert-lhs assert-rhs)
+       (panic (format ,(format "assertion `~s == ~s` failed; ~~s /= ~~s" a b)
+                      assert-lhs assert-rhs)))))
+
+;; Defines a test function, named `name` prefixed with `test-`.
+(macro (deftest name :rest body)
+  `(define (,(make-test-name name))
+     (do ,@body)))
+
+;; Given a set of `(define (name) ...)` expressions, runs each test function.
+(macro (run-tests :rest test-defs)
+  `(do
+     ,@test-defs
+
+     (define (run-test name fn)
+       (do
+         (print "Running test ~25a ... " name)
+         (fn)
+         (println "ok")))
+
+     ,@(map-into ()
+        (lambda (def)
+          (let ((name (first (second def))))
+            `(run-test ',name ,name)))
+        test-defs)))
+
+(define (map-into out fn li)
+  (if (null li)
+    out
+    (map-into (append out (fn (first li))) fn (tail li))))
+"#;
+
+/// Names exported by the `test` module
+const TEST_EXPORTS: &'static [&'static str] = &[
+    "assert", "assert-eq", "assert-not", "assert-raises", "deftest", "run-tests",
+];
+
+/// Loads the `test` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    let m = ModuleBuilder::new("test", scope.clone())
+        .doc("Provides assertions and macros for defining tests.")
+        .add_function_doc("assert-raises", fn_assert_raises, Range(1, 2),
+            "(assert-raises thunk [code])",
+            "Calls `thunk` with no arguments and panics if it does not \
+            produce an error. If `code` is given, panics unless the error \
+            identifier, such as `\"exec/panic\"`, is equal to `code`. \
+            Returns the error message.")
+        .add_function("make-test-name", fn_make_test_name, Exact(1))
+        .finish();
+
+    load_macros(&scope).expect("failed to load test module macros");
+
+    let exports = TEST_EXPORTS.iter()
+        .map(|name| scope.add_name(name)).collect::<NameSetSlice>();
+    scope.set_exports(exports);
+
+    m
+}
+
+fn load_macros(scope: &Scope) -> Result<(), Error> {
+    let exprs = {
+        let mut names = scope.borrow_names_mut();
+        let offset = scope.borrow_codemap_mut().add_source(TEST_MACROS,
+            Some("<test>".to_owned()));
+
+        try!(Parser::new(&mut names, Lexer::new(TEST_MACROS, offset)).parse_exprs())
+    };
+
+    for expr in &exprs {
+        let code = try!(compile(scope, expr));
+        try!(execute(scope, Rc::new(code)));
+    }
+
+    Ok(())
+}
+
+/// `assert-raises` calls a function and returns the message of the error
+/// it produces, panicking if no error is produced.
+fn fn_assert_raises(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let code = match args.get(1) {
+        Some(v) => Some(try!(<&str>::from_value_ref(v))),
+        None => None
+    };
+
+    let err = match call_function(scope, args[0].clone(), Vec::new()) {
+        Ok(v) => {
+            let msg = format!("assertion failed: expected error; found {}",
+                debug_names(&scope.borrow_names(), &v));
+            return Err(From::from(ExecError::Panic(Some(msg.into()))));
+        }
+        Err(e) => e
+    };
+
+    if let Some(code) = code {
+        if err.code() != code {
+            let msg = format!("assertion failed: expected error `{}`; found `{}`: {}",
+                code, err.code(), display_names(&scope.borrow_names(), &err));
+            return Err(From::from(ExecError::Panic(Some(msg.into()))));
+        }
+    }
+
+    let msg = display_names(&scope.borrow_names(), &err).to_string();
+    Ok(msg.into())
+}
+
+/// `make-test-name` returns the name of the function defined by `deftest`.
+fn fn_make_test_name(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = match args[0] {
+        Value::Name(name) => name,
+        ref v => return Err(From::from(ExecError::expected("name", v)))
+    };
+
+    let test_name = scope.with_name(name, |s| format!("{}{}", TEST_PREFIX, s));
+    Ok(Value::Name(scope.add_name(&test_name)))
+}
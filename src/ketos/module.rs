@@ -1,44 +1,176 @@
 //! Implements loading named values from code modules.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::fmt;
 use std::fs::{File, Metadata};
 use std::io::{stderr, Read, Write};
+use std::mem::replace;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::vec::IntoIter;
 
-use compile::{compile, CompileError};
+use compile::{compile_with_spans, CompileError};
 use encode::{DecodeError, ModuleCode, read_bytecode_file, write_bytecode_file};
 use error::Error;
 use exec::execute;
 use function::{Arity, Function, FunctionImpl, Lambda, SystemFn};
 use io::{IoError, IoMode};
 use lexer::Lexer;
-use name::{Name, NameMap};
+use metrics::Metrics;
+use name::{get_system_fn, Name, NameMap};
 use parser::Parser;
 use scope::{GlobalScope, ImportSet, Scope};
 use value::Value;
 
+use mod_clojure;
 use mod_code;
 use mod_math;
 use mod_random;
+use mod_scheme;
+use mod_sb;
+use mod_test;
+
+/// Function called when a module is first loaded into a `ModuleRegistry`
+pub type ModuleInitFn = Rc<Fn(&Scope) -> Result<(), Error>>;
+
+/// Function called when a loaded module is released by its `ModuleRegistry`
+pub type ModuleTeardownFn = Rc<Fn(&Scope)>;
 
 /// Contains the values in a loaded module's namespace.
+///
+/// Each module is loaded into its own `GlobalScope`. Code within a module
+/// cannot see definitions made in the scope which imported it, and other
+/// scopes may only import names which the module has exported.
 #[derive(Clone)]
 pub struct Module {
     /// Module name
     pub name: Name,
     /// Module scope
     pub scope: Scope,
+    /// Module documentation
+    pub doc: Option<String>,
+    /// Module version, if declared
+    pub version: Option<ModuleVersion>,
+    /// Called once, when the module is first loaded
+    pub init: Option<ModuleInitFn>,
+    /// Called once, when the module is released by the registry
+    pub teardown: Option<ModuleTeardownFn>,
 }
 
 impl Module {
     /// Creates a new module using the given scope.
     pub fn new(name: &str, scope: Scope) -> Module {
         let name = scope.add_name(name);
+        Module::with_name(name, scope)
+    }
+
+    fn with_name(name: Name, scope: Scope) -> Module {
         Module{
             name: name,
+            version: scope.get_version(),
             scope: scope,
+            doc: None,
+            init: None,
+            teardown: None,
+        }
+    }
+
+    /// Returns an error if the module does not satisfy the given minimum version.
+    pub fn check_version(&self, required: &ModuleVersion) -> Result<(), CompileError> {
+        match self.version {
+            Some(ref v) if v >= required => Ok(()),
+            ref found => Err(CompileError::ModuleVersionError{
+                module: self.name,
+                required: required.clone(),
+                found: found.clone(),
+            })
+        }
+    }
+
+    /// Returns the names exported by the module.
+    pub fn exports(&self) -> Vec<Name> {
+        self.scope.with_exports(
+            |e| e.map_or_else(Vec::new, |e| e.iter().collect()))
+    }
+}
+
+/// Version of a module, consisting of a series of integer components,
+/// e.g. `1.2.0`.
+///
+/// Versions are compared component-wise; missing trailing components
+/// are considered to be zero, so `1.2` and `1.2.0` are equal.
+#[derive(Clone, Debug)]
+pub struct ModuleVersion(Vec<u32>);
+
+impl ModuleVersion {
+    /// Creates a `ModuleVersion` from a series of components.
+    ///
+    /// # Panics
+    ///
+    /// If `parts` is empty.
+    pub fn new(parts: Vec<u32>) -> ModuleVersion {
+        assert!(!parts.is_empty(), "empty module version");
+        ModuleVersion(parts)
+    }
+
+    /// Parses a version string of dot-separated integers, e.g. `"1.2.0"`.
+    pub fn parse(s: &str) -> Option<ModuleVersion> {
+        s.split('.').map(|p| p.parse().ok())
+            .collect::<Option<Vec<u32>>>().map(ModuleVersion)
+    }
+
+    /// Returns the components of the version.
+    pub fn parts(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ModuleVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut iter = self.0.iter();
+
+        if let Some(n) = iter.next() {
+            try!(write!(f, "{}", n));
+        }
+
+        for n in iter {
+            try!(write!(f, ".{}", n));
+        }
+
+        Ok(())
+    }
+}
+
+impl Ord for ModuleVersion {
+    fn cmp(&self, rhs: &ModuleVersion) -> Ordering {
+        let n = self.0.len().max(rhs.0.len());
+
+        for i in 0..n {
+            let a = self.0.get(i).cloned().unwrap_or(0);
+            let b = rhs.0.get(i).cloned().unwrap_or(0);
+
+            match a.cmp(&b) {
+                Ordering::Equal => (),
+                ord => return ord
+            }
         }
+
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for ModuleVersion {
+    fn partial_cmp(&self, rhs: &ModuleVersion) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl Eq for ModuleVersion {}
+
+impl PartialEq for ModuleVersion {
+    fn eq(&self, rhs: &ModuleVersion) -> bool {
+        self.cmp(rhs) == Ordering::Equal
     }
 }
 
@@ -47,6 +179,9 @@ impl Module {
 pub struct ModuleBuilder {
     name: Name,
     scope: Scope,
+    doc: Option<String>,
+    init: Option<ModuleInitFn>,
+    teardown: Option<ModuleTeardownFn>,
 }
 
 impl ModuleBuilder {
@@ -57,6 +192,9 @@ impl ModuleBuilder {
         ModuleBuilder{
             name: mod_name,
             scope: scope.clone(),
+            doc: None,
+            init: None,
+            teardown: None,
         }
     }
 
@@ -70,13 +208,42 @@ impl ModuleBuilder {
     /// Adds a function to the module.
     pub fn add_function(self, name: &str,
             callback: FunctionImpl, arity: Arity) -> Self {
-        self.add_value_with_name(name, |name| Value::Function(Function{
+        self.add_system_fn(name, SystemFn{
+            arity: arity,
+            callback: callback,
+            signature: None,
+            doc: None,
+        })
+    }
+
+    /// Adds a function to the module, along with a human-readable parameter
+    /// signature, e.g. `"(foo a b)"`, and a documentation string.
+    pub fn add_function_doc(self, name: &str,
+            callback: FunctionImpl, arity: Arity,
+            signature: &'static str, doc: &'static str) -> Self {
+        self.add_system_fn(name, SystemFn{
+            arity: arity,
+            callback: callback,
+            signature: Some(signature),
+            doc: Some(doc),
+        })
+    }
+
+    fn add_system_fn(self, name: &str, sys_fn: SystemFn) -> Self {
+        self.add_value_with_name(name, |name| Value::Function(Rc::new(Function{
                 name: name,
-                sys_fn: SystemFn{
-                    arity: arity,
-                    callback: callback,
-                },
-            }))
+                sys_fn: sys_fn,
+            })))
+    }
+
+    /// Adds a system function to the module under another name.
+    ///
+    /// # Panics
+    ///
+    /// If `sys_name` does not name a system function.
+    pub fn add_alias(self, name: &str, sys_name: Name) -> Self {
+        let sys_fn = *get_system_fn(sys_name).expect("not a system function");
+        self.add_system_fn(name, sys_fn)
     }
 
     /// Adds a value to the module.
@@ -92,6 +259,36 @@ impl ModuleBuilder {
         self
     }
 
+    /// Sets the documentation string for the module.
+    pub fn doc(mut self, doc: &str) -> Self {
+        self.doc = Some(doc.to_owned());
+        self
+    }
+
+    /// Sets the version of the module.
+    pub fn version(self, version: ModuleVersion) -> Self {
+        self.scope.set_version(version);
+        self
+    }
+
+    /// Sets a function to be called when the module is first loaded.
+    ///
+    /// If the function returns an error, loading the module fails and the
+    /// error is returned to the importing scope.
+    pub fn on_init<F>(mut self, f: F) -> Self
+            where F: 'static + Fn(&Scope) -> Result<(), Error> {
+        self.init = Some(Rc::new(f));
+        self
+    }
+
+    /// Sets a function to be called when the module is released by the
+    /// `ModuleRegistry` into which it was loaded.
+    pub fn on_teardown<F>(mut self, f: F) -> Self
+            where F: 'static + Fn(&Scope) {
+        self.teardown = Some(Rc::new(f));
+        self
+    }
+
     /// Consumes the builder and returns the new `Module`.
     pub fn finish(self) -> Module {
         let mut exports = Vec::new();
@@ -107,7 +304,11 @@ impl ModuleBuilder {
 
         Module{
             name: self.name,
+            version: self.scope.get_version(),
             scope: self.scope,
+            doc: self.doc,
+            init: self.init,
+            teardown: self.teardown,
         }
     }
 }
@@ -116,6 +317,13 @@ impl ModuleBuilder {
 pub struct ModuleRegistry {
     loader: Box<ModuleLoader>,
     modules: RefCell<NameMap<Module>>,
+    /// Names of loaded modules, in the order in which they were loaded
+    load_order: RefCell<Vec<Name>>,
+    /// Whether loading of new modules is disallowed
+    frozen: Cell<bool>,
+    /// Whether modules are loaded with their own reader macros
+    /// and format directives
+    isolated: Cell<bool>,
 }
 
 impl ModuleRegistry {
@@ -125,9 +333,44 @@ impl ModuleRegistry {
         ModuleRegistry{
             loader: loader,
             modules: RefCell::new(NameMap::new()),
+            load_order: RefCell::new(Vec::new()),
+            frozen: Cell::new(false),
+            isolated: Cell::new(false),
         }
     }
 
+    /// Prevents the registry from loading any further modules.
+    ///
+    /// Modules already loaded remain available. Requests for any other
+    /// module produce a `ModuleError`. This allows a registry populated
+    /// during setup to be shared by untrusted scripts which should not
+    /// cause new modules to be loaded into it.
+    pub fn freeze(&self) {
+        self.frozen.set(true);
+    }
+
+    /// Returns whether the registry has been frozen by a call to `freeze`.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.get()
+    }
+
+    /// Loads each further module with its own reader macros and format
+    /// directives, copied from those of the importing scope.
+    ///
+    /// Without isolation, a module which registers a reader macro or format
+    /// directive, e.g. from its init function, changes how every other
+    /// module and the importing scope are parsed and formatted.
+    /// Once the registry is isolated, such changes are visible only
+    /// within the module which made them.
+    pub fn isolate(&self) {
+        self.isolated.set(true);
+    }
+
+    /// Returns whether the registry has been isolated by a call to `isolate`.
+    pub fn is_isolated(&self) -> bool {
+        self.isolated.get()
+    }
+
     /// Returns a loaded module. If the module has not been loaded in this
     /// registry; the contained `ModuleLoader` instance will be used to load it.
     pub fn get_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
@@ -139,11 +382,106 @@ impl ModuleRegistry {
 
         // ... And the borrow_mut must be dropped before load_module is called.
 
+        if self.frozen.get() {
+            return Err(From::from(CompileError::ModuleError(name)));
+        }
+
         let m = try!(self.loader.load_module(name, scope));
+        scope.get_metrics().add(&Metrics{module_loads: 1, ..Metrics::new()});
+
+        if let Some(ref init) = m.init {
+            try!(init(&m.scope));
+        }
+
         self.modules.borrow_mut().insert(name, m.clone());
+        self.load_order.borrow_mut().push(name);
 
         Ok(m)
     }
+
+    /// Removes a loaded module from the registry, calling its teardown
+    /// function, and returns the module, if it was loaded.
+    ///
+    /// Scopes which have already imported names from the module retain the
+    /// values they imported. A later `use` of the module will cause it to be
+    /// loaded again by the contained `ModuleLoader`.
+    pub fn remove(&self, name: Name) -> Option<Module> {
+        let m = self.take_module(name);
+
+        if let Some(ref m) = m {
+            if let Some(ref teardown) = m.teardown {
+                teardown(&m.scope);
+            }
+        }
+
+        m
+    }
+
+    /// Replaces a module in the registry with the given module and returns
+    /// the previously loaded module, if any.
+    ///
+    /// The new module's init function is called first; if it returns an error,
+    /// the registry is left unchanged. Otherwise, the previous module is
+    /// removed as if by `remove`.
+    ///
+    /// Scopes which have already imported names from the previous module retain
+    /// the values they imported. Later imports will be taken from the new module.
+    pub fn replace(&self, name: Name, module: Module) -> Result<Option<Module>, Error> {
+        if let Some(ref init) = module.init {
+            try!(init(&module.scope));
+        }
+
+        let old = self.remove(name);
+
+        self.modules.borrow_mut().insert(name, module);
+        self.load_order.borrow_mut().push(name);
+
+        Ok(old)
+    }
+
+    fn take_module(&self, name: Name) -> Option<Module> {
+        let m = self.modules.borrow_mut().remove(name);
+
+        if m.is_some() {
+            self.load_order.borrow_mut().retain(|&n| n != name);
+        }
+
+        m
+    }
+
+    /// Returns an iterator over loaded modules,
+    /// in the order in which they were loaded.
+    pub fn iter(&self) -> IntoIter<Module> {
+        let modules = self.modules.borrow();
+
+        self.load_order.borrow().iter()
+            .filter_map(|&name| modules.get(name).cloned())
+            .collect::<Vec<_>>().into_iter()
+    }
+
+    /// Releases all loaded modules, calling teardown functions in the
+    /// reverse of the order in which modules were loaded.
+    ///
+    /// Modules requested after this call will be loaded anew.
+    pub fn clear(&self) {
+        // Borrows are released before any teardown function is called.
+        let modules = replace(&mut *self.modules.borrow_mut(), NameMap::new());
+        let order = replace(&mut *self.load_order.borrow_mut(), Vec::new());
+
+        for name in order.into_iter().rev() {
+            if let Some(m) = modules.get(name) {
+                if let Some(ref teardown) = m.teardown {
+                    teardown(&m.scope);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ModuleRegistry {
+    fn drop(&mut self) {
+        self.clear();
+    }
 }
 
 /// Loads modules into separate namespaces
@@ -209,9 +547,13 @@ impl ModuleLoader for BuiltinModuleLoader {
 
 fn get_loader(name: &str) -> Option<fn(Scope) -> Module> {
     match name {
+        "clojure" => Some(mod_clojure::load),
         "code" => Some(mod_code::load),
         "math" => Some(mod_math::load),
         "random" => Some(mod_random::load),
+        "scheme" => Some(mod_scheme::load),
+        "string-builder" => Some(mod_sb::load),
+        "test" => Some(mod_test::load),
         _ => None
     }
 }
@@ -233,6 +575,10 @@ pub struct FileModuleLoader {
     chain: RefCell<Vec<PathBuf>>,
     /// Directories to search for files
     paths: Vec<PathBuf>,
+    /// Source file extensions, in order of preference
+    extensions: Vec<String>,
+    /// Transforms source text before it is parsed
+    transform: Option<Box<Fn(&Path, String) -> String>>,
 }
 
 /// File extension for `ketos` source files.
@@ -254,6 +600,8 @@ impl FileModuleLoader {
         FileModuleLoader{
             chain: RefCell::new(Vec::new()),
             paths: paths,
+            extensions: vec![FILE_EXTENSION.to_owned()],
+            transform: None,
         }
     }
 
@@ -262,6 +610,30 @@ impl FileModuleLoader {
         self.paths.push(path);
     }
 
+    /// Adds a file extension, without the leading `.`, to search for
+    /// module source files.
+    ///
+    /// Within each directory, extensions are tried in the order in which they
+    /// were added, after the default extension, `ket`. Source files of any
+    /// extension are compiled to a `ketc` bytecode file.
+    pub fn add_extension(&mut self, ext: &str) {
+        self.extensions.push(ext.to_owned());
+    }
+
+    /// Sets a function which is applied to the contents of each module source
+    /// file before it is parsed. The function receives the path of the file
+    /// and its contents and returns the source text to be parsed.
+    pub fn set_source_transform<F>(&mut self, f: F)
+            where F: 'static + Fn(&Path, String) -> String {
+        self.transform = Some(Box::new(f));
+    }
+
+    fn load_source(&self, scope: Scope, name: Name,
+            src_path: &Path, code_path: &Path) -> Result<Module, Error> {
+        load_module_from_file(scope, name, src_path, code_path,
+            self.transform.as_ref().map(|f| &**f))
+    }
+
     fn guard_import<F, T>(&self, name: Name, path: &Path, f: F) -> Result<T, Error>
             where F: FnOnce() -> Result<T, Error> {
         if self.chain.borrow().iter().any(|p| p == path) {
@@ -278,17 +650,21 @@ impl FileModuleLoader {
 
 impl ModuleLoader for FileModuleLoader {
     fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
-        let (src_fname, code_fname) = try!(scope.with_name(name, |name_str| {
+        let (src_fnames, code_fname) = try!(scope.with_name(name, |name_str| {
             if name_str.chars().any(|c| c == '.' || c == '/' || c == '\\') {
                 Err(CompileError::InvalidModuleName(name))
             } else {
-                Ok((PathBuf::from(format!("{}.{}", name_str, FILE_EXTENSION)),
+                Ok((self.extensions.iter()
+                        .map(|ext| PathBuf::from(format!("{}.{}", name_str, ext)))
+                        .collect::<Vec<_>>(),
                     PathBuf::from(format!("{}.{}", name_str, COMPILED_FILE_EXTENSION))))
             }
         }));
 
         for base in &self.paths {
-            let src_path = base.join(&src_fname);
+            let src_path = src_fnames.iter().map(|f| base.join(f))
+                .find(|p| p.exists())
+                .unwrap_or_else(|| base.join(&src_fnames[0]));
             let code_path = base.join(&code_fname);
 
             match try!(find_module_file(&code_path, &src_path)) {
@@ -307,7 +683,7 @@ impl ModuleLoader for FileModuleLoader {
                             }
                             Err(Error::DecodeError(DecodeError::IncorrectVersion(_)))
                                     if src_path.exists() => {
-                                load_module_from_file(new_scope, name, &src_path, &code_path)
+                                self.load_source(new_scope, name, &src_path, &code_path)
                             }
                             Err(e) => Err(e)
                         }
@@ -317,7 +693,7 @@ impl ModuleLoader for FileModuleLoader {
                     let new_scope = GlobalScope::new_using(scope);
 
                     return self.guard_import(name, &src_path,
-                        || load_module_from_file(new_scope, name, &src_path, &code_path))
+                        || self.load_source(new_scope, name, &src_path, &code_path))
                 }
                 ModuleFileResult::NotFound => ()
             }
@@ -368,7 +744,8 @@ fn is_younger_impl(ma: &Metadata, mb: &Metadata) -> bool {
 }
 
 fn load_module_from_file(scope: Scope, name: Name,
-        src_path: &Path, code_path: &Path) -> Result<Module, Error> {
+        src_path: &Path, code_path: &Path,
+        transform: Option<&Fn(&Path, String) -> String>) -> Result<Module, Error> {
     let mut file = try!(File::open(src_path)
         .map_err(|e| IoError::new(IoMode::Open, src_path, e)));
     let mut buf = String::new();
@@ -376,16 +753,26 @@ fn load_module_from_file(scope: Scope, name: Name,
     try!(file.read_to_string(&mut buf)
         .map_err(|e| IoError::new(IoMode::Read, src_path, e)));
 
-    let exprs = {
+    if let Some(f) = transform {
+        buf = f(src_path, buf);
+    }
+
+    let (exprs, spans) = {
         let mut names = scope.borrow_names_mut();
         let offset = scope.borrow_codemap_mut().add_source(&buf,
             Some(src_path.to_string_lossy().into_owned()));
 
-        try!(Parser::new(&mut names, Lexer::new(&buf, offset)).parse_exprs())
+        let mut p = Parser::new(&mut names, Lexer::new(&buf, offset));
+        scope.configure_parser(&mut p);
+
+        let exprs = try!(p.parse_exprs());
+        (exprs, p.take_spans())
     };
 
     let code = try!(exprs.iter()
-        .map(|e| compile(&scope, e).map(Rc::new)).collect::<Result<Vec<_>, _>>());
+        .map(|e| compile_with_spans(&scope, e, &spans)
+            .map(Rc::new).map_err(|(e, _)| e))
+        .collect::<Result<Vec<_>, _>>());
 
     for code in &code {
         try!(execute(&scope, code.clone()));
@@ -400,6 +787,7 @@ fn load_module_from_file(scope: Scope, name: Name,
                 .map(|&(name, ref l)| (name, l.code.clone())).collect()),
         exports: scope.with_exports(|e| e.cloned().unwrap()),
         imports: scope.with_imports(|i| i.to_vec()),
+        version: scope.get_version(),
     };
 
     let r = {
@@ -411,10 +799,7 @@ fn load_module_from_file(scope: Scope, name: Name,
         let _ = writeln!(stderr(), "failed to write compiled bytecode: {}", e);
     }
 
-    Ok(Module{
-        name: name,
-        scope: scope,
-    })
+    Ok(Module::with_name(name, scope))
 }
 
 fn process_imports(scope: &Scope, imports: &[ImportSet]) -> Result<(), Error> {
@@ -423,7 +808,13 @@ fn process_imports(scope: &Scope, imports: &[ImportSet]) -> Result<(), Error> {
     for imp in imports {
         let m = try!(mods.get_module(imp.module_name, scope));
 
+        if let Some(ref v) = imp.min_version {
+            try!(m.check_version(v));
+        }
+
         for &(src, dest) in &imp.constants {
+            try!(check_import_privacy(&m, src));
+
             let v = try!(m.scope.get_constant(src)
                 .ok_or(CompileError::ImportError{
                     module: imp.module_name,
@@ -431,9 +822,15 @@ fn process_imports(scope: &Scope, imports: &[ImportSet]) -> Result<(), Error> {
                 }));
 
             scope.add_constant(dest, v);
+
+            if let Some(doc) = m.scope.get_doc(src) {
+                scope.add_doc(dest, doc);
+            }
         }
 
         for &(src, dest) in &imp.macros {
+            try!(check_import_privacy(&m, src));
+
             let mac = try!(m.scope.get_macro(src)
                 .ok_or(CompileError::ImportError{
                     module: imp.module_name,
@@ -444,6 +841,8 @@ fn process_imports(scope: &Scope, imports: &[ImportSet]) -> Result<(), Error> {
         }
 
         for &(src, dest) in &imp.values {
+            try!(check_import_privacy(&m, src));
+
             let v = try!(m.scope.get_value(src)
                 .ok_or(CompileError::ImportError{
                     module: imp.module_name,
@@ -457,17 +856,31 @@ fn process_imports(scope: &Scope, imports: &[ImportSet]) -> Result<(), Error> {
     Ok(())
 }
 
+/// Bytecode imports were checked when they were compiled, but the imported
+/// module may since have changed; privacy is checked again at load time.
+fn check_import_privacy(m: &Module, name: Name) -> Result<(), CompileError> {
+    if m.scope.contains_name(name) && !m.scope.is_exported(name) {
+        Err(CompileError::PrivacyError{
+            module: m.name,
+            name: name,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 fn run_module_code(name: Name, scope: Scope, mcode: ModuleCode) -> Result<Module, Error> {
     scope.set_exports(mcode.exports);
 
+    if let Some(v) = mcode.version {
+        scope.set_version(v);
+    }
+
     for code in mcode.code {
         try!(execute(&scope, code));
     }
 
-    Ok(Module{
-        name: name,
-        scope: scope,
-    })
+    Ok(Module::with_name(name, scope))
 }
 
 fn check_exports(scope: &Scope, mod_name: Name) -> Result<(), CompileError> {
@@ -1,13 +1,18 @@
 //! Implements name interning and containers using names as keys.
 
+use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::Ref;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::{BuildHasherDefault, Hasher};
 use std::iter::FromIterator;
 use std::mem::replace;
 use std::rc::Rc;
 use std::slice::Iter;
 use std::sync::Arc;
 
+use unicode_normalization::UnicodeNormalization;
+
 use function::{SystemFn, SYSTEM_FNS};
 
 /// Represents a name interned within a `NameStore`.
@@ -76,24 +81,67 @@ macro_rules! standard_names {
             }
         }
 
+        /// String representations of standard names, indexed by name value
+        static STANDARD_NAME_STRS: [&'static str; NUM_STANDARD_NAMES as usize] = {
+            let mut strs = [""; NUM_STANDARD_NAMES as usize];
+            $( strs[$value] = $s; )+
+            strs
+        };
+
+        /// Standard names paired with their string representations,
+        /// sorted by string so that lookup can use binary search
+        static SORTED_STANDARD_NAMES: [(&'static str, Name); NUM_STANDARD_NAMES as usize] =
+            sort_names([ $( ($s, standard_names::$name), )+ ]);
+
         /// Returns the standard name for the given string representation,
         /// if one exists.
         pub fn get_standard_name_for(s: &str) -> Option<Name> {
-            match s {
-                $( $s => Some(standard_names::$name), )+
-                _ => None
-            }
+            SORTED_STANDARD_NAMES.binary_search_by(|&(n, _)| n.cmp(s))
+                .ok().map(|pos| SORTED_STANDARD_NAMES[pos].1)
         }
 
         /// Returns the string representation of the given name,
         /// if it is a standard name.
         pub fn standard_name(name: Name) -> Option<&'static str> {
-            match name {
-                $( standard_names::$name => Some($s), )+
-                _ => None
-            }
+            STANDARD_NAME_STRS.get(name.0 as usize).cloned()
+        }
+    }
+}
+
+/// Sorts standard names by string representation, at compile time.
+const fn sort_names(mut names: [(&'static str, Name); NUM_STANDARD_NAMES as usize])
+        -> [(&'static str, Name); NUM_STANDARD_NAMES as usize] {
+    let mut i = 1;
+
+    while i < names.len() {
+        let mut j = i;
+
+        while j > 0 && str_less(names[j].0, names[j - 1].0) {
+            let t = names[j];
+            names[j] = names[j - 1];
+            names[j - 1] = t;
+            j -= 1;
         }
+
+        i += 1;
     }
+
+    names
+}
+
+/// Returns whether `a` is ordered before `b`, consistent with `str::cmp`.
+const fn str_less(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut i = 0;
+
+    while i < a.len() && i < b.len() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+        i += 1;
+    }
+
+    a.len() < b.len()
 }
 
 // Any addition, deletion, or modification to these constants constitutes
@@ -160,73 +208,106 @@ standard_names!{
     "format" => FORMAT = 55,
     "print" => PRINT = 56,
     "println" => PRINTLN = 57,
-    "panic" => PANIC = 58,
-    "xor" => XOR = 59,
-    "not" => NOT = 60,
+    "eprint" => EPRINT = 58,
+    "eprintln" => EPRINTLN = 59,
+    "read-line" => READ_LINE = 60,
+    "read-char" => READ_CHAR = 61,
+    "flush" => FLUSH = 62,
+    "close" => CLOSE = 63,
+    "panic" => PANIC = 64,
+    "xor" => XOR = 65,
+    "not" => NOT = 66,
+    "loaded-modules" => LOADED_MODULES = 67,
+    "hash-map" => HASH_MAP = 68,
+    "get" => GET = 69,
+    "insert" => INSERT = 70,
+    "remove" => REMOVE = 71,
+    "keys" => KEYS = 72,
+    "values" => VALUES = 73,
+    "contains" => CONTAINS = 74,
+    "set" => SET = 75,
+    "union" => UNION = 76,
+    "intersection" => INTERSECTION = 77,
+    "difference" => DIFFERENCE = 78,
+    "subset?" => IS_SUBSET = 79,
+    "raise" => RAISE = 80,
+    "read" => READ = 81,
+    "eval" => EVAL = 82,
+    "macroexpand" => MACROEXPAND = 83,
+    "macroexpand-1" => MACROEXPAND_1 = 84,
     // End of names referring to system functions.
     // The constant `NUM_SYSTEM_FNS` below should be one greater than
     // the value immediately above this comment.
 
     // Boolean names; the parser will replace these with boolean values.
     // These names must follow immediately after system function names.
-    "false" => FALSE = 61,
-    "true" => TRUE = 62,
+    "false" => FALSE = 85,
+    "true" => TRUE = 86,
     // End of names referring to standard values.
     // The constant `NUM_STANDARD_VALUES` below should be one6 greater than
     // the value immediately above this comment.
 
     // Special operators follow; these are not represented as values in global
     // scope. They are only handled by the compiler.
-    "apply" => APPLY = 63,
-    "do" => DO = 64,
-    "let" => LET = 65,
-    "define" => DEFINE = 66,
-    "macro" => MACRO = 67,
-    "struct" => STRUCT = 68,
-    "if" => IF = 69,
-    "and" => AND = 70,
-    "or" => OR = 71,
-    "case" => CASE = 72,
-    "cond" => COND = 73,
-    "lambda" => LAMBDA = 74,
-    "export" => EXPORT = 75,
-    "use" => USE = 76,
-    "const" => CONST = 77,
+    "apply" => APPLY = 87,
+    "do" => DO = 88,
+    "let" => LET = 89,
+    "define" => DEFINE = 90,
+    "macro" => MACRO = 91,
+    "struct" => STRUCT = 92,
+    "if" => IF = 93,
+    "and" => AND = 94,
+    "or" => OR = 95,
+    "case" => CASE = 96,
+    "cond" => COND = 97,
+    "lambda" => LAMBDA = 98,
+    "export" => EXPORT = 99,
+    "use" => USE = 100,
+    "const" => CONST = 101,
+    "try" => TRY = 102,
 
     // Just plain names follow; these are used by system functions or operators
     // to delineate syntactical constructs or just as name values.
-    "all" => ALL = 78,
-    "else" => ELSE = 79,
-    "optional" => OPTIONAL = 80,
-    "key" => KEY = 81,
-    "rest" => REST = 82,
-    "unbound" => UNBOUND = 83,
-    "unit" => UNIT = 84,
-    "bool" => BOOL = 85,
-    "char" => CHAR = 86,
-    "integer" => INTEGER = 87,
-    "ratio" => RATIO = 88,
-    "struct-def" => STRUCT_DEF = 89,
-    "keyword" => KEYWORD = 90,
-    "object" => OBJECT = 91,
-    "name" => NAME = 92,
-    "number" => NUMBER = 93,
-    "function" => FUNCTION = 94,
+    "all" => ALL = 103,
+    "else" => ELSE = 104,
+    "optional" => OPTIONAL = 105,
+    "key" => KEY = 106,
+    "rest" => REST = 107,
+    "rest-keys" => REST_KEYS = 108,
+    "unbound" => UNBOUND = 109,
+    "unit" => UNIT = 110,
+    "bool" => BOOL = 111,
+    "char" => CHAR = 112,
+    "integer" => INTEGER = 113,
+    "ratio" => RATIO = 114,
+    "struct-def" => STRUCT_DEF = 115,
+    "keyword" => KEYWORD = 116,
+    "object" => OBJECT = 117,
+    "name" => NAME = 118,
+    "number" => NUMBER = 119,
+    "function" => FUNCTION = 120,
+    "version" => VERSION = 121,
+    "map" => MAP = 122,
+    "catch" => CATCH = 123,
+    "code" => CODE = 124,
+    "message" => MESSAGE = 125,
+    "ok" => OK = 126,
+    "error" => ERROR = 127,
 }
 
 /// Number of standard names
-pub const NUM_STANDARD_NAMES: u32 = 95;
+pub const NUM_STANDARD_NAMES: u32 = 128;
 
 /// Number of names, starting at `0`, which refer to system functions.
-pub const NUM_SYSTEM_FNS: usize = 61;
+pub const NUM_SYSTEM_FNS: usize = 85;
 
 /// Number of names, starting at `0`, which refer to standard values.
-pub const NUM_STANDARD_VALUES: u32 = 63;
+pub const NUM_STANDARD_VALUES: u32 = 87;
 
 /// First standard name which refers to a system operator.
 pub const SYSTEM_OPERATORS_BEGIN: u32 = NUM_STANDARD_VALUES;
 /// One-past-the-end of standard names which refer to system operators.
-pub const SYSTEM_OPERATORS_END: u32 = 78;
+pub const SYSTEM_OPERATORS_END: u32 = 103;
 
 /// Number of system operators, beginning at `SYSTEM_OPERATORS_BEGIN`.
 pub const NUM_SYSTEM_OPERATORS: usize =
@@ -276,6 +357,32 @@ impl<'a, T: NameDisplay> fmt::Display for NameDisplayer<'a, T> {
     }
 }
 
+/// Displays a `NameDisplay` value, holding a borrow of the `NameStore`.
+///
+/// Unlike `NameDisplayer`, which refers to a `NameStore` borrowed by the
+/// caller, a `DisplayNames` value may be produced by a single call, e.g. to
+/// `Interpreter::display_names`, and passed directly to formatting macros.
+pub struct DisplayNames<'a, T: 'a> {
+    names: Ref<'a, NameStore>,
+    value: &'a T,
+}
+
+impl<'a, T: 'a> DisplayNames<'a, T> {
+    /// Creates a `DisplayNames` from a borrowed `NameStore` and a value.
+    pub fn new(names: Ref<'a, NameStore>, value: &'a T) -> DisplayNames<'a, T> {
+        DisplayNames{
+            names: names,
+            value: value,
+        }
+    }
+}
+
+impl<'a, T: NameDisplay> fmt::Display for DisplayNames<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(&self.names, f)
+    }
+}
+
 macro_rules! impl_box {
     ( $( $name:ident ),+ ) => {
         $(
@@ -374,6 +481,9 @@ impl<'a> NameOutputConversion<'a> {
 }
 
 /// Maps interned `Name` values to their `String` representations
+///
+/// Names are stored in Unicode Normalization Form C, so that names which
+/// differ only in their composition of characters are considered equal.
 #[derive(Clone, Debug)]
 pub struct NameStore {
     /// Name string representation mapped to name values.
@@ -390,19 +500,38 @@ impl NameStore {
 
     /// Adds a name to the `NameStore` if it is not present.
     /// Returns a `Name` value to refer to the new or existing name.
+    ///
+    /// If the string is the representation of a standard name,
+    /// the standard name is returned. Otherwise, a name such as `"list"`
+    /// given by a host program would be distinct from the `list` read
+    /// by the parser, and could never be used to look up that value.
     pub fn add(&mut self, name: &str) -> Name {
-        if let Some(pos) = self.names.iter().position(|n| n == name) {
+        let name = normalize(name);
+
+        if let Some(name) = get_standard_name_for(&name) {
+            return name;
+        }
+
+        if let Some(pos) = self.names.iter().position(|n| *n == name) {
             Name(pos as u32 + NUM_STANDARD_NAMES)
         } else {
             let n = self.names.len();
-            self.names.push(name.to_owned());
+            self.names.push(name.into_owned());
             Name(n as u32 + NUM_STANDARD_NAMES)
         }
     }
 
     /// Returns the `Name` value of a given string, if it exists.
+    ///
+    /// Standard names always exist.
     pub fn get_name(&self, name: &str) -> Option<Name> {
-        if let Some(pos) = self.names.iter().position(|n| n == name) {
+        let name = normalize(name);
+
+        if let Some(name) = get_standard_name_for(&name) {
+            return Some(name);
+        }
+
+        if let Some(pos) = self.names.iter().position(|n| *n == name) {
             Some(Name(pos as u32 + NUM_STANDARD_NAMES))
         } else {
             None
@@ -427,38 +556,112 @@ impl NameStore {
     }
 }
 
+/// Returns a name in Unicode Normalization Form C.
+fn normalize(name: &str) -> Cow<str> {
+    // ASCII strings are already normalized
+    if name.is_ascii() {
+        Borrowed(name)
+    } else {
+        Owned(name.nfc().collect())
+    }
+}
+
+/// Number of entries above which `NameMap` and `NameMapSlice` maintain
+/// a hash index, rather than using binary search for lookups.
+const INDEX_THRESHOLD: usize = 16;
+
+/// Hashes `Name` values, which are small, unique integers.
+#[derive(Default)]
+struct NameHasher(u64);
+
+impl Hasher for NameHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.write_u64(u64::from(b));
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(u64::from(n));
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0 ^ n).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+/// Maps names to positions within a sorted sequence of name-value pairs
+type NameIndex = HashMap<Name, usize, BuildHasherDefault<NameHasher>>;
+
+/// Builds an index for the sorted values, if there are enough of them
+/// to benefit from one.
+fn build_index<T>(values: &[(Name, T)]) -> Option<NameIndex> {
+    if values.len() > INDEX_THRESHOLD {
+        Some(values.iter().enumerate()
+            .map(|(pos, &(name, _))| (name, pos)).collect())
+    } else {
+        None
+    }
+}
+
+/// Returns the position of a name within sorted values, as by
+/// `binary_search`, consulting the index, if one is present.
+fn find_name<T>(values: &[(Name, T)], index: Option<&NameIndex>, name: Name)
+        -> Result<usize, usize> {
+    if let Some(index) = index {
+        if let Some(&pos) = index.get(&name) {
+            return Ok(pos);
+        }
+    }
+
+    values.binary_search_by(|&(n, _)| n.cmp(&name))
+}
+
 /// Maps names to values in a sorted `Vec`
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+///
+/// Small maps are searched using binary search. Once a map contains more
+/// than a handful of entries, a hash index is maintained alongside the values,
+/// so that lookups in large global scopes remain fast.
+#[derive(Clone, Default)]
 pub struct NameMap<T> {
     values: Vec<(Name, T)>,
+    index: Option<NameIndex>,
 }
 
 impl<T> NameMap<T> {
     /// Returns a new `NameMap`.
     pub fn new() -> NameMap<T> {
-        NameMap{values: Vec::new()}
+        NameMap{values: Vec::new(), index: None}
     }
 
     /// Lowers the map into a `NameMapSlice`, which may not receive new
     /// key-value pairs, but can overwrite existing values.
     pub fn into_slice(self) -> NameMapSlice<T> {
-        NameMapSlice::new(self.values.into_boxed_slice())
+        NameMapSlice{values: self.values.into_boxed_slice(), index: self.index}
     }
 
     /// Removes all values from the map.
     pub fn clear(&mut self) {
         self.values.clear();
+        self.index = None;
+    }
+
+    fn find(&self, name: Name) -> Result<usize, usize> {
+        find_name(&self.values, self.index.as_ref(), name)
     }
 
     /// Returns whether the map contains a value for the given name.
     pub fn contains_key(&self, name: Name) -> bool {
-        self.values.binary_search_by(|&(ref n, _)| n.cmp(&name)).is_ok()
+        self.find(name).is_ok()
     }
 
     /// Returns the value corresponding to the given name.
     pub fn get(&self, name: Name) -> Option<&T> {
-        self.values.binary_search_by(|&(ref n, _)| n.cmp(&name))
-            .ok().map(|pos| &self.values[pos].1)
+        self.find(name).ok().map(|pos| &self.values[pos].1)
     }
 
     /// Returns a slice of the contained names and values.
@@ -479,13 +682,23 @@ impl<T> NameMap<T> {
     /// Insert a name-value pair into the map.
     /// If a value was already present for the name, it is returned.
     pub fn insert(&mut self, name: Name, value: T) -> Option<T> {
-        match self.values.binary_search_by(|&(ref n, _)| n.cmp(&name)) {
+        match self.find(name) {
             Ok(pos) => {
                 let old = replace(&mut self.values[pos].1, value);
                 Some(old)
             }
             Err(pos) => {
                 self.values.insert(pos, (name, value));
+
+                // Names are usually inserted in increasing order,
+                // in which case only the new name need be indexed.
+                match self.index {
+                    Some(ref mut index) if pos == self.values.len() - 1 => {
+                        index.insert(name, pos);
+                    }
+                    _ => self.index = build_index(&self.values)
+                }
+
                 None
             }
         }
@@ -495,14 +708,45 @@ impl<T> NameMap<T> {
     pub fn len(&self) -> usize {
         self.values.len()
     }
+
+    /// Removes the value for the given name and returns it, if present.
+    pub fn remove(&mut self, name: Name) -> Option<T> {
+        match self.find(name) {
+            Ok(pos) => {
+                let (_, value) = self.values.remove(pos);
+                if self.index.is_some() {
+                    self.index = build_index(&self.values);
+                }
+                Some(value)
+            }
+            Err(_) => None
+        }
+    }
 }
 
+impl<T: fmt::Debug> fmt::Debug for NameMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NameMap")
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for NameMap<T> {
+    fn eq(&self, rhs: &NameMap<T>) -> bool {
+        self.values == rhs.values
+    }
+}
+
+impl<T: Eq> Eq for NameMap<T> {}
+
 impl<T> FromIterator<(Name, T)> for NameMap<T> {
     fn from_iter<I>(iterator: I) -> Self where I: IntoIterator<Item=(Name, T)> {
         let mut v = iterator.into_iter()
             .collect::<Vec<_>>();
         v.sort_by(|a, b| a.0.cmp(&b.0));
-        NameMap{values: v}
+        let index = build_index(&v);
+        NameMap{values: v, index: index}
     }
 }
 
@@ -518,27 +762,26 @@ impl<'a, T> IntoIterator for &'a NameMap<T> {
 /// Maps names to values in a sorted boxed slice.
 ///
 /// Values may overwrite existing values, but new names cannot be inserted.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+/// As with `NameMap`, a hash index is kept for large maps.
+#[derive(Clone, Default)]
 pub struct NameMapSlice<T> {
     values: Box<[(Name, T)]>,
+    index: Option<NameIndex>,
 }
 
 impl<T> NameMapSlice<T> {
-    /// Creates a `NameMapSlice` wrapping the given boxed slice,
-    /// which must already be sorted by name.
-    fn new(values: Box<[(Name, T)]>) -> NameMapSlice<T> {
-        NameMapSlice{values: values}
+    fn find(&self, name: Name) -> Result<usize, usize> {
+        find_name(&self.values, self.index.as_ref(), name)
     }
 
     /// Returns whether the map contains a value for the given name.
     pub fn contains_key(&self, name: Name) -> bool {
-        self.values.binary_search_by(|&(n, _)| n.cmp(&name)).is_ok()
+        self.find(name).is_ok()
     }
 
     /// Returns the value corresponding to the given name.
     pub fn get(&self, name: Name) -> Option<&T> {
-        self.values.binary_search_by(|&(n, _)| n.cmp(&name))
-            .ok().map(|pos| &self.values[pos].1)
+        self.find(name).ok().map(|pos| &self.values[pos].1)
     }
 
     /// Returns a slice of the contained names and values.
@@ -551,7 +794,7 @@ impl<T> NameMapSlice<T> {
     /// Returns `None` if the name does not exist in the mapping.
     /// When `None` is returned, no value will have been stored in the mapping.
     pub fn set(&mut self, name: Name, value: T) -> Option<T> {
-        match self.values.binary_search_by(|&(n, _)| n.cmp(&name)) {
+        match self.find(name) {
             Ok(n) => Some(replace(&mut self.values[n].1, value)),
             Err(_) => None
         }
@@ -569,7 +812,7 @@ impl<T> NameMapSlice<T> {
 
     /// Elevates the map into `NameMap`, which may receive new key-value pairs.
     pub fn into_name_map(self) -> NameMap<T> {
-        NameMap{values: self.values.into_vec()}
+        NameMap{values: self.values.into_vec(), index: self.index}
     }
 
     /// Returns the number of name-value pairs contained in the map.
@@ -578,6 +821,22 @@ impl<T> NameMapSlice<T> {
     }
 }
 
+impl<T: fmt::Debug> fmt::Debug for NameMapSlice<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NameMapSlice")
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for NameMapSlice<T> {
+    fn eq(&self, rhs: &NameMapSlice<T>) -> bool {
+        self.values == rhs.values
+    }
+}
+
+impl<T: Eq> Eq for NameMapSlice<T> {}
+
 impl<T> FromIterator<(Name, T)> for NameMapSlice<T> {
     fn from_iter<I>(iterator: I) -> Self where I: IntoIterator<Item=(Name, T)> {
         iterator.into_iter().collect::<NameMap<_>>().into_slice()
@@ -724,3 +983,71 @@ impl<'a> Iterator for SetIter<'a> {
         self.0.next().map(|&(n, _)| n)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{get_standard_name, get_standard_name_for, standard_name,
+        standard_names, Name, NameMap, NameStore, INDEX_THRESHOLD, NUM_STANDARD_NAMES};
+
+    #[test]
+    fn test_standard_names() {
+        for i in 0..NUM_STANDARD_NAMES {
+            let name = get_standard_name(i).unwrap();
+            let s = standard_name(name).unwrap();
+
+            assert!(!s.is_empty());
+            assert_eq!(get_standard_name_for(s), Some(name));
+        }
+
+        assert_eq!(get_standard_name_for("not-a-standard-name"), None);
+        assert_eq!(get_standard_name(NUM_STANDARD_NAMES), None);
+    }
+
+    #[test]
+    fn test_name_store_standard_names() {
+        let mut names = NameStore::new();
+
+        assert_eq!(names.get_name("list"), Some(standard_names::LIST));
+        assert_eq!(names.add("list"), standard_names::LIST);
+        assert_eq!(names.get(standard_names::LIST), "list");
+        assert_eq!(names.iter().count(), 0);
+
+        let foo = names.add("foo");
+        assert!(foo.get() >= NUM_STANDARD_NAMES);
+        assert_eq!(names.get_name("foo"), Some(foo));
+        assert_eq!(names.get_name("bar"), None);
+    }
+
+    #[test]
+    fn test_name_map_index() {
+        let n = INDEX_THRESHOLD as u32 * 2;
+        let mut map = NameMap::new();
+
+        // Insert even names in order, then odd names out of order
+        for i in (0..n).filter(|i| i % 2 == 0).chain((0..n).rev().filter(|i| i % 2 == 1)) {
+            assert_eq!(map.insert(Name(i), i), None);
+        }
+
+        assert!(map.index.is_some());
+        assert_eq!(map.len(), n as usize);
+
+        for i in 0..n {
+            assert_eq!(map.get(Name(i)), Some(&i));
+        }
+
+        assert_eq!(map.get(Name(n)), None);
+        assert_eq!(map.insert(Name(3), 0), Some(3));
+        assert_eq!(map.insert(Name(3), 3), Some(0));
+
+        for i in 0..n - 4 {
+            assert_eq!(map.remove(Name(i)), Some(i));
+            assert_eq!(map.get(Name(i + 1)), Some(&(i + 1)));
+        }
+
+        assert!(map.index.is_none());
+
+        let slice = map.into_slice();
+        assert_eq!(slice.get(Name(n - 1)), Some(&(n - 1)));
+        assert_eq!(slice.get(Name(0)), None);
+    }
+}
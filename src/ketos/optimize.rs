@@ -0,0 +1,553 @@
+//! Implements optimization passes over compiled bytecode.
+//!
+//! The compiler merges common instruction sequences and prunes empty blocks
+//! as it emits code, but it only ever sees a small part of a function at once.
+//! Passes in this module operate on the assembled bytecode of a `Code` object,
+//! where the complete control flow of the function is visible:
+//!
+//! * Jump threading retargets jumps whose destination is another jump
+//!   which is certain to be taken or certain not to be taken.
+//! * Peephole simplification removes values pushed onto the stack only to be
+//!   skipped, loads whose value is immediately replaced, and conditional
+//!   jumps which test a constant value.
+//! * Dead code removal discards instructions which can no longer be reached.
+//!
+//! Passes are repeated until no further changes are made.
+//! The source span of each remaining instruction is preserved.
+
+use std::mem::replace;
+
+use bytecode::{Code, CodeBlock, CodeReader, Instruction,
+    MAX_SHORT_OPERAND, MAX_LONG_OPERAND};
+use lexer::Span;
+use value::Value;
+
+/// Maximum number of times the complete set of passes is run
+const MAX_PASSES: usize = 8;
+
+/// Optimizes the bytecode of a `Code` object in place.
+///
+/// Returns whether the bytecode was changed. If the bytecode cannot be
+/// decoded or the optimized result cannot be encoded, it is left unchanged.
+pub fn optimize(code: &mut Code) -> bool {
+    let (mut instrs, offsets) = match decode(&code.code) {
+        Some(r) => r,
+        None => return false
+    };
+
+    let mut spans = offsets.iter().map(|&off| code.span_at(off)).collect::<Vec<_>>();
+    let mut changed = false;
+
+    for _ in 0..MAX_PASSES {
+        let threaded = thread_jumps(&mut instrs);
+        let simplified = simplify(&mut instrs, &mut spans, &code.consts);
+        let removed = remove_dead_code(&mut instrs, &mut spans);
+
+        if !(threaded || simplified || removed) {
+            break;
+        }
+
+        changed = true;
+    }
+
+    if !changed {
+        return false;
+    }
+
+    match encode(&instrs) {
+        Some((bytes, offsets)) => {
+            let mut span_table = Vec::new();
+            let mut last = None;
+
+            for (&off, &span) in offsets.iter().zip(&spans) {
+                if let Some(sp) = span {
+                    if last != span {
+                        span_table.push((off, sp));
+                        last = span;
+                    }
+                }
+            }
+
+            code.code = bytes.into_boxed_slice();
+            code.spans = span_table.into_boxed_slice();
+            code.clear_decoded();
+            true
+        }
+        None => false
+    }
+}
+
+/// Decodes bytecode into a series of instructions whose jump labels refer to
+/// instruction indices rather than byte offsets.
+/// Returns the instructions and the offset of each instruction.
+///
+/// Returns `None` if any instruction is invalid, a jump label does not refer
+/// to the start of an instruction, or the code may run past its final
+/// instruction.
+fn decode(bytes: &[u8]) -> Option<(Vec<Instruction>, Vec<u32>)> {
+    let mut offsets = Vec::new();
+    let mut instrs = Vec::new();
+    let mut r = CodeReader::new(bytes, 0);
+
+    while r.get_offset() < bytes.len() {
+        offsets.push(r.get_offset() as u32);
+
+        match r.read_instruction() {
+            Ok(instr) => instrs.push(instr),
+            Err(_) => return None
+        }
+    }
+
+    match instrs.last() {
+        Some(instr) if is_terminator(instr) => (),
+        _ => return None
+    }
+
+    for instr in &mut instrs {
+        if let Some(label) = instr.get_jump_label() {
+            match offsets.binary_search(&label) {
+                Ok(idx) => *instr = set_label(*instr, idx as u32),
+                Err(_) => return None
+            }
+        }
+    }
+
+    Some((instrs, offsets))
+}
+
+/// Encodes a series of instructions, translating jump labels from
+/// instruction indices into byte offsets.
+/// Returns the bytecode and the offset of each instruction.
+fn encode(instrs: &[Instruction]) -> Option<(Vec<u8>, Vec<u32>)> {
+    // Encoded label operands vary in length, so offsets are computed by
+    // repeatedly encoding the code. Beginning with the largest possible
+    // offsets, each round may only shrink instructions, so this converges.
+    let mut offsets = vec![MAX_LONG_OPERAND; instrs.len() + 1];
+    let mut short = false;
+
+    loop {
+        let mut block = CodeBlock::new();
+        let mut new_offsets = Vec::with_capacity(instrs.len() + 1);
+
+        for &instr in instrs {
+            new_offsets.push(block.len() as u32);
+
+            let instr = match instr.get_jump_label() {
+                Some(label) => set_label(instr, offsets[label as usize]),
+                None => instr
+            };
+
+            if instr.encode(&mut block, short).is_err() {
+                return None;
+            }
+        }
+
+        new_offsets.push(block.len() as u32);
+
+        if new_offsets == offsets {
+            return Some((block.get_bytes().to_vec(), offsets));
+        }
+
+        short = block.len() <= MAX_SHORT_OPERAND as usize;
+        offsets = new_offsets;
+    }
+}
+
+/// Retargets jumps whose destination immediately passes control elsewhere.
+fn thread_jumps(instrs: &mut [Instruction]) -> bool {
+    let mut changed = false;
+
+    for i in 0..instrs.len() {
+        let instr = instrs[i];
+
+        let mut label = match instr.get_jump_label() {
+            Some(label) => label,
+            None => continue
+        };
+
+        // Bound the number of steps, in case jumps form a cycle
+        for _ in 0..instrs.len() {
+            match follow_jump(&instr, &instrs[label as usize], label) {
+                Some(dest) if dest != label => label = dest,
+                _ => break
+            }
+        }
+
+        let new = match instr {
+            Instruction::Jump(_) if instrs[label as usize] == Instruction::Return =>
+                Instruction::Return,
+            _ => set_label(instr, label)
+        };
+
+        if new != instr {
+            instrs[i] = new;
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+/// Returns where control will pass when the jump `instr` arrives at
+/// instruction `dest`, at index `idx`, if it can be known without
+/// executing `dest`.
+fn follow_jump(instr: &Instruction, dest: &Instruction, idx: u32) -> Option<u32> {
+    if let Instruction::Jump(label) = *dest {
+        return Some(label);
+    }
+
+    // Conditional jumps do not modify the value, so a jump which was taken
+    // determines the result of a following jump on the same test.
+    match (value_test(instr), value_test(dest)) {
+        (Some((a, a_jumps)), Some((b, b_jumps))) if a == b => {
+            if a_jumps == b_jumps {
+                dest.get_jump_label()
+            } else {
+                Some(idx + 1)
+            }
+        }
+        _ => None
+    }
+}
+
+/// Performs peephole simplification of adjacent instructions.
+fn simplify(instrs: &mut Vec<Instruction>, spans: &mut Vec<Option<Span>>,
+        consts: &[Value]) -> bool {
+    use bytecode::Instruction::*;
+
+    let targets = jump_targets(instrs);
+    let mut keep = vec![true; instrs.len()];
+    let mut changed = false;
+    let mut i = 0;
+
+    while i < instrs.len() {
+        let idx = i as u32;
+        // The following instruction may only be combined with this one
+        // if control cannot reach it by any other path.
+        let next = if i + 1 < instrs.len() && !targets[i + 1] {
+            Some(instrs[i + 1])
+        } else {
+            None
+        };
+
+        match (instrs[i], next) {
+            (Jump(label), _) if label == idx + 1 => {
+                keep[i] = false;
+            }
+            (Skip(0), _) => {
+                keep[i] = false;
+            }
+            (Skip(a), Some(Skip(b))) if a.checked_add(b).is_some() => {
+                keep[i] = false;
+                instrs[i + 1] = Skip(a + b);
+            }
+            (instr, Some(Skip(n))) if n != 0 && unpush(instr).is_some() => {
+                match unpush(instr) {
+                    Some(Unpushed::Pure) => keep[i] = false,
+                    Some(Unpushed::Replace(new)) => instrs[i] = new,
+                    None => unreachable!()
+                }
+
+                if n == 1 {
+                    keep[i + 1] = false;
+                } else {
+                    instrs[i + 1] = Skip(n - 1);
+                }
+                i += 1;
+            }
+            (instr, Some(ref after)) if is_pure_load(&instr) &&
+                    replaces_value(after) => {
+                keep[i] = false;
+            }
+            (instr, Some(Skip(_))) if is_pure_load(&instr) && i + 2 < instrs.len() &&
+                    !targets[i + 2] && replaces_value(&instrs[i + 2]) => {
+                keep[i] = false;
+            }
+            (instr, Some(Jump(dest))) if is_pure_load(&instr) &&
+                    replaces_value(&instrs[dest as usize]) => {
+                keep[i] = false;
+            }
+            (instr, Some(Jump(dest))) if const_test(
+                    &instr, &instrs[dest as usize], consts).is_some() => {
+                let jump = instrs[dest as usize];
+
+                // The destination tests a constant value; jump directly
+                // to wherever it would pass control.
+                instrs[i + 1] = Jump(if const_test(&instr, &jump, consts) == Some(true) {
+                    jump.get_jump_label().unwrap()
+                } else {
+                    dest + 1
+                });
+                i += 1;
+            }
+            (instr, Some(jump)) if const_test(&instr, &jump, consts).is_some() => {
+                if const_test(&instr, &jump, consts) == Some(true) {
+                    instrs[i + 1] = Jump(jump.get_jump_label().unwrap());
+                } else {
+                    keep[i + 1] = false;
+                }
+                i += 1;
+            }
+            (jump, Some(Jump(dest))) if jump.get_jump_label() == Some(idx + 2) &&
+                    invert_jump(jump).is_some() => {
+                instrs[i] = set_label(invert_jump(jump).unwrap(), dest);
+                keep[i + 1] = false;
+                i += 1;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+
+        changed = true;
+        i += 1;
+    }
+
+    if keep.iter().any(|&k| !k) {
+        compact(instrs, spans, &keep);
+    }
+
+    changed
+}
+
+/// Removes instructions which cannot be reached from the start of the code.
+fn remove_dead_code(instrs: &mut Vec<Instruction>, spans: &mut Vec<Option<Span>>)
+        -> bool {
+    let mut live = vec![false; instrs.len()];
+    let mut pending = vec![0];
+
+    while let Some(i) = pending.pop() {
+        if i >= instrs.len() || live[i] {
+            continue;
+        }
+
+        live[i] = true;
+
+        let instr = instrs[i];
+
+        if let Some(label) = instr.get_jump_label() {
+            pending.push(label as usize);
+        }
+
+        if !is_terminator(&instr) {
+            pending.push(i + 1);
+        }
+    }
+
+    if live.iter().all(|&l| l) {
+        false
+    } else {
+        compact(instrs, spans, &live);
+        true
+    }
+}
+
+/// Removes instructions, along with their spans, not marked in `keep`,
+/// adjusting jump labels. Jumps to a removed instruction will target
+/// the next remaining instruction.
+fn compact(instrs: &mut Vec<Instruction>, spans: &mut Vec<Option<Span>>,
+        keep: &[bool]) {
+    let mut new_index = Vec::with_capacity(instrs.len() + 1);
+    let mut n = 0;
+
+    for &k in keep {
+        new_index.push(n);
+        if k {
+            n += 1;
+        }
+    }
+
+    new_index.push(n);
+
+    let old = replace(instrs, Vec::with_capacity(n as usize));
+
+    for (instr, &k) in old.into_iter().zip(keep) {
+        if k {
+            instrs.push(match instr.get_jump_label() {
+                Some(label) => set_label(instr, new_index[label as usize]),
+                None => instr
+            });
+        }
+    }
+
+    let old_spans = replace(spans, Vec::with_capacity(n as usize));
+
+    spans.extend(old_spans.into_iter().zip(keep)
+        .filter(|&(_, &k)| k).map(|(span, _)| span));
+}
+
+/// Returns a set of flags indicating whether each instruction is the target
+/// of some jump.
+fn jump_targets(instrs: &[Instruction]) -> Vec<bool> {
+    let mut targets = vec![false; instrs.len()];
+
+    for instr in instrs {
+        if let Some(label) = instr.get_jump_label() {
+            targets[label as usize] = true;
+        }
+    }
+
+    targets
+}
+
+/// Returns whether control never passes from an instruction
+/// to the instruction that follows it.
+fn is_terminator(instr: &Instruction) -> bool {
+    use bytecode::Instruction::*;
+
+    match *instr {
+        Jump(_) | Return |
+        TailCall(_) | TailCallConst(..) | TailCallFunction(_) | TailApply(_) => true,
+        _ => false
+    }
+}
+
+/// Returns an instruction with its jump label replaced.
+fn set_label(instr: Instruction, label: u32) -> Instruction {
+    use bytecode::Instruction::*;
+
+    match instr {
+        Jump(_) => Jump(label),
+        JumpIf(_) => JumpIf(label),
+        JumpIfBound(_, n) => JumpIfBound(label, n),
+        JumpIfNot(_) => JumpIfNot(label),
+        JumpIfNull(_) => JumpIfNull(label),
+        JumpIfNotNull(_) => JumpIfNotNull(label),
+        JumpIfEq(_) => JumpIfEq(label),
+        JumpIfNotEq(_) => JumpIfNotEq(label),
+        JumpIfEqConst(_, n) => JumpIfEqConst(label, n),
+        JumpIfNotEqConst(_, n) => JumpIfNotEqConst(label, n),
+        Try(_) => Try(label),
+        instr => instr
+    }
+}
+
+/// Returns a conditional jump which jumps exactly when the given
+/// conditional jump does not.
+fn invert_jump(instr: Instruction) -> Option<Instruction> {
+    use bytecode::Instruction::*;
+
+    let new = match instr {
+        JumpIf(label) => JumpIfNot(label),
+        JumpIfNot(label) => JumpIf(label),
+        JumpIfNull(label) => JumpIfNotNull(label),
+        JumpIfNotNull(label) => JumpIfNull(label),
+        JumpIfEq(label) => JumpIfNotEq(label),
+        JumpIfNotEq(label) => JumpIfEq(label),
+        JumpIfEqConst(label, n) => JumpIfNotEqConst(label, n),
+        JumpIfNotEqConst(label, n) => JumpIfEqConst(label, n),
+        _ => return None
+    };
+
+    Some(new)
+}
+
+/// Test performed on the value by a conditional jump
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Test {
+    Bool,
+    Null,
+    EqConst(u32),
+}
+
+/// Returns the test performed by a conditional jump on the value, which it
+/// does not modify, and whether the jump is taken when the test succeeds.
+fn value_test(instr: &Instruction) -> Option<(Test, bool)> {
+    use bytecode::Instruction::*;
+
+    match *instr {
+        JumpIf(_) => Some((Test::Bool, true)),
+        JumpIfNot(_) => Some((Test::Bool, false)),
+        JumpIfNull(_) => Some((Test::Null, true)),
+        JumpIfNotNull(_) => Some((Test::Null, false)),
+        JumpIfEqConst(_, n) => Some((Test::EqConst(n), true)),
+        JumpIfNotEqConst(_, n) => Some((Test::EqConst(n), false)),
+        _ => None
+    }
+}
+
+/// If `instr` loads a constant value which is tested by the conditional
+/// jump `jump`, returns whether the jump will be taken.
+fn const_test(instr: &Instruction, jump: &Instruction, consts: &[Value]) -> Option<bool> {
+    use bytecode::Instruction::*;
+
+    let value = match *instr {
+        Unit => Value::Unit,
+        True => Value::Bool(true),
+        False => Value::Bool(false),
+        Const(n) => match consts.get(n as usize) {
+            Some(&Value::Unit) => Value::Unit,
+            Some(&Value::Bool(b)) => Value::Bool(b),
+            _ => return None
+        },
+        _ => return None
+    };
+
+    let (test, jumps) = match value_test(jump) {
+        Some(test) => test,
+        None => return None
+    };
+
+    let success = match (test, value) {
+        (Test::Bool, Value::Bool(b)) => b,
+        (Test::Null, Value::Unit) => true,
+        (Test::Null, _) => false,
+        _ => return None
+    };
+
+    Some(success == jumps)
+}
+
+/// Result of removing the push from an instruction which pushes its value
+enum Unpushed {
+    /// The instruction has no other effect and may be removed
+    Pure,
+    /// The instruction must be replaced with the given instruction
+    Replace(Instruction),
+}
+
+/// Returns the effect of removing the push from an instruction which
+/// pushes its value onto the stack.
+fn unpush(instr: Instruction) -> Option<Unpushed> {
+    use bytecode::Instruction::*;
+
+    let res = match instr {
+        Push | LoadPush(_) | LoadCPush(_) | UnitPush | TruePush | FalsePush |
+        ConstPush(_) => Unpushed::Pure,
+        GetDefPush(n) => Unpushed::Replace(GetDef(n)),
+        IncPush => Unpushed::Replace(Inc),
+        DecPush => Unpushed::Replace(Dec),
+        FirstPush => Unpushed::Replace(First),
+        TailPush => Unpushed::Replace(Tail),
+        InitPush => Unpushed::Replace(Init),
+        LastPush => Unpushed::Replace(Last),
+        CallSysPush(n) => Unpushed::Replace(CallSys(n)),
+        CallSysArgsPush(n, n_args) => Unpushed::Replace(CallSysArgs(n, n_args)),
+        _ => return None
+    };
+
+    Some(res)
+}
+
+/// Returns whether an instruction only loads the value, with no other effect.
+fn is_pure_load(instr: &Instruction) -> bool {
+    use bytecode::Instruction::*;
+
+    match *instr {
+        Load(_) | LoadC(_) | Unit | True | False | Const(_) => true,
+        _ => false
+    }
+}
+
+/// Returns whether an instruction replaces the value without reading it.
+fn replaces_value(instr: &Instruction) -> bool {
+    use bytecode::Instruction::*;
+
+    match *instr {
+        Load(_) | LoadC(_) | GetDef(_) | Unit | True | False | Const(_) |
+        LoadPush(_) | LoadCPush(_) | GetDefPush(_) | UnitPush | TruePush |
+        FalsePush | ConstPush(_) => true,
+        _ => false
+    }
+}
@@ -1,13 +1,21 @@
 //! Parses a series of `lexer` tokens into a code expression.
 
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
+use std::io::BufRead;
+use std::mem::replace;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use num::Num;
 
+use error::Error;
 use integer::{Integer, Ratio};
-use lexer::{Lexer, Span, Token};
+use io::{IoError, IoMode};
+use lexer::{BytePos, Lexer, Span, Token};
+use map::{Map, Set};
 use name::{get_standard_name_for, Name, NameDisplay, NameStore};
 use string;
 use value::Value;
@@ -18,6 +26,149 @@ pub struct Parser<'a, 'lex> {
     names: &'a mut NameStore,
     name_cache: HashMap<&'lex str, Name>,
     cur_token: Option<(Span, Token<'lex>)>,
+    /// Last token produced by the lexer; used to suggest fixes for errors
+    prev_token: Option<(Span, Token<'lex>)>,
+    spans: SpanMap,
+    readers: Option<Rc<RefCell<ReaderMacros>>>,
+    comments: Option<Vec<Comment>>,
+    /// Values of datum labels defined in the current top-level expression;
+    /// `None` while the labeled value is being parsed
+    labels: HashMap<u32, Option<Value>>,
+    expr_depth: u32,
+    max_depth: Option<usize>,
+    /// Depth of groups enclosing a reader macro which is parsing
+    /// a nested expression
+    outer_depth: usize,
+    /// Text of a doc comment preceding the next token
+    pending_doc: Option<String>,
+    /// Doc comments of unclosed lists, keyed by position of opening paren
+    open_docs: HashMap<BytePos, String>,
+}
+
+/// A comment found in source code, recorded by a `Parser`
+/// when comments are preserved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comment {
+    /// Span of source code containing the comment
+    pub span: Span,
+    /// Text of the comment, including the leading `;` characters or
+    /// enclosing `#|` and `|#`
+    pub text: String,
+    /// Whether the comment is a doc comment, beginning with `;;`
+    pub doc: bool,
+    /// Suggests the expression to which the comment belongs
+    pub attachment: CommentAttachment,
+}
+
+/// Suggests the expression to which a comment belongs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CommentAttachment {
+    /// Comment appears on its own line and describes the following expression
+    Leading,
+    /// Comment follows an expression on the same line and describes it
+    Trailing,
+}
+
+/// Function which reads a value following a dispatch sequence, e.g. `#t`.
+///
+/// The function receives the `Parser`, positioned immediately after the
+/// dispatch character, and the span of the dispatch sequence.
+pub type ReaderFn = Rc<Fn(&mut Parser, Span) -> Result<Value, ParseError>>;
+
+/// Maps dispatch characters to reader macro functions.
+#[derive(Clone, Default)]
+pub struct ReaderMacros {
+    fns: HashMap<char, ReaderFn>,
+}
+
+impl ReaderMacros {
+    /// Creates a new empty `ReaderMacros`.
+    pub fn new() -> ReaderMacros {
+        ReaderMacros::default()
+    }
+
+    /// Adds a reader function for the given dispatch character.
+    /// If a function was previously registered for the character,
+    /// it is replaced and returned.
+    pub fn add<F>(&mut self, ch: char, f: F) -> Option<ReaderFn>
+            where F: Fn(&mut Parser, Span) -> Result<Value, ParseError> + 'static {
+        self.fns.insert(ch, Rc::new(f))
+    }
+
+    /// Returns the reader function for the given dispatch character.
+    pub fn get(&self, ch: char) -> Option<ReaderFn> {
+        self.fns.get(&ch).cloned()
+    }
+
+    /// Removes the reader function for the given dispatch character.
+    pub fn remove(&mut self, ch: char) -> Option<ReaderFn> {
+        self.fns.remove(&ch)
+    }
+}
+
+/// Records the source spans of list expressions produced by a `Parser`,
+/// along with the text of any doc comment immediately preceding a list.
+///
+/// Spans are keyed on the identity of a list value, rather than its contents.
+/// Only the values produced by the parser, and any clones of these values,
+/// will be found in the map.
+///
+/// The map holds a reference to each recorded list, so that its storage
+/// cannot be freed and reused by an unrelated list while the map exists.
+#[derive(Clone, Debug, Default)]
+pub struct SpanMap {
+    spans: HashMap<ValueKey, Span>,
+    docs: HashMap<ValueKey, String>,
+    values: Vec<Value>,
+}
+
+impl SpanMap {
+    /// Creates a new empty `SpanMap`.
+    pub fn new() -> SpanMap {
+        SpanMap::default()
+    }
+
+    /// Returns the span of source code from which the given value was parsed.
+    pub fn get(&self, value: &Value) -> Option<Span> {
+        value_key(value).and_then(|key| self.spans.get(&key).cloned())
+    }
+
+    /// Records the span of source code from which a value was parsed.
+    /// Values other than lists are ignored.
+    pub fn insert(&mut self, value: &Value, span: Span) {
+        if let Some(key) = value_key(value) {
+            self.spans.insert(key, span);
+            self.values.push(value.clone());
+        }
+    }
+
+    /// Returns the doc comment preceding the given value, without
+    /// leading semicolons.
+    pub fn doc(&self, value: &Value) -> Option<&str> {
+        value_key(value).and_then(|key| self.docs.get(&key)).map(|s| &s[..])
+    }
+
+    /// Records the doc comment preceding a value.
+    /// Values other than lists are ignored.
+    pub fn insert_doc(&mut self, value: &Value, doc: String) {
+        if let Some(key) = value_key(value) {
+            self.docs.insert(key, doc);
+            self.values.push(value.clone());
+        }
+    }
+}
+
+/// Address of the first element and length of a list.
+///
+/// The length distinguishes a list from a slice of it which shares
+/// the same first element.
+type ValueKey = (usize, usize);
+
+fn value_key(value: &Value) -> Option<ValueKey> {
+    match *value {
+        Value::List(ref li) => Some((li.as_ptr() as usize, li.len())),
+        _ => None
+    }
 }
 
 /// Represents an error in parsing input.
@@ -27,14 +178,45 @@ pub struct ParseError {
     pub span: Span,
     /// Kind of error generated
     pub kind: ParseErrorKind,
+    /// Span of the opening delimiter of a list which was left unclosed
+    /// or closed by the wrong delimiter
+    pub open_span: Option<Span>,
+    /// Likely fix for the error, if one is known
+    pub suggestion: Option<Suggestion>,
 }
 
 impl ParseError {
+    /// Returns whether the error was caused by input ending before
+    /// an expression was complete. Such an error may be resolved by
+    /// supplying additional input.
+    pub fn is_incomplete(&self) -> bool {
+        match self.kind {
+            ParseErrorKind::MissingCloseParen |
+            ParseErrorKind::UnexpectedEof |
+            ParseErrorKind::UnterminatedComment |
+            ParseErrorKind::UnterminatedString => true,
+            _ => false
+        }
+    }
+
     /// Creates a new `ParseError`.
     pub fn new(span: Span, kind: ParseErrorKind) -> ParseError {
         ParseError{
             span: span,
             kind: kind,
+            open_span: None,
+            suggestion: None,
+        }
+    }
+
+    /// Creates a `ParseError` caused by the list whose opening delimiter,
+    /// of kind `delim`, begins at `open`.
+    fn unclosed(span: Span, kind: ParseErrorKind, open: BytePos, delim: Delim)
+            -> ParseError {
+        ParseError{
+            open_span: Some(Span{lo: open, hi: open + delim.open().len() as BytePos}),
+            suggestion: Some(Suggestion::Close(delim.close())),
+            ..ParseError::new(span, kind)
         }
     }
 }
@@ -51,6 +233,34 @@ impl NameDisplay for ParseError {
     }
 }
 
+/// Describes a likely fix for a `ParseError`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Suggestion {
+    /// Close the list with the given delimiter
+    Close(&'static str),
+    /// Write the name preceding a `:` as a keyword
+    Keyword(Name),
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Suggestion::Close(s) => write!(f, "insert `{}` to close the list", s),
+            Suggestion::Keyword(_) => f.write_str("did you mean a keyword?"),
+        }
+    }
+}
+
+impl NameDisplay for Suggestion {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Suggestion::Keyword(name) =>
+                write!(f, "did you mean the keyword `:{}`?", names.get(name)),
+            _ => fmt::Display::fmt(self, f)
+        }
+    }
+}
+
 /// Describes the kind of error encountered in parsing.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ParseErrorKind {
@@ -60,12 +270,38 @@ pub enum ParseErrorKind {
     InvalidToken,
     /// Invalid character in input
     InvalidChar(char),
+    /// Digit out of range for the base of a numeric literal
+    InvalidDigit{
+        /// Digit found
+        digit: char,
+        /// Base of numeric literal
+        base: u32,
+    },
+    /// Misplaced or invalid exponent in numeric literal
+    InvalidExponent,
     /// Invalid character in numeric escape sequence `\xNN` or `\u{NNNN}`
     InvalidNumericEscape(char),
     /// Error parsing literal string into value
     LiteralParseError,
+    /// Key in a map literal which may not be used as a map key
+    InvalidMapKey,
+    /// Map literal containing a key without a value
+    MissingMapValue,
+    /// Element in a set literal which may not be a member of a set
+    InvalidSetElement,
     /// Missing closing parenthesis
     MissingCloseParen,
+    /// Expressions nested beyond the parser's maximum depth
+    DepthExceeded,
+    /// Datum label defined more than once in an expression
+    DuplicateLabel(u32),
+    /// Datum label referenced within the value it labels;
+    /// self-referential values cannot be constructed
+    RecursiveLabel(u32),
+    /// Reference to a datum label which has not been defined
+    UndefinedLabel(u32),
+    /// Numeric literal is missing digits in its value, exponent, or denominator
+    MissingDigits,
     /// More commas than backquotes
     UnbalancedComma,
     /// Unexpected end-of-file
@@ -81,6 +317,8 @@ pub enum ParseErrorKind {
     UnknownCharEscape(char),
     /// Unmatched `)`
     UnmatchedParen,
+    /// Dispatch character with no registered reader macro
+    UnknownDispatch(char),
     /// Unterminated character constant
     UnterminatedChar,
     /// Unterminated block comment
@@ -96,10 +334,29 @@ impl fmt::Display for ParseErrorKind {
             ParseErrorKind::InvalidToken => f.write_str("invalid token"),
             ParseErrorKind::InvalidChar(ch) =>
                 write!(f, "invalid character: {:?}", ch),
+            ParseErrorKind::InvalidDigit{digit, base} =>
+                write!(f, "invalid digit {:?} in base {} literal", digit, base),
+            ParseErrorKind::InvalidExponent =>
+                f.write_str("invalid exponent in numeric literal"),
             ParseErrorKind::InvalidNumericEscape(ch) =>
                 write!(f, "invalid character in {} escape sequence", ch),
             ParseErrorKind::LiteralParseError => f.write_str("literal parse error"),
+            ParseErrorKind::InvalidMapKey => f.write_str("invalid key in map literal"),
+            ParseErrorKind::MissingMapValue =>
+                f.write_str("missing value for key in map literal"),
+            ParseErrorKind::InvalidSetElement =>
+                f.write_str("invalid element in set literal"),
             ParseErrorKind::MissingCloseParen => f.write_str("missing close paren"),
+            ParseErrorKind::DepthExceeded =>
+                f.write_str("maximum expression depth exceeded"),
+            ParseErrorKind::DuplicateLabel(n) =>
+                write!(f, "duplicate datum label #{}=", n),
+            ParseErrorKind::RecursiveLabel(n) =>
+                write!(f, "datum label #{}# refers to a value containing itself", n),
+            ParseErrorKind::UndefinedLabel(n) =>
+                write!(f, "undefined datum label #{}#", n),
+            ParseErrorKind::MissingDigits =>
+                f.write_str("missing digits in numeric literal"),
             ParseErrorKind::UnbalancedComma => f.write_str("unbalanced ` and ,"),
             ParseErrorKind::UnexpectedEof => f.write_str("unexpected end-of-file"),
             ParseErrorKind::UnexpectedToken{expected, found} =>
@@ -107,6 +364,8 @@ impl fmt::Display for ParseErrorKind {
             ParseErrorKind::UnknownCharEscape(ch) =>
                 write!(f, "unknown char escape: {:?}", ch),
             ParseErrorKind::UnmatchedParen => f.write_str("unmatched `)`"),
+            ParseErrorKind::UnknownDispatch(ch) =>
+                write!(f, "unknown dispatch character: {:?}", ch),
             ParseErrorKind::UnterminatedChar => f.write_str("unterminated char constant"),
             ParseErrorKind::UnterminatedComment => f.write_str("unterminated block comment"),
             ParseErrorKind::UnterminatedString => f.write_str("unterminated string constant"),
@@ -122,8 +381,45 @@ enum Group {
     /// Number of quotes preceding group.
     /// If zero, this is an unquoted parentheses group.
     Quotes(u32),
-    /// Values in a parenthetical expression
-    Parens(Vec<Value>),
+    /// Values in a parenthetical expression, the position of the
+    /// opening delimiter, and the kind of delimiter
+    Parens(Vec<Value>, BytePos, Delim),
+    /// Datum label assigned to the following value
+    Label(u32),
+}
+
+/// Kind of delimiter enclosing a parenthetical expression
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Delim {
+    /// `( ... )`; a list
+    Paren,
+    /// `[ ... ]`; a list whose elements are evaluated
+    Bracket,
+    /// `{ ... }`; a call to `hash-map`
+    Brace,
+    /// `#{ ... }`; a set
+    SetBrace,
+}
+
+impl Delim {
+    /// Returns the token which opens a group with this delimiter.
+    fn open(self) -> &'static str {
+        match self {
+            Delim::Paren => "(",
+            Delim::Bracket => "[",
+            Delim::Brace => "{",
+            Delim::SetBrace => "#{",
+        }
+    }
+
+    /// Returns the token which closes a group opened by this delimiter.
+    fn close(self) -> &'static str {
+        match self {
+            Delim::Paren => ")",
+            Delim::Bracket => "]",
+            Delim::Brace | Delim::SetBrace => "}",
+        }
+    }
 }
 
 impl<'a, 'lex> Parser<'a, 'lex> {
@@ -136,39 +432,179 @@ impl<'a, 'lex> Parser<'a, 'lex> {
             names: names,
             name_cache: HashMap::new(),
             cur_token: None,
+            prev_token: None,
+            spans: SpanMap::new(),
+            readers: None,
+            comments: None,
+            labels: HashMap::new(),
+            expr_depth: 0,
+            max_depth: None,
+            outer_depth: 0,
+            pending_doc: None,
+            open_docs: HashMap::new(),
+        }
+    }
+
+    /// Instructs the parser to record comments found in the input.
+    /// Recorded comments may be retrieved using `take_comments`.
+    pub fn preserve_comments(&mut self) {
+        self.lexer.set_comments(true);
+
+        if self.comments.is_none() {
+            self.comments = Some(Vec::new());
+        }
+    }
+
+    /// Removes and returns comments recorded so far.
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        match self.comments {
+            Some(ref mut comments) => replace(comments, Vec::new()),
+            None => Vec::new()
         }
     }
 
+    /// Sets the maximum depth of nested expressions which may be parsed.
+    /// Input exceeding this depth produces a `DepthExceeded` error.
+    ///
+    /// By default, there is no limit. Hosts parsing untrusted input should
+    /// set a limit, as deeply nested values may overflow the stack when
+    /// they are later compiled, compared, or dropped.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = Some(depth);
+    }
+
+    /// Sets the reader macros used to parse dispatch sequences, e.g. `#t`.
+    pub fn set_readers(&mut self, readers: Rc<RefCell<ReaderMacros>>) {
+        self.readers = Some(readers);
+    }
+
+    /// Returns the spans of list expressions parsed so far.
+    pub fn spans(&self) -> &SpanMap {
+        &self.spans
+    }
+
+    /// Removes and returns the spans of list expressions parsed so far.
+    pub fn take_spans(&mut self) -> SpanMap {
+        replace(&mut self.spans, SpanMap::new())
+    }
+
     /// Skips the "shebang" line of a source file.
     pub fn skip_shebang(&mut self) {
         self.lexer.skip_shebang();
     }
 
     /// Parses an expression from the input stream.
+    ///
+    /// Datum labels, `#n=`, may be referenced, using `#n#`, only within
+    /// the top-level expression in which they are defined.
     pub fn parse_expr(&mut self) -> Result<Value, ParseError> {
+        if self.expr_depth == 0 {
+            self.labels.clear();
+            self.open_docs.clear();
+        }
+
         let mut stack = Vec::new();
+
+        self.expr_depth += 1;
+        let r = self.parse_expr_stack(&mut stack);
+        self.expr_depth -= 1;
+
+        r
+    }
+
+    /// Parses an expression using the given group stack.
+    /// If an error is returned, the stack will contain any parenthetical
+    /// groups left unclosed at the point of error.
+    fn parse_expr_stack(&mut self, stack: &mut Vec<Group>) -> Result<Value, ParseError> {
         let mut total_backticks = 0;
 
         loop {
             let (sp, tok) = try!(self.next());
+            let doc = self.pending_doc.take();
 
             let r = match tok {
-                Token::DocComment(_) => unreachable!(),
-                Token::LeftParen => {
-                    stack.push(Group::Parens(Vec::new()));
+                Token::DocComment(_) | Token::Comment(_) => unreachable!(),
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace |
+                        Token::LeftSetBrace => {
+                    let delim = match tok {
+                        Token::LeftBracket => Delim::Bracket,
+                        Token::LeftBrace => Delim::Brace,
+                        Token::LeftSetBrace => Delim::SetBrace,
+                        _ => Delim::Paren
+                    };
+                    if let Some(doc) = doc {
+                        self.open_docs.insert(sp.lo, doc);
+                    }
+                    try!(self.push_group(stack,
+                        Group::Parens(Vec::new(), sp.lo, delim), sp));
                     continue;
                 }
-                Token::RightParen => {
+                Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                    let delim = match tok {
+                        Token::RightBracket => Delim::Bracket,
+                        Token::RightBrace => Delim::Brace,
+                        _ => Delim::Paren
+                    };
                     let group = try!(stack.pop().ok_or_else(
                         || ParseError::new(sp, ParseErrorKind::UnmatchedParen)));
 
                     match group {
-                        Group::Parens(values) => Ok(values.into()),
-                        _ => Err(ParseError::new(sp,
-                            ParseErrorKind::UnexpectedToken{
-                                expected: "expression",
-                                found: ")",
-                            }))
+                        Group::Parens(values, lo, Delim::Brace) if delim == Delim::Brace => {
+                            map_literal(values)
+                                .map(Value::from)
+                                .map_err(|kind| ParseError::new(
+                                    Span{lo: lo, hi: sp.hi}, kind))
+                        }
+                        Group::Parens(values, lo, Delim::SetBrace) if delim == Delim::Brace => {
+                            set_literal(values)
+                                .map(Value::from)
+                                .map_err(|kind| ParseError::new(
+                                    Span{lo: lo, hi: sp.hi}, kind))
+                        }
+                        Group::Parens(mut values, lo, d) if d == delim => {
+                            // Outside of a quoted expression, `[a b]` is
+                            // read as `` `(,a ,b) ``, so that the list is
+                            // constructed without calling a named function.
+                            let eval_list = delim == Delim::Bracket &&
+                                !is_quoted(stack, total_backticks);
+
+                            if eval_list {
+                                values = values.into_iter()
+                                    .map(|v| v.comma(1)).collect();
+                            }
+
+                            let v = values.into();
+                            self.spans.insert(&v, Span{lo: lo, hi: sp.hi});
+                            if let Some(doc) = self.open_docs.remove(&lo) {
+                                self.spans.insert_doc(&v, doc);
+                            }
+
+                            match v {
+                                Value::List(_) if eval_list => Ok(v.quasiquote(1)),
+                                v => Ok(v)
+                            }
+                        }
+                        Group::Parens(_, lo, d) => {
+                            Err(ParseError::unclosed(sp,
+                                ParseErrorKind::UnexpectedToken{
+                                    expected: d.close(),
+                                    found: tok.name(),
+                                }, lo, d))
+                        }
+                        _ => {
+                            // Discard the group closed by this token
+                            while let Some(group) = stack.pop() {
+                                if let Group::Parens(..) = group {
+                                    break;
+                                }
+                            }
+
+                            Err(ParseError::new(sp,
+                                ParseErrorKind::UnexpectedToken{
+                                    expected: "expression",
+                                    found: tok.name(),
+                                }))
+                        }
                     }
                 }
                 Token::Float(f) => parse_float(f)
@@ -177,22 +613,48 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                 Token::Integer(i, base) => parse_integer(i, base)
                     .map(|i| Value::Integer(i))
                     .map_err(|kind| ParseError::new(sp, kind)),
-                Token::Ratio(r) => parse_ratio(r)
+                Token::Ratio(r, base) => parse_ratio(r, base)
+                    .map(|r| Value::Ratio(r))
+                    .map_err(|kind| ParseError::new(sp, kind)),
+                Token::Decimal(d) => parse_decimal(d)
                     .map(|r| Value::Ratio(r))
-                    .map_err(|_| ParseError::new(sp, ParseErrorKind::LiteralParseError)),
+                    .map_err(|kind| ParseError::new(sp, kind)),
                 Token::Char(ch) => parse_char(ch)
                     .map(|ch| Value::Char(ch)),
                 Token::String(s) => parse_string(s)
-                    .map(|s| Value::String(s)),
+                    .map(Value::from),
                 Token::Name(name) => Ok(self.name_value(name)),
-                Token::Keyword(name) => Ok(Value::Keyword(self.add_name(name))),
+                Token::Keyword(name) => Ok(Value::Keyword(self.add_lex_name(name))),
+                Token::Dispatch(ch) => self.read_dispatch(ch, sp, stack.len()),
+                Token::Label(n) => {
+                    let n = try!(parse_label(n, sp));
+
+                    if self.labels.contains_key(&n) {
+                        return Err(ParseError::new(sp, ParseErrorKind::DuplicateLabel(n)));
+                    }
+
+                    self.labels.insert(n, None);
+                    try!(self.push_group(stack, Group::Label(n), sp));
+                    continue;
+                }
+                Token::LabelRef(n) => {
+                    let n = try!(parse_label(n, sp));
+
+                    match self.labels.get(&n) {
+                        Some(&Some(ref v)) => Ok(v.clone()),
+                        Some(&None) => Err(ParseError::new(sp,
+                            ParseErrorKind::RecursiveLabel(n))),
+                        None => Err(ParseError::new(sp,
+                            ParseErrorKind::UndefinedLabel(n)))
+                    }
+                }
                 Token::BackQuote => {
                     total_backticks += 1;
                     if let Some(&mut Group::Backticks(ref mut n)) = stack.last_mut() {
                         *n += 1;
                         continue;
                     }
-                    stack.push(Group::Backticks(1));
+                    try!(self.push_group(stack, Group::Backticks(1), sp));
                     continue;
                 }
                 Token::Comma => {
@@ -204,7 +666,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                         *n -= 1;
                         continue;
                     }
-                    stack.push(Group::Backticks(-1));
+                    try!(self.push_group(stack, Group::Backticks(-1), sp));
                     continue;
                 }
                 Token::CommaAt => {
@@ -212,7 +674,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                         return Err(ParseError::new(sp, ParseErrorKind::UnbalancedComma));
                     }
                     total_backticks -= 1;
-                    stack.push(Group::CommaAt);
+                    try!(self.push_group(stack, Group::CommaAt, sp));
                     continue;
                 }
                 Token::Quote => {
@@ -220,22 +682,21 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                         *n += 1;
                         continue;
                     }
-                    stack.push(Group::Quotes(1));
+                    try!(self.push_group(stack, Group::Quotes(1), sp));
                     continue;
                 }
                 Token::End => {
-                    let any_paren = stack.iter().any(|group| {
+                    let paren = stack.iter().rev().filter_map(|group| {
                         match *group {
-                            Group::Parens(_) => true,
-                            _ => false
+                            Group::Parens(_, lo, d) => Some((lo, d)),
+                            _ => None
                         }
-                    });
+                    }).next();
 
-                    if any_paren {
-                        Err(ParseError::new(sp,
-                            ParseErrorKind::MissingCloseParen))
-                    } else {
-                        Err(ParseError::new(sp,
+                    match paren {
+                        Some((lo, d)) => Err(ParseError::unclosed(sp,
+                            ParseErrorKind::MissingCloseParen, lo, d)),
+                        None => Err(ParseError::new(sp,
                             ParseErrorKind::UnexpectedEof))
                     }
                 }
@@ -246,7 +707,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
             loop {
                 match stack.last_mut() {
                     None => return Ok(v),
-                    Some(&mut Group::Parens(ref mut values)) => {
+                    Some(&mut Group::Parens(ref mut values, _, _)) => {
                         values.push(v);
                         break;
                     }
@@ -271,12 +732,32 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                         v = v.comma_at(1);
                     }
                     Group::Quotes(n) => v = v.quote(n),
+                    Group::Label(n) => {
+                        self.labels.insert(n, Some(v.clone()));
+                    }
                     _ => ()
                 }
             }
         }
     }
 
+    /// Pushes a group onto the stack, checking that the maximum depth
+    /// is not exceeded. The group is pushed even if an error is returned,
+    /// so that error recovery will skip its contents.
+    fn push_group(&self, stack: &mut Vec<Group>, group: Group, sp: Span)
+            -> Result<(), ParseError> {
+        stack.push(group);
+        self.check_depth(self.outer_depth + stack.len(), sp)
+    }
+
+    fn check_depth(&self, depth: usize, sp: Span) -> Result<(), ParseError> {
+        match self.max_depth {
+            Some(max) if depth > max =>
+                Err(ParseError::new(sp, ParseErrorKind::DepthExceeded)),
+            _ => Ok(())
+        }
+    }
+
     /// Parses a single expression from the input stream.
     /// If any tokens remain after the expression, an error is returned.
     pub fn parse_single_expr(&mut self) -> Result<Value, ParseError> {
@@ -305,6 +786,161 @@ impl<'a, 'lex> Parser<'a, 'lex> {
         Ok(res)
     }
 
+    /// Parse a series of expressions from the input stream, distinguishing
+    /// input which is merely incomplete from input which is malformed.
+    ///
+    /// If the input ends before an expression is complete, as determined by
+    /// `ParseError::is_incomplete`, `Ok(None)` is returned. A REPL may then
+    /// read another line and parse the combined input.
+    pub fn parse_exprs_partial(&mut self) -> Result<Option<Vec<Value>>, ParseError> {
+        match self.parse_exprs() {
+            Ok(v) => Ok(Some(v)),
+            Err(ref e) if e.is_incomplete() => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Parse a series of expressions from the input stream,
+    /// recovering from errors to report all errors found in the input.
+    ///
+    /// When an error is encountered, input is skipped until the end of
+    /// the enclosing top-level expression or until a `(` at the beginning
+    /// of a line, whichever comes first. Parsing then resumes normally.
+    pub fn parse_exprs_recover(&mut self) -> Result<Vec<Value>, Vec<ParseError>> {
+        let mut res = Vec::new();
+        let mut errors = Vec::new();
+
+        loop {
+            match self.peek() {
+                Ok((_sp, Token::End)) => break,
+                Ok(_) => (),
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            }
+
+            let mut stack = Vec::new();
+            self.labels.clear();
+            self.open_docs.clear();
+
+            match self.parse_expr_stack(&mut stack) {
+                Ok(v) => res.push(v),
+                Err(e) => {
+                    errors.push(e);
+
+                    let depth = stack.iter().filter(|group| {
+                        match **group {
+                            Group::Parens(..) => true,
+                            _ => false
+                        }
+                    }).count();
+
+                    self.skip_groups(depth, &mut errors);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(res)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Skips tokens until `depth` parenthetical groups have been closed
+    /// or a `(` token is found at the beginning of a line.
+    fn skip_groups(&mut self, mut depth: usize, errors: &mut Vec<ParseError>) {
+        while depth != 0 {
+            match self.peek() {
+                Ok((_, Token::End)) => break,
+                Ok((_, Token::LeftParen)) => {
+                    if self.lexer.line_start() {
+                        break;
+                    }
+                    depth += 1;
+                }
+                Ok((_, Token::LeftBracket)) |
+                Ok((_, Token::LeftBrace)) |
+                Ok((_, Token::LeftSetBrace)) => depth += 1,
+                Ok((_, Token::RightParen)) |
+                Ok((_, Token::RightBracket)) |
+                Ok((_, Token::RightBrace)) => depth -= 1,
+                Ok(_) => (),
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            }
+
+            self.cur_token = None;
+        }
+    }
+
+    /// Consumes and returns the next token from the input stream.
+    ///
+    /// This may be used by reader macro functions to read tokens directly.
+    pub fn next_token(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
+        self.next()
+    }
+
+    /// Returns the next token from the input stream without consuming it.
+    pub fn peek_token(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
+        self.peek()
+    }
+
+    /// Adds a name to the contained `NameStore`.
+    pub fn add_name(&mut self, name: &str) -> Name {
+        get_standard_name_for(name).unwrap_or_else(|| self.names.add(name))
+    }
+
+    /// Calls the reader macro for a dispatch character. Expressions parsed
+    /// by the reader macro count toward the maximum depth as though they
+    /// were nested within the `depth` groups enclosing the dispatch sequence.
+    fn read_dispatch(&mut self, ch: char, sp: Span, depth: usize) -> Result<Value, ParseError> {
+        let f = self.readers.as_ref().and_then(|r| r.borrow().get(ch));
+
+        match f {
+            Some(f) => {
+                let outer = self.outer_depth;
+                let depth = outer + depth + 1;
+                try!(self.check_depth(depth, sp));
+
+                self.outer_depth = depth;
+                let r = f(self, sp);
+                self.outer_depth = outer;
+                r
+            }
+            None => Err(ParseError::new(sp, ParseErrorKind::UnknownDispatch(ch)))
+        }
+    }
+
+    /// Parses a series of expressions from the input stream, preserving
+    /// any comments found within the input.
+    pub fn parse_exprs_with_comments(&mut self)
+            -> Result<(Vec<Value>, Vec<Comment>), ParseError> {
+        self.preserve_comments();
+
+        let exprs = try!(self.parse_exprs());
+        Ok((exprs, self.take_comments()))
+    }
+
+    /// Parses the next expression from the input stream, returning it along
+    /// with the position of the end of the expression.
+    /// If no expression remains in the input, `None` is returned.
+    ///
+    /// The returned position includes the code offset given to the `Lexer`.
+    /// No input following the end of the expression is consumed.
+    pub fn parse_next_expr(&mut self) -> Result<Option<(Value, BytePos)>, ParseError> {
+        match try!(self.peek()) {
+            (_, Token::End) => Ok(None),
+            _ => {
+                let v = try!(self.parse_expr());
+                Ok(Some((v, self.lexer.position())))
+            }
+        }
+    }
+
     /// Returns the the next token if it is a doc comment.
     /// Otherwise, `None` is returned and the token will be processed later.
     pub fn read_doc_comment(&mut self) -> Result<Option<&'lex str>, ParseError> {
@@ -314,7 +950,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
         }
     }
 
-    fn add_name(&mut self, name: &'lex str) -> Name {
+    fn add_lex_name(&mut self, name: &'lex str) -> Name {
         let names = &mut *self.names;
         *self.name_cache.entry(name).or_insert_with(
             || get_standard_name_for(name).unwrap_or_else(|| names.add(name)))
@@ -325,38 +961,173 @@ impl<'a, 'lex> Parser<'a, 'lex> {
             "true" => Value::Bool(true),
             "false" => Value::Bool(false),
             _ => {
-                let name = self.add_name(name);
+                let name = self.add_lex_name(name);
                 Value::Name(name)
             }
         }
     }
 
     fn next(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
-        let r = try!(self.peek_all());
+        let r = try!(self.peek());
         self.cur_token = None;
         Ok(r)
     }
 
-    /// Returns the next non-`DocComment` token without consuming it
+    /// Returns the next non-comment token without consuming it
     fn peek(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
         loop {
             match try!(self.peek_all()) {
-                (_, Token::DocComment(_)) => { self.cur_token.take(); }
+                (sp, Token::DocComment(text)) => {
+                    self.cur_token.take();
+                    self.record_comment(sp, text, true);
+                    self.pending_doc = Some(doc_text(text));
+                }
+                (sp, Token::Comment(text)) => {
+                    self.cur_token.take();
+                    self.record_comment(sp, text, false);
+                }
                 tok => return Ok(tok)
             }
         }
     }
 
+    fn record_comment(&mut self, span: Span, text: &str, doc: bool) {
+        let first = self.lexer.first_on_line();
+
+        if let Some(ref mut comments) = self.comments {
+            comments.push(Comment{
+                span: span,
+                text: text.trim_right().to_owned(),
+                doc: doc,
+                attachment: if first {
+                    CommentAttachment::Leading
+                } else {
+                    CommentAttachment::Trailing
+                },
+            });
+        }
+    }
+
     /// Returns the next token without consuming it
     fn peek_all(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
         if let Some(tok) = self.cur_token.clone() {
             Ok(tok)
         } else {
-            let tok = try!(self.lexer.next_token());
+            let pos = self.lexer.position();
+            let rest = self.lexer.remaining();
+
+            let tok = match self.lexer.next_token() {
+                Ok(tok) => tok,
+                Err(e) => return Err(self.suggest_fix(e, pos, rest))
+            };
             self.cur_token = Some(tok);
+            self.prev_token = Some(tok);
             Ok(tok)
         }
     }
+
+    /// Adds a suggestion to a lexer error, if a likely fix is known.
+    /// `rest` is the input remaining at position `pos`, before the error.
+    fn suggest_fix(&mut self, mut e: ParseError, pos: BytePos, rest: &str)
+            -> ParseError {
+        // A name followed immediately by a lone `:`, e.g. `foo:`,
+        // was probably meant to be a keyword.
+        if let ParseErrorKind::InvalidToken = e.kind {
+            if let Some((sp, Token::Name(name))) = self.prev_token {
+                let err_text = rest.get((e.span.lo - pos) as usize..).unwrap_or("");
+
+                if sp.hi == e.span.lo && err_text.starts_with(':') {
+                    e.suggestion = Some(Suggestion::Keyword(self.add_lex_name(name)));
+                }
+            }
+        }
+
+        e
+    }
+}
+
+/// Parses expressions incrementally from a `BufRead` input stream.
+///
+/// Input is read a line at a time, as it is needed, and only the text of
+/// expressions which have not yet been parsed is retained in memory.
+///
+/// Source text is not added to a `CodeMap`; spans of any errors
+/// are relative to the beginning of the stream.
+pub struct StreamParser<R> {
+    reader: R,
+    path: PathBuf,
+    buf: String,
+    offset: BytePos,
+    eof: bool,
+}
+
+impl<R: BufRead> StreamParser<R> {
+    /// Creates a new `StreamParser` reading from the given stream.
+    /// `path` describes the source of input in any I/O errors.
+    pub fn new(reader: R, path: &Path) -> StreamParser<R> {
+        StreamParser{
+            reader: reader,
+            path: path.to_owned(),
+            buf: String::new(),
+            offset: 0,
+            eof: false,
+        }
+    }
+
+    /// Parses the next expression from the input stream.
+    /// Identifiers will be inserted into the given `NameStore`.
+    ///
+    /// Returns `Ok(None)` when the end of input is reached.
+    pub fn parse_next(&mut self, names: &mut NameStore) -> Result<Option<Value>, Error> {
+        loop {
+            let res = Parser::new(names, Lexer::new(&self.buf, self.offset))
+                .parse_next_expr();
+
+            match res {
+                Ok(Some((v, end))) => {
+                    let n = (end - self.offset) as usize;
+                    self.buf.drain(..n);
+                    self.offset = end;
+                    return Ok(Some(v));
+                }
+                Ok(None) => {
+                    // Discard any whitespace and comments
+                    self.offset += self.buf.len() as BytePos;
+                    self.buf.clear();
+
+                    if self.eof {
+                        return Ok(None);
+                    }
+                }
+                Err(ref e) if !self.eof && e.is_incomplete() => (),
+                Err(e) => return Err(From::from(e))
+            }
+
+            try!(self.read_more());
+        }
+    }
+
+    fn read_more(&mut self) -> Result<(), Error> {
+        // Read at least as much as is currently buffered, so that the total
+        // cost of re-parsing a long, incomplete expression remains linear.
+        let target = self.buf.len() * 2;
+
+        loop {
+            let n = try!(self.reader.read_line(&mut self.buf)
+                .map_err(|e| IoError::new(IoMode::Read, &self.path, e)));
+
+            if n == 0 {
+                self.eof = true;
+                break;
+            }
+
+            if self.buf.len() >= target {
+                break;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 fn parse_char(s: &str) -> Result<char, ParseError> {
@@ -365,7 +1136,9 @@ fn parse_char(s: &str) -> Result<char, ParseError> {
 }
 
 fn parse_string(s: &str) -> Result<String, ParseError> {
-    let (s, _) = if s.starts_with('r') {
+    let (s, _) = if s.starts_with("#<<") {
+        try!(string::parse_heredoc(s, 0))
+    } else if s.starts_with('r') {
         try!(string::parse_raw_string(s, 0))
     } else {
         try!(string::parse_string(s, 0))
@@ -373,9 +1146,111 @@ fn parse_string(s: &str) -> Result<String, ParseError> {
     Ok(s)
 }
 
+/// Parses a float literal. Values too large to be represented,
+/// which would otherwise produce an infinite value, are an error.
 fn parse_float(s: &str) -> Result<f64, ParseErrorKind> {
-    strip_underscores(s).parse()
-        .map_err(|_| ParseErrorKind::LiteralParseError)
+    let s = strip_underscores(s);
+
+    let f = if s.starts_with("0x") {
+        try!(parse_radix_float(&s[2..], 16))
+    } else if s.starts_with("0o") {
+        try!(parse_radix_float(&s[2..], 8))
+    } else if s.starts_with("0b") {
+        try!(parse_radix_float(&s[2..], 2))
+    } else {
+        try!(s.parse().map_err(|_| ParseErrorKind::LiteralParseError))
+    };
+
+    if f.is_finite() {
+        Ok(f)
+    } else {
+        Err(ParseErrorKind::LiteralParseError)
+    }
+}
+
+/// Parses a floating point value in the given base,
+/// with an optional binary exponent following `p` or `P`.
+fn parse_radix_float(s: &str, base: u32) -> Result<f64, ParseErrorKind> {
+    let (mantissa, exp) = match s.find(|ch| ch == 'p' || ch == 'P') {
+        Some(pos) => (&s[..pos], try!(s[pos + 1..].parse::<i32>()
+            .map_err(|_| ParseErrorKind::LiteralParseError))),
+        None => (s, 0)
+    };
+
+    let mut value = 0.0;
+    let mut frac_digits = 0;
+    let mut frac = false;
+
+    for ch in mantissa.chars() {
+        if ch == '.' {
+            frac = true;
+            continue;
+        }
+
+        let digit = try!(ch.to_digit(base)
+            .ok_or(ParseErrorKind::LiteralParseError));
+
+        value = value * base as f64 + digit as f64;
+
+        if frac {
+            frac_digits += 1;
+        }
+    }
+
+    Ok(value * (base as f64).powi(-frac_digits) * 2f64.powi(exp))
+}
+
+/// Returns the text of a doc comment, without leading semicolons.
+fn doc_text(comment: &str) -> String {
+    comment.lines()
+        .map(|line| {
+            let line = line.trim_left().trim_left_matches(';');
+            let line = if line.starts_with(' ') { &line[1..] } else { line };
+            line.trim_right()
+        })
+        .collect::<Vec<_>>().join("\n")
+}
+
+/// Builds the map read from a literal `{key value ...}`.
+/// Keys are read as data; values are evaluated when the map is compiled.
+fn map_literal(values: Vec<Value>) -> Result<Map, ParseErrorKind> {
+    if values.len() % 2 != 0 {
+        return Err(ParseErrorKind::MissingMapValue);
+    }
+
+    let mut map = Map::new();
+    let mut values = values.into_iter();
+
+    while let (Some(k), Some(v)) = (values.next(), values.next()) {
+        try!(map.insert(k, v).map_err(|_| ParseErrorKind::InvalidMapKey));
+    }
+
+    Ok(map)
+}
+
+/// Builds the set read from a literal `#{value ...}`.
+/// Elements are read as data and are not evaluated.
+fn set_literal(values: Vec<Value>) -> Result<Set, ParseErrorKind> {
+    let mut set = Set::new();
+
+    for v in values {
+        try!(set.insert(v).map_err(|_| ParseErrorKind::InvalidSetElement));
+    }
+
+    Ok(set)
+}
+
+/// Returns whether an expression closed at the current position is within
+/// a quoted or quasiquoted expression, rather than evaluated.
+fn is_quoted(stack: &[Group], total_backticks: i32) -> bool {
+    total_backticks > 0 || stack.iter().any(|group| match *group {
+        Group::Quotes(n) => n != 0,
+        _ => false
+    })
+}
+
+fn parse_label(s: &str, sp: Span) -> Result<u32, ParseError> {
+    s.parse().map_err(|_| ParseError::new(sp, ParseErrorKind::LiteralParseError))
 }
 
 fn parse_integer(s: &str, base: u32) -> Result<Integer, ParseErrorKind> {
@@ -388,9 +1263,75 @@ fn parse_integer(s: &str, base: u32) -> Result<Integer, ParseErrorKind> {
         .map_err(|_| ParseErrorKind::LiteralParseError)
 }
 
-fn parse_ratio(s: &str) -> Result<Ratio, ParseErrorKind> {
-    strip_underscores(s).parse()
-        .map_err(|_| ParseErrorKind::LiteralParseError)
+/// Parses a ratio literal. In a radix-prefixed literal, both numerator
+/// and denominator are written in the given base.
+fn parse_ratio(s: &str, base: u32) -> Result<Ratio, ParseErrorKind> {
+    let s = match base {
+        10 => strip_underscores(s),
+        _ => strip_underscores(&s[2..])
+    };
+
+    let pos = try!(s.find('/').ok_or(ParseErrorKind::LiteralParseError));
+
+    let numer = try!(Integer::from_str_radix(&s[..pos], base)
+        .map_err(|_| ParseErrorKind::LiteralParseError));
+    let denom = try!(Integer::from_str_radix(&s[pos + 1..], base)
+        .map_err(|_| ParseErrorKind::LiteralParseError));
+
+    if denom.is_zero() {
+        return Err(ParseErrorKind::LiteralParseError);
+    }
+
+    Ok(Ratio::new(numer, denom))
+}
+
+/// Greatest magnitude of the effective exponent of a decimal literal;
+/// larger exponents would produce values too costly to construct.
+const MAX_DECIMAL_EXPONENT: i32 = 4096;
+
+/// Parses a decimal literal, with an optional decimal exponent,
+/// into an exact `Ratio` value.
+fn parse_decimal(s: &str) -> Result<Ratio, ParseErrorKind> {
+    let s = strip_underscores(s);
+
+    let (mantissa, exp) = match s.find(|ch| ch == 'e' || ch == 'E') {
+        Some(pos) => (&s[..pos], try!(s[pos + 1..].parse::<i32>()
+            .map_err(|_| ParseErrorKind::LiteralParseError))),
+        None => (&s[..], 0)
+    };
+
+    let (digits, exp) = match mantissa.find('.') {
+        Some(pos) => {
+            let frac = &mantissa[pos + 1..];
+            let exp = try!(i32_len(frac.len()).and_then(|n| exp.checked_sub(n))
+                .ok_or(ParseErrorKind::LiteralParseError));
+            (Owned(format!("{}{}", &mantissa[..pos], frac)), exp)
+        }
+        None => (Borrowed(mantissa), exp)
+    };
+
+    let abs_exp = match exp.checked_abs() {
+        Some(n) if n <= MAX_DECIMAL_EXPONENT => n,
+        _ => return Err(ParseErrorKind::LiteralParseError)
+    };
+
+    let numer = try!(digits.parse::<Integer>()
+        .map_err(|_| ParseErrorKind::LiteralParseError));
+    let scale = Integer::from_u32(10).pow(abs_exp as usize);
+
+    if exp < 0 {
+        Ok(Ratio::new(numer, scale))
+    } else {
+        Ok(Ratio::from_integer(numer * scale))
+    }
+}
+
+fn i32_len(n: usize) -> Option<i32> {
+    if n > i32::max_value() as usize {
+        None
+    } else {
+        Some(n as i32)
+    }
 }
 
 fn strip_underscores(s: &str) -> Cow<str> {
@@ -403,8 +1344,11 @@ fn strip_underscores(s: &str) -> Cow<str> {
 
 #[cfg(test)]
 mod test {
-    use super::{ParseError, ParseErrorKind, Parser};
-    use lexer::{Span, Lexer};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{ParseError, ParseErrorKind, Parser, ReaderMacros, Suggestion};
+    use lexer::{Span, Lexer, Token};
     use name::NameStore;
     use value::Value;
 
@@ -414,13 +1358,412 @@ mod test {
         p.parse_expr()
     }
 
+    fn parse_recover(s: &str) -> Result<usize, Vec<(usize, ParseErrorKind)>> {
+        let mut names = NameStore::new();
+        let mut p = Parser::new(&mut names, Lexer::new(s, 0));
+        p.parse_exprs_recover()
+            .map(|v| v.len())
+            .map_err(|errs| errs.into_iter()
+                .map(|e| (e.span.lo as usize, e.kind)).collect())
+    }
+
+    #[test]
+    fn test_stream() {
+        use std::path::Path;
+        use super::StreamParser;
+
+        let input = "1 (foo\n bar) \"a\nb\"\n; comment\n#| block\n|# baz";
+        let mut names = NameStore::new();
+        let mut p = StreamParser::new(input.as_bytes(), Path::new("<input>"));
+        let mut n = 0;
+
+        while let Some(_) = p.parse_next(&mut names).unwrap() {
+            n += 1;
+        }
+
+        assert_eq!(n, 4);
+
+        let mut p = StreamParser::new("(foo\n".as_bytes(), Path::new("<input>"));
+        assert!(p.parse_next(&mut names).is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        use super::CommentAttachment::*;
+
+        let mut names = NameStore::new();
+        let mut p = Parser::new(&mut names,
+            Lexer::new("; a\n(foo ; b\n  ;; c\n  bar) #| d |#", 0));
+
+        let (exprs, comments) = p.parse_exprs_with_comments().unwrap();
+        assert_eq!(exprs.len(), 1);
+
+        let comments = comments.iter()
+            .map(|c| (c.span.lo, &c.text[..], c.doc, c.attachment))
+            .collect::<Vec<_>>();
+
+        assert_eq!(comments, [
+            (0, "; a", false, Leading),
+            (9, "; b", false, Trailing),
+            (15, ";; c", true, Leading),
+            (27, "#| d |#", false, Trailing),
+        ]);
+    }
+
+    #[test]
+    fn test_doc_comment() {
+        assert!(parse("(foo\n;; bar\nbaz)").is_ok());
+    }
+
     #[test]
     fn test_errors() {
         assert_eq!(parse("(foo").unwrap_err(), ParseError{
-            span: Span{lo: 4, hi: 4}, kind: ParseErrorKind::MissingCloseParen});
-        assert_eq!(parse("(foo ,bar)").unwrap_err(), ParseError{
-            span: Span{lo: 5, hi: 6}, kind: ParseErrorKind::UnbalancedComma});
-        assert_eq!(parse("`(foo ,,bar)").unwrap_err(), ParseError{
-            span: Span{lo: 7, hi: 8}, kind: ParseErrorKind::UnbalancedComma});
+            span: Span{lo: 4, hi: 4}, kind: ParseErrorKind::MissingCloseParen,
+            open_span: Some(Span{lo: 0, hi: 1}),
+            suggestion: Some(Suggestion::Close(")"))});
+        assert_eq!(parse("(foo ,bar)").unwrap_err(), ParseError::new(
+            Span{lo: 5, hi: 6}, ParseErrorKind::UnbalancedComma));
+        assert_eq!(parse("`(foo ,,bar)").unwrap_err(), ParseError::new(
+            Span{lo: 7, hi: 8}, ParseErrorKind::UnbalancedComma));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        use name::display_names;
+
+        let e = parse("(a (b [c) d)").unwrap_err();
+        assert_eq!(e.open_span, Some(Span{lo: 6, hi: 7}));
+        assert_eq!(e.suggestion, Some(Suggestion::Close("]")));
+
+        let e = parse("(a\n  (b [c])\n  (d)").unwrap_err();
+        assert_eq!(e.open_span, Some(Span{lo: 0, hi: 1}));
+
+        let mut names = NameStore::new();
+        let e = Parser::new(&mut names, Lexer::new("(foo bar: 1)", 0))
+            .parse_expr().unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::InvalidToken);
+        assert_eq!(display_names(&names, &e.suggestion.unwrap()).to_string(),
+            "did you mean the keyword `:bar`?");
+
+        assert_eq!(parse("(foo bar :)").unwrap_err().suggestion, None);
+        assert_eq!(parse("(foo# 1)").unwrap_err().suggestion, None);
+    }
+
+    #[test]
+    fn test_recover() {
+        assert_eq!(parse_recover("(foo) (bar)"), Ok(2));
+        assert_eq!(parse_recover("(foo ,bar) (baz)\n1 ,2 3").unwrap_err(), [
+            (5, ParseErrorKind::UnbalancedComma),
+            (19, ParseErrorKind::UnbalancedComma),
+        ]);
+        assert_eq!(parse_recover("(foo (bar)\n(baz #x)\n(quux '))").unwrap_err(), [
+            (16, ParseErrorKind::UnknownDispatch('x')),
+            (27, ParseErrorKind::UnexpectedToken{
+                expected: "expression", found: ")"}),
+            (28, ParseErrorKind::UnmatchedParen),
+        ]);
+        assert_eq!(parse_recover("(foo)\n(bar \"baz").unwrap_err(), [
+            (11, ParseErrorKind::UnterminatedString),
+        ]);
+    }
+
+    #[test]
+    fn test_span_identity() {
+        let mut names = NameStore::new();
+        let mut p = Parser::new(&mut names, Lexer::new("(foo bar baz)", 0));
+
+        let v = p.parse_expr().unwrap();
+        let spans = p.take_spans();
+
+        assert!(spans.get(&v).is_some());
+
+        match v {
+            Value::List(ref li) => {
+                assert!(spans.get(&Value::List(li.slice(..2))).is_none());
+                assert!(spans.get(&Value::List(li.slice(1..))).is_none());
+            }
+            _ => panic!("expected list")
+        }
+
+        drop(v);
+
+        let others = (0..16u8)
+            .map(|i| Value::List(vec![Value::Char(i as char); 3].into()))
+            .collect::<Vec<_>>();
+
+        for v in &others {
+            assert!(spans.get(v).is_none());
+        }
+    }
+
+    #[test]
+    fn test_partial() {
+        fn partial(s: &str) -> Result<Option<usize>, ParseErrorKind> {
+            let mut names = NameStore::new();
+            let mut p = Parser::new(&mut names, Lexer::new(s, 0));
+            p.parse_exprs_partial().map(|v| v.map(|v| v.len())).map_err(|e| e.kind)
+        }
+
+        assert_eq!(partial("(foo) bar"), Ok(Some(2)));
+        assert_eq!(partial(""), Ok(Some(0)));
+        assert_eq!(partial("(foo (bar)"), Ok(None));
+        assert_eq!(partial("(foo \"bar"), Ok(None));
+        assert_eq!(partial("#| foo"), Ok(None));
+        assert_eq!(partial("(foo) '"), Ok(None));
+        assert_eq!(partial("(foo))"), Err(ParseErrorKind::UnmatchedParen));
+        assert_eq!(partial("(foo #x"), Err(ParseErrorKind::UnknownDispatch('x')));
+    }
+
+    #[test]
+    fn test_doc_spans() {
+        let mut names = NameStore::new();
+        let mut p = Parser::new(&mut names, Lexer::new("\
+            ;; Doc for foo\n\
+            ;;\n\
+            ;;   indented\n\
+            (foo\n\
+              ;; Doc for bar\n\
+              (bar)\n\
+              ;; Not a list\n\
+              baz\n\
+              (quux))", 0));
+
+        let v = p.parse_expr().unwrap();
+        let spans = p.take_spans();
+
+        assert_eq!(spans.doc(&v), Some("Doc for foo\n\n  indented"));
+
+        match v {
+            Value::List(ref li) => {
+                assert_eq!(spans.doc(&li[1]), Some("Doc for bar"));
+                assert_eq!(spans.doc(&li[3]), None);
+            }
+            _ => panic!("expected list")
+        }
+    }
+
+    #[test]
+    fn test_decimal() {
+        use integer::Ratio;
+
+        fn decimal(s: &str) -> Ratio {
+            match parse(s).unwrap() {
+                Value::Ratio(r) => r,
+                v => panic!("expected ratio; found {:?}", v)
+            }
+        }
+
+        assert_eq!(decimal("1.50d").to_string(), "3/2");
+        assert_eq!(decimal("-0.1d").to_string(), "-1/10");
+        assert_eq!(decimal("1.5e2d").to_string(), "150");
+        assert_eq!(decimal("25e-3d").to_string(), "1/40");
+        assert_eq!(decimal("1_000d").to_string(), "1000");
+        assert_eq!(decimal("1e4096d").to_string().len(), 4097);
+
+        assert_eq!(parse("1.5e-2147483648d").unwrap_err().kind,
+            ParseErrorKind::LiteralParseError);
+        assert_eq!(parse("1e99999999d").unwrap_err().kind,
+            ParseErrorKind::LiteralParseError);
+        assert_eq!(parse("1e4097d").unwrap_err().kind,
+            ParseErrorKind::LiteralParseError);
+    }
+
+    #[test]
+    fn test_brackets() {
+        use name::{debug_names, NameStore};
+
+        let mut names = NameStore::new();
+        let v = Parser::new(&mut names, Lexer::new("[a [] '[b c]]", 0))
+            .parse_expr().unwrap();
+        assert_eq!(debug_names(&names, &v).to_string(),
+            "`(,a ,() ,'(b c))");
+
+        assert_eq!(parse("[a)").unwrap_err(), ParseError{
+            span: Span{lo: 2, hi: 3}, kind: ParseErrorKind::UnexpectedToken{
+                expected: "]", found: ")"},
+            open_span: Some(Span{lo: 0, hi: 1}),
+            suggestion: Some(Suggestion::Close("]"))});
+        assert_eq!(parse("(a]").unwrap_err(), ParseError{
+            span: Span{lo: 2, hi: 3}, kind: ParseErrorKind::UnexpectedToken{
+                expected: ")", found: "]"},
+            open_span: Some(Span{lo: 0, hi: 1}),
+            suggestion: Some(Suggestion::Close(")"))});
+
+        let v = Parser::new(&mut names, Lexer::new("{:a 1 :b {}}", 0))
+            .parse_expr().unwrap();
+        assert_eq!(debug_names(&names, &v).to_string(), "{:a 1 :b {}}");
+
+        assert_eq!(parse("{:a 1 :b}").unwrap_err(), ParseError::new(
+            Span{lo: 0, hi: 9}, ParseErrorKind::MissingMapValue));
+        assert_eq!(parse("{1.0 :a}").unwrap_err(), ParseError::new(
+            Span{lo: 0, hi: 8}, ParseErrorKind::InvalidMapKey));
+
+        assert_eq!(parse("{:a 1]").unwrap_err(), ParseError{
+            span: Span{lo: 5, hi: 6}, kind: ParseErrorKind::UnexpectedToken{
+                expected: "}", found: "]"},
+            open_span: Some(Span{lo: 0, hi: 1}),
+            suggestion: Some(Suggestion::Close("}"))});
+        let v = Parser::new(&mut names, Lexer::new("#{a 1 (b) \"c\"}", 0))
+            .parse_expr().unwrap();
+        assert_eq!(debug_names(&names, &v).to_string(), "#{a 1 (b) \"c\"}");
+
+        assert_eq!(parse("#{1.0}").unwrap_err(), ParseError::new(
+            Span{lo: 0, hi: 6}, ParseErrorKind::InvalidSetElement));
+        assert_eq!(parse("#{1)").unwrap_err(), ParseError{
+            span: Span{lo: 3, hi: 4}, kind: ParseErrorKind::UnexpectedToken{
+                expected: "}", found: ")"},
+            open_span: Some(Span{lo: 0, hi: 2}),
+            suggestion: Some(Suggestion::Close("}"))});
+        assert_eq!(parse_recover("(a [b\n(c)"),
+            Err(vec![(9, ParseErrorKind::MissingCloseParen)]));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut names = NameStore::new();
+        let mut p = Parser::new(&mut names, Lexer::new("(a (b (c)) '(d))", 0));
+        p.set_max_depth(3);
+        assert!(p.parse_expr().is_ok());
+
+        let mut p = Parser::new(&mut names, Lexer::new("(a (b (c (d))))", 0));
+        p.set_max_depth(3);
+        assert_eq!(p.parse_expr().unwrap_err(), ParseError::new(
+            Span{lo: 9, hi: 10}, ParseErrorKind::DepthExceeded));
+
+        let mut p = Parser::new(&mut names, Lexer::new("(a (b '''(c)))", 0));
+        p.set_max_depth(3);
+        assert_eq!(p.parse_expr().unwrap_err(), ParseError::new(
+            Span{lo: 9, hi: 10}, ParseErrorKind::DepthExceeded));
+
+        let mut p = Parser::new(&mut names, Lexer::new("(a (b (c (d)))) (e)", 0));
+        p.set_max_depth(3);
+        assert_eq!(p.parse_exprs_recover().unwrap_err(), [ParseError::new(
+            Span{lo: 9, hi: 10}, ParseErrorKind::DepthExceeded)]);
+
+        // Expressions parsed by reader macros count toward the depth
+        let mut readers = ReaderMacros::new();
+        readers.add('w', |p, _| p.parse_expr());
+        let readers = Rc::new(RefCell::new(readers));
+
+        let mut p = Parser::new(&mut names, Lexer::new("(a #w(b))", 0));
+        p.set_readers(readers.clone());
+        p.set_max_depth(3);
+        assert!(p.parse_expr().is_ok());
+
+        let mut p = Parser::new(&mut names, Lexer::new("(a #w(b #w(c)))", 0));
+        p.set_readers(readers.clone());
+        p.set_max_depth(3);
+        assert_eq!(p.parse_expr().unwrap_err(), ParseError::new(
+            Span{lo: 8, hi: 10}, ParseErrorKind::DepthExceeded));
+
+        let mut p = Parser::new(&mut names, Lexer::new("#w#w#w#w x", 0));
+        p.set_readers(readers);
+        p.set_max_depth(3);
+        assert_eq!(p.parse_expr().unwrap_err(), ParseError::new(
+            Span{lo: 6, hi: 8}, ParseErrorKind::DepthExceeded));
+    }
+
+    #[test]
+    fn test_labels() {
+        match parse("(#0=(a) #0# '#0# #1=b #1#)").unwrap() {
+            Value::List(ref li) => match (&li[0], &li[1], &li[2], &li[4]) {
+                (&Value::List(ref a), &Value::List(ref b),
+                        &Value::Quote(ref c, 1), &Value::Name(_)) => {
+                    assert_eq!(a.as_ptr(), b.as_ptr());
+                    match **c {
+                        Value::List(ref c) => assert_eq!(a.as_ptr(), c.as_ptr()),
+                        _ => panic!("expected list")
+                    }
+                }
+                _ => panic!("unexpected values")
+            },
+            _ => panic!("expected list")
+        }
+
+        assert_eq!(parse("(#0=(a #0#))").unwrap_err(), ParseError::new(
+            Span{lo: 7, hi: 10}, ParseErrorKind::RecursiveLabel(0)));
+        assert_eq!(parse("(#0=a #0=b)").unwrap_err(), ParseError::new(
+            Span{lo: 6, hi: 9}, ParseErrorKind::DuplicateLabel(0)));
+        assert_eq!(parse("(a #1#)").unwrap_err(), ParseError::new(
+            Span{lo: 3, hi: 6}, ParseErrorKind::UndefinedLabel(1)));
+        assert_eq!(parse_recover("(#0=a) #0#"),
+            Err(vec![(7, ParseErrorKind::UndefinedLabel(0))]));
+    }
+
+    #[test]
+    fn test_reader_macro() {
+        let mut readers = ReaderMacros::new();
+
+        readers.add('t', |_, _| Ok(Value::Bool(true)));
+        readers.add('n', |p, sp| {
+            match try!(p.next_token()) {
+                (_, Token::Integer(s, 10)) => Ok(Value::Integer(s.parse().unwrap())),
+                (_, tok) => Err(ParseError::new(sp, ParseErrorKind::UnexpectedToken{
+                    expected: "integer",
+                    found: tok.name(),
+                }))
+            }
+        });
+
+        let readers = Rc::new(RefCell::new(readers));
+        let mut names = NameStore::new();
+
+        let mut p = Parser::new(&mut names, Lexer::new("(#t #n 1)", 0));
+        p.set_readers(readers.clone());
+
+        match p.parse_expr().unwrap() {
+            Value::List(ref li) => match (&li[0], &li[1]) {
+                (&Value::Bool(true), &Value::Integer(ref i)) =>
+                    assert_eq!(i.to_string(), "1"),
+                _ => panic!("unexpected values")
+            },
+            _ => panic!("expected list")
+        }
+
+        let mut p = Parser::new(&mut names, Lexer::new("(#t #n 1 #x)", 0));
+        p.set_readers(readers);
+
+        assert_eq!(p.parse_expr().unwrap_err(), ParseError::new(
+            Span{lo: 9, hi: 11}, ParseErrorKind::UnknownDispatch('x')));
+
+        let mut p = Parser::new(&mut names, Lexer::new("(#t #n 1)", 0));
+        assert_eq!(p.parse_expr().unwrap_err(), ParseError::new(
+            Span{lo: 1, hi: 3}, ParseErrorKind::UnknownDispatch('t')));
+    }
+
+    #[test]
+    fn test_radix_float() {
+        use super::parse_float;
+
+        assert_eq!(parse_float("0x1.8p3"), Ok(12.0));
+        assert_eq!(parse_float("0xf_fp-4"), Ok(15.9375));
+        assert_eq!(parse_float("0b1.01"), Ok(1.25));
+        assert_eq!(parse_float("0o7.4P1"), Ok(15.0));
+        assert_eq!(parse_float("1_0.2_5e1"), Ok(102.5));
+
+        assert_eq!(parse_float("0x1p1024"), Err(ParseErrorKind::LiteralParseError));
+        assert_eq!(parse_float("0x1p-99999999"), Ok(0.0));
+        assert_eq!(parse_float("0xffp2147483647"), Err(ParseErrorKind::LiteralParseError));
+        assert_eq!(parse_float("1e999"), Err(ParseErrorKind::LiteralParseError));
+    }
+
+    #[test]
+    fn test_radix_ratio() {
+        fn ratio(s: &str) -> String {
+            match parse(s).unwrap() {
+                Value::Ratio(r) => r.to_string(),
+                v => panic!("expected ratio; found {:?}", v)
+            }
+        }
+
+        assert_eq!(ratio("0x1f/3"), "31/3");
+        assert_eq!(ratio("0b1_0/11"), "2/3");
+        assert_eq!(ratio("0o10/20"), "1/2");
+        assert_eq!(ratio("-10/4"), "-5/2");
+
+        assert_eq!(parse("1/0").unwrap_err().kind,
+            ParseErrorKind::LiteralParseError);
+        assert_eq!(parse("0x1/0").unwrap_err().kind,
+            ParseErrorKind::LiteralParseError);
     }
 }
@@ -0,0 +1,227 @@
+//! Formats values across multiple lines to fit within a given width.
+//!
+//! A value whose debug representation fits within the remaining width of
+//! a line is written on that line. Otherwise, the elements of lists, maps,
+//! sets, and structs are written on separate lines, aligned with one another.
+//!
+//! ```
+//! use ketos::Interpreter;
+//! use ketos::pretty::pretty_print;
+//!
+//! let interp = Interpreter::new();
+//! let v = interp.run_single_expr("'(foo (bar 1 2) (baz 3 4))", None).unwrap();
+//!
+//! let s = pretty_print(&v, &interp.get_scope().borrow_names(), 16);
+//! assert_eq!(s, "(foo (bar 1 2)\n     (baz 3 4))");
+//! ```
+
+use map::{Map, Set};
+use name::{debug_names, NameStore};
+use value::{Struct, Value};
+
+/// Formats a value, as by its debug representation, breaking lists, maps,
+/// sets, and structs across lines so that each line fits within `width`
+/// characters, where possible.
+///
+/// Atomic values, such as strings and names, are never broken and may
+/// exceed the width. The result does not end with a newline.
+pub fn pretty_print(value: &Value, names: &NameStore, width: usize) -> String {
+    let mut printer = Printer{
+        names: names,
+        width: width,
+        out: String::new(),
+        col: 0,
+    };
+
+    printer.write_value(value);
+    printer.out
+}
+
+struct Printer<'a> {
+    names: &'a NameStore,
+    width: usize,
+    out: String,
+    col: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn push_str(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.col += s.chars().count();
+    }
+
+    fn newline(&mut self, col: usize) {
+        self.out.push('\n');
+        for _ in 0..col {
+            self.out.push(' ');
+        }
+        self.col = col;
+    }
+
+    /// Returns the single-line representation of a value.
+    fn flat(&self, value: &Value) -> String {
+        debug_names(self.names, value).to_string()
+    }
+
+    fn fits(&self, col: usize, s: &str) -> bool {
+        col + s.chars().count() <= self.width
+    }
+
+    fn write_value(&mut self, value: &Value) {
+        let flat = self.flat(value);
+
+        if self.fits(self.col, &flat) {
+            self.push_str(&flat);
+            return;
+        }
+
+        match *value {
+            Value::List(ref l) => self.write_list(l),
+            Value::Map(ref m) => self.write_map(m),
+            Value::Set(ref s) => self.write_set(s),
+            Value::Struct(ref s) if !s.fields.is_empty() => self.write_struct(s),
+            Value::Quasiquote(ref v, depth) => {
+                for _ in 0..depth { self.push_str("`"); }
+                self.write_value(v);
+            }
+            Value::Comma(ref v, depth) => {
+                for _ in 0..depth { self.push_str(","); }
+                self.write_value(v);
+            }
+            Value::CommaAt(ref v, depth) => {
+                for _ in 0..depth { self.push_str(","); }
+                self.push_str("@");
+                self.write_value(v);
+            }
+            Value::Quote(ref v, depth) => {
+                for _ in 0..depth { self.push_str("'"); }
+                self.write_value(v);
+            }
+            _ => self.push_str(&flat)
+        }
+    }
+
+    /// Writes each value on its own line at the given column.
+    /// The first value is written at the current position.
+    fn write_items<'v, I>(&mut self, values: I, col: usize)
+            where I: Iterator<Item=&'v Value> {
+        for (i, v) in values.enumerate() {
+            if i != 0 {
+                self.newline(col);
+            }
+            self.write_value(v);
+        }
+    }
+
+    fn write_list(&mut self, values: &[Value]) {
+        let open_col = self.col;
+        self.push_str("(");
+
+        // A list beginning with a name is written as a call; arguments are
+        // aligned with the first argument, if it fits after the name.
+        if let Value::Name(name) = values[0] {
+            if values.len() > 1 {
+                let names = self.names;
+                self.push_str(names.get(name));
+
+                let args_col = self.col + 1;
+                let first = self.flat(&values[1]);
+
+                if self.fits(args_col, &first) {
+                    self.push_str(" ");
+                    self.write_items(values[1..].iter(), args_col);
+                } else {
+                    let body_col = open_col + 2;
+                    self.newline(body_col);
+                    self.write_items(values[1..].iter(), body_col);
+                }
+
+                self.push_str(")");
+                return;
+            }
+        }
+
+        self.write_items(values.iter(), open_col + 1);
+        self.push_str(")");
+    }
+
+    fn write_map(&mut self, map: &Map) {
+        let col = self.col + 1;
+        self.push_str("{");
+
+        for (i, (k, v)) in map.iter().enumerate() {
+            if i != 0 {
+                self.newline(col);
+            }
+            self.write_value(k);
+            self.push_str(" ");
+            self.write_value(v);
+        }
+
+        self.push_str("}");
+    }
+
+    fn write_set(&mut self, set: &Set) {
+        let col = self.col + 2;
+        self.push_str("#{");
+        self.write_items(set.iter(), col);
+        self.push_str("}");
+    }
+
+    fn write_struct(&mut self, s: &Struct) {
+        let names = self.names;
+        self.push_str(names.get(s.def.name));
+        self.push_str(" { ");
+
+        let col = self.col;
+
+        for (i, &(name, ref value)) in s.fields.iter().enumerate() {
+            if i != 0 {
+                self.push_str(",");
+                self.newline(col);
+            }
+            self.push_str(names.get(name));
+            self.push_str(": ");
+            self.write_value(value);
+        }
+
+        self.push_str(" }");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::pretty_print;
+    use interpreter::Interpreter;
+
+    fn pretty(input: &str, width: usize) -> String {
+        let interp = Interpreter::new();
+        let v = interp.run_single_expr(input, None).unwrap();
+        let names = interp.get_scope().borrow_names();
+        pretty_print(&v, &names, width)
+    }
+
+    #[test]
+    fn test_pretty_list() {
+        assert_eq!(pretty("'(a b c)", 80), "(a b c)");
+        assert_eq!(pretty("'(1 2 3)", 4), "(1\n 2\n 3)");
+        assert_eq!(pretty("'(foo (bar 1 2) (baz 3 4))", 16),
+            "(foo (bar 1 2)\n     (baz 3 4))");
+        assert_eq!(pretty("'(foo (bar 1 2) (baz 3 4))", 10),
+            "(foo\n  (bar 1\n       2)\n  (baz 3\n       4))");
+        assert_eq!(pretty("'(a-long-name (1 2 3 4 5))", 20),
+            "(a-long-name\n  (1 2 3 4 5))");
+        assert_eq!(pretty("''(a b)", 4), "'(a\n   b)");
+        assert_eq!(pretty("\"long string\"", 4), "\"long string\"");
+    }
+
+    #[test]
+    fn test_pretty_struct() {
+        assert_eq!(pretty("(do (struct Foo ((a integer) (b list)))
+                               (new Foo :a 1 :b '(1 2 3)))", 20),
+            "Foo { a: 1,\n      b: (1 2 3) }");
+        assert_eq!(pretty("(do (struct Foo ((a integer) (b list)))
+                               (new Foo :a 1 :b '(1 2 3)))", 12),
+            "Foo { a: 1,\n      b: (1\n          2\n          3) }");
+    }
+}
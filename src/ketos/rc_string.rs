@@ -0,0 +1,276 @@
+//! Implements a reference-counted `String` supporting efficient subslicing.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops;
+use std::rc::Rc;
+
+use rc_vec::RangeArgument;
+
+/// Maximum length, in bytes, of the string contained in an `RcString`.
+pub const MAX_LEN: usize = ::std::u32::MAX as usize;
+
+/// Represents a reference-counted view into a `String`.
+/// Subslices may be created which will share the underlying data buffer.
+///
+/// Boundaries are stored as `u32`, keeping `RcString` within two words,
+/// so an `RcString` may not contain more than `u32::MAX` bytes.
+#[derive(Clone)]
+pub struct RcString {
+    data: Rc<String>,
+    start: u32,
+    end: u32,
+}
+
+impl RcString {
+    /// Constructs a new `RcString` from a `String`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` contains more than `MAX_LEN` bytes.
+    /// Strings of unbounded length should be constructed using `try_new`.
+    pub fn new(data: String) -> RcString {
+        let n = len_u32(data.len());
+
+        RcString{
+            data: Rc::new(data),
+            start: 0,
+            end: n,
+        }
+    }
+
+    /// Constructs a new `RcString` from a `String`,
+    /// returning `None` if `data` contains more than `MAX_LEN` bytes.
+    pub fn try_new(data: String) -> Option<RcString> {
+        if data.len() > MAX_LEN {
+            None
+        } else {
+            Some(RcString::new(data))
+        }
+    }
+
+    /// Returns whether the `RcString` is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the length, in bytes, of the string visible to the `RcString`.
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    /// Returns whether two `RcString` values share the same underlying buffer.
+    pub fn shares_buffer(&self, other: &RcString) -> bool {
+        Rc::ptr_eq(&self.data, &other.data)
+    }
+
+    /// Returns a substring of the `RcString`, with the range being relative
+    /// to this string's boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either end of the range is out of bounds or does not lie
+    /// on a `char` boundary.
+    pub fn slice<R: RangeArgument<usize>>(&self, range: R) -> RcString {
+        let start = range.start().map_or(0, |v| *v);
+        let end = range.end().map_or(self.len(), |v| *v);
+
+        if start > self.len() {
+            panic!("RcString slice out of bounds; start is {} but length is {}",
+                start, self.len());
+        }
+
+        if end > self.len() {
+            panic!("RcString slice out of bounds; end is {} but length is {}",
+                end, self.len());
+        }
+
+        if !self.is_char_boundary(start) || !self.is_char_boundary(end) {
+            panic!("RcString slice {}..{} is not on a char boundary", start, end);
+        }
+
+        RcString{
+            data: self.data.clone(),
+            start: self.start + start as u32,
+            end: self.start + end as u32,
+        }
+    }
+
+    /// Consumes the `RcString` and returns the contained `String`.
+    /// This will copy the string data unless the data was uniquely held.
+    pub fn into_string(self) -> String {
+        match Rc::try_unwrap(self.data) {
+            Ok(mut s) => {
+                s.truncate(self.end as usize);
+                let _ = s.drain(..self.start as usize);
+                s
+            }
+            Err(data) => data[self.start as usize..self.end as usize].to_owned()
+        }
+    }
+
+    /// Makes wrapped data unique and returns a mutable reference,
+    /// after adjusting `start` and `end` fields.
+    ///
+    /// # Note
+    ///
+    /// If the length of the `String` is modified, the `end` field of
+    /// `RcString` must be adjusted manually. That's why this method is private.
+    fn make_mut(&mut self) -> &mut String {
+        let s = Rc::make_mut(&mut self.data);
+
+        s.truncate(self.end as usize);
+        let _ = s.drain(..self.start as usize);
+        let n = len_u32(s.len());
+
+        self.start = 0;
+        self.end = n;
+
+        s
+    }
+
+    /// Appends a `char` to the contained `String`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resulting string contains more than `MAX_LEN` bytes.
+    pub fn push(&mut self, ch: char) {
+        self.make_mut().push(ch);
+        self.end = len_u32(self.data.len());
+    }
+
+    /// Appends a string slice to the contained `String`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resulting string contains more than `MAX_LEN` bytes.
+    pub fn push_str(&mut self, s: &str) {
+        self.make_mut().push_str(s);
+        self.end = len_u32(self.data.len());
+    }
+}
+
+fn len_u32(n: usize) -> u32 {
+    if n > MAX_LEN {
+        panic!("RcString length {} exceeds maximum of {}", n, MAX_LEN);
+    }
+    n as u32
+}
+
+impl AsRef<str> for RcString {
+    fn as_ref(&self) -> &str {
+        &self.data[self.start as usize..self.end as usize]
+    }
+}
+
+impl Borrow<str> for RcString {
+    fn borrow(&self) -> &str {
+        self.as_ref()
+    }
+}
+
+impl ops::Deref for RcString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_ref()
+    }
+}
+
+impl fmt::Debug for RcString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for RcString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl Hash for RcString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl PartialEq for RcString {
+    fn eq(&self, rhs: &RcString) -> bool { **self == **rhs }
+}
+
+impl Eq for RcString {}
+
+impl PartialOrd for RcString {
+    fn partial_cmp(&self, rhs: &RcString) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl Ord for RcString {
+    fn cmp(&self, rhs: &RcString) -> Ordering {
+        (**self).cmp(&**rhs)
+    }
+}
+
+macro_rules! impl_eq {
+    ( $rhs:ty ) => {
+        impl<'a> PartialEq<$rhs> for RcString {
+            fn eq(&self, rhs: &$rhs) -> bool { self[..] == rhs[..] }
+        }
+
+        impl<'a> PartialEq<RcString> for $rhs {
+            fn eq(&self, rhs: &RcString) -> bool { self[..] == rhs[..] }
+        }
+    }
+}
+
+impl_eq!{ str }
+impl_eq!{ &'a str }
+impl_eq!{ String }
+
+impl From<String> for RcString {
+    fn from(s: String) -> RcString {
+        RcString::new(s)
+    }
+}
+
+impl<'a> From<&'a str> for RcString {
+    fn from(s: &str) -> RcString {
+        RcString::new(s.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RcString;
+
+    #[test]
+    fn test_rcstring() {
+        let a = RcString::from("foobar");
+        let mut b = a.slice(1..4);
+        let c = b.slice(1..);
+
+        assert!(a.shares_buffer(&b));
+        assert!(a.shares_buffer(&c));
+        assert_eq!(a, "foobar");
+        assert_eq!(b, "oob");
+        assert_eq!(c, "ob");
+        assert_eq!(b.len(), 3);
+
+        b.push_str("az");
+        assert_eq!(b, "oobaz");
+        assert!(!a.shares_buffer(&b));
+
+        assert_eq!(c.into_string(), "ob");
+        assert_eq!(b.into_string(), "oobaz");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_char_boundary() {
+        RcString::from("\u{e9}t\u{e9}").slice(1..);
+    }
+}
@@ -1,15 +1,24 @@
 //! Implements a reference-counted `Vec` supporting efficient subslicing.
 
+use std::cell::RefCell;
+use std::mem::{self, ManuallyDrop};
 use std::ops;
 use std::rc::Rc;
 
 /// Represents a reference-counted view into a `Vec`.
 /// Subslices may be created which will share the underlying data buffer.
+///
+/// Boundaries are stored as `u32`, keeping `RcVec` within two words,
+/// so an `RcVec` may not contain more than `u32::MAX` elements.
+///
+/// Dropping the last reference to deeply nested `RcVec` values, such as
+/// a list containing a list containing a list..., does not recurse;
+/// see the `Drop` implementation.
 #[derive(Clone, Debug)]
 pub struct RcVec<T> {
-    data: Rc<Vec<T>>,
-    start: usize,
-    end: usize,
+    data: ManuallyDrop<Rc<Vec<T>>>,
+    start: u32,
+    end: u32,
 }
 
 // A duplicate of `collections::range::RangeArgument`, which is unstable.
@@ -35,11 +44,15 @@ impl<T> RangeArgument<T> for ops::RangeTo<T> {
 
 impl<T> RcVec<T> {
     /// Constructs a new `RcVec` from a `Vec`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` contains more than `u32::MAX` elements.
     pub fn new(data: Vec<T>) -> RcVec<T> {
-        let n = data.len();
+        let n = len_u32(data.len());
 
         RcVec{
-            data: Rc::new(data),
+            data: ManuallyDrop::new(Rc::new(data)),
             start: 0,
             end: n,
         }
@@ -52,7 +65,7 @@ impl<T> RcVec<T> {
 
     /// Returns the number of elements visible to the `RcVec`.
     pub fn len(&self) -> usize {
-        self.end - self.start
+        (self.end - self.start) as usize
     }
 
     /// Returns a subslice of the `RcVec`, with the range being relative
@@ -61,38 +74,51 @@ impl<T> RcVec<T> {
         let start = range.start().map_or(0, |v| *v);
         let end = range.end().map_or(self.len(), |v| *v);
 
-        let a = self.start + start;
-        let b = self.start + end;
+        let a = self.start as usize + start;
+        let b = self.start as usize + end;
 
-        if a > self.end {
+        if a > self.end as usize {
             panic!("RcVec slice out of bounds; start is {} but length is {}",
                 start, self.len());
         }
 
-        if b > self.end {
+        if b > self.end as usize {
             panic!("RcVec slice out of bounds; end is {} but length is {}",
                 end, self.len());
         }
 
         RcVec{
             data: self.data.clone(),
-            start: a,
-            end: b,
+            start: a as u32,
+            end: b as u32,
         }
     }
 }
 
+impl<T> RcVec<T> {
+    /// Consumes the `RcVec` without running its destructor,
+    /// returning the shared data and boundaries.
+    fn into_parts(self) -> (Rc<Vec<T>>, u32, u32) {
+        let mut this = ManuallyDrop::new(self);
+        // Safe because `this` is never used or dropped again.
+        let data = unsafe { ManuallyDrop::take(&mut this.data) };
+        (data, this.start, this.end)
+    }
+}
+
 impl<T: Clone> RcVec<T> {
     /// Consumes the `RcVec` and returns the contained `Vec`.
     /// This will clone the contained values unless the data was uniquely held.
     pub fn into_vec(self) -> Vec<T> {
-        match Rc::try_unwrap(self.data) {
+        let (data, start, end) = self.into_parts();
+
+        match Rc::try_unwrap(data) {
             Ok(mut v) => {
-                let _ = v.drain(self.end..);
-                let _ = v.drain(..self.start);
+                let _ = v.drain(end as usize..);
+                let _ = v.drain(..start as usize);
                 v
             }
-            Err(data) => data[self.start..self.end].to_vec()
+            Err(data) => data[start as usize..end as usize].to_vec()
         }
     }
 
@@ -106,9 +132,9 @@ impl<T: Clone> RcVec<T> {
     fn make_mut(&mut self) -> &mut Vec<T> {
         let mut v = Rc::make_mut(&mut self.data);
 
-        let _ = v.drain(self.end..);
-        let _ = v.drain(..self.start);
-        let n = v.len();
+        let _ = v.drain(self.end as usize..);
+        let _ = v.drain(..self.start as usize);
+        let n = len_u32(v.len());
 
         self.start = 0;
         self.end = n;
@@ -119,13 +145,102 @@ impl<T: Clone> RcVec<T> {
     /// Pushes a value into the contained `Vec`.
     pub fn push(&mut self, t: T) {
         self.make_mut().push(t);
-        self.end += 1;
+        self.end = len_u32(self.data.len());
+    }
+}
+
+fn len_u32(n: usize) -> u32 {
+    if n > u32::max_value() as usize {
+        panic!("RcVec length {} exceeds maximum of {}", n, u32::max_value());
+    }
+    n as u32
+}
+
+/// Reference to a uniquely held `Vec` whose destruction has been deferred
+struct Deferred {
+    data: *const (),
+    drop: unsafe fn(*const ()),
+}
+
+unsafe fn drop_deferred<T>(data: *const ()) {
+    drop(Rc::from_raw(data as *const Vec<T>));
+}
+
+thread_local!{
+    /// Destructors deferred while another `RcVec` is being destroyed;
+    /// `None` when no `RcVec` destruction is in progress.
+    static DEFERRED: RefCell<Option<Vec<Deferred>>> = RefCell::new(None)
+}
+
+impl<T> Drop for RcVec<T> {
+    /// Releases the reference to the shared data.
+    ///
+    /// When the last reference to data containing other `RcVec` values is
+    /// released, contained values are not destroyed recursively. Instead,
+    /// the outermost `drop` call destroys each nested `Vec` in turn,
+    /// so that deeply nested values cannot overflow the stack.
+    fn drop(&mut self) {
+        // Safe because `self.data` is never used again.
+        let data = unsafe { ManuallyDrop::take(&mut self.data) };
+
+        if !mem::needs_drop::<T>() || Rc::strong_count(&data) != 1 {
+            return;
+        }
+
+        let data = Rc::into_raw(data);
+
+        let outermost = DEFERRED.try_with(|d| {
+            let mut d = d.borrow_mut();
+
+            match *d {
+                Some(ref mut q) => {
+                    q.push(Deferred{
+                        data: data as *const (),
+                        drop: drop_deferred::<T>,
+                    });
+                    false
+                }
+                None => {
+                    *d = Some(Vec::new());
+                    true
+                }
+            }
+        });
+
+        match outermost {
+            Ok(true) => (),
+            Ok(false) => return,
+            // Thread-local storage is being destroyed; drop recursively.
+            Err(_) => {
+                unsafe { drop_deferred::<T>(data as *const ()) };
+                return;
+            }
+        }
+
+        let _guard = DeferGuard;
+
+        unsafe { drop_deferred::<T>(data as *const ()) };
+
+        while let Some(next) = DEFERRED.with(|d| d.borrow_mut().as_mut()
+                .and_then(|q| q.pop())) {
+            unsafe { (next.drop)(next.data) };
+        }
+    }
+}
+
+/// Ends deferral of `RcVec` destructors, even if a destructor panics.
+/// In that case, any values still awaiting destruction are leaked.
+struct DeferGuard;
+
+impl Drop for DeferGuard {
+    fn drop(&mut self) {
+        DEFERRED.with(|d| *d.borrow_mut() = None);
     }
 }
 
 impl<T> AsRef<[T]> for RcVec<T> {
     fn as_ref(&self) -> &[T] {
-        &self.data[self.start..self.end]
+        &self.data[self.start as usize..self.end as usize]
     }
 }
 
@@ -146,14 +261,14 @@ impl<T: Clone> ops::DerefMut for RcVec<T> {
 impl<T: Clone> Extend<T> for RcVec<T> {
     fn extend<I>(&mut self, iterable: I) where I: IntoIterator<Item=T> {
         self.make_mut().extend(iterable);
-        self.end = self.data.len();
+        self.end = len_u32(self.data.len());
     }
 }
 
 impl<'a, T: Clone> Extend<&'a T> for RcVec<T> where T: Copy + 'a {
     fn extend<I>(&mut self, iterable: I) where I: IntoIterator<Item=&'a T> {
         self.make_mut().extend(iterable);
-        self.end = self.data.len();
+        self.end = len_u32(self.data.len());
     }
 }
 
@@ -222,4 +337,19 @@ mod test {
         c.extend(&[4, 5, 6]);
         assert_eq!(c.into_vec(), [1, 2, 3, 4, 5, 6]);
     }
+
+    #[allow(dead_code)]
+    #[derive(Clone)]
+    struct Nested(Option<RcVec<Nested>>);
+
+    #[test]
+    fn test_drop_nested() {
+        let mut v = Nested(None);
+
+        for _ in 0..1_000_000 {
+            v = Nested(Some(RcVec::new(vec![v])));
+        }
+
+        drop(v);
+    }
 }
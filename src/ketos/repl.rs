@@ -0,0 +1,276 @@
+//! Implements the core of an interactive read-eval-print loop.
+//!
+//! `Repl` accepts input one line at a time, buffering lines until a complete
+//! expression is available, then evaluates it and reports the result.
+//! Reading input and displaying output are left to the caller, so that
+//! the same engine may drive a terminal, a GUI console, or a network session.
+//!
+//! ```
+//! use ketos::Interpreter;
+//! use ketos::repl::{Prompt, Repl, ReplOutput};
+//!
+//! let mut repl = Repl::new(Interpreter::new());
+//!
+//! match repl.feed("(+ 1") {
+//!     ReplOutput::Incomplete(Prompt::OpenParen) => (),
+//!     _ => panic!()
+//! }
+//!
+//! match repl.feed("2)") {
+//!     ReplOutput::Value{number: Some(1), ref text, ..} => assert_eq!(text, "3"),
+//!     _ => panic!()
+//! }
+//!
+//! match repl.feed("(* $1 10)") {
+//!     ReplOutput::Value{number: Some(2), ref text, ..} => assert_eq!(text, "30"),
+//!     _ => panic!()
+//! }
+//! ```
+
+use error::Error;
+use interpreter::Interpreter;
+use parser::ParseErrorKind;
+use scope::{GlobalScope, MasterScope};
+use value::Value;
+
+/// Describes the state of buffered input
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Prompt {
+    /// No input is buffered
+    Normal,
+    /// Buffered input ends within a block comment
+    OpenComment,
+    /// Buffered input ends within an unclosed list
+    OpenParen,
+    /// Buffered input ends within a string literal
+    OpenString,
+    /// Buffered input ends within some other incomplete expression,
+    /// such as a quoted expression
+    Continue,
+}
+
+/// Result of feeding a line of input into a `Repl`
+#[derive(Debug)]
+pub enum ReplOutput {
+    /// Input contained no expressions
+    Empty,
+    /// Input is buffered until further lines complete an expression
+    Incomplete(Prompt),
+    /// Input was evaluated successfully
+    Value{
+        /// Number of the result, if it was bound to a name such as `$1`;
+        /// unit results are not numbered
+        number: Option<usize>,
+        /// Value of the last expression in the input
+        value: Value,
+        /// Debug representation of the value
+        text: String,
+    },
+    /// Input produced an error
+    Error{
+        /// Error produced by the input
+        error: Error,
+        /// Formatted error message, including source location when known
+        message: String,
+    },
+}
+
+/// Read-eval-print loop engine
+///
+/// Each non-unit result is bound in the global scope to a name
+/// consisting of `$` followed by the result number, beginning at `$1`.
+pub struct Repl {
+    interp: Interpreter,
+    buffer: String,
+    prompt: Prompt,
+    count: usize,
+}
+
+impl Repl {
+    /// Creates a new `Repl` evaluating input within the given interpreter.
+    pub fn new(interp: Interpreter) -> Repl {
+        Repl{
+            interp: interp,
+            buffer: String::new(),
+            prompt: Prompt::Normal,
+            count: 0,
+        }
+    }
+
+    /// Returns the wrapped `Interpreter`.
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interp
+    }
+
+    /// Returns the state of buffered input.
+    pub fn prompt(&self) -> Prompt {
+        self.prompt
+    }
+
+    /// Returns the number of results which have been numbered.
+    pub fn result_count(&self) -> usize {
+        self.count
+    }
+
+    /// Discards any buffered input.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.prompt = Prompt::Normal;
+    }
+
+    /// Feeds a line of input into the `Repl`.
+    ///
+    /// If the line, together with any buffered input, forms one or more
+    /// complete expressions, they are evaluated. Otherwise, the line is
+    /// buffered and `ReplOutput::Incomplete` is returned.
+    pub fn feed(&mut self, line: &str) -> ReplOutput {
+        if self.buffer.is_empty() && line.chars().all(|c| c.is_whitespace()) {
+            return ReplOutput::Empty;
+        }
+
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+
+        let res = self.interp.compile_exprs(&self.buffer);
+
+        let prompt = match res {
+            Err(Error::ParseError(ref e)) if e.is_incomplete() => match e.kind {
+                ParseErrorKind::MissingCloseParen => Some(Prompt::OpenParen),
+                ParseErrorKind::UnterminatedComment => Some(Prompt::OpenComment),
+                ParseErrorKind::UnterminatedString => Some(Prompt::OpenString),
+                _ => Some(Prompt::Continue)
+            },
+            _ => None
+        };
+
+        if let Some(prompt) = prompt {
+            self.prompt = prompt;
+            return ReplOutput::Incomplete(prompt);
+        }
+
+        let out = match res {
+            Ok(ref code) if code.is_empty() => ReplOutput::Empty,
+            Ok(code) => match self.interp.execute_program(code) {
+                Ok(v) => self.result(v),
+                Err(e) => self.error(e)
+            },
+            Err(e) => self.error(e)
+        };
+
+        self.reset();
+        self.interp.clear_codemap();
+
+        out
+    }
+
+    /// Returns the common prefix and possible completion suffixes
+    /// for the text within `line[start..end]`, using names defined
+    /// in the interpreter's global scope.
+    pub fn complete(&self, line: &str, start: usize, end: usize)
+            -> Option<(String, Vec<String>)> {
+        complete(line, start, end, self.interp.get_scope())
+    }
+
+    fn result(&mut self, value: Value) -> ReplOutput {
+        let number = match value {
+            Value::Unit => None,
+            ref v => {
+                self.count += 1;
+                self.interp.get_scope().add_named_value(
+                    &format!("${}", self.count), v.clone());
+                Some(self.count)
+            }
+        };
+
+        let text = self.interp.format_value(&value);
+
+        ReplOutput::Value{
+            number: number,
+            value: value,
+            text: text,
+        }
+    }
+
+    fn error(&self, error: Error) -> ReplOutput {
+        let message = self.interp.format_error(&error);
+
+        ReplOutput::Error{
+            error: error,
+            message: message,
+        }
+    }
+}
+
+/// Returns common prefix and possible completion suffixes for the text
+/// within `text[start..end]`.
+///
+/// Candidates are those returned by `complete_name`.
+pub fn complete(text: &str, start: usize, end: usize, scope: &GlobalScope)
+        -> Option<(String, Vec<String>)> {
+    // Don't attempt to complete when the input is empty
+    if text.chars().all(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    let text = &text[start..end];
+    let prefix_len = text.len();
+
+    let results = complete_name(text, scope).into_iter()
+        .map(|name| name[prefix_len..].to_owned())
+        .collect::<Vec<_>>();
+
+    if results.is_empty() {
+        None
+    } else {
+        let prefix = common_prefix(&results);
+        Some((prefix, results))
+    }
+}
+
+/// Returns all names beginning with `prefix`, sorted and without duplicates.
+///
+/// Candidates are standard names; names of values, macros, and constants
+/// defined in the given scope; and names exported by loaded modules.
+pub fn complete_name(prefix: &str, scope: &GlobalScope) -> Vec<String> {
+    let mut names = MasterScope::get_names().collect::<Vec<_>>();
+
+    scope.with_values(|v| names.extend(v.iter().map(|&(name, _)| name)));
+    scope.with_macros(|v| names.extend(v.iter().map(|&(name, _)| name)));
+    scope.with_constants(|v| names.extend(v.iter().map(|&(name, _)| name)));
+
+    for module in scope.get_modules().iter() {
+        names.extend(module.exports());
+    }
+
+    let store = scope.borrow_names();
+
+    let mut results = names.into_iter()
+        .map(|name| store.get(name))
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_owned())
+        .collect::<Vec<_>>();
+
+    results.sort();
+    results.dedup();
+    results
+}
+
+/// Returns the (possibly empty) common prefix of the given strings.
+/// Input strings must be non-empty.
+fn common_prefix(strs: &[String]) -> String {
+    assert!(!strs.is_empty());
+
+    let mut prefix: String = strs[0].clone();
+
+    for c in strs[1..].iter() {
+        while !c.starts_with(&prefix) {
+            prefix.pop();
+        }
+
+        if prefix.is_empty() {
+            break;
+        }
+    }
+
+    prefix
+}
@@ -0,0 +1,222 @@
+//! Configures limits on the resources consumed by executing code.
+//!
+//! Restrictions allow a host program to run untrusted code without risking
+//! that a script runs forever or exhausts available memory.
+//! The instruction limit applies to each top-level invocation of
+//! an `Interpreter`, such as `run_code` or `call`, and is reset when the
+//! next invocation begins. Size limits apply to each value produced by
+//! system functions and bytecode instructions.
+//!
+//! ```
+//! use ketos::{Error, ExecError, Interpreter, RestrictConfig};
+//!
+//! let interp = Interpreter::with_restrictions(RestrictConfig{
+//!     max_instructions: Some(10_000),
+//!     .. RestrictConfig::permissive()
+//! });
+//!
+//! let r = interp.run_code("(define (spin) (spin)) (spin)", None);
+//!
+//! match r {
+//!     Err(Error::ExecError(ExecError::InstructionLimit)) => (),
+//!     _ => panic!("expected instruction limit")
+//! }
+//!
+//! // The limit is reset for the next invocation
+//! assert!(interp.run_code("(+ 1 2)", None).is_ok());
+//! ```
+
+use std::mem::size_of;
+
+use exec::ExecError;
+use value::Value;
+
+/// Number of bytes of output which a function implemented in Rust
+/// may produce for the cost of one instruction
+pub const BYTES_PER_INSTRUCTION: usize = 64;
+
+/// Maximum call depth under `RestrictConfig::permissive`
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+/// Maximum depth of nested execution under `RestrictConfig::permissive`
+pub const DEFAULT_MAX_NATIVE_DEPTH: usize = 32;
+
+/// Maximum number of instructions executed under `RestrictConfig::strict`
+pub const STRICT_MAX_INSTRUCTIONS: u64 = 1_000_000;
+/// Maximum size, in bits, of integers under `RestrictConfig::strict`
+pub const STRICT_MAX_INTEGER_BITS: usize = 65_536;
+/// Maximum number of elements in a list under `RestrictConfig::strict`
+pub const STRICT_MAX_LIST_LEN: usize = 100_000;
+/// Maximum length, in bytes, of strings under `RestrictConfig::strict`
+pub const STRICT_MAX_STRING_LEN: usize = 1 << 20;
+/// Maximum number of values defined in a scope under `RestrictConfig::strict`
+pub const STRICT_MAX_NAMESPACE_SIZE: usize = 1024;
+/// Maximum call depth under `RestrictConfig::strict`
+pub const STRICT_MAX_CALL_DEPTH: usize = 256;
+/// Maximum depth of nested execution under `RestrictConfig::strict`
+pub const STRICT_MAX_NATIVE_DEPTH: usize = 16;
+/// Maximum depth of nested expressions parsed under `RestrictConfig::strict`
+pub const STRICT_MAX_PARSE_DEPTH: usize = 256;
+
+/// Limits placed on code executed within a `GlobalScope`
+#[derive(Clone, Debug)]
+pub struct RestrictConfig {
+    /// Maximum number of bytecode instructions executed in one invocation,
+    /// including instructions executed while expanding macros.
+    /// If `None`, there is no limit.
+    ///
+    /// Each call to a function implemented in Rust is additionally charged
+    /// in proportion to the size of the value it returns; see `output_cost`.
+    pub max_instructions: Option<u64>,
+    /// Maximum size, in bits, of integer values, including the numerator
+    /// and denominator of ratio values
+    pub max_integer_bits: Option<usize>,
+    /// Maximum number of elements in a list, map, or set
+    pub max_list_len: Option<usize>,
+    /// Maximum length, in bytes, of a string
+    pub max_string_len: Option<usize>,
+    /// Maximum number of values defined in a global scope
+    pub max_namespace_size: Option<usize>,
+    /// Maximum depth of nested function calls, including calls made
+    /// through functions implemented in Rust.
+    ///
+    /// Calls between functions defined in Ketos do not consume
+    /// the stack of the host thread; see `max_native_depth` for calls
+    /// which do.
+    pub max_call_depth: usize,
+    /// Maximum depth of nested execution, i.e. the number of times code
+    /// may be reentered through functions implemented in Rust, such as
+    /// a foreign function which calls a function value it was given.
+    /// The outermost execution counts as one.
+    ///
+    /// Each reentry consumes the stack of the host thread, so this limit
+    /// is always enforced. The default permits at least 32 nested
+    /// executions on a thread with a 2 MiB stack in a debug build.
+    /// Threads with smaller stacks may require a lower limit.
+    pub max_native_depth: usize,
+    /// Maximum depth of nested expressions in parsed source code.
+    /// If `None`, there is no limit.
+    ///
+    /// Deeply nested values may overflow the stack of the host thread
+    /// when they are compiled, compared, or dropped.
+    pub max_parse_depth: Option<usize>,
+    /// Whether code may call `eval` and `read` to compile and parse
+    /// values at runtime
+    pub allow_eval: bool,
+    /// Whether code may read from the standard input source of the scope
+    /// using `read-line` and `read-char`
+    ///
+    /// Reading from a port given as an argument is not affected.
+    pub allow_stdin: bool,
+}
+
+impl RestrictConfig {
+    /// Returns a `RestrictConfig` which places no limits on execution,
+    /// other than the default call depth limit.
+    ///
+    /// This is the configuration used by `Interpreter::new`.
+    pub fn permissive() -> RestrictConfig {
+        RestrictConfig{
+            max_instructions: None,
+            max_integer_bits: None,
+            max_list_len: None,
+            max_string_len: None,
+            max_namespace_size: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_native_depth: DEFAULT_MAX_NATIVE_DEPTH,
+            max_parse_depth: None,
+            allow_eval: true,
+            allow_stdin: true,
+        }
+    }
+
+    /// Returns a `RestrictConfig` with limits suitable for running
+    /// untrusted code.
+    pub fn strict() -> RestrictConfig {
+        RestrictConfig{
+            max_instructions: Some(STRICT_MAX_INSTRUCTIONS),
+            max_integer_bits: Some(STRICT_MAX_INTEGER_BITS),
+            max_list_len: Some(STRICT_MAX_LIST_LEN),
+            max_string_len: Some(STRICT_MAX_STRING_LEN),
+            max_namespace_size: Some(STRICT_MAX_NAMESPACE_SIZE),
+            max_call_depth: STRICT_MAX_CALL_DEPTH,
+            max_native_depth: STRICT_MAX_NATIVE_DEPTH,
+            max_parse_depth: Some(STRICT_MAX_PARSE_DEPTH),
+            allow_eval: false,
+            allow_stdin: false,
+        }
+    }
+
+    /// Returns an error if an integer of the given size exceeds the limit.
+    pub fn check_integer_bits(&self, bits: usize) -> Result<(), ExecError> {
+        check_limit(self.max_integer_bits, bits, ExecError::IntegerLimit)
+    }
+
+    /// Returns an error if a list of the given length exceeds the limit.
+    pub fn check_list_len(&self, len: usize) -> Result<(), ExecError> {
+        check_limit(self.max_list_len, len, ExecError::ListLimit)
+    }
+
+    /// Returns an error if a string of the given length exceeds the limit.
+    pub fn check_string_len(&self, len: usize) -> Result<(), ExecError> {
+        check_limit(self.max_string_len, len, ExecError::StringLimit)
+    }
+
+    /// Returns an error if a scope defining the given number of values
+    /// exceeds the limit.
+    pub fn check_namespace_size(&self, size: usize) -> Result<(), ExecError> {
+        check_limit(self.max_namespace_size, size, ExecError::NamespaceLimit)
+    }
+
+    /// Returns an error if the given value exceeds any size limit.
+    ///
+    /// Only the value itself is checked; elements of a list are not.
+    /// Because values are checked as they are produced, elements of a list
+    /// will already have been checked.
+    pub fn check_value(&self, v: &Value) -> Result<(), ExecError> {
+        match *v {
+            Value::Integer(ref i) => self.check_integer_bits(i.bits()),
+            Value::Ratio(ref r) => {
+                try!(self.check_integer_bits(r.numer().bits()));
+                self.check_integer_bits(r.denom().bits())
+            }
+            Value::String(ref s) => self.check_string_len(s.len()),
+            Value::List(ref li) => self.check_list_len(li.len()),
+            Value::Map(ref m) => self.check_list_len(m.len()),
+            Value::Set(ref s) => self.check_list_len(s.len()),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Default for RestrictConfig {
+    fn default() -> RestrictConfig {
+        RestrictConfig::permissive()
+    }
+}
+
+/// Returns the number of instructions charged, under an instruction limit,
+/// for a value returned by a function implemented in Rust.
+///
+/// The cost is one instruction for each `BYTES_PER_INSTRUCTION` bytes
+/// of integer digits, string contents, or list, map, or set elements.
+/// As with `RestrictConfig::check_value`, only the value itself is measured.
+pub fn output_cost(v: &Value) -> u64 {
+    let bytes = match *v {
+        Value::Integer(ref i) => i.bits() / 8,
+        Value::Ratio(ref r) => r.bits() / 8,
+        Value::String(ref s) => s.len(),
+        Value::List(ref li) => li.len() * size_of::<Value>(),
+        Value::Map(ref m) => m.len() * 2 * size_of::<Value>(),
+        Value::Set(ref s) => s.len() * size_of::<Value>(),
+        _ => 0
+    };
+
+    (bytes / BYTES_PER_INSTRUCTION) as u64
+}
+
+fn check_limit(limit: Option<usize>, n: usize, err: ExecError) -> Result<(), ExecError> {
+    match limit {
+        Some(limit) if n > limit => Err(err),
+        _ => Ok(())
+    }
+}
@@ -1,17 +1,31 @@
 //! Contains values associated with names in a given execution context.
 
-use std::cell::{Ref, RefMut, RefCell};
+use std::cell::{Cell, Ref, RefMut, RefCell};
+use std::cmp::min;
 use std::io;
+use std::path::Path;
 use std::rc::{Rc, Weak};
-
-use function::{Function, Lambda, SystemFn};
-use io::SharedWrite;
-use lexer::CodeMap;
-use module::ModuleRegistry;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rand::{Rng, StdRng};
+
+use arena::Arena;
+use error::Error;
+use exec::{ErrorLocation, ExecError};
+use function::{Function, Lambda};
+use io::{IoError, IoMode, SharedRead, SharedWrite};
+use lexer::{CodeMap, Span};
+use metrics::MetricsCell;
+use module::{ModuleRegistry, ModuleVersion};
 use name::{get_standard_name, get_system_fn, is_system_operator,
     is_standard_value, NUM_STANDARD_VALUES,
     SYSTEM_OPERATORS_END, Name, NameMap, NameSetSlice, NameStore};
-use value::Value;
+use parser::{ParseError, Parser, ReaderMacros};
+use restrict::RestrictConfig;
+use string_fmt::{FormatCache, FormatDirectives};
+use trace::Trace;
+use value::{struct_field_name, Struct, StructDef, StructValue, Value};
 
 /// Represents the global namespace of an execution context.
 pub struct GlobalScope {
@@ -20,25 +34,104 @@ pub struct GlobalScope {
     codemap: Rc<RefCell<CodeMap>>,
     modules: Rc<ModuleRegistry>,
     io: Rc<GlobalIo>,
+    readers: Rc<RefCell<ReaderMacros>>,
+    arena: Rc<Arena>,
+    formats: Rc<FormatCache>,
+    directives: Rc<FormatDirectives>,
+    metrics: Rc<MetricsCell>,
+    rng: Rc<RefCell<Option<Box<Rng>>>>,
+    interrupt: Arc<AtomicBool>,
+    restrict: Rc<RestrictConfig>,
+    /// Instructions remaining in the current invocation, if limited
+    instructions: Rc<Cell<u64>>,
+    /// Depth of function calls in progress
+    call_depth: Rc<Cell<usize>>,
+    /// Number of executions in progress, nested through Rust functions
+    native_depth: Rc<Cell<usize>>,
+    /// Location at which the most recent runtime error was produced
+    error_location: Rc<Cell<Option<ErrorLocation>>>,
+    /// Calls active when the most recent runtime error was produced
+    error_trace: Rc<RefCell<Trace>>,
+    generation: Cell<usize>,
+}
+
+/// Source of unique generation numbers for `GlobalScope` values
+static NEXT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+fn next_generation() -> usize {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
 }
 
 /// Contains global shared I/O objects
 pub struct GlobalIo {
     /// Shared standard output writer
     pub stdout: Rc<SharedWrite>,
+    /// Shared standard error writer
+    pub stderr: Rc<SharedWrite>,
+    /// Shared standard input reader, if scripts may read input
+    pub stdin: Option<Rc<SharedRead>>,
+    /// Determines when output written by scripts is flushed
+    pub flush: FlushPolicy,
+}
+
+/// Determines when output streams are flushed after being written to
+/// by `print` and related functions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FlushPolicy {
+    /// Flush after every write
+    Always,
+    /// Flush after writes containing a newline
+    Line,
+    /// Flush only when requested by the `flush` function
+    Manual,
 }
 
 impl GlobalIo {
-    /// Creates a `GlobalIo` instance using the given `stdout` writer.
+    /// Creates a `GlobalIo` instance using the given `stdout` writer
+    /// and the standard error writer.
     pub fn new(stdout: Rc<SharedWrite>) -> GlobalIo {
+        GlobalIo::with_stderr(stdout, Rc::new(io::stderr()))
+    }
+
+    /// Creates a `GlobalIo` instance using the given `stdout`
+    /// and `stderr` writers.
+    ///
+    /// No input source is provided; scripts attempting to read input
+    /// will receive an error.
+    pub fn with_stderr(stdout: Rc<SharedWrite>, stderr: Rc<SharedWrite>) -> GlobalIo {
         GlobalIo{
             stdout: stdout,
+            stderr: stderr,
+            stdin: None,
+            flush: FlushPolicy::Always,
         }
     }
 
-    /// Creates a `GlobalIo` instance using standard output writer.
+    /// Sets the input source from which scripts may read using
+    /// `read-line` and `read-char`.
+    pub fn with_stdin(mut self, stdin: Rc<SharedRead>) -> GlobalIo {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    /// Sets the policy determining when output streams are flushed.
+    ///
+    /// The default policy, `FlushPolicy::Always`, flushes after every write.
+    /// Scripts which produce a large amount of output may run faster
+    /// using `FlushPolicy::Line` or `FlushPolicy::Manual`.
+    pub fn with_flush_policy(mut self, flush: FlushPolicy) -> GlobalIo {
+        self.flush = flush;
+        self
+    }
+
+    /// Creates a `GlobalIo` instance using standard input, standard output,
+    /// and standard error streams.
+    ///
+    /// Whether scripts may read standard input is determined by
+    /// the `allow_stdin` field of the scope's `RestrictConfig`.
     pub fn default() -> GlobalIo {
         GlobalIo::new(Rc::new(io::stdout()))
+            .with_stdin(Rc::new(io::stdin()))
     }
 }
 
@@ -46,10 +139,14 @@ struct Namespace {
     constants: NameMap<Value>,
     macros: NameMap<Lambda>,
     values: NameMap<Value>,
+    /// Documentation of named constants, taken from preceding doc comments
+    docs: NameMap<String>,
     /// Exported names defined by an `export` declaration
     exports: Option<NameSetSlice>,
     /// Names imported by a `use` declaration
     imports: Vec<ImportSet>,
+    /// Module version defined by an `export` declaration
+    version: Option<ModuleVersion>,
 }
 
 /// Represents a set of named macros and values imported from a module.
@@ -67,6 +164,8 @@ pub struct ImportSet {
     pub macros: Vec<(Name, Name)>,
     /// Named values which are imported
     pub values: Vec<(Name, Name)>,
+    /// Minimum required version of the module
+    pub min_version: Option<ModuleVersion>,
 }
 
 impl ImportSet {
@@ -77,6 +176,7 @@ impl ImportSet {
             constants: Vec::new(),
             macros: Vec::new(),
             values: Vec::new(),
+            min_version: None,
         }
     }
 }
@@ -99,16 +199,63 @@ impl GlobalScope {
             codemap: codemap,
             modules: registry,
             io: io,
+            readers: Rc::new(RefCell::new(ReaderMacros::new())),
+            arena: Rc::new(Arena::new()),
+            formats: Rc::new(FormatCache::new()),
+            directives: Rc::new(FormatDirectives::new()),
+            metrics: Rc::new(MetricsCell::new()),
+            rng: Rc::new(RefCell::new(None)),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            restrict: Rc::new(RestrictConfig::permissive()),
+            instructions: Rc::new(Cell::new(0)),
+            call_depth: Rc::new(Cell::new(0)),
+            native_depth: Rc::new(Cell::new(0)),
+            error_location: Rc::new(Cell::new(None)),
+            error_trace: Rc::new(RefCell::new(Trace::new())),
+            generation: Cell::new(next_generation()),
         }
     }
 
+    /// Sets the restrictions placed on code executed within the scope.
+    pub fn with_restrictions(mut self, restrict: RestrictConfig) -> GlobalScope {
+        self.instructions.set(restrict.max_instructions.unwrap_or(0));
+        self.restrict = Rc::new(restrict);
+        self
+    }
+
     /// Creates a new global scope using the shared data from the given scope.
+    ///
+    /// If the `ModuleRegistry` of the given scope is isolated, the new scope
+    /// receives a copy of its reader macros and format directives, rather
+    /// than sharing them.
     pub fn new_using(scope: &Scope) -> Scope {
-        Rc::new(GlobalScope::new(
-            scope.name_store.clone(),
-            scope.codemap.clone(),
-            scope.modules.clone(),
-            scope.io.clone()))
+        let (readers, directives) = if scope.modules.is_isolated() {
+            (Rc::new(RefCell::new(scope.readers.borrow().clone())),
+                Rc::new((*scope.directives).clone()))
+        } else {
+            (scope.readers.clone(), scope.directives.clone())
+        };
+
+        Rc::new(GlobalScope{
+            readers: readers,
+            arena: scope.arena.clone(),
+            formats: scope.formats.clone(),
+            directives: directives,
+            metrics: scope.metrics.clone(),
+            rng: scope.rng.clone(),
+            interrupt: scope.interrupt.clone(),
+            restrict: scope.restrict.clone(),
+            instructions: scope.instructions.clone(),
+            call_depth: scope.call_depth.clone(),
+            native_depth: scope.native_depth.clone(),
+            error_location: scope.error_location.clone(),
+            error_trace: scope.error_trace.clone(),
+            ..GlobalScope::new(
+                scope.name_store.clone(),
+                scope.codemap.clone(),
+                scope.modules.clone(),
+                scope.io.clone())
+        })
     }
 
     /// Add a named constant value to the scope.
@@ -116,11 +263,24 @@ impl GlobalScope {
         self.namespace.borrow_mut().constants.insert(name, value);
     }
 
+    /// Adds documentation for a named constant to the scope.
+    pub fn add_doc(&self, name: Name, doc: String) {
+        self.namespace.borrow_mut().docs.insert(name, doc);
+    }
+
     /// Adds a macro function to the global scope.
     pub fn add_macro(&self, name: Name, lambda: Lambda) {
         self.namespace.borrow_mut().macros.insert(name, lambda);
     }
 
+    /// Adds a reader macro function for the given dispatch character.
+    /// The function will be used to parse source code beginning with `#`
+    /// followed by the dispatch character.
+    pub fn add_reader_macro<F>(&self, ch: char, f: F)
+            where F: Fn(&mut Parser, Span) -> Result<Value, ParseError> + 'static {
+        self.readers.borrow_mut().add(ch, f);
+    }
+
     /// Adds a string representation to the contained `NameStore`.
     pub fn add_name(&self, name: &str) -> Name {
         self.name_store.borrow_mut().add(name)
@@ -134,6 +294,7 @@ impl GlobalScope {
     /// Adds a value to the global scope.
     pub fn add_value(&self, name: Name, value: Value) {
         self.namespace.borrow_mut().values.insert(name, value);
+        self.generation.set(next_generation());
     }
 
     /// Adds a value with the given name to the global scope.
@@ -170,6 +331,13 @@ impl GlobalScope {
         self.name_store.borrow_mut()
     }
 
+    /// Borrows a reference to the set of defined values.
+    ///
+    /// Values cannot be defined in the scope while the borrow is held.
+    pub fn borrow_values(&self) -> Ref<NameMap<Value>> {
+        Ref::map(self.namespace.borrow(), |ns| &ns.values)
+    }
+
     /// Returns a borrowed reference to the contained `CodeMap`.
     pub fn get_codemap(&self) -> &Rc<RefCell<CodeMap>> {
         &self.codemap
@@ -180,6 +348,281 @@ impl GlobalScope {
         self.namespace.borrow().constants.get(name).cloned()
     }
 
+    /// Returns the documentation of a named constant, if any.
+    pub fn get_doc(&self, name: Name) -> Option<String> {
+        self.namespace.borrow().docs.get(name).cloned()
+    }
+
+    /// Returns a borrowed reference to the `Arena` used for temporary values.
+    pub fn get_arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    /// Returns a borrowed reference to the cache of parsed format strings.
+    pub fn get_format_cache(&self) -> &FormatCache {
+        &self.formats
+    }
+
+    /// Adds a struct definition for the given Rust type to the scope
+    /// and returns the new definition.
+    pub fn add_struct_def<T: StructValue>(&self) -> Rc<StructDef> {
+        let (name, fields) = {
+            let mut names = self.name_store.borrow_mut();
+
+            let fields = T::struct_fields().iter()
+                .map(|&(field, ty)| (names.add(&struct_field_name(field)), names.add(ty)))
+                .collect();
+
+            (names.add(T::struct_name()), fields)
+        };
+
+        let def = Rc::new(StructDef::new(name, fields));
+        self.add_value(name, Value::StructDef(def.clone()));
+        def
+    }
+
+    /// Converts a Rust value into a struct value using the definition
+    /// previously added to the scope by `add_struct_def`.
+    pub fn struct_to_value<T: StructValue>(&self, t: T) -> Result<Value, ExecError> {
+        let name = self.add_name(T::struct_name());
+
+        let def = match self.get_value(name) {
+            Some(Value::StructDef(def)) => def,
+            Some(ref v) => return Err(ExecError::expected("struct-def", v)),
+            None => return Err(ExecError::NameError(name))
+        };
+
+        let fields = {
+            let mut names = self.name_store.borrow_mut();
+
+            T::struct_fields().iter().zip(t.into_fields())
+                .map(|(&(field, _), v)| (names.add(&struct_field_name(field)), v))
+                .collect()
+        };
+
+        Ok(Value::Struct(Rc::new(Struct::new(def, fields))))
+    }
+
+    /// Converts a struct value into a Rust value.
+    ///
+    /// Returns an error if the value is not an instance of the struct
+    /// defined for the Rust type.
+    pub fn struct_from_value<T: StructValue>(&self, v: &Value) -> Result<T, ExecError> {
+        let s = match *v {
+            Value::Struct(ref s) if self.with_name(s.def.name,
+                |name| name == T::struct_name()) => s,
+            ref v => return Err(ExecError::expected(T::struct_name(), v))
+        };
+
+        let mut fields = Vec::with_capacity(T::struct_fields().len());
+
+        for &(field, _) in T::struct_fields() {
+            let name = self.add_name(&struct_field_name(field));
+
+            match s.get_field(name) {
+                Some(v) => fields.push(v.clone()),
+                None => return Err(ExecError::MissingField{
+                    struct_name: s.def.name,
+                    field: name,
+                })
+            }
+        }
+
+        T::from_fields(fields)
+    }
+
+    /// Sets the random number generator used by the `random` module.
+    ///
+    /// Hosts on platforms without an operating system entropy source,
+    /// such as `wasm32-unknown-unknown`, must provide a generator
+    /// before scripts may use the `random` module.
+    /// Hosts may also provide a seeded generator for reproducible results.
+    pub fn set_rng(&self, rng: Box<Rng>) {
+        *self.rng.borrow_mut() = Some(rng);
+    }
+
+    /// Calls a closure with the random number generator.
+    ///
+    /// If no generator has been set, one is created using operating system
+    /// entropy. An error is returned if no entropy source is available.
+    pub fn with_rng<F, R>(&self, f: F) -> Result<R, Error>
+            where F: FnOnce(&mut Rng) -> R {
+        let mut rng = self.rng.borrow_mut();
+
+        if rng.is_none() {
+            let std_rng = try!(StdRng::new()
+                .map_err(|e| IoError::new(IoMode::Read, Path::new("<entropy>"), e)));
+            *rng = Some(Box::new(std_rng));
+        }
+
+        Ok(f(&mut **rng.as_mut().unwrap()))
+    }
+
+    /// Returns a borrowed reference to the user-defined format directives.
+    pub fn get_format_directives(&self) -> &FormatDirectives {
+        &self.directives
+    }
+
+    /// Returns a borrowed reference to the execution counters
+    /// accumulated during the current top-level invocation.
+    pub fn get_metrics(&self) -> &MetricsCell {
+        &self.metrics
+    }
+
+    /// Returns the flag which, when set, interrupts execution
+    /// within this scope.
+    ///
+    /// The flag may be set from another thread, e.g. by a signal handler
+    /// or a watchdog timer.
+    pub fn get_interrupt(&self) -> &Arc<AtomicBool> {
+        &self.interrupt
+    }
+
+    /// Returns an `Interrupted` error if the interrupt flag has been set.
+    ///
+    /// The flag is cleared when an interrupt is reported, so that
+    /// subsequent execution may proceed normally.
+    pub fn check_interrupt(&self) -> Result<(), ExecError> {
+        if self.interrupt.load(Ordering::Relaxed) &&
+                self.interrupt.swap(false, Ordering::SeqCst) {
+            Err(ExecError::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the restrictions placed on code executed within this scope.
+    pub fn get_restrictions(&self) -> &RestrictConfig {
+        &self.restrict
+    }
+
+    /// Returns the number of instructions which may yet be executed
+    /// during the current top-level invocation, if limited.
+    pub fn remaining_instructions(&self) -> Option<u64> {
+        self.restrict.max_instructions.map(|_| self.instructions.get())
+    }
+
+    /// Restores the full instruction budget given by the restrictions.
+    ///
+    /// This is called at the beginning of each top-level invocation.
+    pub fn reset_instructions(&self) {
+        self.instructions.set(self.restrict.max_instructions.unwrap_or(0));
+    }
+
+    /// Takes up to `n` instructions from the remaining budget, returning
+    /// the number taken, or an `InstructionLimit` error if none remain.
+    ///
+    /// If instructions are not limited, returns `u64::MAX`.
+    pub fn take_instructions(&self, n: u64) -> Result<u64, ExecError> {
+        if self.restrict.max_instructions.is_none() {
+            return Ok(u64::MAX);
+        }
+
+        match self.instructions.get() {
+            0 => Err(ExecError::InstructionLimit),
+            rem => {
+                let n = min(n, rem);
+                self.instructions.set(rem - n);
+                Ok(n)
+            }
+        }
+    }
+
+    /// Removes `n` instructions from the remaining budget, or returns
+    /// an `InstructionLimit` error, leaving no instructions, if fewer remain.
+    ///
+    /// If instructions are not limited, this has no effect.
+    pub fn consume_instructions(&self, n: u64) -> Result<(), ExecError> {
+        if self.restrict.max_instructions.is_none() {
+            return Ok(());
+        }
+
+        match self.instructions.get() {
+            rem if rem < n => {
+                self.instructions.set(0);
+                Err(ExecError::InstructionLimit)
+            }
+            rem => {
+                self.instructions.set(rem - n);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns unused instructions, previously taken with `take_instructions`,
+    /// to the remaining budget.
+    pub fn return_instructions(&self, n: u64) {
+        if self.restrict.max_instructions.is_some() {
+            self.instructions.set(self.instructions.get() + n);
+        }
+    }
+
+    /// Returns the depth of function calls currently in progress
+    /// within this scope.
+    pub fn call_depth(&self) -> usize {
+        self.call_depth.get()
+    }
+
+    /// Sets the depth of function calls currently in progress.
+    ///
+    /// This is updated by the executing `Machine` before calling into
+    /// functions which may reenter execution.
+    pub fn set_call_depth(&self, depth: usize) {
+        self.call_depth.set(depth);
+    }
+
+    /// Returns the number of executions currently in progress within
+    /// this scope, each nested within the last through a Rust function.
+    pub fn native_depth(&self) -> usize {
+        self.native_depth.get()
+    }
+
+    /// Sets the number of executions currently in progress.
+    ///
+    /// This is updated by each `Machine` as it begins and ends execution.
+    pub fn set_native_depth(&self, depth: usize) {
+        self.native_depth.set(depth);
+    }
+
+    /// Returns the location at which the most recent runtime error
+    /// was produced, if it is known.
+    ///
+    /// The location is reset when a new top-level execution begins
+    /// and when an error is caught by `try`.
+    pub fn error_location(&self) -> Option<ErrorLocation> {
+        self.error_location.get()
+    }
+
+    /// Sets the location at which the most recent runtime error was produced.
+    ///
+    /// Setting the location to `None` also clears the error trace.
+    pub fn set_error_location(&self, loc: Option<ErrorLocation>) {
+        self.error_location.set(loc);
+
+        if loc.is_none() {
+            self.error_trace.borrow_mut().clear();
+        }
+    }
+
+    /// Returns the calls which were active when the most recent runtime error
+    /// was produced, innermost first.
+    pub fn error_trace(&self) -> Trace {
+        self.error_trace.borrow().clone()
+    }
+
+    /// Adds the location of a call to the error trace.
+    pub fn push_error_trace(&self, loc: ErrorLocation) {
+        self.error_trace.borrow_mut().push(loc);
+    }
+
+    /// Returns a number identifying the current set of defined values.
+    ///
+    /// The number changes whenever a value is defined or redefined,
+    /// and is never shared by two scopes.
+    pub fn generation(&self) -> usize {
+        self.generation.get()
+    }
+
     /// Returns a borrowed reference to the contained `GlobalIo`.
     pub fn get_io(&self) -> &Rc<GlobalIo> {
         &self.io
@@ -190,11 +633,32 @@ impl GlobalScope {
         &self.modules
     }
 
+    /// Returns a borrowed reference to the contained `ReaderMacros`.
+    pub fn get_readers(&self) -> &Rc<RefCell<ReaderMacros>> {
+        &self.readers
+    }
+
+    /// Configures a `Parser` to parse code for this scope, using the
+    /// contained `ReaderMacros` and the maximum parse depth given by
+    /// the scope's restrictions.
+    pub fn configure_parser(&self, p: &mut Parser) {
+        p.set_readers(self.readers.clone());
+
+        if let Some(depth) = self.restrict.max_parse_depth {
+            p.set_max_depth(depth);
+        }
+    }
+
     /// Returns a borrowed reference to the contained `NameStore`.
     pub fn get_names(&self) -> &Rc<RefCell<NameStore>> {
         &self.name_store
     }
 
+    /// Returns the module version declared for this scope, if any.
+    pub fn get_version(&self) -> Option<ModuleVersion> {
+        self.namespace.borrow().version.clone()
+    }
+
     /// Returns whether the scope contains a given exportable name.
     pub fn contains_name(&self, name: Name) -> bool {
         let ns = self.namespace.borrow();
@@ -229,6 +693,16 @@ impl GlobalScope {
         self.namespace.borrow().values.get(name).cloned()
     }
 
+    /// Calls a closure with a borrowed reference to the `Value` for the
+    /// given name, if present, and returns the result.
+    ///
+    /// The scope is borrowed for the duration of the call, so the closure
+    /// must not define or otherwise modify values in the scope.
+    pub fn with_value<F, R>(&self, name: Name, f: F) -> Option<R>
+            where F: FnOnce(&Value) -> R {
+        self.namespace.borrow().values.get(name).map(f)
+    }
+
     /// Clones all constant values from a scope into this one.
     pub fn import_all_constants(&self, other: &GlobalScope) -> Vec<Name> {
         self.namespace.borrow_mut()
@@ -243,6 +717,7 @@ impl GlobalScope {
 
     /// Clones all exported values from a scope into this scope.
     pub fn import_all_values(&self, other: &GlobalScope) -> Vec<Name> {
+        self.generation.set(next_generation());
         self.namespace.borrow_mut()
             .import_all_values(&other.namespace.borrow())
     }
@@ -258,6 +733,11 @@ impl GlobalScope {
         self.namespace.borrow_mut().exports = Some(names);
     }
 
+    /// Assigns a module version for this scope.
+    pub fn set_version(&self, version: ModuleVersion) {
+        self.namespace.borrow_mut().version = Some(version);
+    }
+
     /// Calls a closure with the borrowed string representation of a name.
     pub fn with_name<F, R>(&self, name: Name, f: F) -> R
             where F: FnOnce(&str) -> R {
@@ -307,8 +787,10 @@ impl Namespace {
             constants: NameMap::new(),
             macros: NameMap::new(),
             values: NameMap::new(),
+            docs: NameMap::new(),
             exports: None,
             imports: Vec::new(),
+            version: None,
         }
     }
 
@@ -324,6 +806,10 @@ impl Namespace {
                 if let Some(m) = other.constants.get(name).cloned() {
                     names.push(name);
                     self.constants.insert(name, m);
+
+                    if let Some(doc) = other.docs.get(name).cloned() {
+                        self.docs.insert(name, doc);
+                    }
                 }
             }
         }
@@ -404,13 +890,10 @@ impl MasterScope {
     }
 
     fn get_function(name: Name) -> Option<Value> {
-        get_system_fn(name).map(|f| Value::Function(Function{
+        get_system_fn(name).map(|&f| Value::Function(Rc::new(Function{
             name: name,
-            sys_fn: SystemFn{
-                arity: f.arity,
-                callback: f.callback,
-            },
-        }))
+            sys_fn: f,
+        })))
     }
 }
 
@@ -23,6 +23,71 @@ pub fn parse_raw_string(s: &str, pos: BytePos) -> Result<(String, usize), ParseE
     r.parse_string()
 }
 
+/// Parses a heredoc string constant.
+///
+/// A heredoc string begins with `#<<` followed by a delimiter, which occupies
+/// the remainder of the line. The string consists of all following lines
+/// up to a line consisting solely of the delimiter. No escape sequences are
+/// processed and the newline preceding the closing delimiter is not included.
+pub fn parse_heredoc(s: &str, pos: BytePos) -> Result<(String, usize), ParseError> {
+    let line_end = match s.find('\n') {
+        Some(n) => n,
+        None => return Err(ParseError::new(span(pos, 0, s.len()),
+            ParseErrorKind::UnterminatedString))
+    };
+
+    let delim = trim_cr(&s[3..line_end]);
+
+    if delim.is_empty() {
+        return Err(ParseError::new(span(pos, 0, 3), ParseErrorKind::InvalidToken));
+    }
+
+    if let Some((i, ch)) = delim.char_indices().find(|&(_, ch)| ch.is_whitespace()) {
+        return Err(ParseError::new(span(pos, 3 + i, 3 + i + ch.len_utf8()),
+            ParseErrorKind::InvalidChar(ch)));
+    }
+
+    let mut res = String::new();
+    let mut offset = line_end + 1;
+
+    loop {
+        let rest = &s[offset..];
+
+        let (line, next) = match rest.find('\n') {
+            Some(n) => (trim_cr(&rest[..n]), Some(offset + n + 1)),
+            None => (rest, None)
+        };
+
+        if line == delim {
+            return Ok((res, offset + delim.len()));
+        }
+
+        match next {
+            Some(next) => {
+                if offset != line_end + 1 {
+                    res.push('\n');
+                }
+                res.push_str(line);
+                offset = next;
+            }
+            None => return Err(ParseError::new(span(pos, 0, line_end),
+                ParseErrorKind::UnterminatedString))
+        }
+    }
+}
+
+fn span(pos: BytePos, lo: usize, hi: usize) -> Span {
+    Span{lo: pos + lo as BytePos, hi: pos + hi as BytePos}
+}
+
+fn trim_cr(s: &str) -> &str {
+    if s.ends_with('\r') {
+        &s[..s.len() - 1]
+    } else {
+        s
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum StringType {
     Char,
@@ -278,8 +343,8 @@ impl<'a> StringReader<'a> {
 
 #[cfg(test)]
 mod test {
-    use parser::ParseError;
-    use super::{StringReader, StringType};
+    use parser::{ParseError, ParseErrorKind};
+    use super::{parse_heredoc, StringReader, StringType};
 
     fn parse_char(s: &str) -> Result<char, ParseError> {
         let mut r = StringReader::new(s, 0, StringType::Char);
@@ -305,4 +370,23 @@ mod test {
         assert_eq!(parse_string(r#"r"foo""#, r).unwrap(), "foo");
         assert_eq!(parse_string(r##"r#""foo""#"##, r).unwrap(), r#""foo""#);
     }
+
+    #[test]
+    fn test_parse_heredoc() {
+        assert_eq!(parse_heredoc("#<<END\nfoo \"bar\"\n\\n\nEND", 0).unwrap(),
+            ("foo \"bar\"\n\\n".to_owned(), 23));
+        assert_eq!(parse_heredoc("#<<END\r\nfoo\r\nEND\r\n", 0).unwrap(),
+            ("foo".to_owned(), 16));
+        assert_eq!(parse_heredoc("#<<END\nEND", 0).unwrap(),
+            ("".to_owned(), 10));
+        assert_eq!(parse_heredoc("#<<END\nfoo\n END\nEND\n)", 0).unwrap(),
+            ("foo\n END".to_owned(), 19));
+
+        assert_eq!(parse_heredoc("#<<\nfoo", 0).unwrap_err().kind,
+            ParseErrorKind::InvalidToken);
+        assert_eq!(parse_heredoc("#<<E D\nfoo", 0).unwrap_err().kind,
+            ParseErrorKind::InvalidChar(' '));
+        assert_eq!(parse_heredoc("#<<END\nfoo\nEN", 0).unwrap_err().kind,
+            ParseErrorKind::UnterminatedString);
+    }
 }
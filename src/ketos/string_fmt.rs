@@ -2,11 +2,14 @@
 
 use std::ascii::AsciiExt;
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::RefCell;
 use std::cmp::max;
+use std::collections::HashMap;
 use std::f64;
 use std::fmt::{self, Write};
 use std::iter::repeat;
 use std::mem::transmute;
+use std::rc::Rc;
 use std::str::CharIndices;
 
 use num::{One, Signed, ToPrimitive};
@@ -15,6 +18,7 @@ use exec::ExecError;
 use integer::Integer;
 use lexer::{BytePos, Span};
 use name::{debug_names, display_names, NameDebug, NameDisplay, NameStore};
+use rc_string::RcString;
 use value::Value;
 
 /// Represents an error in formatting a string.
@@ -64,6 +68,10 @@ pub enum FormatError {
         /// Type found
         found: &'static str,
     },
+    /// Named argument not supplied to format string
+    UndefinedArgument(Box<str>),
+    /// Named argument given to a directive which does not consume a value
+    UnexpectedName,
     /// Unrecognized directive character
     UnrecognizedDirective(char),
 }
@@ -101,21 +109,295 @@ impl fmt::Display for FormatError {
             FormatError::ExtraBranch => f.write_str("extraneous branch directive"),
             FormatError::TypeError{expected, found} =>
                 write!(f, "expected {}; found {}", expected, found),
+            FormatError::UndefinedArgument(ref name) => write!(f, "no argument named `{}`", name),
+            FormatError::UnexpectedName => f.write_str("directive does not accept a named argument"),
             FormatError::UnrecognizedDirective(ch) => write!(f, "unrecognized directive `{}`", ch),
         }
     }
 }
 
+/// Parameter value passed to a user-defined format directive
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FormatParam {
+    /// Integer parameter; also produced by `#` and by `v`
+    /// when the consumed argument is an integer
+    Integer(i32),
+    /// Character parameter; also produced by `v`
+    /// when the consumed argument is a character
+    Char(char),
+}
+
+/// Describes an occurrence of a user-defined directive in a format string
+#[derive(Copy, Clone, Debug)]
+pub struct CustomDirective<'a> {
+    /// Directive character
+    pub command: char,
+    /// Directive parameters; `None` for each omitted parameter
+    pub params: &'a [Option<FormatParam>],
+    /// Whether the `@` flag is present
+    pub at: bool,
+    /// Whether the `:` flag is present
+    pub colon: bool,
+}
+
+/// Function which formats the argument of a user-defined directive
+pub type DirectiveFn = Fn(&NameStore, &Value, &CustomDirective)
+    -> Result<String, FormatError>;
+
+/// Holds user-defined format directives.
+///
+/// Each directive consumes a single argument, which may be named,
+/// and is formatted by a function supplied by the embedding program.
+///
+/// ```
+/// use ketos::{FromValue, Interpreter};
+/// use ketos::name::display_names;
+///
+/// let interp = Interpreter::new();
+///
+/// interp.get_scope().get_format_directives().add('q', |names, v, _dir| {
+///     Ok(format!("<{}>", display_names(names, v)))
+/// });
+///
+/// let v = interp.run_single_expr(r#"(format "~q" 123)"#, None).unwrap();
+/// assert_eq!(String::from_value(v).unwrap(), "<123>");
+/// ```
+#[derive(Clone, Default)]
+pub struct FormatDirectives {
+    directives: RefCell<HashMap<char, Rc<DirectiveFn>>>,
+}
+
+impl FormatDirectives {
+    /// Creates an empty set of directives.
+    pub fn new() -> FormatDirectives {
+        FormatDirectives::default()
+    }
+
+    /// Adds a directive, replacing any previous directive for the
+    /// same character. Directive characters are case-insensitive.
+    ///
+    /// # Panics
+    ///
+    /// If `command` is the character of a builtin directive.
+    pub fn add<F>(&self, command: char, f: F)
+            where F: Fn(&NameStore, &Value, &CustomDirective)
+                -> Result<String, FormatError> + 'static {
+        let command = command.to_ascii_lowercase();
+
+        if is_builtin_directive(command) {
+            panic!("cannot redefine builtin format directive `{}`", command);
+        }
+
+        self.directives.borrow_mut().insert(command, Rc::new(f));
+    }
+
+    /// Returns the function for the given directive character, if defined.
+    pub fn get(&self, command: char) -> Option<Rc<DirectiveFn>> {
+        self.directives.borrow().get(&command).cloned()
+    }
+}
+
 /// Constructs a formatted string using given the format `fmt` and input values.
 pub fn format_string(names: &NameStore, fmt: &str, values: &[Value])
         -> Result<String, ExecError> {
     let mut buf = String::new();
-    let mut fmter = StringFormatter::new(fmt, names, values);
-    try!(fmter.format_string(&mut buf));
-    try!(fmter.finish());
+    try!(format_string_to(&mut buf, names, fmt, values));
     Ok(buf)
 }
 
+/// Writes a formatted string, using the format `fmt` and input values,
+/// to the end of the given buffer.
+pub fn format_string_to(buf: &mut String, names: &NameStore, fmt: &str,
+        values: &[Value]) -> Result<(), ExecError> {
+    format_with_directives(buf, names, None, fmt, values)
+}
+
+fn format_with_directives(buf: &mut String, names: &NameStore,
+        directives: Option<&FormatDirectives>, fmt: &str,
+        values: &[Value]) -> Result<(), ExecError> {
+    let (values, named) = if uses_named_args(fmt) {
+        split_named_args(values)
+    } else {
+        (values, &[][..])
+    };
+
+    let mut fmter = StringFormatter::new(fmt, names, values);
+    fmter.named = named;
+    fmter.directives = directives;
+    try!(fmter.format_string(buf));
+    fmter.finish()
+}
+
+/// Returns whether the format string contains any directive which refers
+/// to an argument by name.
+fn uses_named_args(fmt: &str) -> bool {
+    let mut chars = fmt.char_indices();
+
+    while let Some((_, ch)) = chars.next() {
+        if ch == '~' {
+            match chars.clone().next() {
+                Some((_, '~')) => { chars.next(); }
+                Some((ind, _)) => if parse_arg_name(&fmt[ind..]).is_some() {
+                    return true;
+                },
+                None => ()
+            }
+        }
+    }
+
+    false
+}
+
+/// Parses an argument name, `` `name` ``, at the beginning of the string.
+/// Returns the name and the length of the specifier, including backticks.
+fn parse_arg_name(s: &str) -> Option<(&str, usize)> {
+    if !s.starts_with('`') {
+        return None;
+    }
+
+    for (ind, ch) in s[1..].char_indices() {
+        match ch {
+            '`' if ind != 0 => return Some((&s[1..ind + 1], ind + 2)),
+            '`' | '~' => return None,
+            ch if ch.is_whitespace() => return None,
+            _ => ()
+        }
+    }
+
+    None
+}
+
+/// Separates trailing `:name value` pairs from positional arguments.
+fn split_named_args(values: &[Value]) -> (&[Value], &[Value]) {
+    let mut start = values.len();
+
+    while start >= 2 {
+        match values[start - 2] {
+            Value::Keyword(_) => start -= 2,
+            _ => break
+        }
+    }
+
+    values.split_at(start)
+}
+
+/// Returns whether the directive command may refer to an argument by name.
+fn accepts_named_arg(command: char) -> bool {
+    match command {
+        'a' | 's' | 'c' | 'f' | 'e' | 'r' | 'd' | 'b' | 'o' | 'x' => true,
+        ch => !is_builtin_directive(ch)
+    }
+}
+
+/// Format string which has been parsed in advance of formatting.
+///
+/// Formatting values with a `CompiledFormat` avoids parsing the format string
+/// on each use. Format strings containing grouping directives, such as
+/// `~[...~]` or `~{...~}`, are not parsed in advance and are instead
+/// formatted as by `format_string`.
+#[derive(Clone, Debug)]
+pub struct CompiledFormat {
+    fmt: RcString,
+    pieces: Option<Vec<Piece>>,
+    named: bool,
+}
+
+impl CompiledFormat {
+    /// Parses a format string.
+    ///
+    /// Errors in the format string are not reported until it is used.
+    pub fn new(fmt: RcString) -> CompiledFormat {
+        let pieces = {
+            let names = NameStore::new();
+            StringFormatter::new(&fmt, &names, &[]).compile()
+        };
+
+        let named = uses_named_args(&fmt);
+
+        CompiledFormat{
+            fmt: fmt,
+            pieces: pieces,
+            named: named,
+        }
+    }
+
+    /// Returns the format string.
+    pub fn format_str(&self) -> &str {
+        &self.fmt
+    }
+
+    /// Writes a formatted string, using the given input values,
+    /// to the end of the given buffer.
+    pub fn format_to(&self, buf: &mut String, names: &NameStore, values: &[Value])
+            -> Result<(), ExecError> {
+        self.format_to_with(buf, names, None, values)
+    }
+
+    /// Writes a formatted string, using the given input values, to the end
+    /// of the given buffer. User-defined directives are formatted using
+    /// the functions in `directives`.
+    pub fn format_to_with(&self, buf: &mut String, names: &NameStore,
+            directives: Option<&FormatDirectives>, values: &[Value])
+            -> Result<(), ExecError> {
+        match self.pieces {
+            Some(ref pieces) => {
+                let (values, named) = if self.named {
+                    split_named_args(values)
+                } else {
+                    (values, &[][..])
+                };
+
+                let mut fmter = StringFormatter::new(&self.fmt, names, values);
+                fmter.named = named;
+                fmter.directives = directives;
+                try!(fmter.format_pieces(pieces, buf));
+                fmter.finish()
+            }
+            None => format_with_directives(buf, names, directives, &self.fmt, values)
+        }
+    }
+}
+
+/// Maximum number of entries held by a `FormatCache`
+const MAX_CACHED_FORMATS: usize = 64;
+
+/// Holds `CompiledFormat` values for recently used format strings.
+///
+/// Entries are keyed by the address of the string buffer, rather than its
+/// contents, so that a format string held in a code object's constants is
+/// found without hashing its contents. Each entry holds a reference to the
+/// buffer, ensuring that the address is not reused for a different string.
+#[derive(Default)]
+pub struct FormatCache {
+    formats: RefCell<HashMap<(usize, usize), Rc<CompiledFormat>>>,
+}
+
+impl FormatCache {
+    /// Creates an empty `FormatCache`.
+    pub fn new() -> FormatCache {
+        FormatCache::default()
+    }
+
+    /// Returns a `CompiledFormat` for the given format string,
+    /// parsing it if it is not yet present in the cache.
+    pub fn get(&self, fmt: &RcString) -> Rc<CompiledFormat> {
+        let key = (fmt.as_ptr() as usize, fmt.len());
+        let mut formats = self.formats.borrow_mut();
+
+        if let Some(f) = formats.get(&key) {
+            return f.clone();
+        }
+
+        if formats.len() >= MAX_CACHED_FORMATS {
+            formats.clear();
+        }
+
+        let f = Rc::new(CompiledFormat::new(fmt.clone()));
+        formats.insert(key, f.clone());
+        f
+    }
+}
+
 struct StringFormatter<'fmt, 'names, 'value> {
     /// Input format string; contains a substring for subgroups
     fmt: &'fmt str,
@@ -123,7 +405,11 @@ struct StringFormatter<'fmt, 'names, 'value> {
     full_fmt: &'fmt str,
     chars: CharIndices<'fmt>,
     values: &'value [Value],
+    /// Trailing `:name value` pairs, referenced by ``~`name` `` directives
+    named: &'value [Value],
     names: &'names NameStore,
+    /// User-defined directives
+    directives: Option<&'names FormatDirectives>,
     /// Stack of group specifiers
     groups: Vec<(Group, Span)>,
     /// Last group-closing directive
@@ -174,6 +460,8 @@ impl Group {
 
 #[derive(Copy, Clone, Debug)]
 struct Directive<'fmt> {
+    /// Argument name, given as ``~`name` ``
+    name: Option<&'fmt str>,
     at: bool,
     colon: bool,
     command: char,
@@ -181,6 +469,42 @@ struct Directive<'fmt> {
     span: Span,
 }
 
+/// Returns whether the directive command may be run without affecting
+/// the processing of any other part of the format string.
+fn is_simple_directive(command: char) -> bool {
+    match command {
+        'a' | 's' | 'c' | 'f' | 'e' | 'r' | 'd' | 'b' | 'o' | 'x' |
+        'p' | 't' | '?' | '*' | '|' | '%' | '&' | '~' => true,
+        _ => false
+    }
+}
+
+/// Returns whether the directive command is defined by the formatter,
+/// rather than by a user-defined `FormatDirectives` entry.
+fn is_builtin_directive(command: char) -> bool {
+    is_simple_directive(command) || match command {
+        '<' | '>' | '(' | ')' | '[' | ']' | '{' | '}' | '^' | ';' | '`' |
+        '\n' => true,
+        _ => false
+    }
+}
+
+/// Part of a format string parsed by `CompiledFormat`
+#[derive(Copy, Clone, Debug)]
+enum Piece {
+    /// Literal text, as a range of bytes
+    Literal(usize, usize),
+    /// Simple directive; fields are a range of bytes
+    Directive{
+        name: Option<(usize, usize)>,
+        at: bool,
+        colon: bool,
+        command: char,
+        fields: (usize, usize),
+        span: Span,
+    },
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Field {
     Empty,
@@ -254,7 +578,9 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
             full_fmt: s,
             chars: s.char_indices(),
             values: values,
+            named: &[],
             names: names,
+            directives: None,
             groups: Vec::new(),
             close_dir: None,
             terminate: false,
@@ -270,6 +596,8 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
             -> StringFormatter<'fmt, 'names, 'value> {
         StringFormatter{
             full_fmt: self.full_fmt,
+            named: self.named,
+            directives: self.directives,
             .. StringFormatter::new(s, self.names, values)
         }
     }
@@ -285,21 +613,12 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
                 '~' => {
                     let dir = try!(self.parse_directive());
 
+                    if is_simple_directive(dir.command) {
+                        try!(self.format_simple(&dir, buf));
+                        continue;
+                    }
+
                     match dir.command {
-                        'a' => try!(self.format_aesthetic(&dir, buf)),
-                        's' => try!(self.format_standard(&dir, buf)),
-                        'c' => try!(self.format_character(&dir, buf)),
-                        'f' => try!(self.format_float(&dir, buf)),
-                        'e' => try!(self.format_exponent(&dir, buf)),
-                        'r' => try!(self.format_radix(&dir, buf)),
-                        'd' => try!(self.format_integer(&dir, buf, 10)),
-                        'b' => try!(self.format_integer(&dir, buf, 2)),
-                        'o' => try!(self.format_integer(&dir, buf, 8)),
-                        'x' => try!(self.format_integer(&dir, buf, 16)),
-                        'p' => try!(self.format_plural(&dir, buf)),
-                        't' => try!(self.format_tab(&dir, buf)),
-                        '?' => try!(self.process_indirection(&dir, buf)),
-                        '*' => try!(self.process_goto(&dir)),
                         '<' => {
                             try!(self.process_justification(&dir, buf));
                             if self.terminate {
@@ -349,13 +668,8 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
                             return Err(self.error(dir.span,
                                 FormatError::MisplacedDirective));
                         },
-                        '|' => try!(self.repeat_char(&dir, buf, '\x0c')),
-                        '%' => try!(self.repeat_char(&dir, buf, '\n')),
-                        '&' => try!(self.fresh_line(&dir, buf)),
                         '\n' => self.consume_whitespace(),
-                        '~' => try!(self.repeat_char(&dir, buf, '~')),
-                        ch => return Err(self.error(self.span_one(),
-                            FormatError::UnrecognizedDirective(ch)))
+                        _ => try!(self.format_custom(&dir, buf))
                     }
                 }
                 ch => buf.push(ch),
@@ -365,6 +679,111 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         Ok(())
     }
 
+    /// Runs a directive which does not affect the processing of any other
+    /// part of the format string; see `is_simple_directive`.
+    fn format_simple(&mut self, dir: &Directive, buf: &mut String)
+            -> Result<(), ExecError> {
+        match dir.command {
+            'a' => self.format_aesthetic(dir, buf),
+            's' => self.format_standard(dir, buf),
+            'c' => self.format_character(dir, buf),
+            'f' => self.format_float(dir, buf),
+            'e' => self.format_exponent(dir, buf),
+            'r' => self.format_radix(dir, buf),
+            'd' => self.format_integer(dir, buf, 10),
+            'b' => self.format_integer(dir, buf, 2),
+            'o' => self.format_integer(dir, buf, 8),
+            'x' => self.format_integer(dir, buf, 16),
+            'p' => self.format_plural(dir, buf),
+            't' => self.format_tab(dir, buf),
+            '?' => self.process_indirection(dir, buf),
+            '*' => self.process_goto(dir),
+            '|' => self.repeat_char(dir, buf, '\x0c'),
+            '%' => self.repeat_char(dir, buf, '\n'),
+            '&' => self.fresh_line(dir, buf),
+            '~' => self.repeat_char(dir, buf, '~'),
+            _ => self.format_custom(dir, buf)
+        }
+    }
+
+    /// Parses the format string into a series of `Piece` values.
+    ///
+    /// Returns `None` if the format string contains a grouping or termination
+    /// directive or cannot be parsed; such a string must be formatted by
+    /// `format_string`.
+    fn compile(&mut self) -> Option<Vec<Piece>> {
+        let mut pieces = Vec::new();
+        let mut lit_start = 0;
+
+        while let Some(ch) = self.consume_char() {
+            if ch != '~' {
+                continue;
+            }
+
+            if lit_start != self.last_index {
+                pieces.push(Piece::Literal(lit_start, self.last_index));
+            }
+
+            let dir = match self.parse_directive() {
+                Ok(dir) => dir,
+                Err(_) => return None
+            };
+
+            if is_simple_directive(dir.command) || !is_builtin_directive(dir.command) {
+                let start = substr_offset(dir.fields, self.fmt);
+
+                let name = dir.name.map(|name| {
+                    let start = substr_offset(name, self.fmt);
+                    (start, start + name.len())
+                });
+
+                pieces.push(Piece::Directive{
+                    name: name,
+                    at: dir.at,
+                    colon: dir.colon,
+                    command: dir.command,
+                    fields: (start, start + dir.fields.len()),
+                    span: dir.span,
+                });
+            } else if dir.command == '\n' {
+                self.consume_whitespace();
+            } else {
+                return None;
+            }
+
+            lit_start = self.end_index;
+        }
+
+        if lit_start != self.fmt.len() {
+            pieces.push(Piece::Literal(lit_start, self.fmt.len()));
+        }
+
+        Some(pieces)
+    }
+
+    /// Formats using pieces produced by `compile`.
+    fn format_pieces(&mut self, pieces: &[Piece], buf: &mut String)
+            -> Result<(), ExecError> {
+        for piece in pieces {
+            match *piece {
+                Piece::Literal(start, end) => buf.push_str(&self.fmt[start..end]),
+                Piece::Directive{name, at, colon, command, fields, span} => {
+                    let dir = Directive{
+                        name: name.map(|name| &self.fmt[name.0..name.1]),
+                        at: at,
+                        colon: colon,
+                        command: command,
+                        fields: &self.fmt[fields.0..fields.1],
+                        span: span,
+                    };
+                    try!(self.format_simple(&dir, buf));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn check_open_groups(&self) -> Result<(), ExecError> {
         match self.groups.last() {
             None => Ok(()),
@@ -399,6 +818,18 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
 
     fn parse_directive(&mut self) -> Result<Directive<'fmt>, ExecError> {
         let start = self.last_index;
+
+        let name = match parse_arg_name(&self.fmt[self.end_index..]) {
+            Some((name, len)) => {
+                let end = self.end_index + len;
+                while self.end_index < end {
+                    self.consume_char();
+                }
+                Some(name)
+            }
+            None => None
+        };
+
         let field_start = self.end_index;
 
         'fields: loop {
@@ -465,10 +896,18 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
                     colon = true;
                 }
                 Some(ch) => {
+                    let command = ch.to_ascii_lowercase();
+
+                    if name.is_some() && !accepts_named_arg(command) {
+                        return Err(self.error(self.span_start(start),
+                            FormatError::UnexpectedName));
+                    }
+
                     return Ok(Directive{
+                        name: name,
                         at: at,
                         colon: colon,
-                        command: ch.to_ascii_lowercase(),
+                        command: command,
                         fields: fields,
                         span: self.span_start(start),
                     })
@@ -486,12 +925,18 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         let col_inc = try!(self.get_u32_field(&mut fields, dir.span)).unwrap_or(0);
         let min_pad = try!(self.get_u32_field(&mut fields, dir.span)).unwrap_or(0);
         let pad_char = try!(self.get_char_field(&mut fields, dir.span)).unwrap_or(' ');
+        let max_col = try!(self.get_u32_field(&mut fields, dir.span));
         try!(self.no_fields(fields, dir.span));
 
-        let arg = try!(self.consume_arg(dir.span));
+        let arg = try!(self.consume_dir_arg(dir));
+
+        let mut s = display_names(self.names, arg).to_string();
 
-        let s = display_names(self.names, arg).to_string();
-        pad_str(buf, &s, min_col, col_inc, min_pad, pad_char, dir.at);
+        if let Some(n) = max_col {
+            truncate_chars(&mut s, n);
+        }
+
+        pad_str(buf, &s, min_col, col_inc, min_pad, pad_char, text_align(dir));
         Ok(())
     }
 
@@ -502,11 +947,17 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         let col_inc = try!(self.get_u32_field(&mut fields, dir.span)).unwrap_or(0);
         let min_pad = try!(self.get_u32_field(&mut fields, dir.span)).unwrap_or(0);
         let pad_char = try!(self.get_char_field(&mut fields, dir.span)).unwrap_or(' ');
+        let max_col = try!(self.get_u32_field(&mut fields, dir.span));
         try!(self.no_fields(fields, dir.span));
 
-        let arg = try!(self.consume_arg(dir.span));
-        let s = debug_names(self.names, arg).to_string();
-        pad_str(buf, &s, min_col, col_inc, min_pad, pad_char, dir.at);
+        let arg = try!(self.consume_dir_arg(dir));
+        let mut s = debug_names(self.names, arg).to_string();
+
+        if let Some(n) = max_col {
+            truncate_chars(&mut s, n);
+        }
+
+        pad_str(buf, &s, min_col, col_inc, min_pad, pad_char, text_align(dir));
         Ok(())
     }
 
@@ -515,7 +966,7 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         // TODO: ':' flag prints name for special characters, e.g. "Space";
         // '@' flag prints name in literal format, e.g. "#\Space"
         try!(self.no_fields(FieldParser::new(dir.fields), dir.span));
-        let arg = try!(self.consume_arg(dir.span));
+        let arg = try!(self.consume_dir_arg(dir));
 
         let ch = match *arg {
             Value::Char(ch) => ch,
@@ -526,6 +977,36 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         Ok(())
     }
 
+    fn format_custom(&mut self, dir: &Directive, buf: &mut String)
+            -> Result<(), ExecError> {
+        let f = match self.directives.and_then(|d| d.get(dir.command)) {
+            Some(f) => f,
+            None => return Err(self.error(dir.span,
+                FormatError::UnrecognizedDirective(dir.command)))
+        };
+
+        let mut fields = FieldParser::new(dir.fields);
+        let mut params = Vec::new();
+
+        while let Some(param) = try!(self.get_param_field(&mut fields, dir.span)) {
+            params.push(param);
+        }
+
+        let arg = try!(self.consume_dir_arg(dir));
+
+        let custom = CustomDirective{
+            command: dir.command,
+            params: &params,
+            at: dir.at,
+            colon: dir.colon,
+        };
+
+        let s = try!(f(self.names, arg, &custom)
+            .map_err(|e| self.error(dir.span, e)));
+        buf.push_str(&s);
+        Ok(())
+    }
+
     fn no_fields(&self, mut fields: FieldParser, span: Span) -> Result<(), ExecError> {
         match fields.next() {
             Ok(None) => Ok(()),
@@ -618,28 +1099,30 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         let width = try!(self.get_u32_field(&mut fields, dir.span));
         let prec = try!(self.get_u32_field(&mut fields, dir.span));
         let pad_char = try!(self.get_char_field(&mut fields, dir.span)).unwrap_or(' ');
+        let comma = try!(self.get_char_field(&mut fields, dir.span));
+        let comma_interval = try!(self.get_u32_field(&mut fields, dir.span)).unwrap_or(3);
         try!(self.no_fields(fields, dir.span));
 
-        let arg = try!(self.consume_arg(dir.span));
+        let arg = try!(self.consume_dir_arg(dir));
         let f = try!(self.get_float(arg, dir.span));
 
-        // This is horrible and hacky because there's no way I'm implementing
-        // float formatting myself. Deal with it.
-        let s = match (dir.at, width, prec) {
-            (false, None,    None   ) => format!("{}",        f),
-            (false, Some(w), None   ) => format!("{:1$}",     f, w as usize),
-            (false, None,    Some(p)) => format!("{:.1$}",    f,             p as usize),
-            (false, Some(w), Some(p)) => format!("{:1$.2$}",  f, w as usize, p as usize),
-            (true,  None,    None   ) => format!("{:+}",      f),
-            (true,  Some(w), None   ) => format!("{:+1$}",    f, w as usize),
-            (true,  None,    Some(p)) => format!("{:+.1$}",   f,             p as usize),
-            (true,  Some(w), Some(p)) => format!("{:+1$.2$}", f, w as usize, p as usize),
+        let mut s = match (dir.at, prec) {
+            (false, None   ) => format!("{}",      f),
+            (false, Some(p)) => format!("{:.1$}",  f, p as usize),
+            (true,  None   ) => format!("{:+}",    f),
+            (true,  Some(p)) => format!("{:+.1$}", f, p as usize),
         };
 
-        if pad_char != ' ' {
-            buf.extend(s.chars().map(|ch| if ch == ' ' { pad_char } else { ch }));
+        if let Some(comma) = comma {
+            let start = if f.is_sign_negative() || dir.at { 1 } else { 0 };
+            let end = s.find('.').unwrap_or(s.len());
+            group_digits(&mut s, start, end, comma, comma_interval);
+        }
+
+        if dir.colon {
+            pad_str(buf, &s, width.unwrap_or(0), 0, 0, pad_char, Align::Left);
         } else {
-            buf.push_str(&s);
+            pad_number(buf, &s, width.unwrap_or(0), pad_char);
         }
 
         Ok(())
@@ -653,26 +1136,20 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         let pad_char = try!(self.get_char_field(&mut fields, dir.span)).unwrap_or(' ');
         try!(self.no_fields(fields, dir.span));
 
-        let arg = try!(self.consume_arg(dir.span));
+        let arg = try!(self.consume_dir_arg(dir));
         let f = try!(self.get_float(arg, dir.span));
 
-        // This is horrible and hacky because there's no way I'm implementing
-        // float formatting myself. Deal with it.
-        let s = match (dir.at, width, prec) {
-            (false, None,    None   ) => format!("{:e}",       f),
-            (false, Some(w), None   ) => format!("{:1$e}",     f, w as usize),
-            (false, None,    Some(p)) => format!("{:.1$e}",    f,             p as usize),
-            (false, Some(w), Some(p)) => format!("{:1$.2$e}",  f, w as usize, p as usize),
-            (true,  None,    None   ) => format!("{:+e}",      f),
-            (true,  Some(w), None   ) => format!("{:+1$e}",    f, w as usize),
-            (true,  None,    Some(p)) => format!("{:+.1$e}",   f,             p as usize),
-            (true,  Some(w), Some(p)) => format!("{:+1$.2$e}", f, w as usize, p as usize),
+        let s = match (dir.at, prec) {
+            (false, None   ) => format!("{:e}",     f),
+            (false, Some(p)) => format!("{:.1$e}",  f, p as usize),
+            (true,  None   ) => format!("{:+e}",    f),
+            (true,  Some(p)) => format!("{:+.1$e}", f, p as usize),
         };
 
-        if pad_char != ' ' {
-            buf.extend(s.chars().map(|ch| if ch == ' ' { pad_char } else { ch }));
+        if dir.colon {
+            pad_str(buf, &s, width.unwrap_or(0), 0, 0, pad_char, Align::Left);
         } else {
-            buf.push_str(&s);
+            pad_number(buf, &s, width.unwrap_or(0), pad_char);
         }
 
         Ok(())
@@ -691,7 +1168,7 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
             None => ()
         }
 
-        let arg = try!(self.consume_arg(dir.span));
+        let arg = try!(self.consume_dir_arg(dir));
         let i = try!(self.get_integer(arg, dir.span));
 
         let n = match (dir.at, i.to_u32()) {
@@ -723,17 +1200,14 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         let comma_interval = try!(self.get_u32_field(&mut fields, dir.span)).unwrap_or(3);
         try!(self.no_fields(fields, dir.span));
 
-        let arg = try!(self.consume_arg(dir.span));
+        let arg = try!(self.consume_dir_arg(dir));
         let i = try!(self.get_integer(arg, dir.span));
 
         let mut s = i.abs().to_str_radix(radix);
 
         if dir.colon {
-            let mut n = s.len();
-            while n > comma_interval as usize {
-                n -= comma_interval as usize;
-                s.insert(n, comma);
-            }
+            let end = s.len();
+            group_digits(&mut s, 0, end, comma, comma_interval);
         }
 
         if dir.at || i.is_negative() {
@@ -741,7 +1215,7 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
             s.insert(0, sign);
         }
 
-        pad_str(buf, &s, min_col, 0, 0, pad_char, true);
+        pad_number(buf, &s, min_col, pad_char);
 
         Ok(())
     }
@@ -792,6 +1266,25 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         }
     }
 
+    /// Consumes the value argument of a directive; either the argument
+    /// named by the directive or the next positional argument.
+    fn consume_dir_arg(&mut self, dir: &Directive) -> Result<&'value Value, ExecError> {
+        let name = match dir.name {
+            Some(name) => name,
+            None => return self.consume_arg(dir.span)
+        };
+
+        for pair in self.named.chunks(2) {
+            if let Value::Keyword(kw) = pair[0] {
+                if self.names.get(kw) == name {
+                    return Ok(&pair[1]);
+                }
+            }
+        }
+
+        Err(self.error(dir.span, FormatError::UndefinedArgument(name.into())))
+    }
+
     fn inside_group(&self, group: Group) -> bool {
         match self.groups.last() {
             Some(&(grp, _)) => grp == group,
@@ -1487,6 +1980,34 @@ impl<'fmt, 'names, 'value> StringFormatter<'fmt, 'names, 'value> {
         }
     }
 
+    /// Parses a field for a user-defined directive.
+    /// Returns `None` when no fields remain.
+    fn get_param_field(&mut self, fields: &mut FieldParser, span: Span)
+            -> Result<Option<Option<FormatParam>>, ExecError> {
+        let r = fields.next().map_err(|e| self.error(span, e));
+
+        match try!(r) {
+            None => Ok(None),
+            Some(Field::Empty) => Ok(Some(None)),
+            Some(Field::Integer(i)) => Ok(Some(Some(FormatParam::Integer(i)))),
+            Some(Field::Char(ch)) => Ok(Some(Some(FormatParam::Char(ch)))),
+            Some(Field::ArgCount) => match (self.args_left() as u64).to_i32() {
+                Some(i) => Ok(Some(Some(FormatParam::Integer(i)))),
+                None => Err(self.error(span, FormatError::FieldOverflow))
+            },
+            Some(Field::ArgValue) => {
+                let arg = try!(self.consume_arg(span));
+                match *arg {
+                    Value::Char(ch) => Ok(Some(Some(FormatParam::Char(ch)))),
+                    ref v => match try!(self.get_integer(v, span)).to_i32() {
+                        Some(i) => Ok(Some(Some(FormatParam::Integer(i)))),
+                        None => Err(self.error(span, FormatError::FieldOverflow))
+                    }
+                }
+            }
+        }
+    }
+
     fn get_u32_field(&mut self, fields: &mut FieldParser, span: Span)
             -> Result<Option<u32>, ExecError> {
         let r = fields.next().map_err(|e| self.error(span, e));
@@ -1583,12 +2104,29 @@ fn substr_offset(child: &str, parent: &str) -> usize {
     child.as_ptr() as usize - parent.as_ptr() as usize
 }
 
+/// Position of formatted text within padding
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// Returns the alignment requested for `a` and `s` directives.
+fn text_align(dir: &Directive) -> Align {
+    match (dir.at, dir.colon) {
+        (_, true) => Align::Center,
+        (true, false) => Align::Right,
+        (false, false) => Align::Left,
+    }
+}
+
 fn pad_str(buf: &mut String, s: &str, min_col: u32, col_inc: u32,
-        min_pad: u32, pad_char: char, left: bool) {
+        min_pad: u32, pad_char: char, align: Align) {
     if min_col == 0 && min_pad == 0 {
         buf.push_str(s);
     } else {
-        let pad = min_col.saturating_sub(s.len() as u32);
+        let pad = min_col.saturating_sub(s.chars().count() as u32);
         let pad = max(min_pad, pad);
 
         let n = if col_inc > 1 {
@@ -1597,13 +2135,48 @@ fn pad_str(buf: &mut String, s: &str, min_col: u32, col_inc: u32,
             pad
         };
 
-        if left {
-            buf.extend(repeat(pad_char).take(n as usize));
-            buf.push_str(&s);
-        } else {
-            buf.push_str(&s);
-            buf.extend(repeat(pad_char).take(n as usize));
-        }
+        let (before, after) = match align {
+            Align::Left => (0, n),
+            Align::Right => (n, 0),
+            Align::Center => (n / 2, n - n / 2),
+        };
+
+        buf.extend(repeat(pad_char).take(before as usize));
+        buf.push_str(s);
+        buf.extend(repeat(pad_char).take(after as usize));
+    }
+}
+
+/// Left-pads a formatted float. When padding with `'0'`,
+/// padding is inserted between the sign and the digits.
+fn pad_number(buf: &mut String, s: &str, min_col: u32, pad_char: char) {
+    if pad_char == '0' && (s.starts_with('-') || s.starts_with('+')) {
+        buf.push_str(&s[..1]);
+        pad_str(buf, &s[1..], min_col.saturating_sub(1), 0, 0, pad_char, Align::Right);
+    } else {
+        pad_str(buf, s, min_col, 0, 0, pad_char, Align::Right);
+    }
+}
+
+/// Inserts `comma` between every group of `interval` digits
+/// in the range `start..end`, beginning from the end of the range.
+fn group_digits(s: &mut String, start: usize, end: usize,
+        comma: char, interval: u32) {
+    if interval == 0 {
+        return;
+    }
+
+    let mut n = end;
+    while n - start > interval as usize {
+        n -= interval as usize;
+        s.insert(n, comma);
+    }
+}
+
+/// Truncates a string to at most `n` characters.
+fn truncate_chars(s: &mut String, n: u32) {
+    if let Some((idx, _)) = s.char_indices().nth(n as usize) {
+        s.truncate(idx);
     }
 }
 
@@ -0,0 +1,139 @@
+//! Discovers and runs tests defined in `ketos` code.
+//!
+//! A test is a function taking no arguments whose name begins with `test-`,
+//! such as one defined using the `deftest` macro of the builtin `test` module.
+//! A test passes if it returns without error.
+//!
+//! ```
+//! use ketos::Interpreter;
+//! use ketos::testing::run_tests;
+//!
+//! let interp = Interpreter::new();
+//!
+//! interp.run_code(r#"
+//!     (use test :all :macro :all)
+//!
+//!     (deftest addition
+//!       (assert-eq (+ 1 2) 3))
+//!
+//!     (deftest failure
+//!       (assert-eq (+ 1 2) 4))
+//!     "#, None).unwrap();
+//!
+//! let report = run_tests(&interp);
+//!
+//! assert_eq!(report.passed(), 1);
+//! assert_eq!(report.failed(), 1);
+//! assert!(!report.is_success());
+//! ```
+
+use exec::call_function;
+use interpreter::Interpreter;
+use mod_test::TEST_PREFIX;
+use name::Name;
+use scope::Scope;
+use value::Value;
+
+/// Result of running a single test
+#[derive(Clone, Debug)]
+pub struct TestResult {
+    /// Name of the module containing the test, or `None` if the test
+    /// was defined in the interpreter's global scope
+    pub module: Option<String>,
+    /// Name of the test function
+    pub name: String,
+    /// Formatted error message, if the test failed
+    pub error: Option<String>,
+}
+
+impl TestResult {
+    /// Returns whether the test passed.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Results of a series of tests
+#[derive(Clone, Debug, Default)]
+pub struct TestReport {
+    /// Results of each test, in the order in which tests were run
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    /// Returns the number of tests which passed.
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.is_success()).count()
+    }
+
+    /// Returns the number of tests which failed.
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    /// Returns whether all tests passed.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|r| r.is_success())
+    }
+
+    /// Returns the results of tests which failed.
+    pub fn failures(&self) -> Vec<&TestResult> {
+        self.results.iter().filter(|r| !r.is_success()).collect()
+    }
+}
+
+/// Returns the tests defined in the given scope, sorted by name.
+pub fn find_tests(scope: &Scope) -> Vec<(Name, Value)> {
+    let mut tests = Vec::new();
+
+    scope.with_values(|values| {
+        for &(name, ref v) in values {
+            let is_test = match *v {
+                Value::Lambda(ref l) => l.code.req_params == 0 &&
+                    scope.with_name(name, |s| s.starts_with(TEST_PREFIX)),
+                _ => false
+            };
+
+            if is_test {
+                tests.push((name, v.clone()));
+            }
+        }
+    });
+
+    let names = scope.borrow_names();
+    tests.sort_by(|a, b| names.get(a.0).cmp(names.get(b.0)));
+    tests
+}
+
+/// Runs tests defined in the interpreter's global scope, followed by tests
+/// defined in each loaded module, in the order in which modules were loaded.
+pub fn run_tests(interp: &Interpreter) -> TestReport {
+    let scope = interp.get_scope();
+    let mut report = TestReport::default();
+
+    run_scope_tests(interp, scope, None, &mut report);
+
+    for m in scope.get_modules().iter() {
+        let name = scope.with_name(m.name, |s| s.to_owned());
+        run_scope_tests(interp, &m.scope, Some(name), &mut report);
+    }
+
+    report
+}
+
+/// Runs tests defined in the given scope.
+///
+/// `module` is the name of the module to which the scope belongs, if any.
+pub fn run_scope_tests(interp: &Interpreter, scope: &Scope,
+        module: Option<String>, report: &mut TestReport) {
+    for (name, test) in find_tests(scope) {
+        let error = call_function(scope, test, Vec::new()).err()
+            .map(|e| interp.display(&e));
+
+        report.results.push(TestResult{
+            module: module.clone(),
+            name: scope.with_name(name, |s| s.to_owned()),
+            error: error,
+        });
+    }
+}
@@ -0,0 +1,110 @@
+//! Records the chain of function calls active when a runtime error occurred.
+//!
+//! When an error propagates out of bytecode execution, the location of each
+//! active call is recorded, innermost first, and made available through
+//! `Interpreter::error_trace`.
+//!
+//! ```
+//! use ketos::Interpreter;
+//!
+//! let interp = Interpreter::new();
+//!
+//! interp.run_code("
+//!     (define (foo) (list (bar \"x\")))
+//!     (define (bar a) (+ 1 a))
+//!     ", None).unwrap();
+//!
+//! assert!(interp.call("foo", vec![]).is_err());
+//!
+//! let trace = interp.error_trace();
+//! let calls = interp.describe_trace(&trace);
+//!
+//! assert_eq!(calls.len(), 2);
+//! assert!(calls[0].starts_with("`bar` at <input>:3:"));
+//! assert!(calls[1].starts_with("`foo` at <input>:2:"));
+//! ```
+//!
+//! Calls which were replaced by a tail call are not recorded.
+
+use std::fmt::Write;
+
+use exec::ErrorLocation;
+use lexer::CodeMap;
+use name::NameStore;
+
+/// Chain of function calls active when a runtime error was produced
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Trace {
+    items: Vec<ErrorLocation>,
+}
+
+impl Trace {
+    /// Creates an empty `Trace`.
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    /// Adds the location of a call, which is outside of all previously
+    /// added calls.
+    pub fn push(&mut self, loc: ErrorLocation) {
+        self.items.push(loc);
+    }
+
+    /// Removes all recorded calls.
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Returns the locations of recorded calls, innermost first.
+    pub fn items(&self) -> &[ErrorLocation] {
+        &self.items
+    }
+
+    /// Returns whether no calls are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the number of recorded calls.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns a one-line description of each recorded call, innermost first,
+    /// such as ``"`foo` at main.ket:3:4"``.
+    ///
+    /// Code which is not within a named function is described as
+    /// `<anonymous>`. The source location is omitted if it is not known.
+    pub fn describe(&self, names: &NameStore, codemap: &CodeMap) -> Vec<String> {
+        self.items.iter().map(|loc| {
+            let mut s = match loc.function {
+                Some(name) => format!("`{}`", names.get(name)),
+                None => "<anonymous>".to_owned()
+            };
+
+            if let Some(hi) = loc.span.and_then(|sp| codemap.try_highlight_span(sp)) {
+                let _ = write!(s, " at {}:{}:{}",
+                    hi.filename.unwrap_or("<input>"), hi.line, hi.col);
+            }
+
+            s
+        }).collect()
+    }
+
+    /// Renders the trace as a series of lines, each ending with a newline.
+    ///
+    /// The result is empty if no calls are recorded.
+    pub fn render(&self, names: &NameStore, codemap: &CodeMap) -> String {
+        let mut buf = String::new();
+
+        if !self.is_empty() {
+            buf.push_str("traceback (innermost call first):\n");
+
+            for line in self.describe(names, codemap) {
+                let _ = writeln!(buf, "  in {}", line);
+            }
+        }
+
+        buf
+    }
+}
@@ -2,15 +2,22 @@
 
 use std::any::{Any, TypeId};
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use std::fmt::{self, Write};
-use std::mem::{replace, transmute};
+use std::mem::{self, replace, transmute};
 use std::rc::Rc;
+use std::slice;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use error::Error;
 use exec::ExecError;
 use function::{Function, Lambda};
 use integer::{Integer, Ratio};
-use name::{Name, NameDebug, NameDisplay, NameMapSlice, NameStore};
+use lexer::{Lexer, Token};
+use map::{Map, Set};
+use name::{standard_names, Name, NameDebug, NameDisplay, NameMapSlice, NameStore};
+use rc_string::RcString;
 use rc_vec::RcVec;
 use scope::Scope;
 
@@ -40,8 +47,8 @@ pub enum Value {
     Keyword(Name),
     /// Character
     Char(char),
-    /// String
-    String(String),
+    /// String; the buffer is shared between clones and slices of the value
+    String(RcString),
     /// Quasiquoted value; quote depth **MUST NEVER be zero.**
     Quasiquote(Box<Value>, u32),
     /// Comma'd value; comma depth **MUST NEVER be zero.**
@@ -53,10 +60,14 @@ pub enum Value {
     /// Series of one or more values.
     /// **MUST NEVER be of length zero.** Use `Unit` to represent empty lists.
     List(RcVec<Value>),
+    /// Map of keys to values
+    Map(Rc<Map>),
+    /// Set of unique values
+    Set(Rc<Set>),
     /// Function implemented in Rust
-    Function(Function),
+    Function(Rc<Function>),
     /// Compiled bytecode function
-    Lambda(Lambda),
+    Lambda(Rc<Lambda>),
     /// Boxed value of a foreign type
     Foreign(Rc<ForeignValue>),
 }
@@ -81,6 +92,27 @@ impl Value {
     /// If a true, `Ord`-like comparison cannot be made,
     /// `ExecError::CannotCompare(..)` should be returned.
     pub fn compare(&self, rhs: &Value) -> Result<Ordering, ExecError> {
+        let mut pending = Vec::new();
+        let (mut a, mut b) = (self, rhs);
+
+        loop {
+            match try!(a.compare_one(b, &mut pending)) {
+                Ordering::Equal => (),
+                ord => return Ok(ord)
+            }
+
+            match next_pair(&mut pending) {
+                Next::Pair(x, y) => { a = x; b = y; }
+                Next::Unequal(ord) => return Ok(ord),
+                Next::Done => return Ok(Ordering::Equal),
+            }
+        }
+    }
+
+    /// Compares two values without descending into lists or structs;
+    /// the contents of such values are added to `pending`.
+    fn compare_one<'a>(&'a self, rhs: &'a Value, pending: &mut Vec<Pending<'a>>)
+            -> Result<Ordering, ExecError> {
         let ord = match (self, rhs) {
             (&Value::Unit, &Value::Unit) => Ordering::Equal,
             (&Value::Bool(a), &Value::Bool(b)) => a.cmp(&b),
@@ -93,13 +125,14 @@ impl Value {
             (&Value::String(ref a), &Value::String(ref b)) => a.cmp(&b),
             (&Value::Unit, &Value::List(_)) => Ordering::Less,
             (&Value::List(_), &Value::Unit) => Ordering::Greater,
-            (&Value::List(ref a), &Value::List(ref b)) =>
-                try!(cmp_value_slice(a, b)),
+            (&Value::List(ref a), &Value::List(ref b)) => {
+                pending.push(Pending::Values(a, b));
+                Ordering::Equal
+            }
             (&Value::Struct(ref a), &Value::Struct(ref b)) => {
                 if a.def == b.def {
-                    try!(cmp_value_iter(
-                        a.fields.iter().map(|&(_, ref v)| v).zip(
-                            b.fields.iter().map(|&(_, ref v)| v))))
+                    pending.push(Pending::Fields(a.fields.get_values(), b.fields.get_values()));
+                    Ordering::Equal
                 } else {
                     return Err(ExecError::StructMismatch{
                         lhs: a.def.name,
@@ -141,6 +174,10 @@ impl Value {
                 return Err(ExecError::CannotCompare("function")),
             (&Value::Lambda(_), &Value::Lambda(_)) =>
                 return Err(ExecError::CannotCompare("lambda")),
+            (&Value::Map(_), &Value::Map(_)) =>
+                return Err(ExecError::CannotCompare("map")),
+            (&Value::Set(_), &Value::Set(_)) =>
+                return Err(ExecError::CannotCompare("set")),
             (&Value::Quote(_, _), &Value::Quote(_, _)) =>
                 return Err(ExecError::CannotCompare("quote")),
             (&Value::Quasiquote(_, _), &Value::Quasiquote(_, _)) =>
@@ -167,6 +204,26 @@ impl Value {
     /// Tests two values for equality; returns an error if the values cannot be
     /// compared.
     pub fn is_equal(&self, rhs: &Value) -> Result<bool, ExecError> {
+        let mut pending = Vec::new();
+        let (mut a, mut b) = (self, rhs);
+
+        loop {
+            if !try!(a.is_equal_one(b, &mut pending)) {
+                return Ok(false);
+            }
+
+            match next_pair(&mut pending) {
+                Next::Pair(x, y) => { a = x; b = y; }
+                Next::Unequal(_) => return Ok(false),
+                Next::Done => return Ok(true),
+            }
+        }
+    }
+
+    /// Tests two values for equality without descending into lists or structs;
+    /// the contents of such values are added to `pending`.
+    fn is_equal_one<'a>(&'a self, rhs: &'a Value, pending: &mut Vec<Pending<'a>>)
+            -> Result<bool, ExecError> {
         let eq = match (self, rhs) {
             (&Value::Unit, &Value::Unit) => true,
             (&Value::Bool(a), &Value::Bool(b)) => a == b,
@@ -197,16 +254,36 @@ impl Value {
             (&Value::Name(a), &Value::Name(b)) => a == b,
             (&Value::Char(a), &Value::Char(b)) => a == b,
             (&Value::String(ref a), &Value::String(ref b)) => a == b,
-            (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) =>
-                na == nb && try!(a.is_equal(&b)),
+            (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) => {
+                pending.push(Pending::Values(slice::from_ref(a), slice::from_ref(b)));
+                na == nb
+            }
             (&Value::Unit, &Value::List(_)) => false,
             (&Value::List(_), &Value::Unit) => false,
-            (&Value::List(ref a), &Value::List(ref b)) =>
-                try!(eq_value_slice(a, b)),
+            (&Value::List(ref a), &Value::List(ref b)) => {
+                pending.push(Pending::Values(a, b));
+                a.len() == b.len()
+            }
+            (&Value::Map(ref a), &Value::Map(ref b)) => {
+                // Keys never contain floats, so identity is equality
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (k, x) in a.iter() {
+                    match b.get(k) {
+                        Some(y) => pending.push(Pending::Values(
+                            slice::from_ref(x), slice::from_ref(y))),
+                        None => return Ok(false)
+                    }
+                }
+                true
+            }
+            (&Value::Set(ref a), &Value::Set(ref b)) =>
+                a.len() == b.len() && a.iter().all(|v| b.contains(v)),
             (&Value::Struct(ref a), &Value::Struct(ref b)) => {
                 if a.def == b.def {
-                    try!(eq_value_iter(a.fields.iter().zip(b.fields.iter())
-                        .map(|(&(_, ref a), &(_, ref b))| (a, b))))
+                    pending.push(Pending::Fields(a.fields.get_values(), b.fields.get_values()));
+                    true
                 } else {
                     return Err(ExecError::StructMismatch{
                         lhs: a.def.name,
@@ -234,6 +311,25 @@ impl Value {
     /// The notable difference between this and `eq` is that float `NaN` values
     /// will compare equal.
     pub fn is_identical(&self, rhs: &Value) -> bool {
+        let mut pending = Vec::new();
+        let (mut a, mut b) = (self, rhs);
+
+        loop {
+            if !a.is_identical_one(b, &mut pending) {
+                return false;
+            }
+
+            match next_pair(&mut pending) {
+                Next::Pair(x, y) => { a = x; b = y; }
+                Next::Unequal(_) => return false,
+                Next::Done => return true,
+            }
+        }
+    }
+
+    /// Tests two values for identity without descending into compound values;
+    /// the contents of such values are added to `pending`.
+    fn is_identical_one<'a>(&'a self, rhs: &'a Value, pending: &mut Vec<Pending<'a>>) -> bool {
         match (self, rhs) {
             (&Value::Unit, &Value::Unit) => true,
             (&Value::Unbound, &Value::Unbound) => true,
@@ -241,22 +337,49 @@ impl Value {
             (&Value::Float(a), &Value::Float(b)) => float_is_identical(a, b),
             (&Value::Integer(ref a), &Value::Integer(ref b)) => a == b,
             (&Value::Ratio(ref a), &Value::Ratio(ref b)) => a == b,
-            (&Value::Struct(ref a), &Value::Struct(ref b)) =>
-                a.def == b.def &&
-                    a.fields.iter().zip(b.fields.iter())
-                        .all(|(&(_, ref a), &(_, ref b))| a.is_identical(b)),
+            (&Value::Struct(ref a), &Value::Struct(ref b)) => {
+                pending.push(Pending::Fields(a.fields.get_values(), b.fields.get_values()));
+                a.def == b.def
+            }
             (&Value::Name(a), &Value::Name(b)) => a == b,
             (&Value::Keyword(a), &Value::Keyword(b)) => a == b,
             (&Value::Char(a), &Value::Char(b)) => a == b,
             (&Value::String(ref a), &Value::String(ref b)) => a == b,
-            (&Value::Quasiquote(ref a, na), &Value::Quasiquote(ref b, nb)) =>
-                na == nb && a.is_identical(b),
-            (&Value::Comma(ref a, na), &Value::Comma(ref b, nb)) =>
-                na == nb && a.is_identical(b),
-            (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) =>
-                na == nb && a.is_identical(b),
-            (&Value::List(ref a), &Value::List(ref b)) =>
-                list_is_identical(a, b),
+            (&Value::Quasiquote(ref a, na), &Value::Quasiquote(ref b, nb)) => {
+                pending.push(Pending::Values(slice::from_ref(a), slice::from_ref(b)));
+                na == nb
+            }
+            (&Value::Comma(ref a, na), &Value::Comma(ref b, nb)) => {
+                pending.push(Pending::Values(slice::from_ref(a), slice::from_ref(b)));
+                na == nb
+            }
+            (&Value::CommaAt(ref a, na), &Value::CommaAt(ref b, nb)) => {
+                pending.push(Pending::Values(slice::from_ref(a), slice::from_ref(b)));
+                na == nb
+            }
+            (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) => {
+                pending.push(Pending::Values(slice::from_ref(a), slice::from_ref(b)));
+                na == nb
+            }
+            (&Value::List(ref a), &Value::List(ref b)) => {
+                pending.push(Pending::Values(a, b));
+                a.len() == b.len()
+            }
+            (&Value::Map(ref a), &Value::Map(ref b)) => {
+                if a.len() != b.len() {
+                    return false;
+                }
+                for (k, x) in a.iter() {
+                    match b.get(k) {
+                        Some(y) => pending.push(Pending::Values(
+                            slice::from_ref(x), slice::from_ref(y))),
+                        None => return false
+                    }
+                }
+                true
+            }
+            (&Value::Set(ref a), &Value::Set(ref b)) =>
+                a.len() == b.len() && a.iter().all(|v| b.contains(v)),
             (&Value::Function(ref a), &Value::Function(ref b)) => a == b,
             (&Value::Lambda(ref a), &Value::Lambda(ref b)) => a == b,
 
@@ -267,6 +390,14 @@ impl Value {
         }
     }
 
+    /// Returns a string value.
+    ///
+    /// Unlike `Value::from`, which panics if the string is too long to be
+    /// contained in an `RcString`, returns `ExecError::StringLimit`.
+    pub fn new_string(s: String) -> Result<Value, ExecError> {
+        RcString::try_new(s).map(Value::String).ok_or(ExecError::StringLimit)
+    }
+
     /// Replaces the value with `Unit` and returns the old value.
     pub fn take(&mut self) -> Value {
         replace(self, Value::Unit)
@@ -324,6 +455,8 @@ impl Value {
             Value::CommaAt(_, _) |
             Value::Quote(_, _) => "object",
             Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Set(_) => "set",
             Value::Struct(_) => "struct",
             Value::StructDef(_) => "struct-def",
             Value::Function(_) => "function",
@@ -331,6 +464,150 @@ impl Value {
             Value::Foreign(ref a) => a.type_name(),
         }
     }
+
+    /// Returns whether the value can be written, using `Readable`, in a form
+    /// which is parsed to produce an equal value.
+    ///
+    /// Functions, lambdas, sets, structs, foreign values, and infinite or NaN
+    /// floating point values are not readable.
+    pub fn is_readable(&self) -> bool {
+        self.find_unreadable().is_none()
+    }
+
+    /// Returns the first value contained within this value, or this value
+    /// itself, which is not readable, including any name or keyword whose
+    /// text, as stored in `names`, would not be read as the same name.
+    ///
+    /// Names such as `foo bar` or `123`, which may be created at runtime,
+    /// have no literal representation and cannot be written.
+    pub fn find_unreadable_names(&self, names: &NameStore) -> Option<&Value> {
+        if let Some(v) = self.find_unreadable() {
+            return Some(v);
+        }
+
+        let mut stack = vec![self];
+
+        while let Some(v) = stack.pop() {
+            match *v {
+                Value::Name(name) => if !is_readable_name(names.get(name), false) {
+                    return Some(v);
+                },
+                Value::Keyword(name) => if !is_readable_name(names.get(name), true) {
+                    return Some(v);
+                },
+                Value::Quasiquote(ref v, _) |
+                Value::Comma(ref v, _) |
+                Value::CommaAt(ref v, _) |
+                Value::Quote(ref v, _) => stack.push(v),
+                Value::List(ref l) => stack.extend(l.iter().rev()),
+                Value::Map(ref m) => for (k, v) in m.iter().rev() {
+                    stack.push(v);
+                    stack.push(k);
+                },
+                _ => ()
+            }
+        }
+
+        None
+    }
+
+    /// Returns the first value contained within this value,
+    /// or this value itself, which is not readable.
+    pub fn find_unreadable(&self) -> Option<&Value> {
+        let mut stack = vec![self];
+
+        while let Some(v) = stack.pop() {
+            match *v {
+                Value::Unit |
+                Value::Bool(_) |
+                Value::Integer(_) |
+                Value::Ratio(_) |
+                Value::Char(_) |
+                Value::String(_) |
+                Value::Name(_) |
+                Value::Keyword(_) => (),
+                Value::Float(f) => if !is_normal(f) {
+                    return Some(v);
+                },
+                Value::Quasiquote(ref v, _) |
+                Value::Comma(ref v, _) |
+                Value::CommaAt(ref v, _) |
+                Value::Quote(ref v, _) => stack.push(v),
+                Value::List(ref l) => stack.extend(l.iter().rev()),
+                Value::Map(ref m) => for (k, v) in m.iter().rev() {
+                    stack.push(v);
+                    stack.push(k);
+                },
+                Value::Set(ref s) => stack.extend(s.iter().rev()),
+                _ => return Some(v)
+            }
+        }
+
+        None
+    }
+
+    /// Returns an approximation of the number of bytes of memory
+    /// occupied by the value and any values contained within it.
+    ///
+    /// The result accounts for list, map, set, and struct elements, string contents,
+    /// and the digits of arbitrary precision numbers. Function, lambda,
+    /// struct definition, and foreign values are counted as a single value.
+    ///
+    /// The elements of a list, map, set, or struct which is shared between
+    /// several values are counted only once, so that measuring a value
+    /// built by repeatedly sharing the same sublist takes time proportional
+    /// to the storage in use, rather than to the size of the value as printed.
+    ///
+    /// Values are measured iteratively, so deeply nested values
+    /// will not exhaust the stack.
+    pub fn approximate_size(&self) -> usize {
+        let mut size = 0;
+        let mut stack = vec![self];
+        let mut seen = HashSet::new();
+
+        while let Some(v) = stack.pop() {
+            size += mem::size_of::<Value>();
+
+            match *v {
+                Value::Integer(ref i) => size += bits_to_bytes(i.bits()),
+                Value::Ratio(ref r) => size += bits_to_bytes(r.bits()),
+                Value::String(ref s) => size += s.len(),
+                Value::Quasiquote(ref v, _) |
+                Value::Comma(ref v, _) |
+                Value::CommaAt(ref v, _) |
+                Value::Quote(ref v, _) => stack.push(v),
+                Value::List(ref l) => {
+                    if seen.insert((l.as_ptr() as usize, l.len())) {
+                        stack.extend(l.iter());
+                    }
+                }
+                Value::Map(ref m) => {
+                    if seen.insert((&**m as *const Map as usize, 0)) {
+                        stack.extend(m.keys());
+                        stack.extend(m.values());
+                    }
+                }
+                Value::Set(ref s) => {
+                    if seen.insert((&**s as *const Set as usize, 0)) {
+                        stack.extend(s.iter());
+                    }
+                }
+                Value::Struct(ref s) => {
+                    if seen.insert((&**s as *const Struct as usize, 0)) {
+                        size += s.fields.len() * mem::size_of::<Name>();
+                        stack.extend(s.fields.iter().map(|&(_, ref v)| v));
+                    }
+                }
+                _ => ()
+            }
+        }
+
+        size
+    }
+}
+
+fn bits_to_bytes(bits: usize) -> usize {
+    (bits + 7) / 8
 }
 
 /// A helper trait that is necessary as long as `Any::get_type_id` is unstable.
@@ -355,8 +632,13 @@ struct TraitObject {
 pub trait ForeignValue: AnyValue + fmt::Debug {
     /// Performs ordered comparison between two values of a foreign type.
     ///
+    /// This method is used by comparison operators, such as `<`, and by
+    /// functions which order values, such as `sort`.
+    ///
     /// The default implementation unconditionally returns an error.
-    fn compare_to(&self, rhs: &ForeignValue) -> Result<Ordering, ExecError>;
+    fn compare_to(&self, _rhs: &ForeignValue) -> Result<Ordering, ExecError> {
+        Err(ExecError::CannotCompare(self.type_name()))
+    }
 
     /// Performs ordered comparison between two values.
     ///
@@ -381,7 +663,23 @@ pub trait ForeignValue: AnyValue + fmt::Debug {
     }
 
     /// Tests for equality between two values of a foreign type.
-    fn is_equal_to(&self, rhs: &ForeignValue) -> Result<bool, ExecError>;
+    ///
+    /// This method is used by the `=` and `/=` operators.
+    ///
+    /// The default implementation considers a value equal only to itself
+    /// and returns an error if `rhs` is of a different type.
+    fn is_equal_to(&self, rhs: &ForeignValue) -> Result<bool, ExecError> {
+        if self as *const Self as *const () == rhs as *const ForeignValue as *const () {
+            Ok(true)
+        } else if AnyValue::type_id(self) == AnyValue::type_id(rhs) {
+            Ok(false)
+        } else {
+            Err(ExecError::TypeMismatch{
+                lhs: self.type_name(),
+                rhs: rhs.type_name(),
+            })
+        }
+    }
 
     /// Tests for equality between two values.
     ///
@@ -418,6 +716,14 @@ pub trait ForeignValue: AnyValue + fmt::Debug {
         self.type_name() == name
     }
 
+    /// Returns whether the value may be called as a function.
+    ///
+    /// Types which implement `call_value` should also implement this method.
+    /// The default implementation returns `false`.
+    fn is_callable(&self) -> bool {
+        false
+    }
+
     /// Calls the value as a function.
     ///
     /// The default implementation unconditionally returns an error.
@@ -486,6 +792,8 @@ impl<F> ForeignValue for ForeignFn<F>
 
     fn type_name(&self) -> &'static str { "foreign-fn" }
 
+    fn is_callable(&self) -> bool { true }
+
     fn call_value(&self, scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
         (self.f)(scope, args)
     }
@@ -494,41 +802,62 @@ impl<F> ForeignValue for ForeignFn<F>
 /// Creates a foreign function that implicitly converts input arguments
 /// into Rust values and converts its result into a `ketos` value.
 ///
-/// This function is added to the given scope with the given name.
+/// The function is added with the given name to either a `Scope` or a
+/// `ModuleBuilder`. When given a `ModuleBuilder`, the macro evaluates to
+/// the builder, so that further items may be added.
 ///
-/// ```ignore
-/// fn foo(a: &str) -> Result<String, Error> { ... }
+/// Each argument type must implement `FromValueRef` and the result type
+/// must implement `Into<Value>`. Calls with the wrong number of arguments
+/// produce an `ArityError`.
 ///
-/// ketos_fn!{ scope => "my-fn" => fn foo(a: &str) -> String }
+/// ```
+/// #[macro_use] extern crate ketos;
+///
+/// use ketos::{Error, GlobalScope, Interpreter, ModuleBuilder};
+///
+/// fn repeat(s: &str, n: u32) -> Result<String, Error> {
+///     Ok(s.repeat(n as usize))
+/// }
+///
+/// # fn main() {
+/// let interp = Interpreter::new();
+/// let scope = interp.get_scope();
+///
+/// ketos_fn!{ scope => "repeat" => fn repeat(s: &str, n: u32) -> String }
+///
+/// let builder = ModuleBuilder::new("text", GlobalScope::new_using(scope));
+/// let module = ketos_fn!{ builder => "repeat" => fn repeat(s: &str, n: u32) -> String }
+///     .finish();
+/// # let _ = module;
+///
+/// let v = interp.run_code(r#"(repeat "ab" 3)"#, None).unwrap();
+/// assert_eq!(interp.format_value(&v), r#""ababab""#);
+/// # }
 /// ```
 #[macro_export]
 macro_rules! ketos_fn {
     ( $scope:expr => $name:expr => fn $ident:ident
-            ( $( $arg:ident : $arg_ty:ty ),* ) -> $res:ty ) => {
+            ( $( $arg:ident : $arg_ty:ty ),* $(,)* ) -> $res:ty ) => {
         $scope.add_value_with_name($name,
-            |name| Value::new_foreign_fn(name, move |_scope, args| {
-                let mut iter = (&*args).iter();
+            |name| $crate::value::Value::new_foreign_fn(name, move |_scope, args| {
+                let expected = 0 $( + { stringify!($arg); 1 } )*;
+
+                if args.len() != expected {
+                    return Err(From::from($crate::exec::ExecError::ArityError{
+                        name: Some(name),
+                        expected: $crate::function::Arity::Exact(expected as u32),
+                        found: args.len() as u32,
+                    }));
+                }
 
-                let _expected = 0 $( + { stringify!($arg); 1 } )*;
-                let mut _found = 0;
+                let mut _iter = (&*args).iter();
 
                 let res = try!($ident(
-                    $( {
-                        match iter.next() {
-                            Some(v) => {
-                                _found += 1;
-                                try!(<$arg_ty as $crate::value::FromValueRef>::from_value_ref(v))
-                            }
-                            None => return Err(From::from(ExecError::ArityError{
-                                name: Some(name),
-                                expected: $crate::function::Arity::Exact(_expected),
-                                found: _found,
-                            }))
-                        }
-                    } ),*
+                    $( try!(<$arg_ty as $crate::value::FromValueRef>::from_value_ref(
+                        _iter.next().unwrap())) ),*
                 ));
 
-                Ok(<$res as Into<Value>>::into(res))
+                Ok(<$res as Into<$crate::value::Value>>::into(res))
             }))
     }
 }
@@ -587,6 +916,42 @@ impl NameDebug for Value {
 
                 write!(f, ")")
             }
+            Value::Map(ref m) => {
+                try!(write!(f, "{{"));
+
+                let mut iter = m.iter();
+
+                if let Some((k, v)) = iter.next() {
+                    try!(NameDebug::fmt(k, names, f));
+                    try!(write!(f, " "));
+                    try!(NameDebug::fmt(v, names, f));
+                }
+
+                for (k, v) in iter {
+                    try!(write!(f, " "));
+                    try!(NameDebug::fmt(k, names, f));
+                    try!(write!(f, " "));
+                    try!(NameDebug::fmt(v, names, f));
+                }
+
+                write!(f, "}}")
+            }
+            Value::Set(ref s) => {
+                try!(write!(f, "#{{"));
+
+                let mut iter = s.iter();
+
+                if let Some(v) = iter.next() {
+                    try!(NameDebug::fmt(v, names, f));
+                }
+
+                for v in iter {
+                    try!(write!(f, " "));
+                    try!(NameDebug::fmt(v, names, f));
+                }
+
+                write!(f, "}}")
+            }
             // TODO: This output doesn't match the way structs are built.
             // Write out "(new 'name ...)"? Implement a shortcut syntax?
             Value::Struct(ref s) => {
@@ -656,64 +1021,280 @@ impl NameDisplay for Value {
     }
 }
 
-fn is_normal(f: f64) -> bool {
-    !f.is_nan() && f.is_finite()
-}
+/// Wraps a `Value` to produce output which is parsed to produce an equal value.
+///
+/// Strings and characters are quoted and escaped, and floating point values
+/// are always written with a decimal point or exponent. The output for values
+/// which are not readable, as reported by `Value::find_unreadable_names`, is the same as
+/// their debug output and cannot be parsed.
+pub struct Readable<'a>(pub &'a Value);
 
-fn flip_ordering(ord: Ordering) -> Ordering {
-    match ord {
-        Ordering::Equal => Ordering::Equal,
-        Ordering::Greater => Ordering::Less,
-        Ordering::Less => Ordering::Greater,
+impl<'a> NameDebug for Readable<'a> {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Value::Float(fl) if is_normal(fl) => {
+                let s = format!("{:?}", fl);
+
+                if s.contains(|c| c == '.' || c == 'e' || c == 'E') {
+                    f.write_str(&s)
+                } else {
+                    write!(f, "{}.0", s)
+                }
+            }
+            Value::Quasiquote(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, "`")); }
+                NameDebug::fmt(&Readable(v), names, f)
+            }
+            Value::Comma(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, ",")); }
+                NameDebug::fmt(&Readable(v), names, f)
+            }
+            Value::CommaAt(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, ",")); }
+                try!(write!(f, "@"));
+                NameDebug::fmt(&Readable(v), names, f)
+            }
+            Value::Quote(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, "'")); }
+                NameDebug::fmt(&Readable(v), names, f)
+            }
+            Value::List(ref l) => {
+                try!(write!(f, "("));
+
+                let mut iter = l.iter();
+
+                if let Some(v) = iter.next() {
+                    try!(NameDebug::fmt(&Readable(v), names, f));
+                }
+
+                for v in iter {
+                    try!(write!(f, " "));
+                    try!(NameDebug::fmt(&Readable(v), names, f));
+                }
+
+                write!(f, ")")
+            }
+            Value::Map(ref m) => {
+                try!(write!(f, "{{"));
+
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i != 0 {
+                        try!(write!(f, " "));
+                    }
+                    try!(NameDebug::fmt(&Readable(k), names, f));
+                    try!(write!(f, " "));
+                    try!(NameDebug::fmt(&Readable(v), names, f));
+                }
+
+                write!(f, "}}")
+            }
+            ref v => NameDebug::fmt(v, names, f)
+        }
     }
 }
 
-fn cmp_value_iter<'a, I>(iter: I) -> Result<Ordering, ExecError>
-        where I: IntoIterator<Item=(&'a Value, &'a Value)> {
-    for (a, b) in iter {
-        match try!(a.compare(b)) {
-            Ordering::Equal => (),
-            ord => return Ok(ord),
-        }
+/// Wraps a `Value` to produce debug output in which shared structure is
+/// written using datum labels.
+///
+/// A list value which appears more than once is written in full, preceded
+/// by a label `#n=`, at its first appearance and written as a reference `#n#`
+/// thereafter. Such output may be read back by the parser to produce a value
+/// with the same shared structure.
+pub struct Labeled<'a>(pub &'a Value);
+
+impl<'a> NameDebug for Labeled<'a> {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut counts = HashMap::new();
+        count_lists(self.0, &mut counts);
+
+        LabelWriter{
+            names: names,
+            counts: counts,
+            labels: HashMap::new(),
+        }.write(self.0, f)
     }
+}
+
+/// Identifies a list by its storage, rather than its contents
+type ListKey = (usize, usize);
 
-    Ok(Ordering::Equal)
+fn list_key(li: &RcVec<Value>) -> ListKey {
+    (li.as_ptr() as usize, li.len())
 }
 
-fn cmp_value_slice(a: &[Value], b: &[Value]) -> Result<Ordering, ExecError> {
-    for (a, b) in a.iter().zip(b) {
-        match try!(a.compare(b)) {
-            Ordering::Equal => (),
-            ord => return Ok(ord),
+/// Counts the appearances of each list within a value.
+/// Lists which have been seen before are not traversed again.
+fn count_lists(v: &Value, counts: &mut HashMap<ListKey, u32>) {
+    match *v {
+        Value::List(ref li) => {
+            let first = {
+                let n = counts.entry(list_key(li)).or_insert(0);
+                *n += 1;
+                *n == 1
+            };
+
+            if first {
+                for v in li.iter() {
+                    count_lists(v, counts);
+                }
+            }
         }
+        Value::Quasiquote(ref v, _) |
+        Value::Comma(ref v, _) |
+        Value::CommaAt(ref v, _) |
+        Value::Quote(ref v, _) => count_lists(v, counts),
+        _ => ()
     }
+}
 
-    Ok(a.len().cmp(&b.len()))
+struct LabelWriter<'a> {
+    names: &'a NameStore,
+    counts: HashMap<ListKey, u32>,
+    labels: HashMap<ListKey, u32>,
 }
 
-fn eq_value_iter<'a, I>(iter: I) -> Result<bool, ExecError>
-        where I: IntoIterator<Item=(&'a Value, &'a Value)> {
-    for (a, b) in iter {
-        if !try!(a.is_equal(b)) {
-            return Ok(false);
+impl<'a> LabelWriter<'a> {
+    fn write(&mut self, v: &Value, f: &mut fmt::Formatter) -> fmt::Result {
+        match *v {
+            Value::Quasiquote(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, "`")); }
+                self.write(v, f)
+            }
+            Value::Comma(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, ",")); }
+                self.write(v, f)
+            }
+            Value::CommaAt(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, ",")); }
+                try!(write!(f, "@"));
+                self.write(v, f)
+            }
+            Value::Quote(ref v, depth) => {
+                for _ in 0..depth { try!(write!(f, "'")); }
+                self.write(v, f)
+            }
+            Value::List(ref l) => {
+                let key = list_key(l);
+
+                if let Some(&n) = self.labels.get(&key) {
+                    return write!(f, "#{}#", n);
+                }
+
+                if self.counts.get(&key).map_or(false, |&n| n > 1) {
+                    let n = self.labels.len() as u32;
+                    self.labels.insert(key, n);
+                    try!(write!(f, "#{}=", n));
+                }
+
+                try!(write!(f, "("));
+
+                let mut iter = l.iter();
+
+                if let Some(v) = iter.next() {
+                    try!(self.write(v, f));
+                }
+
+                for v in iter {
+                    try!(write!(f, " "));
+                    try!(self.write(v, f));
+                }
+
+                write!(f, ")")
+            }
+            ref v => NameDebug::fmt(v, self.names, f)
         }
     }
+}
+
+/// Returns whether the text of a name, or of a keyword without its leading
+/// colon, is read by the parser as a single name or keyword with that text.
+fn is_readable_name(name: &str, keyword: bool) -> bool {
+    let src = if keyword { format!(":{}", name) } else { name.to_owned() };
+    let mut lexer = Lexer::new(&src, 0);
+
+    let ok = match lexer.next_token() {
+        Ok((_, Token::Name(s))) => !keyword && s == name &&
+            s != "true" && s != "false",
+        Ok((_, Token::Keyword(s))) => keyword && s == name,
+        _ => false
+    };
+
+    ok && match lexer.next_token() {
+        Ok((_, Token::End)) => true,
+        _ => false
+    }
+}
 
-    Ok(true)
+fn is_normal(f: f64) -> bool {
+    !f.is_nan() && f.is_finite()
 }
 
-fn eq_value_slice(a: &[Value], b: &[Value]) -> Result<bool, ExecError> {
-    if a.len() != b.len() {
-        return Ok(false);
+fn flip_ordering(ord: Ordering) -> Ordering {
+    match ord {
+        Ordering::Equal => Ordering::Equal,
+        Ordering::Greater => Ordering::Less,
+        Ordering::Less => Ordering::Greater,
     }
+}
+
+/// Pair of sequences whose elements remain to be compared
+/// by `compare`, `is_equal`, or `is_identical`
+///
+/// Keeping pending work on an explicit stack, rather than recursing,
+/// allows arbitrarily deep structures to be compared without exhausting
+/// the native stack.
+enum Pending<'a> {
+    Values(&'a [Value], &'a [Value]),
+    Fields(&'a [(Name, Value)], &'a [(Name, Value)]),
+}
+
+/// Next step of a comparison, taken from a stack of `Pending` sequences
+enum Next<'a> {
+    /// Pair of values to compare
+    Pair(&'a Value, &'a Value),
+    /// One sequence ended before the other
+    Unequal(Ordering),
+    /// No values remain
+    Done,
+}
+
+fn next_pair<'a>(pending: &mut Vec<Pending<'a>>) -> Next<'a> {
+    loop {
+        let next = match pending.last_mut() {
+            None => return Next::Done,
+            Some(&mut Pending::Values(ref mut a, ref mut b)) => {
+                let (ra, rb): (&'a [Value], &'a [Value]) = (*a, *b);
+
+                match (ra.split_first(), rb.split_first()) {
+                    (Some((x, xs)), Some((y, ys))) => {
+                        *a = xs;
+                        *b = ys;
+                        Some(Next::Pair(x, y))
+                    }
+                    (None, None) => None,
+                    (None, Some(_)) => Some(Next::Unequal(Ordering::Less)),
+                    (Some(_), None) => Some(Next::Unequal(Ordering::Greater)),
+                }
+            }
+            Some(&mut Pending::Fields(ref mut a, ref mut b)) => {
+                let (ra, rb): (&'a [(Name, Value)], &'a [(Name, Value)]) = (*a, *b);
+
+                match (ra.split_first(), rb.split_first()) {
+                    (Some((&(_, ref x), xs)), Some((&(_, ref y), ys))) => {
+                        *a = xs;
+                        *b = ys;
+                        Some(Next::Pair(x, y))
+                    }
+                    _ => None
+                }
+            }
+        };
 
-    for (a, b) in a.iter().zip(b.iter()) {
-        if !try!(a.is_equal(b)) {
-            return Ok(false);
+        match next {
+            Some(next) => return next,
+            None => { pending.pop(); }
         }
     }
-
-    Ok(true)
 }
 
 fn float_is_identical(a: f64, b: f64) -> bool {
@@ -724,11 +1305,6 @@ fn float_is_identical(a: f64, b: f64) -> bool {
     }
 }
 
-fn list_is_identical(a: &[Value], b: &[Value]) -> bool {
-    a.len() == b.len() &&
-        a.iter().zip(b.iter()).all(|(a, b)| a.is_identical(b))
-}
-
 /// Borrows a Rust value from a `Value`
 pub trait FromValueRef<'a>: Sized {
     /// Returns the borrowed value
@@ -792,6 +1368,77 @@ macro_rules! foreign_type_conversions {
     }
 }
 
+/// Generates a `StructValue` implementation for the given struct type.
+///
+/// Each field is given with its Rust type and the name of the `ketos` type
+/// expected for the field, e.g. `"integer"` or `"string"`.
+/// Underscores in Rust field names are replaced with hyphens.
+///
+/// ```
+/// #[macro_use] extern crate ketos;
+///
+/// use ketos::Interpreter;
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Config {
+///     name: String,
+///     max_size: u32,
+/// }
+///
+/// struct_conversions!{ Config => "config" {
+///     name: String => "string",
+///     max_size: u32 => "integer",
+/// } }
+///
+/// # fn main() {
+/// let interp = Interpreter::new();
+/// let scope = interp.get_scope();
+///
+/// // The struct definition must be added before values are converted
+/// scope.add_struct_def::<Config>();
+///
+/// let v = scope.struct_to_value(Config{
+///     name: "test".to_owned(),
+///     max_size: 10,
+/// }).unwrap();
+///
+/// scope.add_named_value("cfg", v);
+///
+/// let v = interp.run_code("(.= cfg :max-size (* (. cfg :max-size) 2))", None).unwrap();
+///
+/// assert_eq!(scope.struct_from_value::<Config>(&v).unwrap(),
+///     Config{name: "test".to_owned(), max_size: 20});
+/// # }
+/// ```
+#[macro_export]
+macro_rules! struct_conversions {
+    ( $ty:ident => $name:tt {
+            $( $field:ident : $field_ty:ty => $field_kty:expr ),* $(,)* } ) => {
+        impl $crate::value::StructValue for $ty {
+            fn struct_name() -> &'static str { $name }
+
+            fn struct_fields() -> &'static [(&'static str, &'static str)] {
+                &[ $( (stringify!($field), $field_kty) ),* ]
+            }
+
+            fn into_fields(self) -> Vec<$crate::value::Value> {
+                vec![ $( self.$field.into() ),* ]
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn from_fields(fields: Vec<$crate::value::Value>)
+                    -> Result<$ty, $crate::exec::ExecError> {
+                let mut iter = fields.into_iter();
+
+                Ok($ty{
+                    $( $field: try!(<$field_ty as $crate::value::FromValue>::from_value(
+                        iter.next().expect("missing struct field"))), )*
+                })
+            }
+        }
+    }
+}
+
 simple_from_ref!{ (); Value::Unit => (); "unit" }
 simple_from_ref!{ bool; Value::Bool(b) => b; "bool" }
 simple_from_ref!{ char; Value::Char(ch) => ch; "char" }
@@ -857,6 +1504,77 @@ impl<'a, T: FromValueRef<'a>> FromValueRef<'a> for Vec<T> {
     }
 }
 
+/// `()` is converted to `None`; any other value is converted to `Some`.
+impl<'a, T: FromValueRef<'a>> FromValueRef<'a> for Option<T> {
+    fn from_value_ref(v: &'a Value) -> Result<Option<T>, ExecError> {
+        match *v {
+            Value::Unit => Ok(None),
+            ref v => T::from_value_ref(v).map(Some)
+        }
+    }
+}
+
+/// A list of the form `(:ok value)` is converted to `Ok` and a list of the
+/// form `(:error value)` is converted to `Err`.
+impl<'a, T, E> FromValueRef<'a> for Result<T, E>
+        where T: FromValueRef<'a>, E: FromValueRef<'a> {
+    fn from_value_ref(v: &'a Value) -> Result<Result<T, E>, ExecError> {
+        if let Value::List(ref li) = *v {
+            if li.len() == 2 {
+                match li[0] {
+                    Value::Keyword(standard_names::OK) =>
+                        return T::from_value_ref(&li[1]).map(Ok),
+                    Value::Keyword(standard_names::ERROR) =>
+                        return E::from_value_ref(&li[1]).map(Err),
+                    _ => ()
+                }
+            }
+        }
+
+        Err(ExecError::expected("result", v))
+    }
+}
+
+impl<'a> FromValueRef<'a> for &'a Map {
+    fn from_value_ref(v: &'a Value) -> Result<&'a Map, ExecError> {
+        match *v {
+            Value::Map(ref m) => Ok(m),
+            ref v => Err(ExecError::expected("map", v))
+        }
+    }
+}
+
+impl<'a, K, V> FromValueRef<'a> for HashMap<K, V>
+        where K: FromValueRef<'a> + Eq + Hash, V: FromValueRef<'a> {
+    fn from_value_ref(v: &'a Value) -> Result<HashMap<K, V>, ExecError> {
+        match *v {
+            Value::Map(ref m) => m.iter()
+                .map(|(k, v)| Ok((try!(K::from_value_ref(k)), try!(V::from_value_ref(v)))))
+                .collect(),
+            ref v => Err(ExecError::expected("map", v))
+        }
+    }
+}
+
+impl<'a> FromValueRef<'a> for &'a Set {
+    fn from_value_ref(v: &'a Value) -> Result<&'a Set, ExecError> {
+        match *v {
+            Value::Set(ref s) => Ok(s),
+            ref v => Err(ExecError::expected("set", v))
+        }
+    }
+}
+
+impl<'a, T> FromValueRef<'a> for HashSet<T>
+        where T: FromValueRef<'a> + Eq + Hash {
+    fn from_value_ref(v: &'a Value) -> Result<HashSet<T>, ExecError> {
+        match *v {
+            Value::Set(ref s) => s.iter().map(|v| T::from_value_ref(v)).collect(),
+            ref v => Err(ExecError::expected("set", v))
+        }
+    }
+}
+
 impl<'a> FromValueRef<'a> for &'a Lambda {
     fn from_value_ref(v: &'a Value) -> Result<&'a Lambda, ExecError> {
         match *v {
@@ -866,6 +1584,30 @@ impl<'a> FromValueRef<'a> for &'a Lambda {
     }
 }
 
+/// A `Duration` is represented as a non-negative number of seconds.
+/// Integer, ratio, and float values are accepted.
+impl<'a> FromValueRef<'a> for Duration {
+    fn from_value_ref(v: &'a Value) -> Result<Duration, ExecError> {
+        match try!(value_to_seconds(v)) {
+            (d, false) => Ok(d),
+            (_, true) => Err(ExecError::expected("non-negative number", v))
+        }
+    }
+}
+
+/// A `SystemTime` is represented as a number of seconds, which may be
+/// negative, relative to the Unix epoch.
+impl<'a> FromValueRef<'a> for SystemTime {
+    fn from_value_ref(v: &'a Value) -> Result<SystemTime, ExecError> {
+        let t = match try!(value_to_seconds(v)) {
+            (d, false) => UNIX_EPOCH.checked_add(d),
+            (d, true) => UNIX_EPOCH.checked_sub(d),
+        };
+
+        t.ok_or(ExecError::Overflow)
+    }
+}
+
 impl<'a> FromValueRef<'a> for &'a Value {
     #[inline]
     fn from_value_ref(v: &'a Value) -> Result<&'a Value, ExecError> {
@@ -910,7 +1652,8 @@ simple_from_value!{ bool; "bool"; Value::Bool(b) => b }
 simple_from_value!{ char; "char"; Value::Char(ch) => ch }
 simple_from_value!{ f32; "float"; Value::Float(f) => f as f32 }
 simple_from_value!{ f64; "float"; Value::Float(f) => f }
-simple_from_value!{ String; "string"; Value::String(s) => s }
+simple_from_value!{ String; "string"; Value::String(s) => s.into_string() }
+simple_from_value!{ RcString; "string"; Value::String(s) => s }
 simple_from_value!{ Integer; "integer"; Value::Integer(i) => i }
 simple_from_value!{ Ratio; "ratio"; Value::Ratio(r) => r }
 
@@ -925,6 +1668,18 @@ integer_from_value!{ u32 to_u32 }
 integer_from_value!{ u64 to_u64 }
 integer_from_value!{ usize to_usize }
 
+impl FromValue for Duration {
+    fn from_value(v: Value) -> Result<Duration, ExecError> {
+        Duration::from_value_ref(&v)
+    }
+}
+
+impl FromValue for SystemTime {
+    fn from_value(v: Value) -> Result<SystemTime, ExecError> {
+        SystemTime::from_value_ref(&v)
+    }
+}
+
 impl FromValue for Value {
     #[inline]
     fn from_value(v: Value) -> Result<Value, ExecError> {
@@ -932,10 +1687,47 @@ impl FromValue for Value {
     }
 }
 
+impl FromValue for Map {
+    fn from_value(v: Value) -> Result<Map, ExecError> {
+        match v {
+            Value::Map(m) => Ok(Rc::try_unwrap(m).unwrap_or_else(|m| (*m).clone())),
+            ref v => Err(ExecError::expected("map", v))
+        }
+    }
+}
+
+impl<K, V> FromValue for HashMap<K, V>
+        where K: FromValue + Eq + Hash, V: FromValue {
+    fn from_value(v: Value) -> Result<HashMap<K, V>, ExecError> {
+        let (keys, values) = try!(Map::from_value(v)).into_parts();
+
+        keys.into_iter().zip(values)
+            .map(|(k, v)| Ok((try!(K::from_value(k)), try!(V::from_value(v)))))
+            .collect()
+    }
+}
+
+impl FromValue for Set {
+    fn from_value(v: Value) -> Result<Set, ExecError> {
+        match v {
+            Value::Set(s) => Ok(Rc::try_unwrap(s).unwrap_or_else(|s| (*s).clone())),
+            ref v => Err(ExecError::expected("set", v))
+        }
+    }
+}
+
+impl<T> FromValue for HashSet<T>
+        where T: FromValue + Eq + Hash {
+    fn from_value(v: Value) -> Result<HashSet<T>, ExecError> {
+        try!(Set::from_value(v)).into_values().into_iter()
+            .map(|v| T::from_value(v)).collect()
+    }
+}
+
 impl FromValue for Lambda {
     fn from_value(v: Value) -> Result<Lambda, ExecError> {
         match v {
-            Value::Lambda(l) => Ok(l),
+            Value::Lambda(l) => Ok(Rc::try_unwrap(l).unwrap_or_else(|l| (*l).clone())),
             ref v => Err(ExecError::expected("lambda", v))
         }
     }
@@ -952,6 +1744,102 @@ impl<T: FromValue> FromValue for Vec<T> {
     }
 }
 
+/// `()` is converted to `None`; any other value is converted to `Some`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(v: Value) -> Result<Option<T>, ExecError> {
+        match v {
+            Value::Unit => Ok(None),
+            v => T::from_value(v).map(Some)
+        }
+    }
+}
+
+/// A list of the form `(:ok value)` is converted to `Ok` and a list of the
+/// form `(:error value)` is converted to `Err`.
+impl<T: FromValue, E: FromValue> FromValue for Result<T, E> {
+    fn from_value(v: Value) -> Result<Result<T, E>, ExecError> {
+        let tag = match v {
+            Value::List(ref li) if li.len() == 2 => match li[0] {
+                Value::Keyword(name @ standard_names::OK) |
+                Value::Keyword(name @ standard_names::ERROR) => name,
+                _ => return Err(ExecError::expected("result", &v))
+            },
+            ref v => return Err(ExecError::expected("result", v))
+        };
+
+        let v = match v {
+            Value::List(li) => li.into_vec().pop().unwrap(),
+            _ => unreachable!()
+        };
+
+        if tag == standard_names::OK {
+            T::from_value(v).map(Ok)
+        } else {
+            E::from_value(v).map(Err)
+        }
+    }
+}
+
+macro_rules! tuple_conversions {
+    ( $n:expr ; $( $name:ident )+ ) => {
+        /// A tuple is converted from a list of the same length.
+        impl<'a, $( $name: FromValueRef<'a> ),+> FromValueRef<'a> for ( $( $name , )+ ) {
+            fn from_value_ref(v: &'a Value) -> Result<( $( $name , )+ ), ExecError> {
+                let li = try!(<&[Value]>::from_value_ref(v));
+
+                if li.len() != $n {
+                    return Err(ExecError::PatternMismatch{
+                        expected: $n,
+                        rest: false,
+                        found: li.len() as u32,
+                    });
+                }
+
+                let mut _iter = li.iter();
+
+                Ok(( $( try!($name::from_value_ref(_iter.next().unwrap())) , )+ ))
+            }
+        }
+
+        /// A tuple is converted from a list of the same length.
+        impl<$( $name: FromValue ),+> FromValue for ( $( $name , )+ ) {
+            fn from_value(v: Value) -> Result<( $( $name , )+ ), ExecError> {
+                let li = try!(<Vec<Value>>::from_value(v));
+
+                if li.len() != $n {
+                    return Err(ExecError::PatternMismatch{
+                        expected: $n,
+                        rest: false,
+                        found: li.len() as u32,
+                    });
+                }
+
+                let mut _iter = li.into_iter();
+
+                Ok(( $( try!($name::from_value(_iter.next().unwrap())) , )+ ))
+            }
+        }
+
+        /// A tuple is converted into a list.
+        impl<$( $name: Into<Value> ),+> From<( $( $name , )+ )> for Value {
+            #[allow(non_snake_case)]
+            fn from(v: ( $( $name , )+ )) -> Value {
+                let ( $( $name , )+ ) = v;
+                vec![ $( $name.into() ),+ ].into()
+            }
+        }
+    }
+}
+
+tuple_conversions!{ 1; A }
+tuple_conversions!{ 2; A B }
+tuple_conversions!{ 3; A B C }
+tuple_conversions!{ 4; A B C D }
+tuple_conversions!{ 5; A B C D E }
+tuple_conversions!{ 6; A B C D E F }
+tuple_conversions!{ 7; A B C D E F G }
+tuple_conversions!{ 8; A B C D E F G H }
+
 macro_rules! value_from {
     ( $ty:ty ; $pat:pat => $expr:expr ) => {
         impl From<$ty> for Value {
@@ -967,13 +1855,14 @@ value_from!{ bool; b => Value::Bool(b) }
 value_from!{ char; c => Value::Char(c) }
 value_from!{ Integer; i => Value::Integer(i) }
 value_from!{ Ratio; r => Value::Ratio(r) }
-value_from!{ String; s => Value::String(s) }
+value_from!{ String; s => Value::String(RcString::new(s)) }
+value_from!{ RcString; s => Value::String(s) }
 value_from!{ f32; f => Value::Float(f as f64) }
 value_from!{ f64; f => Value::Float(f) }
 
 impl<'a> From<&'a str> for Value {
     fn from(s: &str) -> Value {
-        s.to_owned().into()
+        Value::String(RcString::from(s))
     }
 }
 
@@ -1013,6 +1902,146 @@ impl From<RcVec<Value>> for Value {
     }
 }
 
+impl From<Map> for Value {
+    fn from(m: Map) -> Value {
+        Value::Map(Rc::new(m))
+    }
+}
+
+impl From<Set> for Value {
+    fn from(s: Set) -> Value {
+        Value::Set(Rc::new(s))
+    }
+}
+
+/// A `HashSet` is converted into a set value.
+///
+/// # Panics
+///
+/// If any element is converted into a value which may not be
+/// a member of a set, such as a float.
+impl<T: Into<Value> + Eq + Hash> From<HashSet<T>> for Value {
+    fn from(s: HashSet<T>) -> Value {
+        let mut set = Set::new();
+
+        for v in s {
+            set.insert(v.into()).expect("invalid set value");
+        }
+
+        set.into()
+    }
+}
+
+/// A `HashMap` is converted into a map value.
+///
+/// # Panics
+///
+/// If any key is converted into a value which may not be
+/// a map key, such as a float.
+impl<K: Into<Value>, V: Into<Value>> From<HashMap<K, V>> for Value {
+    fn from(m: HashMap<K, V>) -> Value {
+        let mut map = Map::new();
+
+        for (k, v) in m {
+            map.insert(k.into(), v.into()).expect("invalid map key");
+        }
+
+        map.into()
+    }
+}
+
+/// `None` is converted into `()`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        match v {
+            Some(v) => v.into(),
+            None => Value::Unit
+        }
+    }
+}
+
+/// `Ok(v)` is converted into a list `(:ok v)` and `Err(e)` is converted
+/// into a list `(:error e)`.
+impl<T: Into<Value>, E: Into<Value>> From<Result<T, E>> for Value {
+    fn from(v: Result<T, E>) -> Value {
+        let (tag, v) = match v {
+            Ok(v) => (standard_names::OK, v.into()),
+            Err(e) => (standard_names::ERROR, e.into())
+        };
+
+        vec![Value::Keyword(tag), v].into()
+    }
+}
+
+/// A `Duration` is converted into an integer number of seconds or,
+/// if it contains a fractional second, a ratio.
+impl From<Duration> for Value {
+    fn from(d: Duration) -> Value {
+        seconds_to_value(d, false)
+    }
+}
+
+/// A `SystemTime` is converted into a number of seconds
+/// relative to the Unix epoch.
+impl From<SystemTime> for Value {
+    fn from(t: SystemTime) -> Value {
+        match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => seconds_to_value(d, false),
+            Err(e) => seconds_to_value(e.duration(), true)
+        }
+    }
+}
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// Converts a `Duration`, negated if `negative` is `true`,
+/// into an integer or ratio number of seconds.
+fn seconds_to_value(d: Duration, negative: bool) -> Value {
+    let secs = Integer::from_u64(d.as_secs());
+    let nanos = d.subsec_nanos();
+
+    if nanos == 0 {
+        Value::Integer(if negative { -secs } else { secs })
+    } else {
+        let billion = Integer::from_u32(NANOS_PER_SEC);
+        let n = secs * &billion + Integer::from_u32(nanos);
+        Value::Ratio(Ratio::new(if negative { -n } else { n }, billion))
+    }
+}
+
+/// Converts a number of seconds into a `Duration` and a flag indicating
+/// whether the value is negative. Precision beyond nanoseconds is truncated.
+fn value_to_seconds(v: &Value) -> Result<(Duration, bool), ExecError> {
+    match *v {
+        Value::Integer(ref i) => {
+            let secs = try!(i.abs().to_u64().ok_or(ExecError::Overflow));
+            Ok((Duration::new(secs, 0), i.is_negative()))
+        }
+        Value::Ratio(ref r) => {
+            let abs = r.abs();
+            let secs = try!(abs.trunc().to_integer().to_u64()
+                .ok_or(ExecError::Overflow));
+            let billion = Ratio::from_integer(Integer::from_u32(NANOS_PER_SEC));
+            let nanos = (abs.fract() * billion).trunc().to_integer()
+                .to_u32().unwrap_or(0);
+            Ok((Duration::new(secs, nanos), r.is_negative()))
+        }
+        Value::Float(f) => {
+            if !f.is_finite() {
+                return Err(ExecError::expected("finite number", v));
+            }
+            let abs = f.abs();
+            if abs >= 18446744073709551616.0 {
+                return Err(ExecError::Overflow);
+            }
+            let nanos = (abs.fract() * NANOS_PER_SEC as f64) as u32;
+            Ok((Duration::new(abs.trunc() as u64, nanos.min(NANOS_PER_SEC - 1)),
+                f < 0.0))
+        }
+        ref v => Err(ExecError::expected("number", v))
+    }
+}
+
 macro_rules! from_integer {
     ( $ty:ident $meth:ident ) => {
         impl From<$ty> for Value {
@@ -1058,6 +2087,34 @@ impl Struct {
     }
 }
 
+/// Represents a Rust type which is converted to and from a `ketos` struct value.
+///
+/// Implementations are usually generated using the `struct_conversions!` macro.
+/// The struct definition must be added to a scope, using
+/// `GlobalScope::add_struct_def`, before values are converted.
+pub trait StructValue: Sized {
+    /// Returns the name of the struct type.
+    fn struct_name() -> &'static str;
+
+    /// Returns the names of struct fields and names of the types
+    /// expected for each field.
+    fn struct_fields() -> &'static [(&'static str, &'static str)];
+
+    /// Converts the value into a series of field values,
+    /// in the order given by `struct_fields`.
+    fn into_fields(self) -> Vec<Value>;
+
+    /// Constructs a value from a series of field values,
+    /// in the order given by `struct_fields`.
+    fn from_fields(fields: Vec<Value>) -> Result<Self, ExecError>;
+}
+
+/// Returns the `ketos` name of a struct field given its Rust name,
+/// replacing underscores with hyphens.
+pub fn struct_field_name(name: &str) -> String {
+    name.replace('_', "-")
+}
+
 /// Represents the definition of a class of struct value
 #[derive(Clone, Debug)]
 pub struct StructDef {
@@ -1067,6 +2124,10 @@ pub struct StructDef {
     // TODO: Name-based type-checking prevents a StructDef from requiring
     // a specific class of Struct value for a field.
     pub fields: NameMapSlice<Name>,
+    /// Documentation of the struct, taken from a preceding doc comment
+    pub doc: Option<String>,
+    /// Documentation of struct fields, taken from preceding doc comments
+    pub field_docs: NameMapSlice<String>,
 }
 
 impl PartialEq for StructDef {
@@ -1081,6 +2142,13 @@ impl StructDef {
         StructDef{
             name: name,
             fields: fields,
+            doc: None,
+            field_docs: NameMapSlice::default(),
         }
     }
+
+    /// Returns the documentation of the named field, if any.
+    pub fn field_doc(&self, name: Name) -> Option<&str> {
+        self.field_docs.get(name).map(|s| &s[..])
+    }
 }
@@ -1,6 +1,7 @@
 extern crate ketos;
 
 use ketos::{Error, Interpreter, Value};
+use ketos::bytecode::{disassemble, disassemble_lambda, Instruction};
 use ketos::bytecode::opcodes::*;
 use ketos::name::standard_names;
 
@@ -117,6 +118,29 @@ fn test_lambda() {
     ]);
 }
 
+#[test]
+fn test_push_combined() {
+    assert_eq!(lambda("(define (foo a) (foo (- a 1)))").unwrap(), [
+        LOAD_0,
+        DEC_PUSH,
+        TAIL_CALL, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (foo (+ a 1)))").unwrap(), [
+        LOAD_0,
+        INC_PUSH,
+        TAIL_CALL, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a b) (foo (+ a b) b))").unwrap(), [
+        LOAD_PUSH_0,
+        LOAD_PUSH_1,
+        CALL_SYS_ARGS_PUSH, standard_names::ADD.get() as u8, 2,
+        LOAD_PUSH_1,
+        TAIL_CALL, 2,
+    ]);
+}
+
 #[test]
 fn test_call_self() {
     assert_eq!(lambda("(define (foo a) (do (foo a) ()))").unwrap(), [
@@ -168,3 +192,161 @@ fn test_tail_recursion() {
         TAIL_CALL, 0,
     ]);
 }
+
+#[test]
+fn test_tail_call() {
+    assert_eq!(lambda("(define (foo a) (bar a))").unwrap(), [
+        LOAD_PUSH_0,
+        TAIL_CALL_CONST, 0, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (a 1))").unwrap(), [
+        LOAD_PUSH_0,
+        CONST_PUSH_0,
+        TAIL_CALL_FUNCTION, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (apply a 1 a))").unwrap(), [
+        LOAD_PUSH_0,
+        CONST_PUSH_0,
+        LOAD_0,
+        TAIL_APPLY, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (let ((b a)) (bar b)))").unwrap(), [
+        LOAD_PUSH_0,
+        LOAD_PUSH_1,
+        TAIL_CALL_CONST, 0, 1,
+    ]);
+
+    // Calls not in tail position are unaffected
+    assert_eq!(lambda("(define (foo a) (do (bar a) ()))").unwrap(), [
+        LOAD_PUSH_0,
+        CALL_CONST_0, 1,
+        UNIT,
+        RETURN,
+    ]);
+}
+
+#[test]
+fn test_optimize_jumps() {
+    // Jumps to a jump on the same test are threaded
+    assert_eq!(lambda("(define (foo a b) (if (and a b) 1 2))").unwrap(), [
+        LOAD_0,
+        JUMP_IF_NOT, 8,
+        LOAD_1,
+        JUMP_IF_NOT, 8,
+        CONST_0,
+        RETURN,
+        CONST_1,
+        RETURN,
+    ]);
+
+    // Jumps to a test of a constant value are resolved
+    assert_eq!(lambda("(define (foo a) (if (if a false true) 1 2))").unwrap(), [
+        LOAD_0,
+        JUMP_IF, 5,
+        CONST_0,
+        RETURN,
+        CONST_1,
+        RETURN,
+    ]);
+
+    // Conditional branches on a constant value are pruned
+    assert_eq!(lambda("(define (foo a) (if true a 2))").unwrap(), [
+        LOAD_0,
+        RETURN,
+    ]);
+}
+
+#[test]
+fn test_optimize_stack() {
+    // Values pushed and immediately skipped are removed
+    assert_eq!(lambda("(define (foo a) (do (let ((b a)) b) a))").unwrap(), [
+        LOAD_0,
+        RETURN,
+    ]);
+
+    // Operations which may fail are preserved
+    assert_eq!(lambda("(define (foo a) (do (let ((b (first a))) ()) a))").unwrap(), [
+        LOAD_0,
+        FIRST,
+        LOAD_0,
+        RETURN,
+    ]);
+}
+
+#[test]
+fn test_disassemble() {
+    let interp = Interpreter::new();
+    let exprs = interp.compile_exprs(
+        "(define (foo a) (if (= a 1) (bar a) (+ a 2)))").unwrap();
+
+    let l = match exprs[0].consts[1] {
+        Value::Lambda(ref l) => l.clone(),
+        ref v => panic!("expected lambda; got {}", v.type_name())
+    };
+
+    let instrs = l.code.instructions().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(instrs, [
+        (0, Instruction::Load(0)),
+        (1, Instruction::JumpIfNotEqConst(8, 0)),
+        (4, Instruction::LoadPush(0)),
+        (5, Instruction::TailCallConst(1, 1)),
+        (8, Instruction::LoadPush(0)),
+        (9, Instruction::ConstPush(2)),
+        (10, Instruction::CallSysArgs(standard_names::ADD.get(), 2)),
+        (13, Instruction::Return),
+    ]);
+
+    let names = interp.get_scope().borrow_names();
+    let text = disassemble(&l.code, &names);
+
+    assert_eq!(text, disassemble_lambda(&l, &names));
+    assert_eq!(text.lines().collect::<Vec<_>>(), [
+        "8 bytecode instructions:",
+        "        0  Load(0)",
+        "        1  JumpIfNotEqConst(8, 0)         ; L8 1",
+        "        4  LoadPush(0)",
+        "        5  TailCallConst(1, 1)            ; bar",
+        "  >>    8  LoadPush(0)",
+        "        9  ConstPush(2)                   ; 2",
+        "       10  CallSysArgs(0, 2)              ; +",
+        "       13  Return",
+    ]);
+}
+
+#[test]
+fn test_error_span() {
+    let interp = Interpreter::new();
+    let src = "(define (foo a)\n  (bar (if)))";
+
+    assert!(interp.run_code(src, None).is_err());
+
+    let span = interp.error_span().unwrap();
+    assert_eq!(&src[span.lo as usize..span.hi as usize], "(if)");
+
+    assert!(interp.run_code("(define (bar) (foo))", None).is_ok());
+    assert_eq!(interp.error_span(), None);
+}
+
+#[test]
+fn test_code_spans() {
+    let interp = Interpreter::new();
+    let src = "(define (foo a) (if (= a 1) (bar a) (+ a 2)))";
+    let exprs = interp.compile_exprs(src).unwrap();
+
+    let l = match exprs[0].consts[1] {
+        Value::Lambda(ref l) => l.clone(),
+        ref v => panic!("expected lambda; got {}", v.type_name())
+    };
+
+    let text = |off| {
+        let sp = l.code.span_at(off).unwrap();
+        &src[sp.lo as usize..sp.hi as usize]
+    };
+
+    assert_eq!(text(1), "(= a 1)");
+    assert_eq!(text(5), "(bar a)");
+    assert_eq!(text(10), "(+ a 2)");
+}
@@ -1,6 +1,11 @@
 extern crate ketos;
 
-use ketos::{ExecError, FromValue, FromValueRef, Value};
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ketos::{ExecError, FromValue, FromValueRef, Integer, Map, Ratio, Set, Value};
+use ketos::rc_string::RcString;
 
 macro_rules! assert_matches {
     ( $e:expr, $pat:pat ) => {
@@ -36,9 +41,21 @@ fn test_from_value() {
     assert_eq!(from::<()>(Value::Unit).unwrap(), ());
     assert_eq!(from::<i32>(into(123)).unwrap(), 123);
     assert_eq!(from::<String>(into("foo")).unwrap(), "foo");
+    assert_eq!(from::<RcString>(into("foo")).unwrap(), "foo");
 
-    assert_eq!(from::<Vec<i32>>(Value::Unit).unwrap(), vec![]);
+    assert_eq!(from::<Vec<i32>>(Value::Unit).unwrap(), Vec::<i32>::new());
     assert_eq!(from::<Vec<i32>>(into(vec![1, 2, 3])).unwrap(), vec![1, 2, 3]);
+
+    let map = from::<HashMap<String, i32>>(into(string_map())).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["a"], 1);
+    assert_eq!(map["b"], 2);
+    assert_matches!(from::<HashMap<String, i32>>(Value::Unit),
+        Err(ExecError::TypeError{expected: "map", found: "unit"}));
+
+    let set = from::<HashSet<i32>>(into(vec![1, 2, 2].into_iter().collect::<HashSet<_>>()))
+        .unwrap();
+    assert_eq!(set, [1, 2].iter().cloned().collect());
 }
 
 #[test]
@@ -47,6 +64,24 @@ fn test_from_value_ref() {
     assert_eq!(from_ref::<i32>(&into(456)).unwrap(), 456);
     assert_eq!(from_ref::<&str>(&into("foo")).unwrap(), "foo");
     assert_eq!(from_ref::<Vec<i32>>(&into(vec![1, 2, 3])).unwrap(), vec![1, 2, 3]);
+
+    let v = into(string_map());
+    let map = from_ref::<HashMap<&str, i32>>(&v).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["a"], 1);
+    assert_eq!(from_ref::<&Map>(&v).unwrap().get(&into("b")).map(|v| v.type_name()),
+        Some("integer"));
+
+    let v = into(["a", "b"].iter().cloned().collect::<HashSet<_>>());
+    assert_eq!(from_ref::<HashSet<&str>>(&v).unwrap(), ["a", "b"].iter().cloned().collect());
+    assert_eq!(from_ref::<&Set>(&v).unwrap().len(), 2);
+}
+
+fn string_map() -> Map {
+    let mut m = Map::new();
+    m.insert(into("a"), into(1)).unwrap();
+    m.insert(into("b"), into(2)).unwrap();
+    m
 }
 
 #[test]
@@ -56,8 +91,102 @@ fn test_into_value() {
     assert_matches!(into(1.0_f64), Value::Float(1.0));
     assert_matches!(into(123),
         Value::Integer(ref i) if i.to_u32() == Some(123));
-    assert_matches!(into("foo"), Value::String(ref s) if s == "foo");
+    assert_matches!(into("foo"), Value::String(ref s) if &**s == "foo");
+
+    assert_matches!(into(RcString::from("foo")), Value::String(ref s) if s == "foo");
 
     assert_matches!(into(Vec::<i32>::new()), Value::Unit);
     assert_matches!(into(Vec::<Value>::new()), Value::Unit);
 }
+
+#[test]
+fn test_container_conversions() {
+    assert_eq!(from::<Option<i32>>(Value::Unit).unwrap(), None);
+    assert_eq!(from::<Option<i32>>(into(1)).unwrap(), Some(1));
+    assert_eq!(from_ref::<Option<&str>>(&into("a")).unwrap(), Some("a"));
+    assert_matches!(into(None::<i32>), Value::Unit);
+    assert_matches!(into(Some("a")), Value::String(ref s) if s == "a");
+
+    let v = into((1, "a", true));
+    assert_eq!(from::<(i32, String, bool)>(v.clone()).unwrap(), (1, "a".to_owned(), true));
+    assert_eq!(from_ref::<(i32, &str, bool)>(&v).unwrap(), (1, "a", true));
+    assert_matches!(from::<(i32, i32)>(v.clone()),
+        Err(ExecError::PatternMismatch{expected: 2, found: 3, ..}));
+    assert_matches!(from_ref::<(i32,)>(&Value::Unit),
+        Err(ExecError::PatternMismatch{expected: 1, found: 0, ..}));
+
+    let ok = into(Ok::<i32, String>(1));
+    let err = into(Err::<i32, String>("bad".to_owned()));
+    assert_eq!(from::<Result<i32, String>>(ok.clone()).unwrap(), Ok(1));
+    assert_eq!(from::<Result<i32, String>>(err.clone()).unwrap(), Err("bad".to_owned()));
+    assert_eq!(from_ref::<Result<i32, &str>>(&err).unwrap(), Err("bad"));
+    assert_matches!(from::<Result<i32, String>>(into(1)),
+        Err(ExecError::TypeError{expected: "result", found: "integer"}));
+    assert_matches!(from_ref::<Result<i32, i32>>(&into((1, 2))),
+        Err(ExecError::TypeError{expected: "result", found: "list"}));
+
+    let mut map = HashMap::new();
+    map.insert("a".to_owned(), vec![1, 2]);
+    map.insert("b".to_owned(), vec![]);
+    let v = into(map.clone());
+    assert_matches!(v, Value::Map(ref m) if m.len() == 2);
+    assert_eq!(from::<HashMap<String, Vec<i32>>>(v).unwrap(), map);
+}
+
+#[test]
+fn test_time_conversions() {
+    assert_matches!(into(Duration::from_secs(5)),
+        Value::Integer(ref i) if i.to_u32() == Some(5));
+    assert_matches!(into(Duration::from_millis(1500)),
+        Value::Ratio(ref r) if r.numer().to_u32() == Some(3) && r.denom().to_u32() == Some(2));
+
+    assert_eq!(from::<Duration>(into(3)).unwrap(), Duration::from_secs(3));
+    assert_eq!(from::<Duration>(Value::Float(0.25)).unwrap(), Duration::from_millis(250));
+    assert_eq!(from_ref::<Duration>(&into(Duration::new(7, 123))).unwrap(),
+        Duration::new(7, 123));
+    assert_matches!(from::<Duration>(into(-1)), Err(ExecError::TypeError{..}));
+    assert_matches!(from::<Duration>(into("1")), Err(ExecError::TypeError{..}));
+
+    let t = UNIX_EPOCH + Duration::new(1000, 500);
+    assert_eq!(from::<SystemTime>(into(t)).unwrap(), t);
+
+    let t = UNIX_EPOCH - Duration::from_secs(60);
+    assert_matches!(into(t), Value::Integer(ref i) if i.to_i32() == Some(-60));
+    assert_eq!(from::<SystemTime>(into(-60)).unwrap(), t);
+}
+
+#[test]
+fn test_string_clone() {
+    let a = into("foo");
+    let b = a.clone();
+
+    match (&a, &b) {
+        (&Value::String(ref a), &Value::String(ref b)) => assert!(a.shares_buffer(b)),
+        _ => panic!("expected strings")
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn test_value_size() {
+    // Prior to boxing large variants, `Value` occupied 72 bytes,
+    // `Integer` 32 bytes, and `Ratio` 64 bytes.
+    assert_eq!(size_of::<Value>(), 24);
+    assert_eq!(size_of::<Integer>(), 8);
+    assert_eq!(size_of::<Ratio>(), 16);
+}
+
+#[test]
+fn test_ratio_parts() {
+    let r = Ratio::new(Integer::from_i32(6), Integer::from_i32(-4));
+    let numer: &Integer = r.numer();
+
+    assert_eq!(*numer, Integer::from_i32(-3));
+    assert_eq!(*r.denom(), Integer::from_i32(2));
+    assert_eq!(r.to_string(), "-3/2");
+    assert_eq!(r.to_integer(), Integer::from_i32(-1));
+    assert!(r < Ratio::new(Integer::from_i32(-4), Integer::from_i32(3)));
+    assert!(Ratio::from_integer(Integer::from_i32(2)) > r);
+    assert_eq!(r.clone().into_numer(), Integer::from_i32(-3));
+    assert_eq!(r.into_denom(), Integer::from_i32(2));
+}
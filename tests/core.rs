@@ -1,6 +1,22 @@
 extern crate ketos;
 
-use ketos::{CompileError, Error, ExecError, Interpreter, FromValue, Value};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt::Arguments;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use ketos::{Arity, BuiltinModuleLoader, Callable, CompileError, Error, ExecError, GlobalScope,
+    Interpreter, FromValue, NameStore, ParseError, ParseErrorKind, RestrictConfig, ResultExt,
+    Scope, Value};
+use ketos::exec::call_function;
+use ketos::io::{IoError, Port, SharedReader, SharedWrite};
+use ketos::lexer::CodeMap;
+use ketos::module::ModuleRegistry;
+use ketos::scope::{FlushPolicy, GlobalIo};
+use ketos::name::{debug_names, display_names};
+use ketos::string_fmt::{FormatError, FormatParam};
+use ketos::value::Labeled;
 
 macro_rules! assert_matches {
     ( $e:expr, $pat:pat ) => {
@@ -62,6 +78,14 @@ fn test_const() {
         ").unwrap(),
         ["foo", "bar", "baz",
         "(foo 1 2 3)", "(bar (foo 1 2 3))", "(baz foo 1 2 3)"]);
+
+    assert_eq!(run("
+        (* 2 (if true 3 4))
+        (list (if false 1 2))
+        (if false 1)
+        (const foo (if true 5))
+        foo
+        ").unwrap(), ["6", "(2)", "()", "foo", "5"]);
 }
 
 #[test]
@@ -90,6 +114,7 @@ fn test_integer() {
 
 #[test]
 fn test_quasiquote() {
+    assert_eq!(eval("`(,1 2)").unwrap(), "(1 2)");
     assert_eq!(eval("`foo").unwrap(), "foo");
     assert_eq!(eval("``foo").unwrap(), "`foo");
     assert_eq!(eval("```foo").unwrap(), "``foo");
@@ -108,6 +133,68 @@ fn test_quasiquote() {
         "(foo 1 2 3 bar 4 5 6 baz)");
 }
 
+#[test]
+fn test_decimal_literal() {
+    assert_eq!(eval("(+ 0.1d 0.2d)").unwrap(), "3/10");
+    assert_eq!(eval("(= (+ 0.1d 0.2d) 0.3d)").unwrap(), "true");
+    assert_eq!(eval("(* 19.99d 3)").unwrap(), "5997/100");
+}
+
+#[test]
+fn test_bracket_list() {
+    assert_eq!(eval("[1 (+ 1 1) [3 'a]]").unwrap(), "(1 2 (3 a))");
+    assert_eq!(eval("[]").unwrap(), "()");
+    assert_eq!(eval("(let ((a 1)) [a a])").unwrap(), "(1 1)");
+    assert_eq!(eval("'[b c]").unwrap(), "(b c)");
+    assert_eq!(eval("'(a [b [c]])").unwrap(), "(a (b (c)))");
+    assert_eq!(eval("(let ((b 2)) `(a [b ,b]))").unwrap(), "(a (b 2))");
+    assert_eq!(eval("(let ((b 2)) `(a ,[b b]))").unwrap(), "(a (2 2))");
+    assert_eq!(eval("(let ((b '(1 2))) `(a ,@[b b]))").unwrap(), "(a (1 2) (1 2))");
+    assert_eq!(eval("(let ((list 1)) [list list])").unwrap(), "(1 1)");
+}
+
+#[test]
+fn test_unicode_names() {
+    // Composed and decomposed forms of the same name are equal
+    assert_eq!(eval("(let ((caf\u{e9} 1)) cafe\u{301})").unwrap(), "1");
+    assert_eq!(eval("(= 'caf\u{e9} 'cafe\u{301})").unwrap(), "true");
+    assert_eq!(eval("(id '\u{3bb})").unwrap(), "\u{3bb}");
+}
+
+#[test]
+fn test_parse_expr_prefix() {
+    let interp = Interpreter::new();
+
+    let input = "{{ (+ 1 2) }} and {{ foo}}";
+
+    let (v, end) = interp.parse_expr_prefix(&input[2..], None).unwrap();
+    assert_eq!(interp.format_value(&v), "(+ 1 2)");
+    assert_eq!(&input[2 + end..], " }} and {{ foo}}");
+
+    let (v, end) = interp.parse_expr_prefix(&input[20..], None).unwrap();
+    assert_eq!(interp.format_value(&v), "foo");
+    assert_eq!(&input[20 + end..], "}}");
+
+    assert_matches!(interp.parse_expr_prefix(" ; nothing", None).unwrap_err(),
+        Error::ParseError(_));
+}
+
+#[test]
+fn test_datum_labels() {
+    let interp = Interpreter::new();
+
+    let v = interp.parse_single_expr("(#0=(a b) #1=(c #0#) #1# '#0#)", None).unwrap();
+
+    assert_eq!(interp.format_value(&v), "((a b) (c (a b)) (c (a b)) '(a b))");
+    assert_eq!(debug_names(&interp.get_scope().borrow_names(), &Labeled(&v)).to_string(),
+        "(#0=(a b) #1=(c #0#) #1# '#0#)");
+
+    let v = interp.run_code("(define a '(1 2)) (list a (tail a) a)", None).unwrap();
+
+    assert_eq!(debug_names(&interp.get_scope().borrow_names(), &Labeled(&v)).to_string(),
+        "(#0=(1 2) (2) #0#)");
+}
+
 #[test]
 fn test_struct() {
     assert_eq!(run("
@@ -207,6 +294,12 @@ fn test_format() {
 
     assert_eq!(eval_str(r#"(format "~,,3,'-a" "foo")"#).unwrap(), "foo---");
 
+    assert_eq!(eval_str(r#"(format "~9,,,'*:a" "foo")"#).unwrap(), "***foo***");
+    assert_eq!(eval_str(r#"(format "~6:a" "foo")"#).unwrap(), " foo  ");
+    assert_eq!(eval_str(r#"(format "~5,,,,3a" "foobar")"#).unwrap(), "foo  ");
+    assert_eq!(eval_str(r#"(format "~,,,,4s" "foobar")"#).unwrap(), "\"foo");
+    assert_eq!(eval_str(r#"(format "~4@a" "été")"#).unwrap(), " été");
+
     assert_eq!(eval_str(r#"(format "~a" 123)"#).unwrap(), "123");
     assert_eq!(eval_str(r#"(format "~s" 123)"#).unwrap(), "123");
     assert_eq!(eval_str(r#"(format "~s" 'foo)"#).unwrap(), "foo");
@@ -229,6 +322,16 @@ fn test_format() {
     assert_eq!(eval_str(r#"(format "~,3f" 1.0)"#).unwrap(), "1.000");
     assert_eq!(eval_str(r#"(format "~4,,'*f" 1.0)"#).unwrap(), "***1");
     assert_eq!(eval_str(r#"(format "~4,,'*e" 1.0)"#).unwrap(), "*1e0");
+    assert_eq!(eval_str(r#"(format "~8,2f" 3.14159)"#).unwrap(), "    3.14");
+    assert_eq!(eval_str(r#"(format "~8,2:f|" 3.14159)"#).unwrap(), "3.14    |");
+    assert_eq!(eval_str(r#"(format "~8,2,'0@f" 3.14159)"#).unwrap(), "+0003.14");
+    assert_eq!(eval_str(r#"(format "~6,,'0f" -1.5)"#).unwrap(), "-001.5");
+    assert_eq!(eval_str(r#"(format "~,2,,',f" 1234567.891)"#).unwrap(), "1,234,567.89");
+    assert_eq!(eval_str(r#"(format "~,2,,',f" -1234.5)"#).unwrap(), "-1,234.50");
+    assert_eq!(eval_str(r#"(format "~,,,'_,2@f" 12345.0)"#).unwrap(), "+1_23_45");
+    assert_eq!(eval_str(r#"(format "~12,1,,',f" 999.0)"#).unwrap(), "       999.0");
+    assert_eq!(eval_str(r#"(format "~,,,',f" 123.0)"#).unwrap(), "123");
+    assert_eq!(eval_str(r#"(format "~,,,0:d" 12345)"#).unwrap(), "12345");
 
     assert_eq!(eval_str(r#"(format "~@d" 0)"#).unwrap(), "+0");
     assert_eq!(eval_str(r#"(format "~@d" 1)"#).unwrap(), "+1");
@@ -390,6 +493,543 @@ fn test_format() {
         Error::ExecError(ExecError::FormatError{..}));
 }
 
+#[test]
+fn test_format_repeated() {
+    assert_eq!(run(r#"
+        (define (show a b) (format "~a: ~
+                                    ~s~%" a b))
+        (show 1 "one")
+        (show 2 "two")
+        (define (choose b) (format "~:[no~;yes~]" b))
+        (choose true)
+        (choose false)
+        "#).unwrap(), [
+            "show",
+            r#""1: \"one\"\n""#,
+            r#""2: \"two\"\n""#,
+            "choose",
+            r#""yes""#,
+            r#""no""#,
+        ]);
+
+    assert_matches!(run(r#"
+        (define (bad) (format "~a ~a" 1))
+        (bad)
+        "#).unwrap_err(),
+        Error::ExecError(ExecError::FormatError{..}));
+}
+
+#[test]
+fn test_format_named() {
+    assert_eq!(eval_str(r#"(format "~`name`a is ~`age`d" :age 30 :name "Bob")"#).unwrap(),
+        "Bob is 30");
+    assert_eq!(eval_str(r#"(format "~a ~`x`a ~a" 1 2 :x 3)"#).unwrap(), "1 3 2");
+    assert_eq!(eval_str(r#"(format "~s ~`x`a" :foo :x 1)"#).unwrap(), ":foo 1");
+    assert_eq!(eval_str(r#"(format "~`x`5,2f|~`x`8:a|" :x 1.5)"#).unwrap(),
+        " 1.50|  1.5   |");
+    assert_eq!(eval_str(r#"(format "~a ~a" :x 1)"#).unwrap(), ":x 1");
+    assert_eq!(eval_str(r#"(format "~{~a~}" '(1 2))"#).unwrap(), "12");
+    assert_eq!(eval_str(r#"(format "~{x}~a~}" '(1 2))"#).unwrap(), "x}1x}2");
+    assert_eq!(eval_str(r#"(format "~{x}a~a~}" '(1 2))"#).unwrap(), "x}a1x}a2");
+    assert_eq!(eval_str(r#"(format "~{x}a~a~}" '(1 2) :x 3)"#).unwrap(),
+        "x}a1x}a2");
+
+    assert_eq!(run(r#"
+        (define (greet name) (format "Hello, ~`who`a!" :who name))
+        (greet "world")
+        "#).unwrap(), ["greet", r#""Hello, world!""#]);
+
+    assert_matches!(eval_str(r#"(format "~`x`a" :y 1)"#).unwrap_err(),
+        Error::ExecError(ExecError::FormatError{
+            err: FormatError::UndefinedArgument(_), ..}));
+    assert_matches!(eval_str(r#"(format "~`x`%" :x 1)"#).unwrap_err(),
+        Error::ExecError(ExecError::FormatError{
+            err: FormatError::UnexpectedName, ..}));
+}
+
+#[test]
+fn test_format_custom_directive() {
+    let interp = Interpreter::new();
+
+    interp.get_scope().get_format_directives().add('z', |_names, v, dir| {
+        let n = match *v {
+            Value::Integer(ref i) => i.to_string(),
+            ref v => return Err(FormatError::expected("integer", v))
+        };
+
+        let sep = match dir.params.get(0) {
+            Some(&Some(FormatParam::Char(ch))) => ch,
+            _ => ':'
+        };
+
+        Ok(if dir.at { format!("#{}{}", sep, n) } else { format!("id{}{}", sep, n) })
+    });
+
+    let eval = |s: &str| -> Result<String, Error> {
+        let v = try!(interp.run_single_expr(s, None));
+        Ok(FromValue::from_value(v).unwrap())
+    };
+
+    assert_eq!(eval(r#"(format "~z" 1)"#).unwrap(), "id:1");
+    assert_eq!(eval(r#"(format "~'-@Z" 2)"#).unwrap(), "#-2");
+    assert_eq!(eval(r#"(format "~va ~z" 3 "x" 4)"#).unwrap(), "x   id:4");
+    assert_eq!(eval(r#"(format "~{~z~^, ~}" '(1 2))"#).unwrap(), "id:1, id:2");
+    assert_eq!(eval(r#"(format "~`n`z" :n 5)"#).unwrap(), "id:5");
+
+    assert_matches!(eval(r#"(format "~z" "foo")"#).unwrap_err(),
+        Error::ExecError(ExecError::FormatError{
+            err: FormatError::TypeError{..}, ..}));
+    assert_matches!(eval(r#"(format "~y" 1)"#).unwrap_err(),
+        Error::ExecError(ExecError::FormatError{
+            err: FormatError::UnrecognizedDirective('y'), ..}));
+}
+
+struct Capture(RefCell<String>);
+
+impl SharedWrite for Capture {
+    fn write_all(&self, buf: &[u8]) -> Result<(), IoError> {
+        self.0.borrow_mut().push_str(std::str::from_utf8(buf).unwrap());
+        Ok(())
+    }
+
+    fn write_fmt(&self, fmt: Arguments) -> Result<(), IoError> {
+        use std::fmt::Write;
+        self.0.borrow_mut().write_fmt(fmt).unwrap();
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), IoError> { Ok(()) }
+}
+
+#[test]
+fn test_error_context() {
+    let interp = Interpreter::new();
+
+    let err = interp.run_code("(define foo 1) (foo-bar)", None)
+        .context("while evaluating hook on-save")
+        .with_context(|| format!("while loading plugin {}", "x"))
+        .unwrap_err();
+
+    assert_matches!(err.root(), &Error::ExecError(ExecError::NameError(_)));
+    assert_eq!(display_names(&interp.get_scope().borrow_names(), &err).to_string(),
+        "while loading plugin x: while evaluating hook on-save: \
+         name not found in global scope: foo-bar");
+
+    let r: Result<(), ExecError> = Ok(());
+    assert!(r.with_context(|| -> String { panic!("context evaluated") }).is_ok());
+}
+
+#[test]
+fn test_error_report() {
+    let interp = Interpreter::new();
+
+    let err = interp.run_code("(+ 1\n  (foo \"bar)", Some("a.ket".to_owned())).unwrap_err();
+    let report = interp.error_report(&err);
+
+    assert_eq!(report.code, "parse/unterminated-string");
+    assert_eq!(report.file, Some("a.ket".to_owned()));
+    assert_eq!(report.line, Some(2));
+    assert_eq!(report.column, Some(7));
+    assert!(report.span.is_some());
+
+    let err = interp.run_code("(define (f) (g 1 2 3)) (define (g a) a) (f)", None)
+        .context("while running f").unwrap_err();
+    let report = interp.error_report(&err);
+
+    assert_eq!(report.code, "exec/arity-error");
+    assert_eq!(report.message, "`g` expected 1 argument; found 3");
+    assert_eq!(report.context, ["while running f"]);
+    assert!(report.span.is_some());
+    assert_eq!(report.line, Some(1));
+    assert_eq!(report.column, Some(12));
+
+    let loc = interp.error_location().unwrap();
+    assert_eq!(loc.function, interp.get_scope().borrow_names().get_name("f"));
+
+    let err = interp.run_code("(define (h a) (+ 1 a))\n(h \"x\")", Some("b.ket".to_owned()))
+        .unwrap_err();
+    assert_eq!(interp.format_error(&err),
+        "b.ket:1:14:execution error (in function `h`): type error: expected number; found string\n\
+         \x20   (define (h a) (+ 1 a))\n\
+         \x20                 ^~~~~~~\n");
+
+    let err = interp.call("h", vec!["x".into()]).unwrap_err();
+    assert_eq!(interp.error_report(&err).line, Some(1));
+
+    let err = interp.run_code("(let ((a)) a)", None).unwrap_err();
+    assert_eq!(interp.error_report(&err).code, "compile/syntax-error");
+
+    let err = interp.run_code("(< 1.0 (nan))", None).unwrap_err();
+    assert_eq!(interp.error_report(&err).code, "exec/compare-nan");
+}
+
+#[test]
+fn test_error_trace() {
+    let interp = Interpreter::new();
+
+    let err = interp.run_code("(define (f a) (+ 1 a))\n\
+                               (define (g) (list (f \"x\")))\n\
+                               (list (g))", Some("c.ket".to_owned())).unwrap_err();
+
+    let report = interp.error_report(&err);
+    assert_eq!(report.backtrace, [
+        "`f` at c.ket:1:14",
+        "`g` at c.ket:2:18",
+        "<anonymous> at c.ket:3:6",
+    ]);
+
+    assert_eq!(interp.format_error(&err),
+        "c.ket:1:14:execution error (in function `f`): type error: expected number; found string\n\
+         \x20   (define (f a) (+ 1 a))\n\
+         \x20                 ^~~~~~~\n\
+         traceback (innermost call first):\n\
+         \x20 in `f` at c.ket:1:14\n\
+         \x20 in `g` at c.ket:2:18\n\
+         \x20 in <anonymous> at c.ket:3:6\n");
+
+    assert_eq!(interp.error_trace().len(), 3);
+
+    interp.run_code("(g)", None).unwrap_err();
+    assert_eq!(interp.error_trace().len(), 2);
+
+    interp.run_code("(f 1)", None).unwrap();
+    assert!(interp.error_trace().is_empty());
+
+    let err = interp.run_code("(car ())", None).unwrap_err();
+    assert_eq!(interp.error_report(&err).backtrace.len(), 1);
+}
+
+#[test]
+fn test_render_error() {
+    let interp = Interpreter::new();
+
+    let err = interp.run_code("(define (f a) (+ 1 a))\n(list (f \"x\"))",
+        Some("d.ket".to_owned())).context("while testing").unwrap_err();
+
+    assert_eq!(interp.render_error(&err),
+        "error[exec/type-error]: type error: expected number; found string\n\
+         \x20--> d.ket:1:14\n\
+         \x20 |\n\
+         1 | (define (f a) (+ 1 a))\n\
+         \x20 |               ^~~~~~~\n\
+         \x20 = note: while testing\n\
+         \x20 = note: in `f` at d.ket:1:14\n\
+         \x20 = note: in <anonymous> at d.ket:2:6\n");
+
+    let err = interp.call("foo", vec![]).context("while calling").unwrap_err();
+    assert_eq!(interp.render_error(&err),
+        "error[exec/name-error]: name not found in global scope: foo\n\
+         \x20= note: while calling\n");
+}
+
+#[test]
+fn test_write_value() {
+    let interp = Interpreter::new();
+
+    for src in &[
+            r#""tab\there \"quoted\" \\ done""#,
+            r#"#'\''"#,
+            r#"#'\n'"#,
+            "0.0", "-2.5", "1e300", "3/4", "123456789012345678901234567890",
+            "'(a :b `(c ,d ,@e) ())",
+            "'{:b (x 1.5) \"a\" {}}",
+            "#{1 \"a\" b (c)}",
+            ] {
+        let v = interp.run_single_expr(src, None).unwrap();
+        let s = interp.write_value(&v).unwrap();
+        let v2 = interp.parse_value(&s).unwrap();
+
+        assert!(v.is_identical(&v2), "{} => {} => {:?}", src, s, v2);
+    }
+
+    assert_eq!(interp.write_value(&Value::Float(2.0)).unwrap(), "2.0");
+    assert_eq!(interp.write_value(&"a\"b".into()).unwrap(), r#""a\"b""#);
+    for name in &["foo bar", "a(b", "", "123", "true", "a;b", "'a"] {
+        let n = interp.get_scope().add_name(name);
+
+        for v in vec![Value::Name(n), vec![Value::Name(n)].into()] {
+            assert_matches!(interp.write_value(&v).unwrap_err(),
+                Error::ExecError(ExecError::TypeError{expected: "readable value", ..}));
+        }
+    }
+
+    for name in &["foo bar", "a(b", "", "a;b"] {
+        let v = Value::Keyword(interp.get_scope().add_name(name));
+        assert_matches!(interp.write_value(&v).unwrap_err(),
+            Error::ExecError(ExecError::TypeError{expected: "readable value", ..}));
+    }
+
+    for name in &["foo-bar", "a.b", "a+", "<=>", "n\u{e4}me"] {
+        let n = interp.get_scope().add_name(name);
+
+        for v in vec![Value::Name(n), Value::Keyword(n)] {
+            let s = interp.write_value(&v).unwrap();
+            let v2 = interp.parse_value(&s).unwrap();
+            assert!(v.is_identical(&v2), "{:?} => {} => {:?}", name, s, v2);
+        }
+    }
+
+    let v = interp.run_single_expr("{:a 2.0 #'b' {}}", None).unwrap();
+    assert_eq!(interp.write_value(&v).unwrap(), "{:a 2.0 #'b' {}}");
+
+    let v = interp.run_single_expr("(list 1 (list 2 id))", None).unwrap();
+    assert!(!v.is_readable());
+    assert_matches!(interp.write_value(&v).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{found: "function", ..}));
+    assert!(!Value::Float(::std::f64::NAN).is_readable());
+
+    let err = interp.parse_value("(1 2").unwrap_err();
+    assert_eq!(interp.error_report(&err).line, None);
+}
+
+#[test]
+fn test_approximate_size() {
+    let interp = Interpreter::new();
+    let size = |src| interp.run_single_expr(src, None).unwrap().approximate_size();
+
+    assert!(size("()") > 0);
+    assert!(size(r#""hello, world""#) > size(r#""hello""#));
+    assert!(size("123456789012345678901234567890") > size("1"));
+    assert!(size("'(1 2 3 4)") > size("'(1 2)"));
+    assert!(size("'(1 (2 (3 (4))))") > size("'(1 2 3 4)"));
+
+    let mut v = Value::Unit;
+    for _ in 0..1000 {
+        v = Value::Quote(Box::new(v), 1);
+    }
+    assert!(v.approximate_size() > 1000);
+
+    // Shared sublists are measured once, rather than once per reference
+    let v = interp.run_code("
+        (define (dup v n) (if (= n 0) v (let ((v (list v v))) (dup v (- n 1)))))
+        (dup () 64)
+        ", None).unwrap();
+    let one = interp.run_code("(dup () 1)", None).unwrap();
+    assert!(v.approximate_size() < one.approximate_size() * 64);
+}
+
+#[test]
+fn test_print_stream() {
+    let stdout = Rc::new(Capture(RefCell::new(String::new())));
+    let stderr = Rc::new(Capture(RefCell::new(String::new())));
+
+    let interp = Interpreter::with_scope(Rc::new(GlobalScope::new(
+        Rc::new(RefCell::new(NameStore::new())),
+        Rc::new(RefCell::new(CodeMap::new())),
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))),
+        Rc::new(GlobalIo::with_stderr(stdout.clone(), stderr.clone())))));
+
+    interp.run_code(r#"
+        (print "a~a" 1)
+        (println :stderr "b~a" 2)
+        (print :stdout "c")
+        (format :stderr "d~a" 3)
+        (eprint "e")
+        (eprintln "f~a" 4)
+        "#, None).unwrap();
+
+    assert_eq!(*stdout.0.borrow(), "a1c");
+    assert_eq!(*stderr.0.borrow(), "b2\nd3ef4\n");
+
+    assert_eq!(interp.format_value(
+        &interp.run_single_expr(r#"(format :stdout "x")"#, None).unwrap()), "()");
+    assert_eq!(interp.format_value(
+        &interp.run_single_expr(r#"(format "~a" :stderr)"#, None).unwrap()),
+        r#"":stderr""#);
+
+    assert_matches!(interp.run_single_expr(r#"(print :foo "x")"#, None).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "stream", ..}));
+    assert_matches!(interp.run_single_expr(r#"(print :stderr)"#, None).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{..}));
+}
+
+#[test]
+fn test_read_input() {
+    let stdout = Rc::new(Capture(RefCell::new(String::new())));
+    let stdin = Rc::new(SharedReader::new(Cursor::new("ab\u{e9}\nline two\r\nlast")));
+
+    let new_interp = |io| Interpreter::with_scope(Rc::new(GlobalScope::new(
+        Rc::new(RefCell::new(NameStore::new())),
+        Rc::new(RefCell::new(CodeMap::new())),
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))),
+        Rc::new(io))));
+
+    let interp = new_interp(GlobalIo::new(stdout.clone()).with_stdin(stdin));
+
+    let eval = |src| interp.format_value(&interp.run_single_expr(src, None).unwrap());
+
+    assert_eq!(eval("(read-char)"), "#'a'");
+    assert_eq!(eval("(read-line)"), "\"b\u{e9}\"");
+    assert_eq!(eval("(read-line)"), r#""line two""#);
+    assert_eq!(eval("(read-line)"), r#""last""#);
+    assert_eq!(eval("(read-line)"), "()");
+    assert_eq!(eval("(read-char)"), "()");
+
+    let interp = new_interp(GlobalIo::new(stdout.clone()));
+
+    assert_matches!(interp.run_single_expr("(read-line)", None).unwrap_err(),
+        Error::IoError(IoError{..}));
+
+    let stdin = Rc::new(SharedReader::new(Cursor::new("input")));
+    let io = GlobalIo::new(stdout.clone()).with_stdin(stdin);
+    let interp = Interpreter::with_scope(Rc::new(GlobalScope::new(
+            Rc::new(RefCell::new(NameStore::new())),
+            Rc::new(RefCell::new(CodeMap::new())),
+            Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))),
+            Rc::new(io))
+        .with_restrictions(RestrictConfig{
+            allow_stdin: false,
+            .. RestrictConfig::permissive()
+        })));
+
+    assert_matches!(interp.run_single_expr("(read-line)", None).unwrap_err(),
+        Error::ExecError(ExecError::Restricted(_)));
+    assert_matches!(interp.run_single_expr("(read-char)", None).unwrap_err(),
+        Error::ExecError(ExecError::Restricted(_)));
+
+    let interp = Interpreter::with_restrictions(RestrictConfig::strict());
+
+    assert_matches!(interp.run_single_expr("(read-line)", None).unwrap_err(),
+        Error::ExecError(ExecError::Restricted(_)));
+}
+
+/// Records output which has been flushed separately from pending output.
+struct Buffered {
+    pending: RefCell<String>,
+    flushed: RefCell<String>,
+}
+
+impl SharedWrite for Buffered {
+    fn write_all(&self, buf: &[u8]) -> Result<(), IoError> {
+        self.pending.borrow_mut().push_str(std::str::from_utf8(buf).unwrap());
+        Ok(())
+    }
+
+    fn write_fmt(&self, fmt: Arguments) -> Result<(), IoError> {
+        use std::fmt::Write;
+        self.pending.borrow_mut().write_fmt(fmt).unwrap();
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), IoError> {
+        let s = std::mem::replace(&mut *self.pending.borrow_mut(), String::new());
+        self.flushed.borrow_mut().push_str(&s);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flush_policy() {
+    let new_interp = |policy| {
+        let out = Rc::new(Buffered{
+            pending: RefCell::new(String::new()),
+            flushed: RefCell::new(String::new()),
+        });
+        let interp = Interpreter::with_scope(Rc::new(GlobalScope::new(
+            Rc::new(RefCell::new(NameStore::new())),
+            Rc::new(RefCell::new(CodeMap::new())),
+            Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))),
+            Rc::new(GlobalIo::with_stderr(out.clone(), out.clone())
+                .with_flush_policy(policy)))));
+        (interp, out)
+    };
+
+    let (interp, out) = new_interp(FlushPolicy::Always);
+    interp.run_code(r#"(print "a")"#, None).unwrap();
+    assert_eq!(*out.flushed.borrow(), "a");
+
+    let (interp, out) = new_interp(FlushPolicy::Line);
+    interp.run_code(r#"(print "a") (println "b") (eprint "c")"#, None).unwrap();
+    assert_eq!(*out.flushed.borrow(), "ab\n");
+    assert_eq!(*out.pending.borrow(), "c");
+
+    let (interp, out) = new_interp(FlushPolicy::Manual);
+    interp.run_code(r#"(println "a") (eprintln "b")"#, None).unwrap();
+    assert_eq!(*out.flushed.borrow(), "");
+    interp.run_code(r#"(flush :stdout)"#, None).unwrap();
+    assert_eq!(*out.flushed.borrow(), "a\nb\n");
+    interp.run_code(r#"(print "c") (flush)"#, None).unwrap();
+    assert_eq!(*out.flushed.borrow(), "a\nb\nc");
+
+    assert_matches!(interp.run_code(r#"(flush "x")"#, None).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "stream", ..}));
+}
+
+#[test]
+fn test_port() {
+    let interp = Interpreter::new();
+    let log = Rc::new(Capture(RefCell::new(String::new())));
+
+    interp.get_scope().add_named_value("log",
+        Port::output("log", log.clone()).into());
+    interp.get_scope().add_named_value("input",
+        Port::input("input", Rc::new(SharedReader::new(Cursor::new("a\nb")))).into());
+
+    interp.run_code(r#"
+        (println log "x = ~a" 1)
+        (print log "done")
+        (flush log)
+        "#, None).unwrap();
+    assert_eq!(*log.0.borrow(), "x = 1\ndone");
+
+    assert_eq!(eval_str_in(&interp, "(type-of log)"), "port");
+    assert_eq!(eval_str_in(&interp, "(read-line input)"), r#""a""#);
+    assert_eq!(eval_str_in(&interp, "(read-char input)"), "#'b'");
+    assert_eq!(eval_str_in(&interp, "(read-char input)"), "()");
+
+    assert_matches!(interp.run_code("(read-line log)", None).unwrap_err(),
+        Error::IoError(IoError{..}));
+    assert_matches!(interp.run_code("(read-line 1)", None).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "port", ..}));
+
+    interp.run_code("(close log)", None).unwrap();
+    interp.run_code("(close log)", None).unwrap();
+    assert_matches!(interp.run_code(r#"(print log "more")"#, None).unwrap_err(),
+        Error::IoError(IoError{..}));
+    assert_eq!(*log.0.borrow(), "x = 1\ndone");
+}
+
+fn eval_str_in(interp: &Interpreter, s: &str) -> String {
+    interp.format_value(&interp.run_single_expr(s, None).unwrap())
+}
+
+#[test]
+fn test_callable() {
+    struct Handler {
+        callback: Callable<(i32, String), String>,
+    }
+
+    let interp = Interpreter::new();
+    interp.run_code(r#"
+        (define (on-event n s) (format "~a:~a" n s))
+        (define not-fn 1)
+        "#, None).unwrap();
+
+    let h = Handler{callback: interp.get_callable("on-event").unwrap()};
+    assert_eq!(h.callback.call((1, "a".to_owned())).unwrap(), "1:a");
+
+    let f = h.callback.clone().into_fn();
+    assert_eq!(f(2, "b".to_owned()).unwrap(), "2:b");
+
+    let bad: Callable<(i32, String), i32> = interp.get_callable("on-event").unwrap();
+    assert_matches!(bad.call((3, "c".to_owned())).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "integer", ..}));
+
+    assert_matches!(interp.get_callable::<(), ()>("not-fn").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "function", ..}));
+    assert_matches!(interp.get_callable::<(), ()>("missing").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    let f = interp.get_fn::<(i32, &str), String>("on-event").unwrap();
+    assert_eq!(f(4, "d").unwrap(), "4:d");
+
+    let max = interp.get_fn::<(i32, i32, i32), i32>("max").unwrap();
+    assert_eq!(max(1, 3, 2).unwrap(), 3);
+
+    assert_matches!(interp.get_fn::<(), ()>("not-fn").err().unwrap(),
+        Error::ExecError(ExecError::TypeError{expected: "function", ..}));
+}
+
 #[test]
 fn test_format_radix() {
     assert_matches!(eval_str(r#"(format "~@r" 0)"#).unwrap_err(),
@@ -414,6 +1054,14 @@ fn test_format_radix() {
     assert_eq!(eval_str(r#"(format "~o" 0o321)"#).unwrap(), "321");
     assert_eq!(eval_str(r#"(format "~x" 0xabcdef)"#).unwrap(), "abcdef");
 
+    assert_eq!(eval_str(r#"(format "~8,'0b" 0b101)"#).unwrap(), "00000101");
+    assert_eq!(eval_str(r#"(format "~5,'0d" -5)"#).unwrap(), "-0005");
+    assert_eq!(eval_str(r#"(format "~4,'0x" (- 0xf))"#).unwrap(), "-00f");
+    assert_eq!(eval_str(r#"(format "~4,'0@d" 5)"#).unwrap(), "+005");
+    assert_eq!(eval_str(r#"(format "~4,'0o" 0o7)"#).unwrap(), "0007");
+    assert_eq!(eval_str(r#"(format "~16,8,'0r" 0xff)"#).unwrap(), "000000ff");
+    assert_eq!(eval_str(r#"(format "~4,'*x" (- 0xf))"#).unwrap(), "**-f");
+
     assert_eq!(eval_str(r#"(format "~2,,,' ,4:r" 0b101011001111)"#).unwrap(),
         "1010 1100 1111");
 
@@ -621,6 +1269,28 @@ fn test_cmp() {
         Error::ExecError(ExecError::CompareNaN));
 }
 
+#[test]
+fn test_deep_nesting() {
+    fn nested(depth: usize, last: i32) -> Value {
+        let mut v = Value::from(last);
+
+        for _ in 0..depth {
+            v = Value::List(vec![Value::Unit, v].into());
+        }
+
+        v
+    }
+
+    let a = nested(1_000_000, 1);
+    let b = nested(1_000_000, 1);
+    let c = nested(1_000_000, 2);
+
+    assert!(a.is_equal(&b).unwrap());
+    assert!(!a.is_equal(&c).unwrap());
+    assert!(a.is_identical(&b));
+    assert_eq!(a.compare(&c).unwrap(), Ordering::Less);
+}
+
 #[test]
 fn test_zero() {
     assert_eq!(eval("(zero 0 0 0)").unwrap(), "true");
@@ -768,6 +1438,37 @@ fn test_let() {
     assert_eq!(eval("(let ((id 0)) id)").unwrap(), "0");
 }
 
+#[test]
+fn test_destructure() {
+    assert_eq!(eval("(let (((a b) '(1 2)) (c 3)) (list a b c))").unwrap(), "(1 2 3)");
+    assert_eq!(eval("(let (((a (b c)) '(1 (2 3)))) (list c b a))").unwrap(), "(3 2 1)");
+    assert_eq!(eval("(let (((a :rest b) '(1 2 3))) (list a b))").unwrap(), "(1 (2 3))");
+    assert_eq!(eval("(let (((a :rest b) '(1))) (list a b))").unwrap(), "(1 ())");
+    assert_matches!(eval("(let ((() ())) 1)").unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+
+    assert_eq!(run("
+        (define (foo (a b) c) (list a b c))
+        (foo '(1 2) 3)
+        (define (bar a ((b c) :rest d)) (list a b c d))
+        (bar 1 '((2 3) 4 5))
+        (define (baz (a b)) (lambda () (+ a b)))
+        ((baz '(1 2)))
+        ").unwrap(),
+        ["foo", "(1 2 3)", "bar", "(1 2 3 (4 5))", "baz", "3"]);
+
+    assert_matches!(eval("(let (((a b) '(1 2 3))) a)").unwrap_err(),
+        Error::ExecError(ExecError::PatternMismatch{expected: 2, rest: false, found: 3}));
+    assert_matches!(eval("(let (((a b :rest c) '(1))) a)").unwrap_err(),
+        Error::ExecError(ExecError::PatternMismatch{expected: 2, rest: true, found: 1}));
+    assert_matches!(eval("(let (((a b) 1)) a)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "list", found: "integer"}));
+    assert_matches!(run("(define (foo (a b)) a) (foo '(1))").unwrap_err(),
+        Error::ExecError(ExecError::PatternMismatch{expected: 2, rest: false, found: 1}));
+    assert_matches!(run("(define (foo (a b) a) a)").unwrap_err(),
+        Error::CompileError(CompileError::DuplicateParameter(_)));
+}
+
 #[test]
 fn test_chars() {
     assert_eq!(eval(r#"(chars "")"#).unwrap(), "()");
@@ -874,6 +1575,88 @@ fn test_reverse() {
     assert_eq!(eval("(reverse '(1 2 3))").unwrap(), "(3 2 1)");
 }
 
+#[test]
+fn test_map() {
+    assert_eq!(eval("{}").unwrap(), "{}");
+    assert_eq!(eval("{2 :b 1 (+ 0 1)}").unwrap(), "{2 :b 1 1}");
+    assert_eq!(eval("{\"b\" 2 \"a\" 1 c 3 () 4}").unwrap(), "{\"b\" 2 \"a\" 1 c 3 () 4}");
+    assert_eq!(eval("(hash-map 2 'b 1 'a 2 'c)").unwrap(), "{2 c 1 a}");
+    assert_eq!(eval("(let ((a 1)) {a a (a) [a]})").unwrap(), "{a 1 (a) (1)}");
+    assert_eq!(eval("'{:a x}").unwrap(), "{:a x}");
+    assert_eq!(eval("(type-of '{:a x})").unwrap(), "map");
+    assert_eq!(eval("(let ((x 1)) `{:a ,x :b y})").unwrap(), "{:a 1 :b y}");
+    assert_eq!(eval("(type-of {})").unwrap(), "map");
+    assert_eq!(eval("(len {\"a\" 1 \"b\" 2})").unwrap(), "2");
+
+    assert_eq!(eval("(get {:a 1} :a)").unwrap(), "1");
+    assert_eq!(eval("(get {:a 1} :b)").unwrap(), "()");
+    assert_eq!(eval("(get {:a 1} :b 0)").unwrap(), "0");
+    assert_eq!(eval("(get {(1 2) :x} '(1 2))").unwrap(), ":x");
+    assert_eq!(eval("(get '(:a 1 :b 2) :b)").unwrap(), "2");
+    assert_eq!(eval("(get '(1 2 3) 5 :missing)").unwrap(), ":missing");
+
+    assert_eq!(eval("(insert {:a 1} :a 2 :b 3)").unwrap(), "{:a 2 :b 3}");
+    assert_eq!(eval("(remove {:a 1 :b 2 :c 3} :a :c :d)").unwrap(), "{:b 2}");
+    assert_eq!(eval("(let ((m {:a 1})) (list (insert m :a 2) m))").unwrap(),
+        "({:a 2} {:a 1})");
+    assert_eq!(eval("(keys {#'b' 1 #'a' 2})").unwrap(), "(#'b' #'a')");
+    assert_eq!(eval("(values {#'b' 1 #'a' 2})").unwrap(), "(1 2)");
+    assert_eq!(eval("(let ((m (remove {:c 1 :b 2 :a 3} :b))) (list (keys m) (get m :a)))")
+        .unwrap(), "((:c :a) 3)");
+    assert_eq!(eval("(keys {})").unwrap(), "()");
+    assert_eq!(eval("(list (contains {1 2} 1) (contains {1 2} 2))").unwrap(),
+        "(true false)");
+
+    assert_eq!(eval("(= {:a 1 :b 2} {:b 2 :a 1})").unwrap(), "true");
+    assert_eq!(eval("(= {:a 1} {:a 2})").unwrap(), "false");
+    assert_eq!(eval("(= {:a 1} {:b 1})").unwrap(), "false");
+
+    assert_matches!(eval("{:a}").unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::MissingMapValue, ..}));
+    assert_matches!(eval("{'a 1}").unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::InvalidMapKey, ..}));
+    assert_matches!(eval("(hash-map :a)").unwrap_err(),
+        Error::ExecError(ExecError::OddKeywordParams));
+    assert_matches!(eval("(hash-map 1.0 :a)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "map key", found: "float"}));
+    assert_matches!(eval("(insert () :a 1)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "map or set", found: "unit"}));
+    assert_matches!(eval("(< {} {})").unwrap_err(),
+        Error::ExecError(ExecError::CannotCompare("map")));
+}
+
+#[test]
+fn test_set() {
+    assert_eq!(eval("(set)").unwrap(), "#{}");
+    assert_eq!(eval("(set 3 1 2 1 3)").unwrap(), "#{3 1 2}");
+    assert_eq!(eval("#{3 1 2 1}").unwrap(), "#{3 1 2}");
+    assert_eq!(eval("#{a (b c)}").unwrap(), "#{a (b c)}");
+    assert_eq!(eval("(= #{1 2} (set 2 1))").unwrap(), "true");
+    assert_eq!(eval("(type-of (set))").unwrap(), "set");
+    assert_eq!(eval("(len (set \"a\" \"b\" \"a\"))").unwrap(), "2");
+    assert_eq!(eval("(values (set #'b' #'a'))").unwrap(), "(#'b' #'a')");
+
+    assert_eq!(eval("(list (contains (set 1 2) 2) (contains (set 1 2) 3))").unwrap(),
+        "(true false)");
+    assert_eq!(eval("(insert (set 1) 2 1)").unwrap(), "#{1 2}");
+    assert_eq!(eval("(remove (set 1 2 3) 1 4)").unwrap(), "#{2 3}");
+
+    assert_eq!(eval("(union (set 1 2) (set 2 3) (set))").unwrap(), "#{1 2 3}");
+    assert_eq!(eval("(union (set 3 2) (set 1 2))").unwrap(), "#{3 2 1}");
+    assert_eq!(eval("(intersection (set 1 2 3) (set 2 3 4) (set 3))").unwrap(), "#{3}");
+    assert_eq!(eval("(difference (set 1 2 3) (set 2) (set 4))").unwrap(), "#{1 3}");
+    assert_eq!(eval("(list (subset? (set) (set 1)) (subset? (set 1 2) (set 1)))").unwrap(),
+        "(true false)");
+
+    assert_eq!(eval("(= (set 1 2) (set 2 1))").unwrap(), "true");
+    assert_eq!(eval("(= (set 1 2) (set 1 3))").unwrap(), "false");
+
+    assert_matches!(eval("(set 1.0)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "set value", found: "float"}));
+    assert_matches!(eval("(union (set) '(1))").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "set", found: "list"}));
+}
+
 #[test]
 fn test_abs() {
     assert_eq!(eval("(abs -1)").unwrap(), "1");
@@ -1031,6 +1814,8 @@ fn test_type_of() {
 fn test_define() {
     assert_eq!(run("(define foo 123) foo").unwrap(),
         ["foo", "123"]);
+    assert_eq!(run("(define a 1) (do (define b a) (list a b (define a 2) a))").unwrap(),
+        ["a", "(1 1 a 2)"]);
 
     // Standard names cannot be overriden in global scope
     assert_matches!(run("(define (=) ())").unwrap_err(),
@@ -1121,6 +1906,17 @@ fn test_define() {
         ["foo", "(1 2 10 20 ())", "(1 2 3 20 ())", "(1 2 3 4 ())",
             "(1 2 3 4 (5))", "(1 2 3 4 (5 6))"]);
 
+    let interp = Interpreter::new();
+    interp.run_code("(define (foo a b :key c d) (list a b c d))", None).unwrap();
+
+    let c = Value::Keyword(interp.get_scope().add_name("c"));
+    let d = Value::Keyword(interp.get_scope().add_name("d"));
+    let v = interp.call("foo", vec![1.into(), 2.into(), c, 3.into(), d, 4.into()]).unwrap();
+    assert_eq!(interp.format_value(&v), "(1 2 3 4)");
+
+    assert_matches!(interp.call("foo", vec![1.into()]).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{expected: Arity::Range(2, 6), found: 1, ..}));
+
     assert_matches!(run("
         (define (foo a :optional b :key c) ())
         ").unwrap_err(),
@@ -1137,6 +1933,52 @@ fn test_define() {
         Error::CompileError(CompileError::SyntaxError(_)));
 }
 
+#[test]
+fn test_rest_keys() {
+    assert_eq!(run("
+        (define (foo a :key b :rest-keys opts) (list a b opts))
+        (foo 1)
+        (foo 1 :b 2)
+        (foo 1 :c 3 :b 2 :d 4)
+        ").unwrap(),
+        ["foo", "(1 () ())", "(1 2 ())", "(1 2 (:c 3 :d 4))"]);
+
+    assert_eq!(run("
+        (define (bar :key (x 1) (y 2)) (list x y))
+        (define (wrap-bar :key z :rest-keys opts) (list z (apply bar opts)))
+        (wrap-bar :y 3 :z 4)
+        (define (opts :rest-keys opts) (apply hash-map opts))
+        (get (opts :a 1 :b 2) :b)
+        ").unwrap(),
+        ["bar", "wrap-bar", "(4 (1 3))", "opts", "2"]);
+
+    assert_matches!(eval("((lambda (:rest-keys opts) opts) :a)").unwrap_err(),
+        Error::ExecError(ExecError::OddKeywordParams));
+    assert_matches!(eval("((lambda (:key a :rest-keys opts) opts) :a 1 :a 2)").unwrap_err(),
+        Error::ExecError(ExecError::DuplicateKeyword(_)));
+
+    let interp = Interpreter::new();
+    interp.run_code("(define (foo a :rest-keys opts) (list a opts))", None).unwrap();
+
+    let c = Value::Keyword(interp.get_scope().add_name("c"));
+    let v = interp.call("foo", vec![1.into(), c, 3.into()]).unwrap();
+    assert_eq!(interp.format_value(&v), "(1 (:c 3))");
+
+    assert_matches!(interp.call("foo", vec![]).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{expected: Arity::Min(1), found: 0, ..}));
+
+    assert_matches!(eval("(lambda (:optional a :rest-keys opts) ())").unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+    assert_matches!(eval("(lambda (:rest-keys opts :rest rest) ())").unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+    assert_matches!(eval("(lambda (:rest rest :rest-keys opts) ())").unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+    assert_matches!(eval("(lambda (:rest-keys) ())").unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+    assert_matches!(eval("(lambda (a :rest-keys a) ())").unwrap_err(),
+        Error::CompileError(CompileError::DuplicateParameter(_)));
+}
+
 #[test]
 fn test_lambda() {
     assert_eq!(eval("((lambda (n) n) 1)").unwrap(), "1");
@@ -1182,7 +2024,372 @@ fn test_panic() {
             if i.to_u32() == Some(123));
     assert_matches!(eval("(panic \"foo\")").unwrap_err(),
         Error::ExecError(ExecError::Panic(Some(Value::String(ref s))))
-            if s == "foo");
+            if &**s == "foo");
+}
+
+#[test]
+fn test_scope_with_value() {
+    let interp = Interpreter::new();
+    interp.run_code("(define big (list 1 2 3))", None).unwrap();
+
+    let scope = interp.get_scope();
+    let big = scope.add_name("big");
+
+    assert_eq!(scope.with_value(big, |v| match *v {
+        Value::List(ref li) => li.len(),
+        _ => 0
+    }), Some(3));
+    assert_eq!(scope.with_value(scope.add_name("missing"), |_| ()), None);
+}
+
+#[test]
+fn test_try() {
+    assert_eq!(eval("(try (+ 1 2) (catch e 0))").unwrap(), "3");
+    assert_eq!(eval("(try (raise 1) (catch e (+ e 1)))").unwrap(), "2");
+    assert_eq!(eval("(try (/ 1 0) (catch e (get e :code)))").unwrap(),
+        r#""exec/divide-by-zero""#);
+    assert_eq!(eval("(try (< 1.0 (nan)) (catch e (get e :code)))").unwrap(),
+        r#""exec/compare-nan""#);
+    assert_eq!(eval("(try (+ 1 'a) (catch e (get e :message)))").unwrap(),
+        r#""type error: expected number; found name""#);
+    assert_eq!(eval("
+        (try (try (raise 1)
+               (catch e (raise (+ e 1))))
+          (catch e (* e 10)))
+        ").unwrap(), "20");
+
+    assert_eq!(run("
+        (define (foo n) (if (= n 0) (raise :done) (foo (- n 1))))
+        (try (foo 100) (catch e e))
+        (define (bar a)
+          (let ((b 2))
+            (list a (try (list b (raise a)) (catch e (list b e))) b)))
+        (bar 1)
+        ").unwrap(),
+        ["foo", ":done", "bar", "(1 (2 1) 2)"]);
+
+    assert_matches!(eval("(raise 1)").unwrap_err(),
+        Error::ExecError(ExecError::Raised(Value::Integer(_))));
+    assert_matches!(eval("(try (panic 1) (catch e e))").unwrap_err(),
+        Error::ExecError(ExecError::Panic(_)));
+    assert_matches!(eval("(try 1 (rescue e e))").unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+}
+
+#[test]
+fn test_tail_call() {
+    let depth = 100000;
+
+    assert_eq!(run(&format!("
+        (define (even n) (if (= n 0) true (odd (- n 1))))
+        (define (odd n) (if (= n 0) false (even (- n 1))))
+        (even {})
+        ", depth)).unwrap(), ["even", "odd", "true"]);
+
+    assert_eq!(run(&format!("
+        (define (count f n acc)
+          (if (= n 0) acc (f f (- n 1) (+ acc 1))))
+        (count count {} 0)
+        ", depth)).unwrap(), ["count", "100000"]);
+
+    assert_eq!(run(&format!("
+        (define (count n acc)
+          (let ((m (- n 1)))
+            (if (= n 0) acc (apply count (list m (+ acc 1))))))
+        (count {} 0)
+        ", depth)).unwrap(), ["count", "100000"]);
+
+    assert_eq!(run(&format!("
+        (define (loop n)
+          (let ((next (lambda (m) (loop m))))
+            (if (= n 0) (list n) (next (- n 1)))))
+        (loop {})
+        ", depth)).unwrap(), ["loop", "(0)"]);
+}
+
+#[test]
+fn test_interrupt() {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    let interp = Interpreter::new();
+    let flag = interp.interrupt_handle();
+
+    interp.run_code("
+        (define (count n) (if (= n 0) () (count (- n 1))))
+        (define (spin n) (if (= n 0) () (do (count 100) (spin (- n 1)))))
+        ", None).unwrap();
+
+    flag.store(true, Ordering::SeqCst);
+    assert_matches!(interp.run_code("(spin 100)", None).unwrap_err(),
+        Error::ExecError(ExecError::Interrupted));
+
+    // The flag is cleared once the interrupt is reported
+    assert!(!flag.load(Ordering::SeqCst));
+    interp.run_code("(spin 100)", None).unwrap();
+
+    // System functions check the flag, too
+    flag.store(true, Ordering::SeqCst);
+    assert_matches!(interp.run_code("(concat '(1) '(2))", None).unwrap_err(),
+        Error::ExecError(ExecError::Interrupted));
+
+    // Foreign functions calling back into code, such as a sort with
+    // a comparison function, are interrupted at the next call
+    fn sort_by(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+        use std::cmp::Ordering;
+
+        let f = args[0].clone();
+        let mut items = try!(Vec::<Value>::from_value(args[1].take()));
+        let mut err = None;
+
+        items.sort_by(|a, b| {
+            if err.is_some() {
+                return Ordering::Equal;
+            }
+
+            match call_function(scope, f.clone(), vec![a.clone(), b.clone()]) {
+                Ok(Value::Bool(true)) => Ordering::Less,
+                Ok(_) => Ordering::Greater,
+                Err(e) => { err = Some(e); Ordering::Equal }
+            }
+        });
+
+        match err {
+            Some(e) => Err(e),
+            None => Ok(items.into())
+        }
+    }
+
+    interp.get_scope().add_value_with_name("sort-by",
+        |name| Value::new_foreign_fn(name, sort_by));
+
+    assert_eq!(interp.format_value(&interp.run_code(
+        "(sort-by < '(3 1 2))", None).unwrap()), "(1 2 3)");
+
+    flag.store(true, Ordering::SeqCst);
+    assert_matches!(interp.run_code("(sort-by < '(3 1 2))", None).unwrap_err(),
+        Error::ExecError(ExecError::Interrupted));
+
+    flag.store(true, Ordering::SeqCst);
+    assert_matches!(interp.run_code(
+        "(sort-by (lambda (a b) (> a b)) '(3 1 2))", None).unwrap_err(),
+        Error::ExecError(ExecError::Interrupted));
+
+    let handle = {
+        let flag = flag.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::SeqCst);
+        })
+    };
+
+    let r = interp.run_code("
+        (define (forever n) (do (spin 10) (forever n)))
+        (forever 0)
+        ", None);
+    handle.join().unwrap();
+
+    assert_matches!(r.unwrap_err(), Error::ExecError(ExecError::Interrupted));
+}
+
+#[test]
+fn test_instruction_limit() {
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        max_instructions: Some(5000),
+        .. RestrictConfig::permissive()
+    });
+
+    interp.run_code("
+        (define (count n) (if (= n 0) () (count (- n 1))))
+        ", None).unwrap();
+
+    interp.run_code("(count 10)", None).unwrap();
+    assert!(interp.get_scope().remaining_instructions().unwrap() < 5000);
+
+    assert_matches!(interp.run_code("(count 100000)", None).unwrap_err(),
+        Error::ExecError(ExecError::InstructionLimit));
+    assert_eq!(interp.last_run_stats().instructions, 5001);
+
+    // Instructions executed while expanding macros count toward the limit
+    assert_matches!(interp.run_code("
+        (macro (spin) (count 100000))
+        (spin)
+        ", None).unwrap_err(),
+        Error::ExecError(ExecError::InstructionLimit));
+
+    // The limit cannot be caught
+    assert_matches!(interp.run_code("(try (count 100000) (catch e e))", None).unwrap_err(),
+        Error::ExecError(ExecError::InstructionLimit));
+
+    // The budget is restored for each invocation
+    interp.run_code("(count 10)", None).unwrap();
+
+    // Functions implemented in Rust are charged for the size of their output
+    interp.run_code("
+        (define (grow s n) (if (= n 0) (len s) (grow (concat s s) (- n 1))))
+        ", None).unwrap();
+    assert_eq!(interp.format_value(
+        &interp.run_code(r#"(grow "0123456789abcdef" 10)"#, None).unwrap()), "16384");
+    assert_matches!(interp.run_code(r#"(grow "0123456789abcdef" 16)"#, None).unwrap_err(),
+        Error::ExecError(ExecError::InstructionLimit));
+    assert!(interp.last_run_stats().instructions < 5000);
+
+    let shared = interp.new_sharing();
+    assert_matches!(shared.run_code("(define (loop) (loop)) (loop)", None).unwrap_err(),
+        Error::ExecError(ExecError::InstructionLimit));
+
+    assert_eq!(Interpreter::new().get_scope().remaining_instructions(), None);
+}
+
+#[test]
+fn test_call_depth_limit() {
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        max_call_depth: 100,
+        .. RestrictConfig::permissive()
+    });
+
+    interp.run_code("
+        (define (depth n) (if (= n 0) 0 (+ 1 (depth (- n 1)))))
+        ", None).unwrap();
+
+    interp.run_code("(depth 90)", None).unwrap();
+    assert_matches!(interp.run_code("(depth 100)", None).unwrap_err(),
+        Error::ExecError(ExecError::CallStackOverflow));
+    assert_matches!(interp.run_code("(try (depth 100) (catch e e))", None).unwrap_err(),
+        Error::ExecError(ExecError::CallStackOverflow));
+
+    // Tail calls do not increase call depth
+    interp.run_code("
+        (define (count n) (if (= n 0) () (count (- n 1))))
+        (count 1000)
+        ", None).unwrap();
+
+    assert_eq!(interp.get_scope().call_depth(), 0);
+    assert_matches!(Interpreter::new().run_code("
+        (define (depth n) (if (= n 0) 0 (+ 1 (depth (- n 1)))))
+        (depth 100000)
+        ", None).unwrap_err(),
+        Error::ExecError(ExecError::CallStackOverflow));
+}
+
+#[test]
+fn test_parse_depth_limit() {
+    let interp = Interpreter::with_restrictions(RestrictConfig::strict());
+
+    let n = 100_000;
+    let input = "(list ".repeat(n) + &")".repeat(n);
+
+    assert_matches!(interp.run_code(&input, None).unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::DepthExceeded, ..}));
+    assert_matches!(interp.parse_exprs(&input, None).unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::DepthExceeded, ..}));
+
+    let quoted = "'(".repeat(n) + &")".repeat(n);
+
+    assert_matches!(interp.run_code(&quoted, None).unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::DepthExceeded, ..}));
+
+    assert_eq!(interp.format_value(&interp.run_code(
+        "(list (list (list 1)))", None).unwrap()), "(((1)))");
+}
+
+#[test]
+fn test_eval_read() {
+    assert_eq!(eval(r#"(read "(+ 1 2)")"#).unwrap(), "(+ 1 2)");
+    assert_eq!(eval(r#"(read "foo")"#).unwrap(), "foo");
+    assert_eq!(eval(r#"(eval (read "(+ 1 2)"))"#).unwrap(), "3");
+    assert_eq!(eval("(eval '(let ((a 1)) (+ a 2)))").unwrap(), "3");
+    assert_eq!(eval("(eval 1)").unwrap(), "1");
+    assert_eq!(run("
+        (eval '(define (sq x) (* x x)))
+        (sq 4)
+        ").unwrap(), ["sq", "16"]);
+
+    assert_matches!(eval(r#"(read "(+ 1")"#).unwrap_err(),
+        Error::ParseError(_));
+    assert_matches!(eval(r#"(read "1 2")"#).unwrap_err(),
+        Error::ParseError(_));
+    assert_matches!(eval("(eval '(foo 1))").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+    assert_eq!(eval(r#"(try (read ")") (catch e (get e :code)))"#).unwrap(),
+        r#""parse/unmatched-paren""#);
+
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        allow_eval: false,
+        .. RestrictConfig::permissive()
+    });
+
+    assert_matches!(interp.run_code("(eval 1)", None).unwrap_err(),
+        Error::ExecError(ExecError::Restricted(_)));
+    assert_matches!(interp.run_code(r#"(try (read "1") (catch e e))"#, None).unwrap_err(),
+        Error::ExecError(ExecError::Restricted(_)));
+}
+
+#[test]
+fn test_macroexpand() {
+    assert_eq!(run("
+        (macro (inc! a) `(set! ,a 1))
+        (macro (set! a b) `(define ,a ,b))
+        (macroexpand-1 '(inc! x))
+        (macroexpand '(inc! x))
+        (macroexpand '(list (inc! x)))
+        (macroexpand-1 'x)
+        ").unwrap(), ["inc!", "set!", "(set! x 1)", "(define x 1)",
+            "(list (inc! x))", "x"]);
+
+    assert_matches!(run("
+        (macro (loop) '(loop))
+        (macroexpand '(loop))
+        ").unwrap_err(),
+        Error::CompileError(CompileError::MacroRecursionExceeded));
+
+    let interp = Interpreter::new();
+    interp.run_code("(macro (twice x) `(do ,x ,x))", None).unwrap();
+
+    let v = interp.parse_value("(twice (foo))").unwrap();
+    assert_eq!(interp.format_value(&interp.expand_macros(&v).unwrap()),
+        "(do (foo) (foo))");
+}
+
+#[test]
+fn test_size_limits() {
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        max_integer_bits: Some(64),
+        max_list_len: Some(10),
+        max_string_len: Some(10),
+        max_namespace_size: Some(5),
+        .. RestrictConfig::permissive()
+    });
+
+    assert_matches!(interp.run_code("(^ 2 64)", None).unwrap_err(),
+        Error::ExecError(ExecError::IntegerLimit));
+    assert_matches!(interp.run_code("(^ 3 1000000000000)", None).unwrap_err(),
+        Error::ExecError(ExecError::IntegerLimit));
+    assert_matches!(interp.run_code("(<< 1 100000000)", None).unwrap_err(),
+        Error::ExecError(ExecError::IntegerLimit));
+    assert_matches!(interp.run_code("(* 4294967296 4294967296)", None).unwrap_err(),
+        Error::ExecError(ExecError::IntegerLimit));
+    interp.run_code("(^ 2 63)", None).unwrap();
+    interp.run_code("(^ 1 1000000000000)", None).unwrap();
+
+    assert_matches!(interp.run_code("(concat \"hello\" \" world\")", None).unwrap_err(),
+        Error::ExecError(ExecError::StringLimit));
+    assert_matches!(interp.run_code("(list 1 2 3 4 5 6 7 8 9 10 11)", None).unwrap_err(),
+        Error::ExecError(ExecError::ListLimit));
+    interp.run_code("(define (grow li) (grow (append li 0)))", None).unwrap();
+    assert_matches!(interp.run_code("(grow ())", None).unwrap_err(),
+        Error::ExecError(ExecError::ListLimit));
+
+    // Limits cannot be caught
+    assert_matches!(interp.run_code("(try (grow ()) (catch e e))", None).unwrap_err(),
+        Error::ExecError(ExecError::ListLimit));
+
+    interp.run_code("(define a 1) (define b 2) (define c 3) (define d 4)", None).unwrap();
+    // Redefining an existing name does not increase the size
+    interp.run_code("(define a 5)", None).unwrap();
+    assert_matches!(interp.run_code("(define e 5)", None).unwrap_err(),
+        Error::ExecError(ExecError::NamespaceLimit));
 }
 
 #[test]
@@ -1199,3 +2406,196 @@ fn test_use() {
         ").unwrap(),
         ["()", "2.0"]);
 }
+
+#[test]
+fn test_metrics() {
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (define (count n) (if (= n 0) '() (list n (count (- n 1)))))
+        (count 10)
+        ", None).unwrap();
+
+    let m = interp.last_run_stats();
+    assert!(m.instructions > 0);
+    assert!(m.calls >= 11);
+    assert_eq!(m.allocations, 10);
+    assert!(m.peak_stack >= 10);
+
+    interp.call("count", vec![Value::from(2)]).unwrap();
+
+    let n = interp.last_run_stats();
+    assert!(n.instructions < m.instructions);
+    assert!(n.calls >= 3);
+    assert_eq!(n.allocations, 2);
+    assert_eq!(n.macro_expansions, 0);
+    assert_eq!(n.module_loads, 0);
+}
+
+#[test]
+fn test_last_run_stats() {
+    use std::io::Write;
+
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (use math (sqrt))
+        (use random (random))
+        (macro (twice a) `(list ,a ,a))
+        (define (main) (twice (twice 1)))
+        (main)
+        ", None).unwrap();
+
+    let s = interp.last_run_stats();
+    assert_eq!(s.module_loads, 2);
+    // The outer expansion duplicates the inner macro call
+    assert_eq!(s.macro_expansions, 3);
+    assert!(s.instructions > 0);
+
+    // Previously loaded modules are not counted
+    interp.run_code("(use math (cos))", None).unwrap();
+    assert_eq!(interp.last_run_stats().module_loads, 0);
+
+    let dir = std::env::temp_dir().join("ketos-test-last-run-stats");
+    let _ = std::fs::create_dir_all(&dir);
+    let path = dir.join("main.ket");
+    std::fs::File::create(&path).unwrap()
+        .write_all(b"(define (main) (let ((n 1)) (list n n)))").unwrap();
+
+    interp.run_file(&path).unwrap();
+    assert_eq!(interp.last_run_stats().allocations, 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_string_builder() {
+    assert_eq!(run(r#"
+        (use string-builder :all)
+        (define sb (sb-new "a"))
+        (sb-push sb #'b')
+        (sb-push-str sb "cd")
+        (sb-finish sb)
+        (sb-finish sb)
+        "#).unwrap(),
+        ["()", "sb", "()", "()", r#""abcd""#, r#""""#]);
+
+    assert_matches!(eval(r#"
+        (do (use string-builder (sb-push)) (sb-push "a" #'b'))
+        "#).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "string-builder", ..}));
+
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        max_string_len: Some(4),
+        .. RestrictConfig::permissive()
+    });
+
+    interp.run_code(r#"(use string-builder :all) (define sb (sb-new "ab"))"#, None).unwrap();
+    assert_matches!(interp.run_code(r#"(sb-push-str sb "cde")"#, None).unwrap_err(),
+        Error::ExecError(ExecError::StringLimit));
+    interp.run_code(r#"(sb-push-str sb "c")"#, None).unwrap();
+    interp.run_code(r#"(sb-push sb #'d')"#, None).unwrap();
+    assert_matches!(interp.run_code(r#"(sb-push sb #'e')"#, None).unwrap_err(),
+        Error::ExecError(ExecError::StringLimit));
+    let v = interp.run_code("(sb-finish sb)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""abcd""#);
+}
+
+#[test]
+fn test_redefine_callee() {
+    assert_eq!(run("
+        (define (foo) 1)
+        (define (bar) (foo))
+        (bar)
+        (define (foo) 2)
+        (bar)
+        ").unwrap(),
+        ["foo", "bar", "1", "foo", "2"]);
+}
+
+#[test]
+fn test_cached_callee_arity() {
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (define (foo a :optional b) a)
+        (define (bar n)
+          (cond
+            ((= n 0) (foo 1))
+            ((= n 1) (foo 1 2))
+            (else    (foo 1 2 3))))
+        ", None).unwrap();
+
+    assert_eq!(eval_str_in(&interp, "(bar 0)"), "1");
+    assert_eq!(eval_str_in(&interp, "(bar 0)"), "1");
+    assert_eq!(eval_str_in(&interp, "(bar 1)"), "1");
+
+    assert_matches!(interp.run_single_expr("(bar 2)", None).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{found: 3, ..}));
+    assert_matches!(interp.run_single_expr("(bar 2)", None).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{found: 3, ..}));
+
+    assert_eq!(eval_str_in(&interp, "(bar 1)"), "1");
+}
+
+#[test]
+fn test_repl() {
+    use ketos::repl::{complete, Prompt, Repl, ReplOutput};
+
+    let mut repl = Repl::new(Interpreter::new());
+
+    assert_matches!(repl.feed("   "), ReplOutput::Empty);
+    assert_matches!(repl.feed("; comment"), ReplOutput::Empty);
+
+    assert_matches!(repl.feed("(define (foo a)"), ReplOutput::Incomplete(Prompt::OpenParen));
+    assert_eq!(repl.prompt(), Prompt::OpenParen);
+    assert_matches!(repl.feed("  (* a 2))"),
+        ReplOutput::Value{number: Some(1), ref text, ..} if text == "foo");
+    assert_eq!(repl.prompt(), Prompt::Normal);
+
+    assert_matches!(repl.feed("(foo 21)"),
+        ReplOutput::Value{number: Some(2), ref text, ..} if text == "42");
+    assert_matches!(repl.feed("(+ $2 1)"),
+        ReplOutput::Value{number: Some(3), ref text, ..} if text == "43");
+    assert_matches!(repl.feed("()"), ReplOutput::Value{number: None, ..});
+    assert_eq!(repl.result_count(), 3);
+
+    assert_matches!(repl.feed("\"abc"), ReplOutput::Incomplete(Prompt::OpenString));
+    assert_matches!(repl.feed("def\""),
+        ReplOutput::Value{ref text, ..} if text == "\"abc\\ndef\"");
+
+    assert_matches!(repl.feed("#| block"), ReplOutput::Incomplete(Prompt::OpenComment));
+    repl.reset();
+    assert_matches!(repl.feed("'"), ReplOutput::Incomplete(Prompt::Continue));
+    assert_matches!(repl.feed("(a b)"),
+        ReplOutput::Value{ref text, ..} if text == "(a b)");
+    assert_matches!(repl.feed("(foo #x"), ReplOutput::Error{ref error, ..}
+        if !error.is_incomplete());
+    assert_eq!(repl.prompt(), Prompt::Normal);
+
+    assert_matches!(repl.feed("(bar)"),
+        ReplOutput::Error{error: Error::ExecError(_), ref message}
+            if message.contains("bar"));
+    assert_matches!(repl.feed(")"), ReplOutput::Error{error: Error::ParseError(_), ..});
+
+    assert_eq!(repl.complete("(defi", 1, 5).map(|(prefix, _)| prefix),
+        Some("ne".to_owned()));
+    assert_matches!(complete("(zzz", 1, 4, repl.interpreter().get_scope()), None);
+}
+
+#[test]
+fn test_complete_name() {
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (const shu-const 1)
+        (define shu-value 2)
+        (macro (shu-macro) ())
+        (use random (random))
+        ", None).unwrap();
+
+    assert_eq!(interp.complete_name("shu"),
+        ["shu-const", "shu-macro", "shu-value", "shuffle"]);
+    assert_eq!(interp.complete_name("lambd"), ["lambda"]);
+    assert!(interp.complete_name("zzz").is_empty());
+}
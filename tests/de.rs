@@ -0,0 +1,73 @@
+#![cfg(feature = "serde")]
+
+extern crate ketos;
+extern crate serde;
+
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+
+use ketos::{from_value, DeError, Interpreter};
+
+fn de<T: DeserializeOwned>(input: &str) -> Result<T, DeError> {
+    let interp = Interpreter::new();
+    let v = interp.run_single_expr(input, None).unwrap();
+    let names = interp.get_scope().borrow_names();
+    from_value(&names, &v)
+}
+
+#[test]
+fn test_de_primitives() {
+    assert_eq!(de::<()>("()").unwrap(), ());
+    assert_eq!(de::<bool>("true").unwrap(), true);
+    assert_eq!(de::<u8>("200").unwrap(), 200);
+    assert_eq!(de::<i64>("-5").unwrap(), -5);
+    assert_eq!(de::<f64>("1.5").unwrap(), 1.5);
+    assert_eq!(de::<f64>("3/4").unwrap(), 0.75);
+    assert_eq!(de::<char>("#'x'").unwrap(), 'x');
+    assert_eq!(de::<String>(r#""foo""#).unwrap(), "foo");
+    assert_eq!(de::<String>("'foo").unwrap(), "foo");
+    assert_eq!(de::<String>(":foo").unwrap(), "foo");
+
+    assert!(de::<u8>("300").is_err());
+    assert!(de::<String>("1").is_err());
+    assert!(de::<i32>("id").is_err());
+}
+
+#[test]
+fn test_de_containers() {
+    assert_eq!(de::<Option<i32>>("()").unwrap(), None);
+    assert_eq!(de::<Option<i32>>("1").unwrap(), Some(1));
+    assert_eq!(de::<Vec<i32>>("()").unwrap(), Vec::<i32>::new());
+    assert_eq!(de::<Vec<i32>>("'(1 2 3)").unwrap(), vec![1, 2, 3]);
+    assert_eq!(de::<(i32, String)>(r#"'(1 "a")"#).unwrap(), (1, "a".to_owned()));
+
+    let mut map = BTreeMap::new();
+    map.insert("a".to_owned(), 1);
+    map.insert("b-c".to_owned(), 2);
+
+    assert_eq!(de::<BTreeMap<String, i32>>("'(:a 1 :b-c 2)").unwrap(), map);
+    assert_matches_odd(de::<BTreeMap<String, i32>>("'(:a 1 :b)"));
+    assert!(de::<BTreeMap<String, i32>>("'(a 1)").is_err());
+}
+
+#[test]
+fn test_de_struct_value() {
+    let interp = Interpreter::new();
+    let v = interp.run_code(r#"
+        (struct Point ((x integer) (y integer)))
+        (new Point :x 1 :y 2)
+        "#, None).unwrap();
+    let names = interp.get_scope().borrow_names();
+
+    let map: BTreeMap<String, i32> = from_value(&names, &v).unwrap();
+    assert_eq!(map.get("x"), Some(&1));
+    assert_eq!(map.get("y"), Some(&2));
+}
+
+fn assert_matches_odd<T>(r: Result<T, DeError>) {
+    match r {
+        Err(DeError::OddKeywordParams) => (),
+        _ => panic!("expected OddKeywordParams")
+    }
+}
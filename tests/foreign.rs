@@ -1,8 +1,11 @@
 #[macro_use] extern crate ketos;
 
 use std::cmp::Ordering;
+use std::thread;
 
-use ketos::{ExecError, Error, ForeignValue, Interpreter, Scope, Value};
+use ketos::{Callable, ExecError, Error, ForeignValue, FromValue, GlobalScope, Interpreter,
+    ModuleBuilder, RestrictConfig, Scope, Value};
+use ketos::exec::call_function;
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct MyType {
@@ -71,6 +74,123 @@ fn test_raw_foreign_fn() {
     assert_eq!(eval(&interp, "(closure-args 3 2 1)").unwrap(), "(3 2 1)");
 }
 
+fn call_arg(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    call_function(scope, args[0].clone(), args[1..].to_vec())
+}
+
+#[test]
+fn test_foreign_fn_call_depth() {
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        max_call_depth: 20,
+        .. RestrictConfig::permissive()
+    });
+
+    interp.get_scope().add_value_with_name("call",
+        |name| Value::new_foreign_fn(name, call_arg));
+
+    interp.run_code("
+        (define (depth n) (if (= n 0) 0 (+ 1 (call depth (- n 1)))))
+        ", None).unwrap();
+
+    assert_eq!(eval(&interp, "(depth 10)").unwrap(), "10");
+
+    match interp.run_code("(depth 30)", None) {
+        Err(Error::ExecError(ExecError::CallStackOverflow)) => (),
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    assert_eq!(interp.get_scope().call_depth(), 0);
+}
+
+#[test]
+fn test_foreign_fn_native_depth() {
+    let interp = Interpreter::with_restrictions(RestrictConfig{
+        max_native_depth: 8,
+        .. RestrictConfig::permissive()
+    });
+
+    interp.get_scope().add_value_with_name("call",
+        |name| Value::new_foreign_fn(name, call_arg));
+
+    interp.run_code("
+        (define (depth n) (if (= n 0) 0 (+ 1 (call depth (- n 1)))))
+        ", None).unwrap();
+
+    assert_eq!(eval(&interp, "(depth 5)").unwrap(), "5");
+
+    match interp.run_code("(depth 10)", None) {
+        Err(Error::ExecError(ExecError::CallStackOverflow)) => (),
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    assert_eq!(interp.get_scope().native_depth(), 0);
+
+    // The default limit is reached before a 2 MiB stack is exhausted
+    thread::Builder::new().stack_size(2 << 20).spawn(|| {
+        let interp = Interpreter::new();
+
+        interp.get_scope().add_value_with_name("call",
+            |name| Value::new_foreign_fn(name, call_arg));
+
+        match interp.run_code("
+                (define (depth n) (if (= n 0) 0 (+ 1 (call depth (- n 1)))))
+                (depth 100000)
+                ", None) {
+            Err(Error::ExecError(ExecError::CallStackOverflow)) => (),
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }).unwrap().join().unwrap();
+}
+
+fn sort_by(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = args[0].clone();
+    let mut items = try!(Vec::<Value>::from_value(args[1].take()));
+    let mut err = None;
+
+    items.sort_by(|a, b| {
+        if err.is_some() {
+            return Ordering::Equal;
+        }
+
+        match call_function(scope, f.clone(), vec![a.clone(), b.clone()]) {
+            Ok(Value::Bool(true)) => Ordering::Less,
+            Ok(_) => Ordering::Greater,
+            Err(e) => { err = Some(e); Ordering::Equal }
+        }
+    });
+
+    match err {
+        Some(e) => Err(e),
+        None => Ok(items.into())
+    }
+}
+
+#[test]
+fn test_call_function_value() {
+    let interp = Interpreter::new();
+
+    interp.get_scope().add_value_with_name("sort-by",
+        |name| Value::new_foreign_fn(name, sort_by));
+    interp.get_scope().add_value_with_name("reflect-args",
+        |name| Value::new_foreign_fn(name, reflect_args));
+
+    assert_eq!(eval(&interp, "(sort-by < '(3 1 2))").unwrap(), "(1 2 3)");
+    assert_eq!(eval(&interp, "(sort-by (lambda (a b) (> a b)) '(3 1 2))").unwrap(), "(3 2 1)");
+
+    match eval(&interp, "(sort-by (lambda (a) a) '(3 1 2))") {
+        Err(Error::ExecError(ExecError::ArityError{..})) => (),
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    let f = interp.run_code("(lambda (a b) (+ a b))", None).unwrap();
+    let v = interp.call_value(f, vec![1.into(), 2.into()]).unwrap();
+    assert_eq!(interp.format_value(&v), "3");
+
+    let f = interp.get_value("reflect-args").unwrap();
+    let v = interp.call_value(f, vec![1.into(), 2.into()]).unwrap();
+    assert_eq!(interp.format_value(&v), "(1 2)");
+}
+
 fn new_my_type(a: i32) -> Result<MyType, Error> {
     Ok(MyType{a: a})
 }
@@ -83,6 +203,10 @@ fn hello(s: &str) -> Result<String, Error> {
     Ok(format!("Hello, {}!", s))
 }
 
+fn scale(a: f64, b: i32) -> Result<f64, Error> {
+    Ok(a * b as f64)
+}
+
 #[test]
 fn test_foreign_fn() {
     let interp = Interpreter::new();
@@ -95,4 +219,133 @@ fn test_foreign_fn() {
     assert_eq!(eval(&interp, "(new-my-type 1)").unwrap(), "MyType { a: 1 }");
     assert_eq!(eval(&interp, "(get-value (new-my-type 2))").unwrap(), "2");
     assert_eq!(eval(&interp, r#"(hello "world")"#).unwrap(), r#""Hello, world!""#);
+
+    match eval(&interp, r#"(hello "a" "b")"#) {
+        Err(Error::ExecError(ExecError::ArityError{found: 2, ..})) => (),
+        r => panic!("unexpected result: {:?}", r)
+    }
+
+    match eval(&interp, "(hello 1)") {
+        Err(Error::ExecError(ExecError::TypeError{expected: "string", ..})) => (),
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn test_foreign_fn_module() {
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    let builder = ModuleBuilder::new("ops", GlobalScope::new_using(scope));
+    let builder = ketos_fn!{ builder => "scale" => fn scale(a: f64, b: i32) -> f64 };
+    let module = ketos_fn!{ builder => "hello" => fn hello(s: &str,) -> String }.finish();
+
+    let name = scope.add_name("ops");
+    scope.get_modules().replace(name, module).unwrap();
+
+    interp.run_code("(use ops :all)", None).unwrap();
+    assert_eq!(eval(&interp, "(scale 1.5 3)").unwrap(), "4.5");
+    assert_eq!(eval(&interp, r#"(hello "ops")"#).unwrap(), r#""Hello, ops!""#);
+}
+
+#[derive(Debug)]
+pub struct Opaque;
+
+impl ketos::ForeignValue for Opaque {
+    fn type_name(&self) -> &'static str { "opaque" }
+}
+
+#[test]
+fn test_foreign_compare() {
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    ketos_fn!{ scope => "new-my-type" => fn new_my_type(a: i32) -> MyType }
+    ketos_fn!{ scope => "get-value" => fn get_value(a: &MyType) -> i32 }
+
+    scope.add_named_value("a", Value::new_foreign(Opaque));
+    scope.add_named_value("b", Value::new_foreign(Opaque));
+
+    assert_eq!(eval(&interp, "(= (new-my-type 1) (new-my-type 1))").unwrap(), "true");
+    assert_eq!(eval(&interp, "(< (new-my-type 1) (new-my-type 2))").unwrap(), "true");
+    assert_eq!(eval(&interp, "(get-value \
+        (max (new-my-type 3) (new-my-type 1) (new-my-type 2)))").unwrap(), "3");
+
+    assert_eq!(eval(&interp, "(= a a)").unwrap(), "true");
+    assert_eq!(eval(&interp, "(= a b)").unwrap(), "false");
+    assert!(eval(&interp, "(< a b)").is_err());
+    assert!(eval(&interp, "(= a (new-my-type 1))").is_err());
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Point {
+    x: i32,
+    y: i32,
+    point_name: String,
+}
+
+struct_conversions!{ Point => "point" {
+    x: i32 => "integer",
+    y: i32 => "integer",
+    point_name: String => "string",
+} }
+
+#[test]
+fn test_struct_conversions() {
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    scope.add_struct_def::<Point>();
+
+    let p = scope.struct_to_value(Point{x: 1, y: 2, point_name: "a".to_owned()}).unwrap();
+
+    interp.get_scope().add_named_value("p", p);
+    assert_eq!(interp.format_value(&interp.run_code("(. p :point-name)", None).unwrap()), r#""a""#);
+    assert_eq!(interp.format_value(&interp.run_code("(+ (. p :x) (. p :y))", None).unwrap()), "3");
+
+    let v = interp.run_code(r#"(new point :x 3 :y 4 :point-name "b")"#, None).unwrap();
+    assert_eq!(scope.struct_from_value::<Point>(&v).unwrap(),
+        Point{x: 3, y: 4, point_name: "b".to_owned()});
+
+    let v = interp.run_code("(.= p :x 10)", None).unwrap();
+    assert_eq!(scope.struct_from_value::<Point>(&v).unwrap().x, 10);
+
+    match scope.struct_from_value::<Point>(&Value::from(1)) {
+        Err(ExecError::TypeError{expected: "point", ..}) => (),
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[derive(Debug)]
+struct Adder(i32);
+
+impl ketos::ForeignValue for Adder {
+    fn type_name(&self) -> &'static str { "adder" }
+
+    fn is_callable(&self) -> bool { true }
+
+    fn call_value(&self, _scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+        let n = try!(i32::from_value(args[0].take()));
+        Ok((self.0 + n).into())
+    }
+}
+
+#[test]
+fn test_foreign_callable() {
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    let add = Callable::<(i32,), i32>::new(scope,
+        Value::new_foreign(Adder(10))).unwrap();
+    assert_eq!(add.into_fn()(5).unwrap(), 15);
+
+    let name = scope.add_name("count-args");
+    let count = Callable::<Vec<Value>, usize>::new(scope,
+        Value::new_foreign_fn(name, |_scope, args| Ok(args.len().into()))).unwrap();
+    assert_eq!(count.into_fn()(vec![Value::Unit, Value::Unit]).unwrap(), 2);
+
+    match Callable::<(), ()>::new(scope, Value::new_foreign(MyType{a: 1})) {
+        Err(ExecError::TypeError{expected: "function", found: "my-type"}) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ()))
+    }
 }
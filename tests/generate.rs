@@ -0,0 +1,67 @@
+extern crate ketos;
+extern crate rand;
+
+use rand::{SeedableRng, XorShiftRng};
+
+use ketos::{Interpreter, Value};
+use ketos::generate::{GenConfig, ValueGenerator};
+
+fn generator(seed: u32, config: GenConfig) -> ValueGenerator<XorShiftRng> {
+    ValueGenerator::with_config(XorShiftRng::from_seed([seed, 1, 2, 3]), config)
+}
+
+#[test]
+fn test_gen_value_round_trip() {
+    let interp = Interpreter::new();
+    let mut gen = generator(1, GenConfig{floats: false, .. GenConfig::default()});
+
+    for _ in 0..500 {
+        let v = gen.gen_value(&mut interp.get_scope().borrow_names_mut());
+        let s = interp.write_value(&v).unwrap();
+        let r = interp.parse_value(&s).unwrap();
+
+        assert!(v.is_identical(&r), "{} != {}", s, interp.format_value(&r));
+    }
+}
+
+#[test]
+fn test_gen_value_config() {
+    let interp = Interpreter::new();
+    let mut gen = generator(2, GenConfig{
+        max_depth: 0,
+        max_string_len: 3,
+        big_integers: false,
+        floats: false,
+        ratios: false,
+        names: false,
+        quotes: false,
+        unicode: false,
+        .. GenConfig::default()
+    });
+
+    for _ in 0..500 {
+        match gen.gen_value(&mut interp.get_scope().borrow_names_mut()) {
+            Value::Unit | Value::Bool(_) => (),
+            Value::Integer(ref i) => assert!(i.to_i64().is_some()),
+            Value::Char(c) => assert!(c.is_ascii()),
+            Value::String(ref s) => assert!(s.len() <= 3 && s.is_ascii()),
+            v => panic!("unexpected value: {}", interp.format_value(&v))
+        }
+    }
+}
+
+#[test]
+fn test_gen_program() {
+    let mut gen = generator(3, GenConfig::default());
+
+    for _ in 0..200 {
+        let interp = Interpreter::new();
+        let src = gen.gen_program();
+
+        match interp.run_code(&src, None) {
+            Ok(Value::Integer(_)) => (),
+            Ok(v) => panic!("{}: unexpected value {}", src, interp.format_value(&v)),
+            Err(e) => panic!("{}: {}", src, interp.display(&e))
+        }
+    }
+}
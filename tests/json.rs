@@ -0,0 +1,75 @@
+#![cfg(feature = "serde_json")]
+
+extern crate ketos;
+extern crate serde_json;
+
+use ketos::{ExecError, Interpreter};
+use ketos::json::{from_json, to_json};
+
+fn to(input: &str) -> Result<String, ExecError> {
+    let interp = Interpreter::new();
+    let v = interp.run_code(input, None).unwrap();
+    let names = interp.get_scope().borrow_names();
+    to_json(&names, &v).map(|j| j.to_string())
+}
+
+fn from(input: &str) -> String {
+    let interp = Interpreter::new();
+    let json = serde_json::from_str(input).unwrap();
+    let v = from_json(&mut interp.get_scope().borrow_names_mut(), &json);
+    interp.format_value(&v)
+}
+
+#[test]
+fn test_to_json() {
+    assert_eq!(to("()").unwrap(), "null");
+    assert_eq!(to("true").unwrap(), "true");
+    assert_eq!(to("123").unwrap(), "123");
+    assert_eq!(to("100000000000000000000000").unwrap(), r#""100000000000000000000000""#);
+    assert_eq!(to("1.5").unwrap(), "1.5");
+    assert_eq!(to("1/4").unwrap(), "0.25");
+    assert_eq!(to("#'a'").unwrap(), r#""a""#);
+    assert_eq!(to(r#""foo""#).unwrap(), r#""foo""#);
+    assert_eq!(to("'foo").unwrap(), r#""foo""#);
+    assert_eq!(to(":foo").unwrap(), r#""foo""#);
+    assert_eq!(to(r#"'(1 "a" (b))"#).unwrap(), r#"[1,"a",["b"]]"#);
+    assert_eq!(to(r#"
+        (struct point ((x integer) (y integer)))
+        (new point :x 1 :y 2)
+        "#).unwrap(), r#"{"x":1,"y":2}"#);
+
+    assert!(to("id").is_err());
+    assert!(to("(inf)").is_err());
+}
+
+#[test]
+fn test_from_json() {
+    assert_eq!(from("null"), "()");
+    assert_eq!(from("false"), "false");
+    assert_eq!(from("-12"), "-12");
+    assert_eq!(from("2.5"), "2.5");
+    assert_eq!(from(r#""foo""#), r#""foo""#);
+    assert_eq!(from("[]"), "()");
+    assert_eq!(from(r#"[1, [2, "x"]]"#), r#"(1 (2 "x"))"#);
+    assert_eq!(from(r#"{"a": 1, "b-c": [true]}"#), "{:a 1 :b-c (true)}");
+    assert_eq!(from("{}"), "{}");
+}
+
+#[test]
+fn test_json_round_trip() {
+    let inputs = [
+        r#"{"a":1,"b":{"c":[true,null]}}"#,
+        r#"[{"x":"y"},{}]"#,
+    ];
+
+    for input in &inputs {
+        let interp = Interpreter::new();
+        let json = serde_json::from_str(input).unwrap();
+        let v = from_json(&mut interp.get_scope().borrow_names_mut(), &json);
+        let names = interp.get_scope().borrow_names();
+        assert_eq!(to_json(&names, &v).unwrap().to_string(), *input);
+    }
+
+    assert_eq!(to("{:a 1 :b {:c '(true)}}").unwrap(), r#"{"a":1,"b":{"c":[true]}}"#);
+    assert_eq!(from(r#"{"a":1,"b":{"c":[true]}}"#), "{:a 1 :b {:c (true)}}");
+}
@@ -0,0 +1,611 @@
+extern crate ketos;
+extern crate rand;
+
+use std::cell::Cell;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use rand::{SeedableRng, XorShiftRng};
+
+use ketos::{
+    CompileError,
+    Error,
+    ExecError,
+    Interpreter,
+    BuiltinModuleLoader, FileModuleLoader, Module, ModuleBuilder, ModuleLoader, ModuleVersion,
+    Name,
+    GlobalScope, Scope,
+};
+
+macro_rules! assert_matches {
+    ( $e:expr, $pat:pat ) => {
+        match $e {
+            $pat => (),
+            e => panic!("assertion failed: `{:?}` does not match `{}`",
+                e, stringify!($pat))
+        }
+    }
+}
+
+struct HookLoader {
+    inits: Rc<Cell<u32>>,
+    teardowns: Rc<Cell<u32>>,
+}
+
+impl ModuleLoader for HookLoader {
+    fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
+        let load_hooks = scope.with_name(name, |name| name == "hooks");
+
+        if load_hooks {
+            let inits = self.inits.clone();
+            let teardowns = self.teardowns.clone();
+
+            Ok(ModuleBuilder::new("hooks", GlobalScope::new_using(scope))
+                .add_value("value", 1.into())
+                .on_init(move |_scope| { inits.set(inits.get() + 1); Ok(()) })
+                .on_teardown(move |_scope| teardowns.set(teardowns.get() + 1))
+                .finish())
+        } else {
+            BuiltinModuleLoader.load_module(name, scope)
+        }
+    }
+}
+
+/// Loads a module which registers a reader macro and a format directive
+struct ReaderLoader;
+
+impl ModuleLoader for ReaderLoader {
+    fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
+        if scope.with_name(name, |name| name == "reader") {
+            Ok(ModuleBuilder::new("reader", GlobalScope::new_using(scope))
+                .add_value("value", 1.into())
+                .on_init(|scope| {
+                    scope.add_reader_macro('!', |_p, _sp| Ok(1.into()));
+                    scope.get_format_directives().add('q', |_, _, _| Ok("q".to_owned()));
+                    Ok(())
+                })
+                .finish())
+        } else {
+            BuiltinModuleLoader.load_module(name, scope)
+        }
+    }
+}
+
+#[test]
+fn test_module_hooks() {
+    let inits = Rc::new(Cell::new(0));
+    let teardowns = Rc::new(Cell::new(0));
+
+    {
+        let interp = Interpreter::with_loader(Box::new(HookLoader{
+            inits: inits.clone(),
+            teardowns: teardowns.clone(),
+        }));
+
+        interp.run_code("(use hooks (value))", None).unwrap();
+        interp.run_code("(use hooks :all)", None).unwrap();
+
+        assert_eq!(inits.get(), 1);
+        assert_eq!(teardowns.get(), 0);
+    }
+
+    assert_eq!(inits.get(), 1);
+    assert_eq!(teardowns.get(), 1);
+}
+
+#[test]
+fn test_isolated_modules() {
+    let interp = Interpreter::with_loader(Box::new(ReaderLoader));
+    assert!(!interp.get_scope().get_modules().is_isolated());
+
+    interp.run_code("(use reader (value))", None).unwrap();
+    let v = interp.run_code("#!", None).unwrap();
+    assert_eq!(interp.format_value(&v), "1");
+    let v = interp.run_code(r#"(format "~q" 0)"#, None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""q""#);
+
+    let interp = Interpreter::with_loader(Box::new(ReaderLoader));
+    interp.get_scope().get_modules().isolate();
+
+    interp.run_code("(use reader (value))", None).unwrap();
+    assert_matches!(interp.run_code("#!", None).unwrap_err(),
+        Error::ParseError(_));
+    assert!(interp.run_code(r#"(format "~q" 0)"#, None).is_err());
+}
+
+#[test]
+fn test_loaded_modules() {
+    let interp = Interpreter::new();
+
+    interp.run_code("(use random (random)) (use math (sqrt))", None).unwrap();
+
+    let v = interp.run_code("(loaded-modules)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "(random math)");
+
+    let modules = interp.get_scope().get_modules().iter().collect::<Vec<_>>();
+    assert_eq!(modules.len(), 2);
+
+    let names = interp.get_scope().borrow_names();
+    let math = &modules[1];
+
+    assert_eq!(names.get(math.name), "math");
+    assert!(math.doc.is_some());
+    assert!(math.exports().iter().any(|&n| names.get(n) == "sqrt"));
+}
+
+#[test]
+fn test_remove_module() {
+    let inits = Rc::new(Cell::new(0));
+    let teardowns = Rc::new(Cell::new(0));
+
+    let interp = Interpreter::with_loader(Box::new(HookLoader{
+        inits: inits.clone(),
+        teardowns: teardowns.clone(),
+    }));
+
+    interp.run_code("(use hooks (value))", None).unwrap();
+
+    let name = interp.get_scope().add_name("hooks");
+    let m = interp.get_scope().get_modules().remove(name);
+
+    assert!(m.is_some());
+    assert_eq!(teardowns.get(), 1);
+    assert!(interp.get_scope().get_modules().remove(name).is_none());
+
+    // Previously imported values remain available
+    let v = interp.run_code("value", None).unwrap();
+    assert_eq!(interp.format_value(&v), "1");
+
+    interp.run_code("(use hooks (value))", None).unwrap();
+    assert_eq!(inits.get(), 2);
+}
+
+#[test]
+fn test_replace_module() {
+    let interp = Interpreter::new();
+
+    interp.run_code("(use math (sqrt))", None).unwrap();
+
+    let scope = interp.get_scope();
+    let name = scope.add_name("math");
+
+    let m = ModuleBuilder::new("math", GlobalScope::new_using(scope))
+        .add_value("sqrt", "replaced".into())
+        .finish();
+
+    let old = scope.get_modules().replace(name, m).unwrap();
+    assert!(old.is_some());
+
+    let v = interp.run_code("(= (sqrt 4.0) 2.0)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "true");
+
+    interp.run_code("(use math (:new-sqrt sqrt))", None).unwrap();
+
+    let v = interp.run_code("new-sqrt", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""replaced""#);
+}
+
+fn write_file(dir: &Path, name: &str, src: &str) {
+    let mut f = File::create(dir.join(name)).unwrap();
+    f.write_all(src.as_bytes()).unwrap();
+}
+
+fn write_module(dir: &Path, name: &str, src: &str) {
+    write_file(dir, &format!("{}.ket", name), src);
+}
+
+#[test]
+fn test_module_isolation() {
+    let dir = env::temp_dir().join("ketos-test-module-isolation");
+    let _ = fs::create_dir_all(&dir);
+
+    write_module(&dir, "private", r#"
+        (export (public get-outer))
+        (define (secret) "secret")
+        (define (public) (secret))
+        (define (get-outer) outer)
+        "#);
+
+    let interp = Interpreter::with_search_paths(vec![dir.clone()]);
+
+    interp.run_code("(define outer 1)", None).unwrap();
+    interp.run_code("(use private (public get-outer))", None).unwrap();
+
+    let v = interp.run_code("(public)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""secret""#);
+
+    assert_matches!(interp.run_code("(use private (secret))", None).unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+    assert_matches!(interp.run_code("(get-outer)", None).unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    // Definitions in the importing scope do not affect the module
+    interp.run_code(r#"(define (secret) "replaced")"#, None).unwrap();
+    let v = interp.run_code("(public)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""secret""#);
+
+    // Names imported by a module are not visible through it
+    // unless the module exports them
+    write_module(&dir, "wrapper", r#"
+        (use private (public))
+        (export (wrapped))
+        (define (wrapped) (public))
+        "#);
+
+    interp.run_code("(use wrapper (wrapped))", None).unwrap();
+    let v = interp.run_code("(wrapped)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""secret""#);
+
+    assert_matches!(interp.run_code("(use wrapper (public))", None).unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_shared_interpreters() {
+    let inits = Rc::new(Cell::new(0));
+    let teardowns = Rc::new(Cell::new(0));
+
+    let base = Interpreter::with_loader(Box::new(HookLoader{
+        inits: inits.clone(),
+        teardowns: teardowns.clone(),
+    }));
+
+    base.load_module("hooks").unwrap();
+    base.get_scope().get_modules().freeze();
+
+    let a = base.new_sharing();
+    let b = base.new_sharing();
+
+    a.run_code("(use hooks (value)) (define x 1)", None).unwrap();
+    assert_eq!(a.last_run_stats().module_loads, 0);
+    b.run_code("(use hooks (value)) (define x 2)", None).unwrap();
+
+    // Definitions are not shared
+    let v = a.run_code("(+ x value)", None).unwrap();
+    assert_eq!(a.format_value(&v), "2");
+    let v = b.run_code("(+ x value)", None).unwrap();
+    assert_eq!(b.format_value(&v), "3");
+    assert!(base.get_value("x").is_none());
+
+    // Names are shared
+    let name = a.get_scope().add_name("shared-name");
+    assert_eq!(b.get_scope().with_name(name, |s| s.to_owned()), "shared-name");
+
+    assert_matches!(a.run_code("(use math (sqrt))", None).unwrap_err(),
+        Error::CompileError(CompileError::ModuleError(_)));
+
+    assert_eq!(inits.get(), 1);
+
+    // Modules outlive the interpreter which loaded them
+    drop(base);
+    drop(a);
+    assert_eq!(teardowns.get(), 0);
+
+    let c = b.new_sharing();
+    c.run_code("(use hooks (value))", None).unwrap();
+    drop(b);
+    assert_eq!(teardowns.get(), 0);
+
+    drop(c);
+    assert_eq!(teardowns.get(), 1);
+}
+
+#[test]
+fn test_module_version() {
+    let dir = env::temp_dir().join("ketos-test-module-version");
+    let _ = fs::create_dir_all(&dir);
+
+    write_module(&dir, "versioned", r#"
+        (export (foo) :version "1.2.0")
+        (define foo 1)
+        "#);
+    write_module(&dir, "unversioned", r#"
+        (export (foo))
+        (define foo 1)
+        "#);
+
+    let interp = Interpreter::with_search_paths(vec![dir.clone()]);
+
+    interp.run_code(r#"(use versioned (foo) :version "1.2")"#, None).unwrap();
+    interp.run_code(r#"(use versioned (foo) :version "1.1.9")"#, None).unwrap();
+
+    assert_matches!(interp.run_code(
+            r#"(use versioned (foo) :version "1.10")"#, None).unwrap_err(),
+        Error::CompileError(CompileError::ModuleVersionError{found: Some(_), ..}));
+    assert_matches!(interp.run_code(
+            r#"(use unversioned (foo) :version "1")"#, None).unwrap_err(),
+        Error::CompileError(CompileError::ModuleVersionError{found: None, ..}));
+    assert_matches!(interp.run_code(
+            r#"(use versioned (foo) :version "x.y")"#, None).unwrap_err(),
+        Error::CompileError(CompileError::SyntaxError(_)));
+
+    let name = interp.get_scope().add_name("versioned");
+    let m = interp.get_scope().get_modules().remove(name).unwrap();
+
+    assert_eq!(m.version, Some(ModuleVersion::new(vec![1, 2])));
+
+    // Load the module again from compiled bytecode
+    let interp = Interpreter::with_search_paths(vec![dir.clone()]);
+
+    interp.run_code(r#"(use versioned (foo) :version "1.2")"#, None).unwrap();
+    assert_matches!(interp.run_code(
+            r#"(use versioned (foo) :version "2")"#, None).unwrap_err(),
+        Error::CompileError(CompileError::ModuleVersionError{found: Some(_), ..}));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_function_doc() {
+    let interp = Interpreter::new();
+
+    interp.run_code("(use code (doc help))", None).unwrap();
+
+    let v = interp.run_code("(doc help)", None).unwrap();
+    assert_eq!(interp.format_value(&v),
+        r#""Prints the signature and documentation of a function.""#);
+
+    let v = interp.run_code("(doc +)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "()");
+
+    assert_matches!(interp.run_code("(doc 1)", None).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_definition_doc() {
+    let interp = Interpreter::new();
+
+    interp.run_code(r#"
+        (use code (doc))
+
+        ;; Number of foos
+        (const foo 1)
+        (const bar 2)
+
+        ;; A point
+        (struct Point (
+          ;; Horizontal position
+          (x integer)
+          (y integer)))
+        "#, None).unwrap();
+
+    let v = interp.run_code("(doc 'foo)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""Number of foos""#);
+
+    let v = interp.run_code("(doc 'bar)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "()");
+
+    let v = interp.run_code("(doc Point)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""A point""#);
+
+    let v = interp.run_code("(doc Point 'x)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""Horizontal position""#);
+
+    let v = interp.run_code("(doc Point 'y)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "()");
+
+    assert_matches!(interp.run_code("(doc Point 'z)", None).unwrap_err(),
+        Error::ExecError(ExecError::FieldError{..}));
+}
+
+#[test]
+fn test_module_definition_doc() {
+    let dir = env::temp_dir().join("ketos-test-module-definition-doc");
+    let _ = fs::create_dir_all(&dir);
+
+    write_module(&dir, "documented", r#"
+        (export (limit Config))
+
+        ;; Maximum number of things
+        (const limit 10)
+
+        ;; Configuration
+        (struct Config (
+          ;; Name of the thing
+          (name string)))
+        "#);
+
+    for _ in 0..2 {
+        // Second iteration loads the module from compiled bytecode
+        let interp = Interpreter::with_search_paths(vec![dir.clone()]);
+
+        interp.run_code("(use code (doc)) (use documented (Config))", None).unwrap();
+
+        let v = interp.run_code("(doc Config 'name)", None).unwrap();
+        assert_eq!(interp.format_value(&v), r#""Name of the thing""#);
+    }
+
+    let interp = Interpreter::with_search_paths(vec![dir.clone()]);
+    let _ = fs::remove_file(dir.join("documented.ketc"));
+
+    interp.run_code("(use code (doc)) (use documented () :const (limit))", None).unwrap();
+
+    let v = interp.run_code("(doc 'limit)", None).unwrap();
+    assert_eq!(interp.format_value(&v), r#""Maximum number of things""#);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_file_extensions() {
+    let dir = env::temp_dir().join("ketos-test-file-extensions");
+    let _ = fs::create_dir_all(&dir);
+
+    write_file(&dir, "lispy.lisp", "(export (x)) (define x 1)");
+    write_file(&dir, "config.cfg", "(export (y)) (define y VALUE)");
+
+    let mut loader = FileModuleLoader::with_search_paths(vec![dir.clone()]);
+
+    loader.add_extension("lisp");
+    loader.add_extension("cfg");
+    loader.set_source_transform(|path, src| {
+        if path.extension().map_or(false, |ext| ext == "cfg") {
+            src.replace("VALUE", "42")
+        } else {
+            src
+        }
+    });
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+
+    let v = interp.run_code("(use lispy (x)) (use config (y)) (+ x y)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "43");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_random_seed() {
+    let run = || {
+        let interp = Interpreter::new();
+        interp.get_scope().set_rng(Box::new(XorShiftRng::from_seed([1, 2, 3, 4])));
+
+        let v = interp.run_code(r#"
+            (use random (random shuffle))
+            (list (random) (random) (shuffle '(1 2 3 4 5 6 7 8)))
+            "#, None).unwrap();
+        interp.format_value(&v)
+    };
+
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn test_test_module() {
+    use ketos::testing::run_tests;
+
+    let dir = env::temp_dir().join("ketos-test-test-module");
+    let _ = fs::create_dir_all(&dir);
+
+    write_module(&dir, "tested", r#"
+        (use test :all :macro :all)
+        (export (double))
+
+        (define (double a) (* a 2))
+        (define (divide a b) (/ a b))
+
+        (deftest double
+          (assert-eq (double 2) 4)
+          (assert (= (double 0) 0)))
+
+        (deftest divide
+          (assert-eq (assert-raises (lambda () (divide 1 0)) "exec/divide-by-zero")
+            "attempt to divide by zero"))
+        "#);
+
+    let interp = Interpreter::with_search_paths(vec![dir.clone()]);
+
+    interp.run_code(r#"
+        (use tested (double))
+        (use test :all :macro :all)
+
+        (deftest broken
+          (assert-eq (double 2) 5))
+
+        (deftest no-error
+          (assert-raises (lambda () (double 1))))
+
+        (define (test-with-args a) (panic "not a test"))
+        "#, None).unwrap();
+
+    let report = run_tests(&interp);
+
+    let names = report.results.iter()
+        .map(|r| (r.module.as_ref().map(|s| &s[..]), &r.name[..], r.is_success()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, [
+        (None, "test-broken", false),
+        (None, "test-no-error", false),
+        (Some("tested"), "test-divide", true),
+        (Some("tested"), "test-double", true),
+    ]);
+
+    assert_eq!(report.passed(), 2);
+    assert_eq!(report.failed(), 2);
+    assert!(report.failures()[0].error.as_ref().unwrap()
+        .contains("assertion `(double 2) == 5` failed"));
+    assert!(report.failures()[1].error.as_ref().unwrap()
+        .contains("expected error; found 2"));
+
+    assert_matches!(interp.run_code("(use test (make-test-name))", None).unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_compat_modules() {
+    let interp = Interpreter::new();
+
+    let eval = |s: &str| interp.format_value(&interp.run_code(s, None).unwrap());
+
+    interp.run_code("(use scheme :all)", None).unwrap();
+
+    assert_eq!(eval("(cons 1 (cdr '(2 3 4)))"), "(1 3 4)");
+    assert_eq!(eval("(cons 1 ())"), "(1)");
+    assert_eq!(eval("(list (car '(1 2)) (cadr '(1 2)) (length '(1 2)) (list-ref '(1 2) 1))"),
+        "(1 2 2 2)");
+    assert_eq!(eval("(list (null? ()) (pair? ()) (list? ()) (pair? '(1)) (symbol? 'a))"),
+        "(true false true true true)");
+    assert_eq!(eval("(list (procedure? car) (number? 1/2) (string? \"a\") (equal? 1 1))"),
+        "(true true true true)");
+
+    assert_matches!(interp.run_code("(car)", None).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{..}));
+
+    interp.run_code("(use clojure :all)", None).unwrap();
+
+    assert_eq!(eval("(conj '(1 2) 3 4)"), "(4 3 1 2)");
+    assert_eq!(eval("(conj () 1)"), "(1)");
+    assert_eq!(eval("(conj '(1))"), "(1)");
+    assert_eq!(eval("(list (count '(1 2)) (nth '(1 2) 0) (inc 1) (dec 1/2))"), "(2 1 2 -1/2)");
+    assert_eq!(eval("(list (rest ()) (rest '(1 2)) (nil? ()) (empty? \"\") (empty? '(1)))"),
+        "(() (2) true true false)");
+    assert_eq!(eval("(assoc '(:a 1 :b 2) :a 3 :c 4)"), "(:a 3 :b 2 :c 4)");
+    assert_eq!(eval("(assoc '(1 2) 0 :x 2 :y)"), "(:x 2 :y)");
+    assert_eq!(eval("(list (get '(:a 1 :b 2) :b) (get '(1 2 3) 5 :missing) (get () 0))"),
+        "(2 :missing ())");
+
+    assert_matches!(interp.run_code("(assoc '(1 2) 3 :x)", None).unwrap_err(),
+        Error::ExecError(ExecError::OutOfBounds(3)));
+    assert_matches!(interp.run_code("(assoc '(1 2) :a)", None).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{..}));
+}
+
+#[test]
+fn test_run_script_file() {
+    let dir = env::temp_dir().join("ketos-test-run-script-file");
+    let _ = fs::create_dir_all(&dir);
+
+    write_file(&dir, "count.ket", r#"#!/usr/bin/env ketos
+        (define (helper args) (len args))
+        (define (main args)
+          (if (= (first args) "fail") false (helper args)))
+        "#);
+    write_file(&dir, "unit.ket", "(define (main args) ())");
+    write_file(&dir, "string.ket", "(define (main args) \"oops\")");
+    write_file(&dir, "no-main.ket", "(define foo 1)");
+
+    let interp = Interpreter::new();
+
+    assert_eq!(interp.run_script_file(&dir.join("count.ket"), &["a", "b", "c"]).unwrap(), 3);
+    assert_eq!(interp.run_script_file(&dir.join("count.ket"), &["fail"]).unwrap(), 1);
+    assert_eq!(interp.run_script_file::<&str>(&dir.join("unit.ket"), &[]).unwrap(), 0);
+
+    assert_matches!(interp.run_script_file::<&str>(&dir.join("string.ket"), &[]).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "integer", ..}));
+    assert_matches!(interp.run_script_file::<&str>(&dir.join("no-main.ket"), &[]).unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    // Definitions made by scripts do not leak into the interpreter scope
+    assert!(interp.get_value("helper").is_none());
+
+    let _ = fs::remove_dir_all(&dir);
+}